@@ -0,0 +1,41 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+fn main() {
+    // The gRPC service of proto/procrec.proto, generated from a description
+    // in rust to not require protoc for building. The messages are defined
+    // in src/export/grpc.rs.
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        let service = Service::builder()
+            .name("Recorder")
+            .package("procrec")
+            .method(
+                Method::builder()
+                    .name("subscribe")
+                    .route_name("Subscribe")
+                    .input_type("crate::export::grpc::SubscribeRequest")
+                    .output_type("crate::export::grpc::GrpcSample")
+                    .codec_path("tonic::codec::ProstCodec")
+                    .server_streaming()
+                    .build(),
+            )
+            .build();
+        Builder::new().build_client(false).compile(&[service]);
+    }
+}
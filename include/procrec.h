@@ -0,0 +1,42 @@
+/*
+ * procrec - record/plot cpu and memory usage of processes
+ *
+ * C interface of the procrec library, built with the `ffi` feature:
+ *   cargo rustc --lib --release --features ffi --crate-type cdylib
+ */
+
+#ifndef PROCREC_H
+#define PROCREC_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A single sample, memory in kB */
+typedef struct {
+    float ts;
+    uint32_t pid;
+    float cpu;
+    uint64_t rss;
+    uint64_t vsize;
+} procrec_sample;
+
+typedef struct procrec_handle procrec_handle;
+
+/* Start recording a process, returns NULL if it can not be attached */
+procrec_handle *procrec_start(uint32_t pid, uint32_t interval_ms);
+
+/* 1 if a sample was written to out, 0 if none is available yet,
+ * -1 once the recording finished */
+int32_t procrec_poll_sample(procrec_handle *handle, procrec_sample *out);
+
+/* Stop the recording and release the handle */
+void procrec_stop(procrec_handle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,93 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Alerts raised when a sample breaches a condition.
+
+use crate::condition::Condition;
+use crate::{Event, Sample};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A condition which was breached by a process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    /// Relative time of the recording in seconds
+    pub ts: f32,
+    pub pid: u32,
+    /// Label of the process, its name and PID
+    pub label: String,
+    pub condition: Condition,
+    /// Value of the metric breaching the condition
+    pub value: f64,
+}
+
+impl Alert {
+    /// Marker of the alert in the recording.
+    pub fn event(&self) -> Event {
+        Event {
+            ts: self.ts,
+            label: format!("alert {}: {}", self.condition, self.label),
+            marker: false,
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} is ", self.label, self.condition.metric.name())?;
+        if self.condition.metric.is_memory() {
+            write!(f, "{}kB", self.value)?;
+        } else {
+            write!(f, "{:.1}%", self.value)?;
+        }
+        write!(f, ", breaching {}", self.condition)
+    }
+}
+
+/// Watches a condition for every process, it fires once the condition held
+/// for its duration and again only after it stopped holding in between.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub condition: Condition,
+    // per PID: time since the condition holds, whether it fired already
+    state: HashMap<u32, (f32, bool)>,
+}
+
+impl Trigger {
+    pub fn new(condition: Condition) -> Self {
+        Trigger {
+            condition,
+            state: HashMap::new(),
+        }
+    }
+
+    /// Update with a new sample, returns the value of the metric if the
+    /// trigger fires.
+    pub fn update(&mut self, sample: &Sample) -> Option<f64> {
+        if self.condition.holds(sample) != Some(true) {
+            self.state.remove(&sample.pid);
+            return None;
+        }
+        let (since, fired) = self.state.entry(sample.pid).or_insert((sample.ts, false));
+        let duration = self.condition.duration.unwrap_or_default().as_secs_f32();
+        if *fired || sample.ts - *since < duration {
+            return None;
+        }
+        *fired = true;
+        self.condition.metric.value(sample)
+    }
+}
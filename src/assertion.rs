@@ -0,0 +1,170 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resource budgets checked against a finished recording.
+
+use crate::condition::{self, Comparison};
+use crate::summary::{self, Stats};
+use crate::{Metric, Recording};
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Statistic of a metric over the recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stat {
+    Min,
+    Mean,
+    Max,
+    Stddev,
+    /// Percentile between 0 and 100
+    Percentile(f64),
+}
+
+impl FromStr for Stat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "min" => Stat::Min,
+            "mean" | "avg" => Stat::Mean,
+            "max" => Stat::Max,
+            "stddev" => Stat::Stddev,
+            _ => match s.strip_prefix('p').map(str::parse::<f64>) {
+                Some(Ok(p)) if (0.0..=100.0).contains(&p) => Stat::Percentile(p),
+                _ => bail!(
+                    "Unknown statistic '{}' (min, mean, max, stddev or a percentile like p95)",
+                    s
+                ),
+            },
+        })
+    }
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stat::Min => write!(f, "min"),
+            Stat::Mean => write!(f, "mean"),
+            Stat::Max => write!(f, "max"),
+            Stat::Stddev => write!(f, "stddev"),
+            Stat::Percentile(p) => write!(f, "p{}", p),
+        }
+    }
+}
+
+/// A budget for a statistic of a metric, e.g. `max_rss<512M` or
+/// `p95_cpu<150`.
+///
+/// Metrics are summed up over all processes per sampling round, thresholds
+/// take the units of [`Condition`](crate::condition::Condition).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assertion {
+    pub stat: Stat,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+}
+
+impl Assertion {
+    /// Value of the statistic in the recording, `None` if the metric was not
+    /// recorded.
+    pub fn value(&self, recording: &Recording) -> Option<f64> {
+        let rounds = summary::rounds(&recording.samples);
+        let values: Vec<f64> = summary::totals(&rounds, self.metric)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect();
+        let stats = Stats::of(&values)?;
+        Some(match self.stat {
+            Stat::Min => stats.min,
+            Stat::Mean => stats.mean,
+            Stat::Max => stats.max,
+            Stat::Stddev => stats.stddev,
+            Stat::Percentile(p) => {
+                let mut sorted = values;
+                sorted.sort_by(f64::total_cmp);
+                summary::percentile(&sorted, p)
+            }
+        })
+    }
+
+    /// Check the recording against the budget.
+    pub fn check(&self, recording: &Recording) -> Outcome {
+        let value = self.value(recording);
+        Outcome {
+            assertion: self.clone(),
+            value,
+            passed: value.is_some_and(|v| self.comparison.compare(v, self.threshold)),
+        }
+    }
+}
+
+impl FromStr for Assertion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid assertion '{}', expected e.g. 'max_rss<512M'", s);
+        let (left, comparison, value) = condition::split_comparison(s).ok_or_else(invalid)?;
+        let (stat, metric) = left.split_once('_').ok_or_else(invalid)?;
+        let metric: Metric = metric.parse()?;
+        Ok(Assertion {
+            stat: stat.parse()?,
+            metric,
+            comparison,
+            threshold: condition::parse_value(metric, value)
+                .ok_or_else(|| anyhow!("Invalid threshold in assertion '{}'", s))?,
+        })
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}_{}{}{}",
+            self.stat,
+            self.metric.name(),
+            self.comparison.symbol(),
+            self.threshold
+        )?;
+        if self.metric.is_memory() {
+            write!(f, "kB")?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of checking an assertion, displayed as report line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub assertion: Assertion,
+    /// Value of the statistic, `None` if the metric was not recorded
+    pub value: Option<f64>,
+    pub passed: bool,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.passed { "PASS" } else { "FAIL" };
+        write!(f, "{} {}: ", verdict, self.assertion)?;
+        match self.value {
+            Some(value) if self.assertion.metric.is_memory() => write!(f, "{:.0}kB", value),
+            Some(value) => write!(f, "{:.2}", value),
+            None => write!(f, "not recorded"),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Aggregate statistics of repeated runs of a command.
+
+use crate::significance::{self, MannWhitney};
+use crate::summary::Stats;
+use crate::{Metric, Recording};
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Result of one run of the benchmarked command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Run {
+    /// Wall time in seconds, up to one sampling interval late, unknown for
+    /// runs loaded from recordings made without it
+    pub wall: Option<f64>,
+    /// CPU time in seconds reported by the operating system
+    pub cpu: Option<f64>,
+    /// Highest RSS in kB, the high-water mark if available or the sampled
+    /// maximum
+    pub peak_rss: Option<u64>,
+    /// Exit code, 128 plus the signal if killed
+    pub exit_code: Option<i32>,
+    /// One of the first runs, which `--cold` reports separately
+    pub cold: bool,
+}
+
+impl Run {
+    pub fn new(recording: &Recording, wall: Option<Duration>, exit_code: Option<i32>) -> Run {
+        let processes = &recording.metadata.processes;
+        let cpu = processes
+            .iter()
+            .filter_map(|p| p.cpu_time)
+            .map(|t| t.total())
+            .reduce(|a, b| a + b);
+        let sampled = recording
+            .samples
+            .iter()
+            .filter_map(|s| Metric::Rss.value(s))
+            .reduce(f64::max)
+            .map(|rss| rss as u64);
+        let peak_rss = processes
+            .iter()
+            .filter_map(|p| p.peak_rss)
+            .max()
+            .max(sampled);
+        Run {
+            wall: wall
+                .map(|wall| wall.as_secs_f64())
+                .or(recording.metadata.wall),
+            cpu,
+            peak_rss,
+            exit_code,
+            cold: recording.metadata.cold,
+        }
+    }
+
+    /// Labels of the `values`.
+    pub const MEASURES: [&'static str; 3] = ["wall [s]", "CPU [s]", "peak RSS [kB]"];
+
+    /// Wall time, CPU time and peak RSS.
+    pub fn values(&self) -> [Option<f64>; 3] {
+        [self.wall, self.cpu, self.peak_rss.map(|rss| rss as f64)]
+    }
+
+    pub fn failed(&self) -> bool {
+        self.exit_code.is_some_and(|code| code != 0)
+    }
+}
+
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(wall) = self.wall {
+            parts.push(format!("wall {:.3}s", wall));
+        }
+        if let Some(cpu) = self.cpu {
+            parts.push(format!("CPU {:.3}s", cpu));
+        }
+        if let Some(rss) = self.peak_rss {
+            parts.push(format!("peak RSS {}kB", rss));
+        }
+        write!(f, "{}", parts.join(", "))?;
+        match self.exit_code {
+            Some(code) if code != 0 => write!(f, ", failed with exit code {}", code),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Statistics over a set of runs, displayed as table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub runs: Vec<Run>,
+    pub wall: Option<Stats>,
+    pub cpu: Option<Stats>,
+    pub peak_rss: Option<Stats>,
+}
+
+impl Report {
+    pub fn new(runs: &[Run]) -> Report {
+        let of = |values: Vec<f64>| Stats::of(&values);
+        Report {
+            runs: runs.to_vec(),
+            wall: of(runs.iter().filter_map(|r| r.wall).collect()),
+            cpu: of(runs.iter().filter_map(|r| r.cpu).collect()),
+            peak_rss: of(runs
+                .iter()
+                .filter_map(|r| r.peak_rss.map(|rss| rss as f64))
+                .collect()),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<14} {:>12} {:>12} {:>12} {:>12}",
+            "", "mean", "stddev", "min", "max"
+        )?;
+        for (label, stats) in [
+            ("wall [s]", self.wall),
+            ("CPU [s]", self.cpu),
+            ("peak RSS [kB]", self.peak_rss),
+        ] {
+            if let Some(s) = stats {
+                writeln!(
+                    f,
+                    "{:<14} {:>12.3} {:>12.3} {:>12.3} {:>12.3}",
+                    label, s.mean, s.stddev, s.min, s.max
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Load the runs saved by `procrec bench -o` to the directory, in the order
+/// they ran.
+pub fn load(dir: &Path) -> Result<Vec<Run>> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| anyhow!("Can not read '{}': {}", dir.display(), e))?;
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("run-")?.strip_suffix(".rec"))
+            .and_then(|n| n.parse::<u32>().ok());
+        if let Some(number) = number {
+            files.push((number, path));
+        }
+    }
+    if files.is_empty() {
+        bail!("No run-N.rec files in '{}'", dir.display());
+    }
+    files.sort();
+    files
+        .iter()
+        .map(|(_, path)| Ok(Run::new(&Recording::load(path)?, None, None)))
+        .collect()
+}
+
+/// Difference of a measure between two sets of runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difference {
+    pub label: &'static str,
+    /// Means before and after
+    pub before: f64,
+    pub after: f64,
+    pub test: MannWhitney,
+}
+
+/// Differences of the wall time, CPU time and peak RSS between two sets of
+/// runs, displayed as table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison(pub Vec<Difference>);
+
+impl Comparison {
+    /// Compare the measures known for both sets of runs.
+    pub fn new(before: &[Run], after: &[Run]) -> Comparison {
+        let mut differences = vec![];
+        for (i, &label) in Run::MEASURES.iter().enumerate() {
+            let a: Vec<f64> = before.iter().filter_map(|r| r.values()[i]).collect();
+            let b: Vec<f64> = after.iter().filter_map(|r| r.values()[i]).collect();
+            if let (Some(test), Some(sa), Some(sb)) = (
+                significance::mann_whitney(&a, &b),
+                Stats::of(&a),
+                Stats::of(&b),
+            ) {
+                differences.push(Difference {
+                    label,
+                    before: sa.mean,
+                    after: sb.mean,
+                    test,
+                });
+            }
+        }
+        Comparison(differences)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<14} {:>12} {:>12} {:>9} {:>8}",
+            "", "before", "after", "change", "p"
+        )?;
+        for d in &self.0 {
+            let change = if d.before != 0.0 {
+                format!("{:+.1}%", (d.after - d.before) / d.before * 100.0)
+            } else {
+                "-".to_string()
+            };
+            let verdict = if d.test.significant() {
+                "significant"
+            } else {
+                "noise"
+            };
+            writeln!(
+                f,
+                "{:<14} {:>12.3} {:>12.3} {:>9} {:>8.4}  {}",
+                d.label, d.before, d.after, change, d.test.p, verdict
+            )?;
+        }
+        Ok(())
+    }
+}
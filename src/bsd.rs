@@ -0,0 +1,484 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Process information on FreeBSD and OpenBSD from the kinfo_proc of the
+//! kernel.
+//!
+//! psutil does not support processes on the BSDs, `Process` provides the part
+//! of its interface procrec uses. FreeBSD reads the kinfo_proc with
+//! kvm_getprocs, OpenBSD with the KERN_PROC sysctl. The virtual memory size
+//! is the one `ps` reports as VSZ.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "freebsd")]
+use self::freebsd as sys;
+#[cfg(target_os = "openbsd")]
+use self::openbsd as sys;
+
+pub(crate) use self::sys::parents;
+
+/// Failure to inspect a process, named like the errors of psutil.
+#[derive(Debug)]
+pub enum ProcessError {
+    NoSuchProcess { pid: u32 },
+    ZombieProcess { pid: u32 },
+    AccessDenied { pid: u32 },
+    Os { pid: u32, source: io::Error },
+}
+
+impl ProcessError {
+    fn new(pid: u32, source: io::Error) -> Self {
+        match source.raw_os_error() {
+            Some(libc::ESRCH) => ProcessError::NoSuchProcess { pid },
+            Some(libc::EPERM) | Some(libc::EACCES) => ProcessError::AccessDenied { pid },
+            _ => ProcessError::Os { pid, source },
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::NoSuchProcess { pid } => write!(f, "Process {} does not exist", pid),
+            ProcessError::ZombieProcess { pid } => write!(f, "Process {} is a zombie", pid),
+            ProcessError::AccessDenied { pid } => write!(f, "Access denied for process {}", pid),
+            ProcessError::Os { pid, source } => write!(f, "Process {}: {}", pid, source),
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+pub type ProcessResult<T> = Result<T, ProcessError>;
+
+/// Resident and virtual memory size of a process.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryInfo {
+    rss: u64,
+    vms: u64,
+}
+
+impl MemoryInfo {
+    /// Resident set size in bytes.
+    pub fn rss(&self) -> u64 {
+        self.rss
+    }
+
+    /// Virtual memory size in bytes.
+    pub fn vms(&self) -> u64 {
+        self.vms
+    }
+}
+
+/// CPU times of a process and the children it waited for.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTimes {
+    user: Duration,
+    system: Duration,
+    children_user: Duration,
+    children_system: Duration,
+}
+
+impl CpuTimes {
+    pub fn user(&self) -> Duration {
+        self.user
+    }
+
+    pub fn system(&self) -> Duration {
+        self.system
+    }
+
+    /// User time of the children, on OpenBSD including their system time,
+    /// which the kernel only reports combined.
+    pub fn children_user(&self) -> Duration {
+        self.children_user
+    }
+
+    pub fn children_system(&self) -> Duration {
+        self.children_system
+    }
+}
+
+/// The fields of a kinfo_proc procrec uses.
+struct Info {
+    ppid: u32,
+    name: String,
+    zombie: bool,
+    /// Start time since the epoch, which tells a reused PID apart
+    start: Duration,
+    times: CpuTimes,
+    memory: MemoryInfo,
+}
+
+/// A process whose kinfo_proc is read on every query.
+#[derive(Debug, Clone)]
+pub struct Process {
+    pid: u32,
+    start: Duration,
+    /// CPU time and time of the previous `cpu_percent`
+    last_cpu: (Duration, Instant),
+}
+
+impl Process {
+    pub fn new(pid: u32) -> ProcessResult<Process> {
+        let info = sys::info(pid)?;
+        // like psutil, the first call of cpu_percent covers the time since
+        Ok(Process {
+            pid,
+            start: info.start,
+            last_cpu: (info.times.user + info.times.system, Instant::now()),
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Command name, truncated by the kernel.
+    pub fn name(&self) -> ProcessResult<String> {
+        Ok(self.info()?.name)
+    }
+
+    /// PID of the parent process.
+    pub fn ppid(&self) -> ProcessResult<Option<u32>> {
+        Ok(Some(self.info()?.ppid))
+    }
+
+    /// Arguments of the process including the program, `None` if they are
+    /// not accessible.
+    pub fn cmdline_vec(&self) -> ProcessResult<Option<Vec<String>>> {
+        self.info()?;
+        Ok(sys::cmdline(self.pid))
+    }
+
+    /// CPU utilization since the previous call in percent of one CPU.
+    pub fn cpu_percent(&mut self) -> ProcessResult<f32> {
+        let times = self.live_info()?.times;
+        let used = times.user + times.system;
+        let (last_used, last_time) = self.last_cpu;
+        let elapsed = last_time.elapsed().as_secs_f32();
+        self.last_cpu = (used, Instant::now());
+        if elapsed <= 0.0 {
+            return Ok(0.0);
+        }
+        Ok(used.saturating_sub(last_used).as_secs_f32() / elapsed * 100.0)
+    }
+
+    /// CPU times so far, available until the process is reaped.
+    pub fn cpu_times(&self) -> ProcessResult<CpuTimes> {
+        Ok(self.info()?.times)
+    }
+
+    /// Resident and virtual memory size, fails once the process ended.
+    pub fn memory_info(&self) -> ProcessResult<MemoryInfo> {
+        Ok(self.live_info()?.memory)
+    }
+
+    /// Whether the process was not reaped yet and its PID not reused.
+    pub fn is_running(&self) -> bool {
+        self.info().is_ok()
+    }
+
+    /// Whether the process ended and only waits for its parent.
+    pub fn is_zombie(&self) -> bool {
+        matches!(self.info(), Ok(info) if info.zombie)
+    }
+
+    /// Kill the process with SIGKILL.
+    pub fn kill(&self) -> ProcessResult<()> {
+        self.info()?;
+        if unsafe { libc::kill(self.pid as libc::pid_t, libc::SIGKILL) } != 0 {
+            return Err(ProcessError::new(self.pid, io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    /// The kinfo_proc of the process, fails once its PID was reused.
+    fn info(&self) -> ProcessResult<Info> {
+        let info = sys::info(self.pid)?;
+        if info.start != self.start {
+            return Err(ProcessError::NoSuchProcess { pid: self.pid });
+        }
+        Ok(info)
+    }
+
+    /// Like `info`, but also fails for a zombie, which has no usage left.
+    fn live_info(&self) -> ProcessResult<Info> {
+        let info = self.info()?;
+        if info.zombie {
+            return Err(ProcessError::ZombieProcess { pid: self.pid });
+        }
+        Ok(info)
+    }
+}
+
+fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+/// A string in a fixed size field, cut at the first nul.
+fn field_str(field: &[c_char]) -> String {
+    let bytes: Vec<u8> = field
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// The strings of a null terminated array.
+///
+/// # Safety
+///
+/// `argv` must point to a null terminated array of nul terminated strings.
+unsafe fn argv_strings(argv: *const *const c_char) -> Vec<String> {
+    (0..)
+        .map(|i| *argv.add(i))
+        .take_while(|arg| !arg.is_null())
+        .map(|arg| CStr::from_ptr(arg).to_string_lossy().into_owned())
+        .collect()
+}
+
+fn micros(secs: u64, usecs: u64) -> Duration {
+    Duration::from_secs(secs) + Duration::from_micros(usecs)
+}
+
+#[cfg(target_os = "freebsd")]
+mod freebsd {
+    use super::{argv_strings, field_str, micros, page_size};
+    use super::{CpuTimes, Info, MemoryInfo, ProcessError, ProcessResult};
+    use std::ffi::CStr;
+    use std::io;
+    use std::os::raw::{c_char, c_int};
+    use std::ptr;
+    use std::slice;
+
+    /// Handle on the running kernel, closed when dropped.
+    struct Kvm(*mut libc::kvm_t);
+
+    impl Kvm {
+        fn open() -> io::Result<Self> {
+            let mut errbuf = [0 as c_char; libc::_POSIX2_LINE_MAX as usize];
+            // /dev/null as the core file reads the running kernel with
+            // sysctls, which needs no access to /dev/mem
+            let kd = unsafe {
+                libc::kvm_openfiles(
+                    ptr::null(),
+                    b"/dev/null\0".as_ptr() as *const c_char,
+                    ptr::null(),
+                    libc::O_RDONLY,
+                    errbuf.as_mut_ptr(),
+                )
+            };
+            if kd.is_null() {
+                let msg = unsafe { CStr::from_ptr(errbuf.as_ptr()) };
+                return Err(io::Error::other(msg.to_string_lossy().into_owned()));
+            }
+            Ok(Kvm(kd))
+        }
+
+        /// The processes selected by the KERN_PROC operation, kvm reuses
+        /// their buffer on the next call.
+        fn procs(&mut self, op: c_int, arg: c_int) -> io::Result<&[libc::kinfo_proc]> {
+            let mut count = 0;
+            let procs = unsafe { libc::kvm_getprocs(self.0, op, arg, &mut count) };
+            if procs.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(unsafe { slice::from_raw_parts(procs, count as usize) })
+        }
+    }
+
+    impl Drop for Kvm {
+        fn drop(&mut self) {
+            unsafe { libc::kvm_close(self.0) };
+        }
+    }
+
+    fn timeval(t: libc::timeval) -> std::time::Duration {
+        micros(t.tv_sec as u64, t.tv_usec as u64)
+    }
+
+    pub(super) fn info(pid: u32) -> ProcessResult<Info> {
+        let mut kvm = Kvm::open().map_err(|e| ProcessError::new(pid, e))?;
+        let kp = match kvm.procs(libc::KERN_PROC_PID, pid as c_int) {
+            Ok([kp, ..]) => kp,
+            Ok([]) => return Err(ProcessError::NoSuchProcess { pid }),
+            Err(e) => return Err(ProcessError::new(pid, e)),
+        };
+        Ok(Info {
+            ppid: kp.ki_ppid as u32,
+            name: field_str(&kp.ki_comm),
+            zombie: kp.ki_stat == libc::SZOMB,
+            start: timeval(kp.ki_start),
+            times: CpuTimes {
+                user: timeval(kp.ki_rusage.ru_utime),
+                system: timeval(kp.ki_rusage.ru_stime),
+                children_user: timeval(kp.ki_rusage_ch.ru_utime),
+                children_system: timeval(kp.ki_rusage_ch.ru_stime),
+            },
+            memory: MemoryInfo {
+                rss: kp.ki_rssize as u64 * page_size(),
+                vms: kp.ki_size as u64,
+            },
+        })
+    }
+
+    pub(super) fn cmdline(pid: u32) -> Option<Vec<String>> {
+        let mut kvm = Kvm::open().ok()?;
+        let kp = *kvm.procs(libc::KERN_PROC_PID, pid as c_int).ok()?.first()?;
+        let argv = unsafe { libc::kvm_getargv(kvm.0, &kp, 0) };
+        if argv.is_null() {
+            return None;
+        }
+        Some(unsafe { argv_strings(argv as *const *const c_char) })
+    }
+
+    /// PID and parent PID of every process.
+    pub(crate) fn parents() -> Vec<(u32, u32)> {
+        let mut kvm = match Kvm::open() {
+            Ok(kvm) => kvm,
+            Err(_) => return vec![],
+        };
+        kvm.procs(libc::KERN_PROC_PROC, 0)
+            .map(|procs| {
+                procs
+                    .iter()
+                    .map(|kp| (kp.ki_pid as u32, kp.ki_ppid as u32))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(target_os = "openbsd")]
+mod openbsd {
+    use super::{argv_strings, field_str, micros, page_size};
+    use super::{CpuTimes, Info, MemoryInfo, ProcessError, ProcessResult};
+    use std::ffi::c_void;
+    use std::io;
+    use std::mem;
+    use std::os::raw::{c_char, c_int, c_uint};
+    use std::ptr;
+    use std::time::Duration;
+
+    /// States of an ended process in p_stat, from sys/proc.h.
+    const SZOMB: i8 = 5;
+    const SDEAD: i8 = 6;
+    /// Largest buffer for the arguments of a process in bytes, twice ARG_MAX.
+    const MAX_ARGS: usize = 1 << 20;
+
+    fn sysctl(mib: &[c_int], buf: *mut c_void, len: &mut usize) -> io::Result<()> {
+        let res = unsafe {
+            libc::sysctl(
+                mib.as_ptr(),
+                mib.len() as c_uint,
+                buf,
+                len,
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// The processes selected by the KERN_PROC operation.
+    fn procs(op: c_int, arg: c_int) -> io::Result<Vec<libc::kinfo_proc>> {
+        let size = mem::size_of::<libc::kinfo_proc>();
+        let mut mib = [libc::CTL_KERN, libc::KERN_PROC, op, arg, size as c_int, 0];
+        // the kernel leaves room for processes started in the meantime
+        let mut len = 0;
+        sysctl(&mib, ptr::null_mut(), &mut len)?;
+        let count = len / size;
+        let mut procs: Vec<libc::kinfo_proc> = Vec::with_capacity(count);
+        mib[5] = count as c_int;
+        let mut len = count * size;
+        sysctl(&mib, procs.as_mut_ptr() as *mut c_void, &mut len)?;
+        unsafe { procs.set_len(len / size) };
+        Ok(procs)
+    }
+
+    pub(super) fn info(pid: u32) -> ProcessResult<Info> {
+        let procs =
+            procs(libc::KERN_PROC_PID, pid as c_int).map_err(|e| ProcessError::new(pid, e))?;
+        let kp = procs.first().ok_or(ProcessError::NoSuchProcess { pid })?;
+        let page_size = page_size();
+        Ok(Info {
+            ppid: kp.p_ppid as u32,
+            name: field_str(&kp.p_comm),
+            zombie: kp.p_stat == SZOMB || kp.p_stat == SDEAD,
+            start: micros(kp.p_ustart_sec, kp.p_ustart_usec.into()),
+            times: CpuTimes {
+                user: micros(kp.p_uutime_sec.into(), kp.p_uutime_usec.into()),
+                system: micros(kp.p_ustime_sec.into(), kp.p_ustime_usec.into()),
+                children_user: micros(kp.p_uctime_sec.into(), kp.p_uctime_usec.into()),
+                children_system: Duration::ZERO,
+            },
+            memory: MemoryInfo {
+                rss: kp.p_vm_rssize as u64 * page_size,
+                // text, data and stack like VSZ in ps
+                vms: (kp.p_vm_tsize as u64 + kp.p_vm_dsize as u64 + kp.p_vm_ssize as u64)
+                    * page_size,
+            },
+        })
+    }
+
+    pub(super) fn cmdline(pid: u32) -> Option<Vec<String>> {
+        let mib = [
+            libc::CTL_KERN,
+            libc::KERN_PROC_ARGS,
+            pid as c_int,
+            libc::KERN_PROC_ARGV,
+        ];
+        // aligned for the pointers at its start
+        let mut buf = vec![0usize; 4096 / mem::size_of::<usize>()];
+        loop {
+            let mut len = buf.len() * mem::size_of::<usize>();
+            match sysctl(&mib, buf.as_mut_ptr() as *mut c_void, &mut len) {
+                Ok(()) => break,
+                Err(e)
+                    if e.raw_os_error() == Some(libc::ENOMEM)
+                        && buf.len() * mem::size_of::<usize>() < MAX_ARGS =>
+                {
+                    buf.resize(buf.len() * 2, 0);
+                }
+                Err(_) => return None,
+            }
+        }
+        // the kernel points them at the strings behind them in the buffer
+        Some(unsafe { argv_strings(buf.as_ptr() as *const *const c_char) })
+    }
+
+    /// PID and parent PID of every process.
+    pub(crate) fn parents() -> Vec<(u32, u32)> {
+        procs(libc::KERN_PROC_ALL, 0)
+            .map(|procs| {
+                procs
+                    .iter()
+                    .map(|kp| (kp.p_pid as u32, kp.p_ppid as u32))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,80 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Capture windows keeping only the samples around the times a condition
+//! holds.
+
+use crate::condition::Condition;
+use crate::{Sample, ThreadSample};
+use std::collections::VecDeque;
+
+/// Samples and thread samples of one sampling round.
+type Round = (Vec<Sample>, Vec<ThreadSample>);
+
+pub(crate) struct Capture {
+    condition: Condition,
+    /// Rounds kept before the condition holds
+    pre: usize,
+    /// Rounds kept after the condition stopped holding
+    post: usize,
+    buffer: VecDeque<Round>,
+    // rounds left to capture after the condition held
+    remaining: usize,
+}
+
+impl Capture {
+    pub(crate) fn new(condition: Condition, pre: usize, post: usize) -> Self {
+        Capture {
+            condition,
+            pre,
+            post,
+            buffer: VecDeque::new(),
+            remaining: 0,
+        }
+    }
+
+    /// Filter the round starting at the given indices: keep it if the
+    /// condition holds for any process or held recently, otherwise move it to
+    /// the buffer. The buffer is put in front once the condition holds.
+    pub(crate) fn filter(
+        &mut self,
+        samples: &mut Vec<Sample>,
+        threads: &mut Vec<ThreadSample>,
+        first_sample: usize,
+        first_thread: usize,
+    ) {
+        let holds = samples[first_sample..]
+            .iter()
+            .any(|s| self.condition.holds(s) == Some(true));
+        if holds {
+            self.remaining = self.post;
+            let (mut pre_samples, mut pre_threads): (Vec<_>, Vec<_>) =
+                self.buffer.drain(..).unzip();
+            samples.splice(first_sample..first_sample, pre_samples.drain(..).flatten());
+            threads.splice(first_thread..first_thread, pre_threads.drain(..).flatten());
+        } else if self.remaining > 0 {
+            self.remaining -= 1;
+        } else {
+            self.buffer.push_back((
+                samples.split_off(first_sample),
+                threads.split_off(first_thread),
+            ));
+            if self.buffer.len() > self.pre {
+                self.buffer.pop_front();
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Dedicated control groups for the launched command to limit its resources
+//! or account for them (linux only).
+//!
+//! Both the unified hierarchy of cgroup v2 and the per-controller
+//! hierarchies of cgroup v1 are supported, a controller mounted in a v1
+//! hierarchy is used from there.
+
+use crate::CpuTime;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Period of the CPU bandwidth limit in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+
+/// Resource limits of the launched command.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Memory in kB, more is reclaimed or ends in the OOM killer
+    pub memory: Option<u64>,
+    /// Number of CPUs worth of time, may be fractional
+    pub cpus: Option<f64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpus.is_none()
+    }
+}
+
+/// A control group created by procrec, removed again when dropped.
+#[derive(Debug)]
+pub struct Cgroup {
+    /// The group in every hierarchy it was created in
+    dirs: Vec<PathBuf>,
+    /// The group in the hierarchies of the memory and CPU accounting, and
+    /// whether it is the unified one
+    memory: Option<(PathBuf, bool)>,
+    cpu: Option<(PathBuf, bool)>,
+    /// Highest memory use in bytes seen, for kernels without memory.peak
+    peak_memory: AtomicU64,
+}
+
+/// Counters of a group, covering every process which ever was in it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CgroupUsage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time: Option<CpuTime>,
+    /// Highest memory use in kB, including the page cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory: Option<u64>,
+}
+
+impl fmt::Display for CgroupUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cgroup")?;
+        if let Some(cpu_time) = self.cpu_time {
+            write!(
+                f,
+                " CPU time {:.2}s (user {:.2}s, system {:.2}s)",
+                cpu_time.total(),
+                cpu_time.user,
+                cpu_time.system
+            )?;
+        }
+        if let Some(memory) = self.peak_memory {
+            write!(f, " peak memory {}kB", memory)?;
+        }
+        Ok(())
+    }
+}
+
+impl Cgroup {
+    /// Create a fresh group with the limits, requires root. With `accounting`
+    /// the group also collects the CPU time and memory use of its processes.
+    pub fn create(limits: &Limits, accounting: bool) -> Result<Cgroup> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "procrec-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        if !cfg!(target_os = "linux") {
+            bail!("Cgroups are only supported on linux");
+        }
+        let mounts = Mounts::read()?;
+        let mut cgroup = Cgroup {
+            dirs: vec![],
+            memory: None,
+            cpu: None,
+            peak_memory: AtomicU64::new(0),
+        };
+        if limits.memory.is_some() || accounting {
+            let (dir, v2) = cgroup.join_hierarchy(&mounts, &name, "memory")?;
+            if let Some(kb) = limits.memory {
+                let file = if v2 {
+                    "memory.max"
+                } else {
+                    "memory.limit_in_bytes"
+                };
+                write(&dir.join(file), &kb_to_bytes(kb))?;
+            }
+            if accounting {
+                cgroup.memory = Some((dir, v2));
+            }
+        }
+        if let Some(cpus) = limits.cpus {
+            let (dir, v2) = cgroup.join_hierarchy(&mounts, &name, "cpu")?;
+            if v2 {
+                write(
+                    &dir.join("cpu.max"),
+                    &format!("{} {}", cpu_quota(cpus), CPU_PERIOD),
+                )?;
+            } else {
+                write(&dir.join("cpu.cfs_period_us"), &CPU_PERIOD.to_string())?;
+                write(&dir.join("cpu.cfs_quota_us"), &cpu_quota(cpus).to_string())?;
+            }
+        }
+        if accounting {
+            cgroup.cpu = Some(cgroup.join_hierarchy(&mounts, &name, "cpuacct")?);
+        }
+        Ok(cgroup)
+    }
+
+    /// Create the group in the hierarchy of the controller unless it exists
+    /// already, returns it and whether the hierarchy is the unified one.
+    fn join_hierarchy(
+        &mut self,
+        mounts: &Mounts,
+        name: &str,
+        controller: &str,
+    ) -> Result<(PathBuf, bool)> {
+        let (dir, v2) = match (mounts.v1(controller), mounts.v2.as_deref()) {
+            (Some(root), _) => (root.join(name), false),
+            // the CPU time is accounted for in every group of cgroup v2
+            (None, Some(root)) if controller == "cpuacct" => (root.join(name), true),
+            (None, _) => {
+                let root = mounts.v2_with(controller).ok_or_else(|| {
+                    anyhow!("The {} controller of cgroups is not available", controller)
+                })?;
+                // the root group is exempt from the rule that groups with
+                // processes can not pass on controllers
+                write(
+                    &root.join("cgroup.subtree_control"),
+                    &format!("+{}", controller),
+                )?;
+                (root.join(name), true)
+            }
+        };
+        if !self.dirs.contains(&dir) {
+            fs::create_dir(&dir)
+                .map_err(|e| anyhow!("Can not create cgroup '{}': {}", dir.display(), e))?;
+            self.dirs.push(dir.clone());
+        }
+        Ok((dir, v2))
+    }
+
+    /// Read the counters of a group created with accounting, call it
+    /// regularly to catch memory peaks on kernels before 5.19.
+    pub fn usage(&self) -> Option<CgroupUsage> {
+        if self.memory.is_none() && self.cpu.is_none() {
+            return None;
+        }
+        let mut usage = CgroupUsage::default();
+        if let Some((dir, v2)) = &self.memory {
+            let (current, peak) = if *v2 {
+                ("memory.current", "memory.peak")
+            } else {
+                ("memory.usage_in_bytes", "memory.max_usage_in_bytes")
+            };
+            let bytes = [current, peak]
+                .iter()
+                .filter_map(|file| read_number(&dir.join(file)))
+                .max();
+            if let Some(bytes) = bytes {
+                self.peak_memory.fetch_max(bytes, Ordering::SeqCst);
+            }
+            usage.peak_memory = Some(self.peak_memory.load(Ordering::SeqCst) / 1000);
+        }
+        if let Some((dir, v2)) = &self.cpu {
+            usage.cpu_time = if *v2 {
+                let stat = fs::read_to_string(dir.join("cpu.stat")).ok();
+                let field = |name: &str| {
+                    stat.as_deref()?
+                        .lines()
+                        .filter_map(|line| line.split_once(' '))
+                        .find(|(key, _)| *key == name)
+                        .and_then(|(_, value)| value.parse::<u64>().ok())
+                };
+                match (field("user_usec"), field("system_usec")) {
+                    (Some(user), Some(system)) => Some(CpuTime {
+                        user: user as f64 / 1e6,
+                        system: system as f64 / 1e6,
+                    }),
+                    _ => None,
+                }
+            } else {
+                match (
+                    read_number(&dir.join("cpuacct.usage_user")),
+                    read_number(&dir.join("cpuacct.usage_sys")),
+                ) {
+                    (Some(user), Some(system)) => Some(CpuTime {
+                        user: user as f64 / 1e9,
+                        system: system as f64 / 1e9,
+                    }),
+                    _ => None,
+                }
+            };
+        }
+        Some(usage)
+    }
+
+    /// Make the command join the group before it is executed.
+    #[cfg(unix)]
+    pub fn join_on_exec(&self, cmd: &mut Command) -> Result<()> {
+        use std::os::unix::process::CommandExt;
+        let files = self
+            .dirs
+            .iter()
+            .map(|dir| CString::new(dir.join("cgroup.procs").to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>, _>>()?;
+        // only async-signal-safe calls between fork and exec
+        unsafe {
+            cmd.pre_exec(move || {
+                for file in &files {
+                    let fd = libc::open(file.as_ptr(), libc::O_WRONLY);
+                    if fd < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    // 0 stands for the writing process
+                    let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                    libc::close(fd);
+                    if written != 1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn join_on_exec(&self, _cmd: &mut Command) -> Result<()> {
+        bail!("Cgroups are only supported on linux")
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        for dir in &self.dirs {
+            // fails while processes are left in the group
+            if let Err(e) = fs::remove_dir(dir) {
+                eprintln!("Warning: can not remove cgroup '{}': {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+/// The mounted cgroup hierarchies.
+struct Mounts {
+    v1: Vec<(Vec<String>, PathBuf)>,
+    v2: Option<PathBuf>,
+}
+
+impl Mounts {
+    fn read() -> Result<Mounts> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+            .map_err(|e| anyhow!("Can not read the mounted cgroups: {}", e))?;
+        let mut mounts = Mounts {
+            v1: vec![],
+            v2: None,
+        };
+        for line in mountinfo.lines() {
+            // the optional fields end with a single dash
+            let (left, right) = match line.split_once(" - ") {
+                Some(parts) => parts,
+                None => continue,
+            };
+            let path = match left.split(' ').nth(4) {
+                Some(path) => PathBuf::from(path),
+                None => continue,
+            };
+            let mut right = right.split(' ');
+            match (right.next(), right.nth(1)) {
+                (Some("cgroup2"), _) => mounts.v2 = Some(path),
+                (Some("cgroup"), Some(options)) => {
+                    let options = options.split(',').map(str::to_string).collect();
+                    mounts.v1.push((options, path));
+                }
+                _ => {}
+            }
+        }
+        if mounts.v1.is_empty() && mounts.v2.is_none() {
+            bail!("No cgroup file system is mounted");
+        }
+        Ok(mounts)
+    }
+
+    /// Root of the v1 hierarchy of the controller.
+    fn v1(&self, controller: &str) -> Option<&Path> {
+        self.v1
+            .iter()
+            .find(|(options, _)| options.iter().any(|o| o == controller))
+            .map(|(_, path)| path.as_path())
+    }
+
+    /// Root of the unified hierarchy if it offers the controller.
+    fn v2_with(&self, controller: &str) -> Option<&Path> {
+        let root = self.v2.as_deref()?;
+        let controllers = fs::read_to_string(root.join("cgroup.controllers")).ok()?;
+        controllers
+            .split_whitespace()
+            .any(|c| c == controller)
+            .then_some(root)
+    }
+}
+
+fn write(path: &Path, value: &str) -> Result<()> {
+    fs::write(path, value).map_err(|e| anyhow!("Can not write '{}': {}", path.display(), e))
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn kb_to_bytes(kb: u64) -> String {
+    (kb * 1000).to_string()
+}
+
+fn cpu_quota(cpus: f64) -> u64 {
+    (cpus * CPU_PERIOD as f64).round().max(1000.0) as u64
+}
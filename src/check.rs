@@ -0,0 +1,209 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Monitoring plugin checks of a recording with the exit codes and the
+//! performance data format of Nagios and compatible systems.
+
+use crate::condition::{Comparison, Condition};
+use crate::{Metric, Recording, Sample};
+use std::fmt;
+
+/// Result of a check, the discriminant is the exit code of the plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl Status {
+    pub fn exit_code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        })
+    }
+}
+
+/// Outcome of checking the latest samples against thresholds, displayed as
+/// the plugin output line.
+#[derive(Debug)]
+pub struct Report {
+    pub status: Status,
+    message: String,
+    perfdata: Vec<String>,
+}
+
+impl Report {
+    /// Check the last sample of every process. The status is critical if a
+    /// condition of `critical` does not hold, a warning if one of `warning`
+    /// does not hold and unknown without samples.
+    pub fn new(recording: &Recording, critical: &[Condition], warning: &[Condition]) -> Report {
+        let processes = &recording.metadata.processes;
+        let mut latest: Vec<&Sample> = vec![];
+        for sample in recording.samples.iter().rev() {
+            if !latest.iter().any(|s| s.pid == sample.pid) {
+                latest.push(sample);
+            }
+        }
+        latest.reverse();
+        if latest.is_empty() {
+            return Report::unknown("No samples recorded");
+        }
+
+        let mut status = Status::Ok;
+        let mut violations = vec![];
+        let mut perfdata = vec![];
+        for sample in &latest {
+            let label = processes
+                .iter()
+                .find(|p| p.pid == sample.pid)
+                .map(|p| p.label.clone())
+                .unwrap_or_else(|| sample.pid.to_string());
+            for (conditions, level) in [(critical, Status::Critical), (warning, Status::Warning)] {
+                for condition in conditions {
+                    let violated = match condition.holds(sample) {
+                        Some(holds) => !holds,
+                        None => {
+                            violations.push(format!(
+                                "{}: {} not recorded",
+                                label,
+                                condition.metric.name()
+                            ));
+                            status = status.max(Status::Unknown);
+                            continue;
+                        }
+                    };
+                    if violated {
+                        violations.push(format!(
+                            "{}: {} {} violates {}",
+                            label,
+                            condition.metric.name(),
+                            format_value(condition.metric, sample),
+                            condition
+                        ));
+                        // unknown outranks the others as exit code only
+                        if status != Status::Unknown {
+                            status = status.max(level);
+                        }
+                    }
+                }
+            }
+
+            let mut metrics = vec![Metric::Cpu, Metric::Rss, Metric::Vsize];
+            for condition in critical.iter().chain(warning) {
+                if !metrics.contains(&condition.metric) {
+                    metrics.push(condition.metric);
+                }
+            }
+            for metric in metrics {
+                let value = match metric.value(sample) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let name = if latest.len() > 1 {
+                    format!("{}_{}", metric.name(), sample.pid)
+                } else {
+                    metric.name().to_string()
+                };
+                let (value, unit, factor) = if metric.is_memory() {
+                    (value * 1000.0, "B", 1000.0)
+                } else {
+                    (value, "%", 1.0)
+                };
+                perfdata.push(format!(
+                    "{}={}{};{};{}",
+                    name,
+                    value,
+                    unit,
+                    threshold(metric, warning, factor),
+                    threshold(metric, critical, factor)
+                ));
+            }
+        }
+
+        let message = if violations.is_empty() {
+            latest
+                .iter()
+                .map(|s| {
+                    let label = processes
+                        .iter()
+                        .find(|p| p.pid == s.pid)
+                        .map(|p| p.label.as_str())
+                        .unwrap_or("");
+                    format!("{} cpu {:.1}%, rss {}kB", label, s.cpu, s.rss)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        } else {
+            violations.join(", ")
+        };
+        Report {
+            status,
+            message,
+            perfdata,
+        }
+    }
+
+    /// Report a failure to check at all, e.g. a process that does not exist.
+    pub fn unknown(message: &str) -> Report {
+        Report {
+            status: Status::Unknown,
+            message: message.to_string(),
+            perfdata: vec![],
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PROCREC {} - {}", self.status, self.message)?;
+        if !self.perfdata.is_empty() {
+            write!(f, " | {}", self.perfdata.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+fn format_value(metric: Metric, sample: &Sample) -> String {
+    match metric.value(sample) {
+        Some(value) if metric.is_memory() => format!("{}kB", value),
+        Some(value) => format!("{:.1}%", value),
+        None => "-".to_string(),
+    }
+}
+
+/// Threshold range in the perfdata format, alerting outside of it.
+fn threshold(metric: Metric, conditions: &[Condition], factor: f64) -> String {
+    match conditions.iter().find(|c| c.metric == metric) {
+        Some(c) => match c.comparison {
+            Comparison::Less | Comparison::LessEqual => format!("{}", c.threshold * factor),
+            Comparison::Greater | Comparison::GreaterEqual => {
+                format!("{}:", c.threshold * factor)
+            }
+        },
+        None => String::new(),
+    }
+}
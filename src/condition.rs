@@ -0,0 +1,191 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conditions on the sampled metrics like `rss>1.5G` or `cpu<1 for 60s`,
+//! used for checks, alerts and triggers.
+
+use crate::{Metric, Sample};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    pub(crate) fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+        }
+    }
+
+    pub(crate) fn compare(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Less => value < threshold,
+            Comparison::LessEqual => value <= threshold,
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterEqual => value >= threshold,
+        }
+    }
+}
+
+/// A comparison of a metric with a threshold, which optionally has to hold
+/// for a duration.
+///
+/// Memory thresholds are in kB like the samples, the suffixes `K`, `M`, `G`
+/// and `T` (powers of 1000) may be used. Durations take the suffixes `s`,
+/// `m` and `h`, seconds if omitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub duration: Option<Duration>,
+}
+
+impl Condition {
+    /// Whether the sample fulfills the comparison, `None` if the metric was
+    /// not recorded.
+    pub fn holds(&self, sample: &Sample) -> Option<bool> {
+        self.metric
+            .value(sample)
+            .map(|value| self.comparison.compare(value, self.threshold))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (comparison, duration) = match s.split_once(" for ") {
+            Some((comparison, duration)) => (comparison, Some(parse_duration(duration.trim())?)),
+            None => (s, None),
+        };
+        let (metric, op, value) = split_comparison(comparison)
+            .ok_or_else(|| anyhow!("Invalid condition '{}', expected e.g. 'rss>1.5G'", s))?;
+        let metric: Metric = metric.parse()?;
+        Ok(Condition {
+            metric,
+            comparison: op,
+            threshold: parse_value(metric, value)
+                .ok_or_else(|| anyhow!("Invalid threshold in condition '{}'", s))?,
+            duration,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.metric.name(),
+            self.comparison.symbol(),
+            self.threshold
+        )?;
+        if self.metric.is_memory() {
+            write!(f, "kB")?;
+        }
+        if let Some(duration) = self.duration {
+            write!(f, " for {}s", duration.as_secs_f32())?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialized in the format of its `Display` implementation.
+impl Serialize for Condition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Split a comparison like `rss>1.5G` into its trimmed left side, the
+/// comparison and the value.
+pub(crate) fn split_comparison(s: &str) -> Option<(&str, Comparison, &str)> {
+    let pos = s.find(['<', '>'])?;
+    let rest = &s[pos..];
+    let (op, value) = if let Some(value) = rest.strip_prefix("<=") {
+        (Comparison::LessEqual, value)
+    } else if let Some(value) = rest.strip_prefix(">=") {
+        (Comparison::GreaterEqual, value)
+    } else if let Some(value) = rest.strip_prefix('<') {
+        (Comparison::Less, value)
+    } else {
+        (Comparison::Greater, &rest[1..])
+    };
+    Some((s[..pos].trim(), op, value.trim()))
+}
+
+/// Parse a threshold, memory in kB with optional unit suffix.
+pub(crate) fn parse_value(metric: Metric, s: &str) -> Option<f64> {
+    if !metric.is_memory() {
+        return s.trim_end_matches('%').parse().ok();
+    }
+    let s = s.trim_end_matches(['B', 'b']);
+    let (number, factor) = match s.chars().last()? {
+        'k' | 'K' => (&s[..s.len() - 1], 1.0),
+        'm' | 'M' => (&s[..s.len() - 1], 1e3),
+        'g' | 'G' => (&s[..s.len() - 1], 1e6),
+        't' | 'T' => (&s[..s.len() - 1], 1e9),
+        _ => (s, 1.0),
+    };
+    number.parse::<f64>().ok().map(|n| n * factor)
+}
+
+/// Parse a memory size like `512M` or `2G` in kB, the suffixes are powers
+/// of 1000 and kB is assumed if none is given.
+pub fn parse_memory(s: &str) -> Result<u64> {
+    match parse_value(Metric::Rss, s.trim()) {
+        Some(kb) if kb > 0.0 => Ok(kb as u64),
+        _ => bail!("Invalid memory size '{}', expected e.g. '512M' or '2G'", s),
+    }
+}
+
+/// Parse a duration like `30s`, `5m` or `2h`, seconds if no unit is given.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let (number, factor) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1.0),
+        Some('m') => (&s[..s.len() - 1], 60.0),
+        Some('h') => (&s[..s.len() - 1], 3600.0),
+        _ => (s, 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(n) if n >= 0.0 => Ok(Duration::from_secs_f64(n * factor)),
+        _ => bail!(
+            "Invalid duration '{}', expected e.g. '30s', '5m' or '2h'",
+            s
+        ),
+    }
+}
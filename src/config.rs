@@ -0,0 +1,306 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Defaults of the command line options from a TOML file.
+//!
+//! The keys are the long names of the options, with `-` or `_`, and apply
+//! unless the option is given on the command line. Tables named after a
+//! subcommand only apply to it:
+//!
+//! ```toml
+//! interval = 0.5
+//! output = ["csv:run.csv"]
+//! alert = ["rss>1.5G", "cpu>90 for 30s"]
+//! summary = true
+//! plot-theme = "dark"
+//!
+//! [bench]
+//! runs = 20
+//!
+//! [profile.leakhunt]
+//! interval = 5
+//! smaps = true
+//! detect-leaks = true
+//! summary = true
+//! ```
+//!
+//! The tables below `profile` are bundles of options of the main command,
+//! picked with `--profile`, which take precedence over the other defaults.
+//!
+//! Flags set here are switched off on the command line with `--no-<flag>`,
+//! options conflicting with the command line are skipped.
+
+use anyhow::{anyhow, bail, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::Table;
+pub use toml::Value;
+
+/// Options read from a configuration file.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub path: PathBuf,
+    table: Table,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/procrec/config.toml`, `~/.config` if unset.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => Path::new(&env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("procrec").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Can not read config '{}': {}", path.display(), e))?;
+        let table = content
+            .parse::<Value>()
+            .map_err(|e| anyhow!("Invalid config '{}': {}", path.display(), e))?;
+        match table {
+            Value::Table(table) => Ok(Config {
+                path: path.to_path_buf(),
+                table,
+            }),
+            _ => bail!("Invalid config '{}'", path.display()),
+        }
+    }
+
+    /// Load the given file, or the default one if it exists.
+    pub fn find(path: Option<&Path>) -> Result<Option<Config>> {
+        match path {
+            Some(path) => Config::load(path).map(Some),
+            None => match Config::default_path().filter(|p| p.exists()) {
+                Some(path) => Config::load(&path).map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Options of the main command by their long name, `-` separated.
+    pub fn options(&self) -> Vec<(String, Value)> {
+        options(&self.table)
+    }
+
+    /// Options of the subcommand, from the table named after it.
+    pub fn section(&self, name: &str) -> Result<Vec<(String, Value)>> {
+        match self.table.get(name) {
+            Some(Value::Table(table)) => Ok(options(table)),
+            Some(_) => bail!(
+                "'{}' in config '{}' is not a table",
+                name,
+                self.path.display()
+            ),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Options of the main command merged with the ones of the profile.
+    pub fn profile(&self, name: &str) -> Result<Vec<(String, Value)>> {
+        let profile = match self.table.get("profile").and_then(|p| p.get(name)) {
+            Some(Value::Table(table)) => options(table),
+            Some(_) => bail!(
+                "Profile '{}' in config '{}' is not a table",
+                name,
+                self.path.display()
+            ),
+            None => bail!(
+                "Unknown profile '{}' in config '{}', available: {}",
+                name,
+                self.path.display(),
+                self.profiles().join(", ")
+            ),
+        };
+        let mut merged = self.options();
+        merged.retain(|(key, _)| profile.iter().all(|(k, _)| k != key));
+        merged.extend(profile);
+        Ok(merged)
+    }
+
+    /// Names of the profiles.
+    pub fn profiles(&self) -> Vec<&str> {
+        match self.table.get("profile") {
+            Some(Value::Table(profiles)) => profiles.keys().map(|k| k.as_str()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Names of the tables, the subcommands configured.
+    pub fn sections(&self) -> Vec<&str> {
+        self.table
+            .iter()
+            .filter(|(name, value)| value.is_table() && name.as_str() != "profile")
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+fn options(table: &Table) -> Vec<(String, Value)> {
+    table
+        .iter()
+        .filter(|(_, value)| !value.is_table())
+        .map(|(key, value)| (key.replace('_', "-"), value.clone()))
+        .collect()
+}
+
+/// Options set by the environment variables with the prefix, e.g.
+/// `PROCREC_INTERVAL=0.5` or `PROCREC_SUMMARY=1`, named after the long name
+/// of the option in upper case with `_` instead of `-`.
+pub fn env_options(prefix: &str) -> Vec<(String, Value)> {
+    env::vars_os()
+        .filter_map(|(name, value)| {
+            let long = name.to_str()?.strip_prefix(prefix)?;
+            let long = long.to_lowercase().replace('_', "-");
+            Some((long, env_value(value.to_str()?)))
+        })
+        .collect()
+}
+
+fn env_value(value: &str) -> Value {
+    match value {
+        "true" | "yes" | "on" => Value::Boolean(true),
+        "false" | "no" | "off" => Value::Boolean(false),
+        _ => value
+            .parse()
+            .map(Value::Integer)
+            .unwrap_or_else(|_| Value::String(value.to_string())),
+    }
+}
+
+/// How an option is given on the command line.
+#[derive(Debug, Clone, Copy)]
+pub struct OptionKind {
+    /// Whether it takes a value, a flag otherwise
+    pub takes_value: bool,
+    /// Whether it may be given several times rather than once with a comma
+    /// separated list
+    pub multiple: bool,
+}
+
+/// Command line arguments setting the option to the value.
+///
+/// Flags are set by `true` or `1` or, for repeatable ones like `verbose`,
+/// the number of times. Lists become one argument per element for repeatable
+/// options and a comma separated value otherwise.
+pub fn to_args(long: &str, value: &Value, kind: OptionKind) -> Result<Vec<String>> {
+    let invalid = || anyhow!("Invalid value {} for --{}", value, long);
+    if !kind.takes_value {
+        let times = match value {
+            Value::Boolean(set) => *set as i64,
+            Value::Integer(times) if kind.multiple || *times <= 1 => *times,
+            _ => return Err(invalid()),
+        };
+        return Ok((0..times).map(|_| format!("--{}", long)).collect());
+    }
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Datetime(_) => {
+            Ok(value.to_string())
+        }
+        _ => Err(invalid()),
+    };
+    match value {
+        Value::Array(values) if kind.multiple => values
+            .iter()
+            .map(|v| Ok(format!("--{}={}", long, scalar(v)?)))
+            .collect(),
+        Value::Array(values) => {
+            let values: Result<Vec<String>> = values.iter().map(scalar).collect();
+            Ok(vec![format!("--{}={}", long, values?.join(","))])
+        }
+        value => Ok(vec![format!("--{}={}", long, scalar(value)?)]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAG: OptionKind = OptionKind {
+        takes_value: false,
+        multiple: false,
+    };
+    const REPEATED_FLAG: OptionKind = OptionKind {
+        takes_value: false,
+        multiple: true,
+    };
+    const VALUE: OptionKind = OptionKind {
+        takes_value: true,
+        multiple: false,
+    };
+    const REPEATED_VALUE: OptionKind = OptionKind {
+        takes_value: true,
+        multiple: true,
+    };
+
+    fn args(long: &str, value: Value, kind: OptionKind) -> Vec<String> {
+        to_args(long, &value, kind).unwrap()
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(args("summary", Value::Boolean(true), FLAG), ["--summary"]);
+        assert!(args("summary", Value::Boolean(false), FLAG).is_empty());
+        assert_eq!(args("summary", Value::Integer(1), FLAG), ["--summary"]);
+        assert_eq!(
+            args("verbose", Value::Integer(2), REPEATED_FLAG),
+            ["--verbose", "--verbose"]
+        );
+        assert!(to_args("summary", &Value::Integer(2), FLAG).is_err());
+        assert!(to_args("summary", &Value::String("yes".into()), FLAG).is_err());
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(
+            args("interval", Value::String("1s".into()), VALUE),
+            ["--interval=1s"]
+        );
+        assert_eq!(args("peaks", Value::Integer(5), VALUE), ["--peaks=5"]);
+        assert_eq!(
+            args("fail-threshold", Value::Float(0.5), VALUE),
+            ["--fail-threshold=0.5"]
+        );
+        assert_eq!(args("live", Value::Boolean(true), VALUE), ["--live=true"]);
+    }
+
+    #[test]
+    fn lists() {
+        let list = || {
+            Value::Array(vec![
+                Value::String("rss>1G".into()),
+                Value::String("cpu>90".into()),
+            ])
+        };
+        assert_eq!(
+            args("alert", list(), REPEATED_VALUE),
+            ["--alert=rss>1G", "--alert=cpu>90"]
+        );
+        assert_eq!(args("metrics", list(), VALUE), ["--metrics=rss>1G,cpu>90"]);
+        assert!(args("alert", Value::Array(vec![]), REPEATED_VALUE).is_empty());
+    }
+
+    #[test]
+    fn nested_values_are_invalid() {
+        let table = || Value::Table(Table::new());
+        assert!(to_args("interval", &table(), VALUE).is_err());
+        assert!(to_args("alert", &Value::Array(vec![table()]), REPEATED_VALUE).is_err());
+        assert!(to_args("metrics", &Value::Array(vec![table()]), VALUE).is_err());
+    }
+}
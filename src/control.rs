@@ -0,0 +1,289 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Controlling a recording in flight, from the same process or through a
+//! local socket.
+
+#[cfg(unix)]
+pub use sighup::marker_on_sighup;
+#[cfg(unix)]
+pub use socket::{default_socket_path, send_command, ControlSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+#[cfg(not(unix))]
+pub use unsupported::{default_socket_path, marker_on_sighup, send_command, ControlSocket};
+
+/// Handle to stop, pause and resume a [`Recorder`](crate::Recorder) and to
+/// add markers, which are recorded as events.
+#[derive(Clone)]
+pub struct Control {
+    pub(crate) running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    markers: Arc<Mutex<Vec<(Instant, String)>>>,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Control {
+            running: Arc::new(AtomicBool::new(true)),
+            paused: Arc::new(AtomicBool::new(false)),
+            markers: Arc::default(),
+        }
+    }
+}
+
+impl Control {
+    /// End the recording after the current interval.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Skip the samples until resumed, the processes are still watched.
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            self.marker("paused");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            self.marker("resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Add an event with the label at the current time.
+    pub fn marker(&self, label: &str) {
+        self.markers
+            .lock()
+            .unwrap()
+            .push((Instant::now(), label.to_string()));
+    }
+
+    /// Markers added since the last call.
+    pub(crate) fn take_markers(&self) -> Vec<(Instant, String)> {
+        std::mem::take(&mut *self.markers.lock().unwrap())
+    }
+
+    /// Execute a command of the control socket, returns the reply.
+    #[cfg(unix)]
+    fn execute(&self, line: &str) -> String {
+        let line = line.trim();
+        let (command, arg) = match line.split_once(' ') {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        match command {
+            "pause" => self.pause(),
+            "resume" => self.resume(),
+            "stop" => self.stop(),
+            "marker" if !arg.is_empty() => self.marker(arg),
+            "status" if self.is_paused() => return "paused".to_string(),
+            "status" => return "recording".to_string(),
+            _ => {
+                return format!(
+                    "error: unknown command '{}' (pause, resume, marker <label>, stop, status)",
+                    line
+                )
+            }
+        }
+        "ok".to_string()
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use super::Control;
+    use anyhow::{anyhow, bail, Result};
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    /// Accepts commands on a unix socket, one per line: `pause`, `resume`,
+    /// `marker <label>`, `stop` and `status`. Each command is answered with a
+    /// line, `ok` or an error. The socket file is removed on drop.
+    pub struct ControlSocket {
+        path: PathBuf,
+    }
+
+    impl ControlSocket {
+        pub fn bind(path: &Path, control: Control) -> Result<Self> {
+            // a socket left over by a crashed recording blocks binding, any
+            // other file at the path is not ours to remove
+            if UnixStream::connect(path).is_err() {
+                match fs::symlink_metadata(path) {
+                    Ok(meta) if meta.file_type().is_socket() => {
+                        let _ = fs::remove_file(path);
+                    }
+                    Ok(_) => bail!(
+                        "Can not create control socket '{}': the file exists and is not a socket",
+                        path.display()
+                    ),
+                    Err(_) => {}
+                }
+            }
+            let listener = UnixListener::bind(path).map_err(|e| {
+                anyhow!("Can not create control socket '{}': {}", path.display(), e)
+            })?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let control = control.clone();
+                    thread::spawn(move || {
+                        let _ = serve(stream, &control);
+                    });
+                }
+            });
+            Ok(ControlSocket {
+                path: path.to_path_buf(),
+            })
+        }
+    }
+
+    impl Drop for ControlSocket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// `$XDG_RUNTIME_DIR/procrec.sock`, or in the temporary directory if
+    /// unset.
+    pub fn default_socket_path() -> PathBuf {
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => Path::new(&dir).join("procrec.sock"),
+            None => env::temp_dir().join(format!("procrec-{}.sock", unsafe { libc::getuid() })),
+        }
+    }
+
+    /// Send a command to the control socket of a recording, returns the
+    /// reply. An error reply is returned as error.
+    pub fn send_command(path: &Path, command: &str) -> Result<String> {
+        let mut stream = UnixStream::connect(path).map_err(|e| {
+            anyhow!(
+                "Can not connect to control socket '{}', is a recording running with --control-socket? {}",
+                path.display(),
+                e
+            )
+        })?;
+        writeln!(stream, "{}", command)?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        let reply = reply.trim().to_string();
+        match reply.strip_prefix("error: ") {
+            Some(err) => Err(anyhow!("{}", err)),
+            None => Ok(reply),
+        }
+    }
+
+    fn serve(stream: UnixStream, control: &Control) -> Result<()> {
+        let mut out = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            writeln!(out, "{}", control.execute(&line))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod sighup {
+    use super::Control;
+    use anyhow::{bail, Result};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn handle(_signal: libc::c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    /// Add a marker whenever SIGHUP is received, labeled with the content of
+    /// the file, e.g. written by a script right before signaling.
+    pub fn marker_on_sighup(path: PathBuf, control: Control) -> Result<()> {
+        let handler = handle as extern "C" fn(libc::c_int);
+        if unsafe { libc::signal(libc::SIGHUP, handler as libc::sighandler_t) } == libc::SIG_ERR {
+            bail!("Can not install the SIGHUP handler");
+        }
+        // the handler only sets a flag, reading the file is not signal safe
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(50));
+            if !RECEIVED.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(label) if !label.trim().is_empty() => {
+                    control.marker(&label.split_whitespace().collect::<Vec<_>>().join(" "))
+                }
+                Ok(_) => eprintln!("Warning: marker file '{}' is empty", path.display()),
+                Err(err) => eprintln!(
+                    "Warning: can not read marker file '{}': {}",
+                    path.display(),
+                    err
+                ),
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod unsupported {
+    use super::Control;
+    use anyhow::{bail, Result};
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    /// The control socket is a unix socket, it can not be bound here.
+    pub struct ControlSocket {
+        _control: Control,
+    }
+
+    impl ControlSocket {
+        pub fn bind(_path: &Path, _control: Control) -> Result<Self> {
+            bail!("Control sockets are only supported on unix")
+        }
+    }
+
+    pub fn default_socket_path() -> PathBuf {
+        env::temp_dir().join("procrec.sock")
+    }
+
+    pub fn send_command(_path: &Path, _command: &str) -> Result<String> {
+        bail!("Control sockets are only supported on unix")
+    }
+
+    pub fn marker_on_sighup(_path: PathBuf, _control: Control) -> Result<()> {
+        bail!("Markers on SIGHUP are only supported on unix")
+    }
+}
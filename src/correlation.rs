@@ -0,0 +1,151 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Correlations between the series of a recording, hinting at causal
+//! relationships like RSS growth caused by major page faults.
+
+use crate::counters::Counters;
+use crate::summary;
+use crate::{Metric, Recording};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Minimum number of sampling rounds to correlate
+const MIN_SAMPLES: usize = 5;
+/// Minimum absolute correlation coefficient reported
+const MIN_CORRELATION: f64 = 0.7;
+
+/// Two series of a recording moving together, or in opposite directions for
+/// a negative coefficient.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Correlation {
+    pub a: String,
+    pub b: String,
+    /// Pearson correlation coefficient between -1 and 1
+    pub r: f64,
+}
+
+impl fmt::Display for Correlation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.r > 0.0 { "rises" } else { "falls" };
+        write!(
+            f,
+            "{} {} with {} (r={:.2})",
+            self.b, direction, self.a, self.r
+        )
+    }
+}
+
+/// Pearson correlation coefficient, `None` if a series is constant.
+fn pearson(a: &[f64], b: &[f64]) -> Option<f64> {
+    let n = a.len() as f64;
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let mut cov = 0.0;
+    let (mut var_a, mut var_b) = (0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a == 0.0 || var_b == 0.0 {
+        return None;
+    }
+    Some(cov / (var_a * var_b).sqrt())
+}
+
+/// Series per sampling round, summed up over the processes: the metrics,
+/// the growth of the RSS and the rates of the counters.
+///
+/// The first round is dropped, it has no growth or rate.
+fn series(recording: &Recording) -> Vec<(String, Vec<f64>)> {
+    let rounds = summary::rounds(&recording.samples);
+    if rounds.len() < 2 {
+        return vec![];
+    }
+    let mut series = vec![];
+    for metric in Metric::ALL.iter() {
+        let values: Vec<Option<f64>> = rounds
+            .iter()
+            .map(|(_, samples)| samples.iter().map(|s| metric.value(s)).sum())
+            .collect();
+        let values: Option<Vec<f64>> = values.into_iter().collect();
+        if let Some(values) = values {
+            if *metric == Metric::Rss {
+                let growth = values.windows(2).map(|w| w[1] - w[0]).collect();
+                series.push(("rss_growth".to_string(), growth));
+            }
+            series.push((metric.name().to_string(), values[1..].to_vec()));
+        }
+    }
+    // counter rates from the increase of every process since its previous
+    // sample
+    if rounds
+        .iter()
+        .all(|(_, s)| s.iter().all(|s| s.counters.is_some()))
+    {
+        let mut previous: HashMap<u32, Counters> = HashMap::new();
+        let mut rates = vec![vec![]; Counters::NAMES.len()];
+        let mut last_ts = rounds[0].0;
+        for (i, (ts, samples)) in rounds.iter().enumerate() {
+            let mut increase = [0; 4];
+            for sample in samples {
+                let counters = sample.counters.unwrap_or_default();
+                if let Some(prev) = previous.insert(sample.pid, counters) {
+                    for (j, value) in counters.values().iter().enumerate() {
+                        increase[j] += value.saturating_sub(prev.values()[j]);
+                    }
+                }
+            }
+            if i > 0 {
+                let dt = f64::from(ts - last_ts).max(f64::EPSILON);
+                for (j, rate) in rates.iter_mut().enumerate() {
+                    rate.push(increase[j] as f64 / dt);
+                }
+            }
+            last_ts = *ts;
+        }
+        series.extend(
+            Counters::NAMES
+                .iter()
+                .map(|name| name.to_string())
+                .zip(rates),
+        );
+    }
+    series
+}
+
+/// Pairs of series correlating strongly, the strongest first.
+pub fn analyze(recording: &Recording) -> Vec<Correlation> {
+    let series = series(recording);
+    let mut found = vec![];
+    for (i, (a, values_a)) in series.iter().enumerate() {
+        if values_a.len() < MIN_SAMPLES {
+            continue;
+        }
+        for (b, values_b) in &series[i + 1..] {
+            match pearson(values_a, values_b) {
+                Some(r) if r.abs() >= MIN_CORRELATION => found.push(Correlation {
+                    a: a.clone(),
+                    b: b.clone(),
+                    r,
+                }),
+                _ => {}
+            }
+        }
+    }
+    found.sort_by(|x, y| y.r.abs().total_cmp(&x.r.abs()));
+    found
+}
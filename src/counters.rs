@@ -0,0 +1,85 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cumulative I/O and page fault counters from `/proc/<pid>/io` and
+//! `/proc/<pid>/stat`.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+
+/// Counters of a process since its start, they only grow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counters {
+    /// Bytes read, including pipes and sockets
+    pub read_bytes: u64,
+    /// Bytes written, including pipes and sockets
+    pub write_bytes: u64,
+    /// Page faults served without I/O
+    pub minor_faults: u64,
+    /// Page faults which required loading a page from disk
+    pub major_faults: u64,
+}
+
+impl Counters {
+    /// Names as used in the summary
+    pub const NAMES: [&'static str; 4] =
+        ["read_bytes", "write_bytes", "minor_faults", "major_faults"];
+
+    /// The counters in the order of [`Counters::NAMES`].
+    pub fn values(&self) -> [u64; 4] {
+        [
+            self.read_bytes,
+            self.write_bytes,
+            self.minor_faults,
+            self.major_faults,
+        ]
+    }
+}
+
+/// Read the counters of the given process, only available on linux.
+///
+/// The I/O counters require the permission to trace the process.
+pub fn read(pid: u32) -> io::Result<Counters> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "unexpected format");
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // the name in parentheses may contain spaces, the fields after it start
+    // with the state as third field
+    let fields: Vec<&str> = stat
+        .get(stat.rfind(')').ok_or_else(invalid)? + 1..)
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    let field = |n: usize| -> io::Result<u64> {
+        fields
+            .get(n - 3)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let mut counters = Counters {
+        minor_faults: field(10)?,
+        major_faults: field(12)?,
+        ..Default::default()
+    };
+    for line in fs::read_to_string(format!("/proc/{}/io", pid))?.lines() {
+        match line.split_once(':') {
+            Some(("rchar", value)) => counters.read_bytes = value.trim().parse().unwrap_or(0),
+            Some(("wchar", value)) => counters.write_bytes = value.trim().parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+    Ok(counters)
+}
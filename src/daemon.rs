@@ -0,0 +1,166 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Running procrec in the background for recordings over days (unix only).
+//!
+//! The daemon keeps the working directory, so relative paths of the
+//! options stay valid, and is reached through its control socket.
+
+use anyhow::{anyhow, bail, Result};
+use std::fs;
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+#[cfg(unix)]
+use std::io::{self, Read};
+use std::io::{PipeWriter, Write};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Continue in a child process in a new session, the calling process waits
+/// until the child reported its startup with [`Startup::report`] and exits
+/// with its outcome.
+///
+/// Has to be called before any thread is started, only the calling thread
+/// survives the fork. Stdin reads from `/dev/null`, stdout and stderr are
+/// appended to the log file or discarded.
+#[cfg(unix)]
+pub fn daemonize(log_file: Option<&Path>) -> Result<Startup> {
+    let null = File::open("/dev/null")?;
+    let log = match log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Can not open log file '{}': {}", path.display(), e))?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    // close on exec, the launched command must not keep the parent waiting
+    let (mut reader, writer) = io::pipe()?;
+    match unsafe { libc::fork() } {
+        -1 => bail!("Can not fork: {}", io::Error::last_os_error()),
+        0 => drop(reader),
+        pid => {
+            drop(writer);
+            let mut outcome = String::new();
+            let _ = reader.read_to_string(&mut outcome);
+            let code = match outcome.as_str() {
+                STARTED => {
+                    eprintln!("procrec runs in the background as PID {}", pid);
+                    0
+                }
+                "" => {
+                    eprintln!(
+                        "Error: procrec ended while starting in the background, see its log file"
+                    );
+                    1
+                }
+                error => {
+                    eprintln!("Error: {}", error);
+                    1
+                }
+            };
+            // the child owns the buffered output now
+            unsafe { libc::_exit(code) };
+        }
+    }
+    unsafe {
+        // leave the terminal, its hangup and interrupts
+        libc::setsid();
+        libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+    }
+    Ok(Startup { pipe: writer })
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_log_file: Option<&Path>) -> Result<Startup> {
+    bail!("Running in the background is only supported on unix")
+}
+
+/// Sent to the waiting parent once the recording started.
+const STARTED: &str = "started";
+
+/// Connection to the parent waiting for the daemon to start.
+pub struct Startup {
+    pipe: PipeWriter,
+}
+
+impl Startup {
+    /// Let the parent exit successfully if the recording started, otherwise
+    /// with the error. The parent fails as well if the daemon ends without
+    /// reporting.
+    pub fn report<T>(mut self, result: &Result<T>) {
+        let outcome = match result {
+            Ok(_) => STARTED.to_string(),
+            Err(err) => err.to_string(),
+        };
+        let _ = self.pipe.write_all(outcome.as_bytes());
+    }
+}
+
+/// File holding the PID of the running procrec, removed on drop.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Fail if the file names a procrec still running, call it before
+    /// `daemonize` to report that in the foreground.
+    pub fn check(path: &Path) -> Result<()> {
+        let pid = match fs::read_to_string(path) {
+            Ok(pid) => pid,
+            Err(_) => return Ok(()),
+        };
+        if let Ok(pid) = pid.trim().parse::<u32>() {
+            if pid > 0 && is_alive(pid) {
+                bail!(
+                    "procrec is already running as PID {} according to '{}'",
+                    pid,
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the PID of this process to the file.
+    pub fn create(path: &Path) -> Result<Self> {
+        PidFile::check(path)?;
+        fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|e| anyhow!("Can not write pid file '{}': {}", path.display(), e))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(windows)]
+fn is_alive(pid: u32) -> bool {
+    crate::windows::Process::new(pid).is_ok_and(|p| p.is_running())
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,167 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Process information on macos from libproc and sysctl.
+//!
+//! psutil reads the CPU times from the task info as nanoseconds, while they
+//! are ticks of the mach absolute time, which are longer on Apple silicon.
+//! Most other process information, e.g. the command line and the parent, is
+//! not implemented there at all.
+
+use crate::CpuTime;
+use libc::{c_int, c_void};
+use std::convert::TryInto;
+use std::mem;
+use std::ptr;
+use std::sync::OnceLock;
+
+#[repr(C)]
+struct TimebaseInfo {
+    numer: u32,
+    denom: u32,
+}
+
+extern "C" {
+    fn mach_timebase_info(info: *mut TimebaseInfo) -> c_int;
+}
+
+/// Nanoseconds per tick of the mach absolute time, 1 on intel and 125/3 on
+/// Apple silicon.
+pub(crate) fn ns_per_tick() -> f64 {
+    static RATIO: OnceLock<f64> = OnceLock::new();
+    *RATIO.get_or_init(|| {
+        let mut info = TimebaseInfo { numer: 0, denom: 0 };
+        if unsafe { mach_timebase_info(&mut info) } != 0 || info.denom == 0 {
+            return 1.0;
+        }
+        f64::from(info.numer) / f64::from(info.denom)
+    })
+}
+
+fn rusage(pid: u32) -> Option<libc::rusage_info_v2> {
+    let mut usage: libc::rusage_info_v2 = unsafe { mem::zeroed() };
+    let res = unsafe {
+        libc::proc_pid_rusage(
+            pid as c_int,
+            libc::RUSAGE_INFO_V2,
+            &mut usage as *mut libc::rusage_info_v2 as *mut libc::rusage_info_t,
+        )
+    };
+    (res == 0).then_some(usage)
+}
+
+/// CPU time consumed so far, optionally including the children the process
+/// waited for.
+pub(crate) fn cpu_time(pid: u32, with_children: bool) -> Option<CpuTime> {
+    let usage = rusage(pid)?;
+    let seconds = |ticks: u64| ticks as f64 * ns_per_tick() / 1e9;
+    let mut cpu_time = CpuTime {
+        user: seconds(usage.ri_user_time),
+        system: seconds(usage.ri_system_time),
+    };
+    if with_children {
+        cpu_time.user += seconds(usage.ri_child_user_time);
+        cpu_time.system += seconds(usage.ri_child_system_time);
+    }
+    Some(cpu_time)
+}
+
+/// Physical footprint in bytes, the memory Activity Monitor shows for the
+/// process.
+pub(crate) fn footprint(pid: u32) -> Option<u64> {
+    rusage(pid).map(|usage| usage.ri_phys_footprint)
+}
+
+pub(crate) fn ppid(pid: u32) -> Option<u32> {
+    let mut info: libc::proc_bsdinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_bsdinfo>() as c_int;
+    let res = unsafe {
+        libc::proc_pidinfo(
+            pid as c_int,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut libc::proc_bsdinfo as *mut c_void,
+            size,
+        )
+    };
+    (res == size).then_some(info.pbi_ppid)
+}
+
+/// PID and parent PID of every process.
+pub(crate) fn parents() -> Vec<(u32, u32)> {
+    let count = unsafe { libc::proc_listallpids(ptr::null_mut(), 0) };
+    if count <= 0 {
+        return vec![];
+    }
+    // room for processes started in the meantime
+    let mut pids: Vec<libc::pid_t> = vec![0; count as usize + 64];
+    let count = unsafe {
+        libc::proc_listallpids(
+            pids.as_mut_ptr() as *mut c_void,
+            (pids.len() * mem::size_of::<libc::pid_t>()) as c_int,
+        )
+    };
+    pids.truncate(count.max(0) as usize);
+    pids.into_iter()
+        .filter_map(|pid| Some((pid as u32, ppid(pid as u32)?)))
+        .collect()
+}
+
+/// Arguments of the process including the program, only readable for
+/// processes of the same user unless procrec runs as root.
+pub(crate) fn cmdline(pid: u32) -> Option<Vec<String>> {
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROCARGS2, pid as c_int];
+    let mut size: libc::size_t = 0;
+    let res = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            3,
+            ptr::null_mut(),
+            &mut size,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if res != 0 {
+        return None;
+    }
+    let mut buf = vec![0u8; size];
+    let res = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            3,
+            buf.as_mut_ptr() as *mut c_void,
+            &mut size,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if res != 0 {
+        return None;
+    }
+    buf.truncate(size);
+    // the number of arguments, the path of the executable padded with NULs
+    // and the NUL terminated arguments
+    let argc = i32::from_ne_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+    let mut strings = buf[4..].split(|b| *b == 0).filter(|s| !s.is_empty());
+    let _path = strings.next()?;
+    Some(
+        strings
+            .take(argc)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
@@ -0,0 +1,178 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Comparison of two recordings, e.g. before and after a change.
+
+use crate::summary::{self, Summary};
+use crate::{Metric, Recording};
+use anyhow::Result;
+use std::fmt;
+
+/// Difference of one statistic between the recordings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub metric: String,
+    /// `mean`, `max`, a percentile or `curve`
+    pub stat: &'static str,
+    pub before: f64,
+    pub after: f64,
+    /// Relative change, 0.1 for 10% more than before. For the curve the mean
+    /// absolute difference at the same relative time instead.
+    pub change: f64,
+}
+
+/// Differences between two recordings, displayed as table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diff {
+    /// Recorded time in seconds before and after
+    pub duration: (f32, f32),
+    pub rows: Vec<Row>,
+}
+
+/// Compare the summary statistics of the recordings and their curves over
+/// the time both cover, aligned by their relative time.
+pub fn diff(before: &Recording, after: &Recording) -> Result<Diff> {
+    let (a, b) = (Summary::new(before)?, Summary::new(after)?);
+    let (rounds_a, rounds_b) = (
+        summary::rounds(&before.samples),
+        summary::rounds(&after.samples),
+    );
+    let mut rows = vec![];
+    for metric in Metric::ALL.iter() {
+        let name = metric.name().to_string();
+        let (a, b) = match (a.metrics.get(&name), b.metrics.get(&name)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue,
+        };
+        for (stat, before, after) in [
+            ("mean", a.mean, b.mean),
+            ("max", a.max, b.max),
+            ("p50", a.p50, b.p50),
+            ("p99", a.p99, b.p99),
+        ] {
+            rows.push(Row {
+                metric: name.clone(),
+                stat,
+                before,
+                after,
+                change: relative(after - before, before),
+            });
+        }
+        let curve_a = align(summary::totals(&rounds_a, *metric));
+        let curve_b = align(summary::totals(&rounds_b, *metric));
+        if let Some(row) = compare_curves(&name, &curve_a, &curve_b) {
+            rows.push(row);
+        }
+    }
+    Ok(Diff {
+        duration: (a.duration, b.duration),
+        rows,
+    })
+}
+
+fn relative(delta: f64, base: f64) -> f64 {
+    if base > 0.0 {
+        delta / base
+    } else {
+        0.0
+    }
+}
+
+/// Move the curve to start at zero.
+fn align(mut curve: Vec<(f32, f64)>) -> Vec<(f32, f64)> {
+    if let Some(&(start, _)) = curve.first() {
+        curve.iter_mut().for_each(|(ts, _)| *ts -= start);
+    }
+    curve
+}
+
+/// Interpolate the curve linearly, `None` outside of it.
+fn value_at(curve: &[(f32, f64)], ts: f32) -> Option<f64> {
+    let i = curve.iter().position(|&(t, _)| t >= ts)?;
+    let (t1, v1) = curve[i];
+    if t1 == ts {
+        return Some(v1);
+    }
+    let (t0, v0) = curve[i.checked_sub(1)?];
+    Some(v0 + (v1 - v0) * f64::from((ts - t0) / (t1 - t0)))
+}
+
+/// Compare the curves at the times of the first one covered by both.
+fn compare_curves(metric: &str, a: &[(f32, f64)], b: &[(f32, f64)]) -> Option<Row> {
+    let pairs: Vec<(f64, f64)> = a
+        .iter()
+        .filter_map(|&(ts, v)| Some((v, value_at(b, ts)?)))
+        .collect();
+    if pairs.is_empty() {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let before = pairs.iter().map(|(a, _)| a).sum::<f64>() / n;
+    let after = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+    let distance = pairs.iter().map(|(a, b)| (b - a).abs()).sum::<f64>() / n;
+    Some(Row {
+        metric: metric.to_string(),
+        stat: "curve",
+        before,
+        after,
+        change: relative(distance, before),
+    })
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>14} {:>14} {:>9}",
+            "metric", "before", "after", "change"
+        )?;
+        writeln!(
+            f,
+            "{:<12} {:>14.2} {:>14.2} {:>+8.1}%",
+            "duration",
+            self.duration.0,
+            self.duration.1,
+            relative(
+                f64::from(self.duration.1 - self.duration.0),
+                f64::from(self.duration.0)
+            ) * 100.0
+        )?;
+        for row in &self.rows {
+            let label = format!("{} {}", row.metric, row.stat);
+            if row.stat == "curve" {
+                // the curves differ in both directions
+                writeln!(
+                    f,
+                    "{:<12} {:>14.2} {:>14.2} {:>8}%",
+                    label,
+                    row.before,
+                    row.after,
+                    format!("±{:.1}", row.change * 100.0)
+                )?;
+            } else {
+                writeln!(
+                    f,
+                    "{:<12} {:>14.2} {:>14.2} {:>+8.1}%",
+                    label,
+                    row.before,
+                    row.after,
+                    row.change * 100.0
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,590 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! CPU time, I/O and page faults of processes accounted in the kernel with
+//! eBPF (linux only, requires root).
+//!
+//! A small program on the `sched/sched_switch` tracepoint adds the length
+//! of every time slice to the process it belongs to and notes which thread
+//! runs on every CPU since when, so the slice still running is counted as
+//! well. Unlike polling `/proc`, which counts in clock ticks, this is exact
+//! to the nanosecond even for bursts shorter than a tick.
+//!
+//! Optionally further programs on the exits of the read and write system
+//! calls and on the page fault software events count the bytes transferred
+//! and the faults of the same processes, read as their growth per sample.
+
+use crate::counters::Counters;
+use crate::perf::{self, PerfEventAttr};
+use anyhow::{anyhow, bail, Result};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Instant;
+
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_MAP_LOOKUP_ELEM: libc::c_long = 1;
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_MAP_DELETE_ELEM: libc::c_long = 3;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+const BPF_PROG_TYPE_PERF_EVENT: u32 = 7;
+const BPF_NOEXIST: u64 = 1;
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_TYPE_TRACEPOINT: u32 = 2;
+const PERF_COUNT_SW_PAGE_FAULTS_MIN: u64 = 5;
+const PERF_COUNT_SW_PAGE_FAULTS_MAJ: u64 = 6;
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_SET_BPF: libc::c_ulong = 0x4004_2408;
+
+/// Processes accounted at most, including followed children.
+const MAX_PROCESSES: u32 = 4096;
+
+/// Offsets of `prev_pid` and `next_pid` in the `sched_switch` record.
+const PREV_PID: i16 = 24;
+const NEXT_PID: i16 = 56;
+
+/// Offset of `ret` in the records of the `sys_exit_*` tracepoints.
+const SYSCALL_RET: i16 = 16;
+
+/// System calls transferring bytes, read and written.
+const READ_SYSCALLS: [&str; 6] = ["read", "readv", "pread64", "preadv", "recvfrom", "recvmsg"];
+const WRITE_SYSCALLS: [&str; 6] = [
+    "write", "writev", "pwrite64", "pwritev", "sendto", "sendmsg",
+];
+
+const TRACEFS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+const fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+    Insn {
+        code,
+        regs: dst | (src << 4),
+        off,
+        imm,
+    }
+}
+
+/// Load the address of the map with the file descriptor, two instructions.
+const fn load_map(dst: u8, fd: i32) -> [Insn; 2] {
+    [insn(0x18, dst, 1, 0, fd), insn(0, 0, 0, 0, 0)]
+}
+
+const MOV: u8 = 0xbf;
+const MOV_IMM: u8 = 0xb7;
+const ADD_IMM: u8 = 0x07;
+const SUB: u8 = 0x1f;
+const RSH_IMM: u8 = 0x77;
+const LDX_W: u8 = 0x61;
+const LDX_DW: u8 = 0x79;
+const STX_W: u8 = 0x63;
+const STX_DW: u8 = 0x7b;
+const ATOMIC_DW: u8 = 0xdb;
+const JEQ_IMM: u8 = 0x15;
+const JNE: u8 = 0x5d;
+const JSLE_IMM: u8 = 0xd5;
+const CALL: u8 = 0x85;
+const EXIT: u8 = 0x95;
+const LOOKUP: i32 = 1;
+const KTIME_GET_NS: i32 = 5;
+const SMP_PROCESSOR_ID: i32 = 8;
+const CURRENT_PID_TGID: i32 = 14;
+
+/// The program on the tracepoint, `tgids` maps the accounted processes to
+/// their [`Usage`], `oncpu` holds the start of the running slice and the
+/// thread by CPU.
+fn program(tgids: i32, oncpu: i32) -> Vec<Insn> {
+    let mut prog = vec![
+        insn(MOV, 6, 1, 0, 0),
+        insn(CALL, 0, 0, 0, KTIME_GET_NS),
+        insn(MOV, 7, 0, 0, 0),
+        insn(CALL, 0, 0, 0, SMP_PROCESSOR_ID),
+        insn(STX_W, 10, 0, -4, 0),
+    ];
+    prog.extend(load_map(1, oncpu));
+    prog.extend([
+        insn(MOV, 2, 10, 0, 0),
+        insn(ADD_IMM, 2, 0, 0, -4),
+        insn(CALL, 0, 0, 0, LOOKUP),
+        // to the exit
+        insn(JEQ_IMM, 0, 0, 21, 0),
+        insn(MOV, 9, 0, 0, 0),
+        // the slice on this CPU belongs to the previous thread unless the
+        // program got attached in between, on to noting the next one
+        insn(LDX_W, 1, 6, PREV_PID, 0),
+        insn(LDX_W, 2, 9, 8, 0),
+        insn(JNE, 1, 2, 14, 0),
+        insn(LDX_DW, 8, 9, 0, 0),
+        insn(JEQ_IMM, 8, 0, 12, 0),
+        // the previous thread is still the current task
+        insn(CALL, 0, 0, 0, CURRENT_PID_TGID),
+        insn(RSH_IMM, 0, 0, 0, 32),
+        insn(STX_W, 10, 0, -8, 0),
+    ]);
+    prog.extend(load_map(1, tgids));
+    prog.extend([
+        insn(MOV, 2, 10, 0, 0),
+        insn(ADD_IMM, 2, 0, 0, -8),
+        insn(CALL, 0, 0, 0, LOOKUP),
+        insn(JEQ_IMM, 0, 0, 3, 0),
+        insn(MOV, 1, 7, 0, 0),
+        insn(SUB, 1, 8, 0, 0),
+        // add, atomic as the process may run on several CPUs
+        insn(ATOMIC_DW, 0, 1, 0, 0),
+        // note the next thread
+        insn(STX_DW, 9, 7, 0, 0),
+        insn(LDX_W, 1, 6, NEXT_PID, 0),
+        insn(STX_DW, 9, 1, 8, 0),
+        insn(MOV_IMM, 0, 0, 0, 0),
+        insn(EXIT, 0, 0, 0, 0),
+    ]);
+    prog
+}
+
+/// A program adding to the field at `offset` of the [`Usage`] of the
+/// current process, the positive return value of a system call with `ret`
+/// and else one for every event.
+fn counting_program(tgids: i32, offset: i16, ret: bool) -> Vec<Insn> {
+    let add = if ret {
+        vec![
+            insn(LDX_DW, 1, 6, SYSCALL_RET, 0),
+            // errors and end of file
+            insn(JSLE_IMM, 1, 0, 1, 0),
+            insn(ATOMIC_DW, 0, 1, offset, 0),
+        ]
+    } else {
+        vec![insn(MOV_IMM, 1, 0, 0, 1), insn(ATOMIC_DW, 0, 1, offset, 0)]
+    };
+    let mut prog = vec![
+        insn(MOV, 6, 1, 0, 0),
+        insn(CALL, 0, 0, 0, CURRENT_PID_TGID),
+        insn(RSH_IMM, 0, 0, 0, 32),
+        insn(STX_W, 10, 0, -4, 0),
+    ];
+    prog.extend(load_map(1, tgids));
+    prog.extend([
+        insn(MOV, 2, 10, 0, 0),
+        insn(ADD_IMM, 2, 0, 0, -4),
+        insn(CALL, 0, 0, 0, LOOKUP),
+        // to the exit
+        insn(JEQ_IMM, 0, 0, add.len() as i16, 0),
+    ]);
+    prog.extend(add);
+    prog.extend([insn(MOV_IMM, 0, 0, 0, 0), insn(EXIT, 0, 0, 0, 0)]);
+    prog
+}
+
+#[repr(C)]
+struct MapCreate {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct MapElem {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+struct ProgLoad {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+}
+
+fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<libc::c_long> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T,
+            mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(res)
+}
+
+fn create_map(map_type: u32, value_size: u32, max_entries: u32) -> Result<OwnedFd> {
+    let mut attr = MapCreate {
+        map_type,
+        key_size: 4,
+        value_size,
+        max_entries,
+        map_flags: 0,
+    };
+    let fd = bpf(BPF_MAP_CREATE, &mut attr).map_err(|e| {
+        anyhow!(
+            "Can not create eBPF map: {}, the ebpf backend requires root",
+            e
+        )
+    })?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+fn lookup<V: Default>(map: &OwnedFd, key: u32) -> Option<V> {
+    let mut value = V::default();
+    let mut attr = MapElem {
+        map_fd: map.as_raw_fd() as u32,
+        _pad: 0,
+        key: &key as *const u32 as u64,
+        value: &mut value as *mut V as u64,
+        flags: 0,
+    };
+    bpf(BPF_MAP_LOOKUP_ELEM, &mut attr).ok()?;
+    Some(value)
+}
+
+fn load_program(prog_type: u32, insns: &[Insn]) -> Result<OwnedFd> {
+    let license = b"GPL\0";
+    let mut log = vec![0u8; 64 * 1024];
+    let mut attr = ProgLoad {
+        prog_type,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 1,
+        log_size: log.len() as u32,
+        log_buf: log.as_mut_ptr() as u64,
+        kern_version: 0,
+    };
+    match bpf(BPF_PROG_LOAD, &mut attr) {
+        Ok(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) }),
+        Err(e) => {
+            let len = log.iter().position(|b| *b == 0).unwrap_or(log.len());
+            bail!(
+                "Can not load eBPF program: {}\n{}",
+                e,
+                String::from_utf8_lossy(&log[..len]).trim_end()
+            )
+        }
+    }
+}
+
+/// Id of the tracepoint like `sched/sched_switch`.
+fn tracepoint_id(name: &str) -> Result<u64> {
+    TRACEFS
+        .iter()
+        .find_map(|dir| fs::read_to_string(format!("{}/events/{}/id", dir, name)).ok())
+        .and_then(|id| id.trim().parse().ok())
+        .ok_or_else(|| {
+            anyhow!("The {} tracepoint is not available, is tracefs mounted? (mount -t tracefs nodev /sys/kernel/tracing)", name)
+        })
+}
+
+/// Attach the program to the event on every CPU.
+fn attach(prog: &OwnedFd, mut attr: PerfEventAttr, cpus: u32) -> Result<Vec<OwnedFd>> {
+    attr.sample_period = 1;
+    attr.wakeup_events = 1;
+    let mut events = vec![];
+    let mut error = None;
+    for cpu in 0..cpus {
+        let event = match perf::open_event(&mut attr, -1, cpu as i32) {
+            Ok(event) => event,
+            // offline CPUs are skipped
+            Err(e) => {
+                error = Some(e);
+                continue;
+            }
+        };
+        unsafe {
+            if libc::ioctl(event.as_raw_fd(), PERF_EVENT_IOC_SET_BPF, prog.as_raw_fd()) != 0
+                || libc::ioctl(event.as_raw_fd(), PERF_EVENT_IOC_ENABLE, 0) != 0
+            {
+                bail!(
+                    "Can not attach eBPF program: {}",
+                    io::Error::last_os_error()
+                );
+            }
+        }
+        events.push(event);
+    }
+    if let (true, Some(e)) = (events.is_empty(), error) {
+        bail!("Can not open the event: {}", e);
+    }
+    Ok(events)
+}
+
+/// Attach the program to the tracepoint on every CPU.
+fn attach_tracepoint(prog: &OwnedFd, name: &str, cpus: u32) -> Result<Vec<OwnedFd>> {
+    let attr = PerfEventAttr::new(PERF_TYPE_TRACEPOINT, tracepoint_id(name)?);
+    attach(prog, attr, cpus).map_err(|e| anyhow!("{} ({})", e, name))
+}
+
+/// Load the programs counting I/O and page faults and attach them, the
+/// system calls missing on the architecture are skipped.
+fn attach_counters(tgids: i32, cpus: u32) -> Result<(Vec<OwnedFd>, Vec<OwnedFd>)> {
+    let mut progs = vec![];
+    let mut events = vec![];
+    for (syscalls, offset) in [
+        (READ_SYSCALLS, Usage::READ_BYTES),
+        (WRITE_SYSCALLS, Usage::WRITE_BYTES),
+    ] {
+        let prog = load_program(
+            BPF_PROG_TYPE_TRACEPOINT,
+            &counting_program(tgids, offset, true),
+        )?;
+        for syscall in syscalls {
+            let name = format!("syscalls/sys_exit_{}", syscall);
+            if syscall == "read" || syscall == "write" || tracepoint_id(&name).is_ok() {
+                events.extend(attach_tracepoint(&prog, &name, cpus)?);
+            }
+        }
+        progs.push(prog);
+    }
+    for (config, offset) in [
+        (PERF_COUNT_SW_PAGE_FAULTS_MIN, Usage::MINOR_FAULTS),
+        (PERF_COUNT_SW_PAGE_FAULTS_MAJ, Usage::MAJOR_FAULTS),
+    ] {
+        let prog = load_program(
+            BPF_PROG_TYPE_PERF_EVENT,
+            &counting_program(tgids, offset, false),
+        )?;
+        events.extend(attach(
+            &prog,
+            PerfEventAttr::new(PERF_TYPE_SOFTWARE, config),
+            cpus,
+        )?);
+        progs.push(prog);
+    }
+    Ok((progs, events))
+}
+
+/// What is accounted of a process, the value of the `tgids` map.
+#[repr(C)]
+#[derive(Default)]
+struct Usage {
+    cpu_ns: u64,
+    read_bytes: u64,
+    write_bytes: u64,
+    minor_faults: u64,
+    major_faults: u64,
+}
+
+impl Usage {
+    const READ_BYTES: i16 = 8;
+    const WRITE_BYTES: i16 = 16;
+    const MINOR_FAULTS: i16 = 24;
+    const MAJOR_FAULTS: i16 = 32;
+}
+
+/// Thread running on a CPU and since when, in ns of the monotonic clock.
+#[repr(C)]
+#[derive(Default)]
+struct OnCpu {
+    start: u64,
+    tid: u64,
+}
+
+/// CPU utilization of processes from the scheduler, accounting starts with
+/// the first call of `cpu_percent` for a process.
+pub struct SchedSampler {
+    tgids: OwnedFd,
+    oncpu: OwnedFd,
+    cpus: u32,
+    /// Whether I/O and page faults are counted
+    counting: bool,
+    /// CPU time in ns and time of the previous sample by process
+    last: Vec<(u32, u64, Instant)>,
+    /// Processes which could not be accounted as the map is full
+    unaccounted: Vec<u32>,
+    // closing the events detaches the programs
+    _events: Vec<OwnedFd>,
+    _progs: Vec<OwnedFd>,
+}
+
+impl SchedSampler {
+    /// Attach the programs, with `counters` also the ones counting I/O and
+    /// page faults, which are read from `/proc` if that fails.
+    pub fn start(counters: bool) -> Result<Self> {
+        // SAFETY: sysconf has no preconditions
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as u32;
+        let tgids = create_map(
+            BPF_MAP_TYPE_HASH,
+            mem::size_of::<Usage>() as u32,
+            MAX_PROCESSES,
+        )?;
+        let oncpu = create_map(BPF_MAP_TYPE_ARRAY, mem::size_of::<OnCpu>() as u32, cpus)?;
+        let prog = load_program(
+            BPF_PROG_TYPE_TRACEPOINT,
+            &program(tgids.as_raw_fd(), oncpu.as_raw_fd()),
+        )?;
+        let mut events = attach_tracepoint(&prog, "sched/sched_switch", cpus)?;
+        let mut progs = vec![prog];
+        let mut counting = false;
+        if counters {
+            match attach_counters(tgids.as_raw_fd(), cpus) {
+                Ok((more_progs, more_events)) => {
+                    progs.extend(more_progs);
+                    events.extend(more_events);
+                    counting = true;
+                }
+                Err(e) => eprintln!(
+                    "Warning: Can not count I/O and page faults with eBPF, reading them from /proc: {}",
+                    e
+                ),
+            }
+        }
+        Ok(SchedSampler {
+            tgids,
+            oncpu,
+            cpus,
+            counting,
+            last: vec![],
+            unaccounted: vec![],
+            _events: events,
+            _progs: progs,
+        })
+    }
+
+    /// CPU utilization of the process since the previous call, 0% for the
+    /// first one, `None` if the process can not be accounted.
+    pub fn cpu_percent(&mut self, pid: u32) -> Option<f32> {
+        let now = Instant::now();
+        let total = match self.cpu_time(pid) {
+            Some(total) => total,
+            None if self.unaccounted.contains(&pid) => return None,
+            None => {
+                if let Err(e) = self.account(pid) {
+                    eprintln!("Warning: {}, falling back to psutil for its CPU usage", e);
+                    self.unaccounted.push(pid);
+                    return None;
+                }
+                self.last.retain(|(p, _, _)| *p != pid);
+                self.last.push((pid, 0, now));
+                return Some(0.0);
+            }
+        };
+        let last = self.last.iter_mut().find(|(p, _, _)| *p == pid)?;
+        let elapsed = now.duration_since(last.2).as_secs_f64();
+        let used = total.saturating_sub(last.1) as f64 / 1e9;
+        *last = (pid, total, now);
+        Some(if elapsed > 0.0 {
+            (used / elapsed * 100.0) as f32
+        } else {
+            0.0
+        })
+    }
+
+    /// I/O and page faults of the process since its accounting started,
+    /// `None` if they are not counted.
+    pub fn counters(&self, pid: u32) -> Option<Counters> {
+        if !self.counting {
+            return None;
+        }
+        let usage: Usage = lookup(&self.tgids, pid)?;
+        Some(Counters {
+            read_bytes: usage.read_bytes,
+            write_bytes: usage.write_bytes,
+            minor_faults: usage.minor_faults,
+            major_faults: usage.major_faults,
+        })
+    }
+
+    /// Stop accounting the processes for which `keep` is false, which frees
+    /// their entries of the map for new ones.
+    pub fn retain(&mut self, keep: impl Fn(u32) -> bool) {
+        let tgids = &self.tgids;
+        self.last.retain(|(pid, _, _)| {
+            if keep(*pid) {
+                return true;
+            }
+            let mut attr = MapElem {
+                map_fd: tgids.as_raw_fd() as u32,
+                _pad: 0,
+                key: pid as *const u32 as u64,
+                value: 0,
+                flags: 0,
+            };
+            let _ = bpf(BPF_MAP_DELETE_ELEM, &mut attr);
+            false
+        });
+        self.unaccounted.retain(|pid| keep(*pid));
+    }
+
+    fn account(&self, pid: u32) -> Result<()> {
+        let zero = Usage::default();
+        let mut attr = MapElem {
+            map_fd: self.tgids.as_raw_fd() as u32,
+            _pad: 0,
+            key: &pid as *const u32 as u64,
+            value: &zero as *const Usage as u64,
+            flags: BPF_NOEXIST,
+        };
+        bpf(BPF_MAP_UPDATE_ELEM, &mut attr).map_err(|e| match e.raw_os_error() {
+            Some(libc::E2BIG) => anyhow!(
+                "Can not account process {}, already accounting {} processes",
+                pid,
+                MAX_PROCESSES
+            ),
+            _ => anyhow!("Can not account process {}: {}", pid, e),
+        })?;
+        Ok(())
+    }
+
+    /// CPU time in ns of the finished slices and the ones still running.
+    fn cpu_time(&self, pid: u32) -> Option<u64> {
+        let finished = lookup::<Usage>(&self.tgids, pid)?.cpu_ns;
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        let now = now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64;
+        let running: u64 = (0..self.cpus)
+            .filter_map(|cpu| lookup::<OnCpu>(&self.oncpu, cpu))
+            .filter(|slot| slot.start != 0 && belongs_to(slot.tid as u32, pid))
+            .map(|slot| now.saturating_sub(slot.start))
+            .sum();
+        Some(finished + running)
+    }
+}
+
+/// Whether the thread is part of the process.
+fn belongs_to(tid: u32, pid: u32) -> bool {
+    // signal 0 only checks that the thread is in the thread group
+    tid != 0
+        && (tid == pid
+            || unsafe {
+                libc::syscall(libc::SYS_tgkill, pid as libc::c_int, tid as libc::c_int, 0)
+            } == 0)
+}
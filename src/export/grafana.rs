@@ -0,0 +1,153 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pushes the samples to Grafana Live so dashboards update in real time.
+//!
+//! Grafana accepts the InfluxDB line protocol over a WebSocket at
+//! `/api/live/push/<stream>`, the samples show up in the channel
+//! `stream/<stream>/procrec`. Only the client side of WebSocket needed to
+//! send text frames is implemented, without TLS.
+
+use super::influx;
+use crate::output::Sink;
+use crate::Sample;
+use anyhow::{anyhow, bail, Result};
+use base64::Engine;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+
+/// Streams every sample to a Grafana Live push endpoint.
+pub struct GrafanaLive {
+    stream: TcpStream,
+    // state of the xorshift generating the frame masks
+    seed: u64,
+}
+
+impl GrafanaLive {
+    /// Connect to a push endpoint like
+    /// `ws://localhost:3000/api/live/push/procrec`, authenticated by a
+    /// service account `token`.
+    pub fn connect(url: &str, token: Option<&str>) -> Result<Self> {
+        let rest = match url.strip_prefix("ws://") {
+            Some(rest) => rest,
+            None if url.starts_with("wss://") => {
+                bail!("TLS is not supported for Grafana Live, use ws:// or a local proxy")
+            }
+            None => bail!(
+                "Invalid Grafana Live URL '{}', expected ws://HOST[:PORT]/PATH",
+                url
+            ),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        let stream = TcpStream::connect(&addr)
+            .map_err(|e| anyhow!("Can not connect to Grafana '{}': {}", addr, e))?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+        let mut seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+            | 1;
+        let mut key = [0; 16];
+        for chunk in key.chunks_mut(8) {
+            chunk.copy_from_slice(&next_random(&mut seed).to_le_bytes());
+        }
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+            path,
+            host,
+            base64::engine::general_purpose::STANDARD.encode(key)
+        );
+        if let Some(token) = token {
+            request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        (&stream).write_all(request.as_bytes())?;
+
+        let mut reader = BufReader::new(&stream);
+        let mut status = String::new();
+        reader.read_line(&mut status)?;
+        if status.split_whitespace().nth(1) != Some("101") {
+            bail!(
+                "Grafana refused the WebSocket at '{}': {}",
+                url,
+                status.trim()
+            );
+        }
+        // skip the remaining headers of the handshake
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        Ok(GrafanaLive { stream, seed })
+    }
+
+    /// Send a frame, client frames have to be masked.
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask = (next_random(&mut self.seed) as u32).to_be_bytes();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+}
+
+impl Sink for GrafanaLive {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let line = influx::line(sample, now);
+        self.send(TEXT, line.as_bytes())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.send(CLOSE, &[])
+    }
+}
+
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
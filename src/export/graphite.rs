@@ -0,0 +1,78 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Streams the samples in the Graphite plaintext protocol.
+
+use super::values;
+use crate::output::Sink;
+use crate::Sample;
+use anyhow::{anyhow, Result};
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sends `<prefix>.<pid>.<metric> <value> <timestamp>` lines to Carbon over
+/// TCP, reconnecting after the connection broke.
+pub struct GraphiteExporter {
+    addr: String,
+    prefix: String,
+    stream: Option<TcpStream>,
+}
+
+impl GraphiteExporter {
+    /// Connect to Carbon at `addr`, e.g. `localhost:2003`.
+    pub fn connect(addr: &str, prefix: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|e| anyhow!("Can not connect to Graphite at '{}': {}", addr, e))?;
+        Ok(GraphiteExporter {
+            addr: addr.to_string(),
+            prefix: prefix.to_string(),
+            stream: Some(stream),
+        })
+    }
+}
+
+impl Sink for GraphiteExporter {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut lines = String::new();
+        for (metric, value) in values(sample) {
+            lines += &format!(
+                "{}.{}.{} {} {}\n",
+                self.prefix,
+                sample.pid,
+                metric.name(),
+                value,
+                now
+            );
+        }
+
+        if self.stream.is_none() {
+            self.stream = TcpStream::connect(&self.addr).ok();
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            // keep recording while Carbon is away, the samples are lost then
+            if let Err(err) = stream.write_all(lines.as_bytes()) {
+                eprintln!("Warning: lost connection to Graphite: {}", err);
+                self.stream = None;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! gRPC server streaming the samples to connected clients, the schema is
+//! `proto/procrec.proto`.
+
+use crate::output::Sink;
+use crate::Sample;
+use anyhow::{anyhow, Result};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::thread::{self, JoinHandle};
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+mod service {
+    include!(concat!(env!("OUT_DIR"), "/procrec.Recorder.rs"));
+}
+
+use service::recorder_server::{Recorder, RecorderServer};
+
+/// Samples buffered for slow clients, older ones are skipped
+const BACKLOG: usize = 1024;
+
+/// `SubscribeRequest` of the schema
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubscribeRequest {}
+
+/// `Sample` of the schema
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GrpcSample {
+    #[prost(float, tag = "1")]
+    pub ts: f32,
+    #[prost(uint32, tag = "2")]
+    pub pid: u32,
+    #[prost(float, tag = "3")]
+    pub cpu: f32,
+    #[prost(uint64, tag = "4")]
+    pub rss: u64,
+    #[prost(uint64, tag = "5")]
+    pub vsize: u64,
+}
+
+impl From<&Sample> for GrpcSample {
+    fn from(sample: &Sample) -> Self {
+        GrpcSample {
+            ts: sample.ts,
+            pid: sample.pid,
+            cpu: sample.cpu,
+            rss: sample.rss,
+            vsize: sample.vsize,
+        }
+    }
+}
+
+struct Service {
+    // the streams end once the exporter dropped the sender
+    samples: broadcast::WeakSender<GrpcSample>,
+}
+
+type SampleStream = Pin<Box<dyn Stream<Item = Result<GrpcSample, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Recorder for Service {
+    type SubscribeStream = SampleStream;
+
+    async fn subscribe(
+        &self,
+        _request: Request<SubscribeRequest>,
+    ) -> Result<Response<SampleStream>, Status> {
+        let samples = self
+            .samples
+            .upgrade()
+            .ok_or_else(|| Status::unavailable("The recording finished"))?;
+        // a lagging client misses samples instead of slowing down the others
+        let stream =
+            BroadcastStream::new(samples.subscribe()).filter_map(|sample| sample.ok().map(Ok));
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Streams every new sample to all clients subscribed at that time.
+pub struct GrpcServer {
+    samples: Option<broadcast::Sender<GrpcSample>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GrpcServer {
+    /// Serve on `addr`, e.g. `:50051`, from a background thread.
+    pub fn bind(addr: &str) -> Result<Self> {
+        let socket: SocketAddr = super::listen_addr(addr)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Invalid address '{}'", addr))?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        // bind right away to report a used port before recording
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind(socket))
+            .map_err(|e| anyhow!("Can not listen on '{}': {}", addr, e))?;
+        let (samples, _) = broadcast::channel(BACKLOG);
+        let service = Service {
+            samples: samples.downgrade(),
+        };
+        let (shutdown, stopped) = oneshot::channel();
+        let thread = thread::spawn(move || {
+            let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+            let server = tonic::transport::Server::builder()
+                .add_service(RecorderServer::new(service))
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = stopped.await;
+                });
+            if let Err(err) = runtime.block_on(server) {
+                eprintln!("Warning: gRPC server stopped: {}", err);
+            }
+        });
+        Ok(GrpcServer {
+            samples: Some(samples),
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Sink for GrpcServer {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        if let Some(samples) = &self.samples {
+            // fails without subscribers only
+            let _ = samples.send(GrpcSample::from(sample));
+        }
+        Ok(())
+    }
+
+    /// End the streams of all clients and wait for them to be delivered.
+    fn finish(&mut self) -> Result<()> {
+        self.samples = None;
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        Ok(())
+    }
+}
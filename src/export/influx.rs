@@ -0,0 +1,215 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Writes the samples to InfluxDB v2 in batches.
+
+use crate::output::Sink;
+use crate::{Metric, Sample};
+use anyhow::{anyhow, bail, Result};
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Lines collected before they are written
+const BATCH_SIZE: usize = 1000;
+/// Longest time a sample waits for its batch to be written
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// Attempts to write a batch, waiting twice as long after each failure
+const RETRIES: u32 = 3;
+const BACKOFF: Duration = Duration::from_millis(500);
+/// Lines kept while InfluxDB is unreachable, the oldest are dropped first
+const MAX_PENDING: usize = 100_000;
+/// Lines waiting for the writer thread, newer ones are dropped while it is
+/// busy retrying
+const QUEUE_SIZE: usize = 10_000;
+
+/// Connection settings of the InfluxDB v2 write API.
+pub struct InfluxConfig {
+    /// Base URL, e.g. `http://localhost:8086`
+    pub url: String,
+    pub token: String,
+    pub org: String,
+    pub bucket: String,
+}
+
+/// Posts the samples in the line protocol to the InfluxDB write API as
+/// measurement `procrec`, tagged with the PID.
+///
+/// The requests are made by a thread of their own, so a slow or unreachable
+/// server does not delay the sampling.
+pub struct InfluxExporter {
+    lines: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<Result<()>>>,
+    queue_full: bool,
+}
+
+impl InfluxExporter {
+    pub fn new(config: InfluxConfig) -> Self {
+        let (lines, queue) = mpsc::sync_channel(QUEUE_SIZE);
+        let writer = Writer {
+            config,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build(),
+            pending: VecDeque::new(),
+            last_flush: Instant::now(),
+        };
+        InfluxExporter {
+            lines: Some(lines),
+            writer: Some(thread::spawn(move || writer.run(queue))),
+            queue_full: false,
+        }
+    }
+}
+
+/// Collects the lines in batches and writes them.
+struct Writer {
+    config: InfluxConfig,
+    agent: ureq::Agent,
+    pending: VecDeque<String>,
+    last_flush: Instant,
+}
+
+impl Writer {
+    /// Write the lines until the exporter is finished, then write the rest.
+    fn run(mut self, lines: Receiver<String>) -> Result<()> {
+        loop {
+            let timeout = FLUSH_INTERVAL.saturating_sub(self.last_flush.elapsed());
+            match lines.recv_timeout(timeout) {
+                Ok(line) => {
+                    self.pending.push_back(line);
+                    if self.pending.len() > MAX_PENDING {
+                        self.pending.pop_front();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return self.flush(),
+            }
+            if self.pending.len() >= BATCH_SIZE || self.last_flush.elapsed() >= FLUSH_INTERVAL {
+                // keep recording while InfluxDB is unreachable, retry with the
+                // next batch
+                if let Err(err) = self.flush() {
+                    eprintln!("Warning: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Write all pending lines, retrying with backoff on server and network
+    /// errors. The lines are kept if all attempts failed.
+    fn flush(&mut self) -> Result<()> {
+        self.last_flush = Instant::now();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let body = self.pending.iter().cloned().collect::<Vec<_>>().join("\n");
+        let url = format!("{}/api/v2/write", self.config.url.trim_end_matches('/'));
+        let mut backoff = BACKOFF;
+        for attempt in 1..=RETRIES {
+            let result = self
+                .agent
+                .post(&url)
+                .query("org", &self.config.org)
+                .query("bucket", &self.config.bucket)
+                .query("precision", "ms")
+                .set("Authorization", &format!("Token {}", self.config.token))
+                .set("Content-Type", "text/plain; charset=utf-8")
+                .send_string(&body);
+            match result {
+                Ok(_) => {
+                    self.pending.clear();
+                    return Ok(());
+                }
+                // rejected data or credentials do not get better by retrying
+                Err(ureq::Error::Status(code, response)) if code < 500 => {
+                    self.pending.clear();
+                    bail!(
+                        "InfluxDB rejected the samples ({}): {}",
+                        code,
+                        response.into_string().unwrap_or_default()
+                    );
+                }
+                Err(err) if attempt == RETRIES => {
+                    return Err(anyhow!("Can not write to InfluxDB: {}", err));
+                }
+                Err(_) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format the sample in the line protocol, the timestamp precision is up to
+/// the receiver.
+pub(crate) fn line(sample: &Sample, timestamp: u128) -> String {
+    let fields: Vec<String> = Metric::ALL
+        .iter()
+        .filter_map(|&metric| {
+            metric.value(sample).map(|value| {
+                if metric.is_memory() {
+                    format!("{}={}i", metric.name(), value as u64)
+                } else {
+                    format!("{}={}", metric.name(), value)
+                }
+            })
+        })
+        .collect();
+    format!(
+        "procrec,pid={} {} {}",
+        sample.pid,
+        fields.join(","),
+        timestamp
+    )
+}
+
+impl Sink for InfluxExporter {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        if let Some(lines) = &self.lines {
+            match lines.try_send(line(sample, now)) {
+                Ok(()) => self.queue_full = false,
+                Err(TrySendError::Full(_)) => {
+                    if !self.queue_full {
+                        eprintln!("Warning: InfluxDB is too slow, dropping samples");
+                    }
+                    self.queue_full = true;
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    bail!("The InfluxDB writer stopped unexpectedly")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // closing the queue makes the writer write the rest and exit
+        self.lines = None;
+        match self.writer.take() {
+            Some(writer) => writer
+                .join()
+                .map_err(|_| anyhow!("The InfluxDB writer panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
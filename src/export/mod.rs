@@ -0,0 +1,60 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sinks exporting the samples to monitoring systems while recording.
+
+#[cfg(feature = "network")]
+pub mod grafana;
+#[cfg(feature = "network")]
+pub mod graphite;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "network")]
+pub mod influx;
+#[cfg(feature = "network")]
+pub mod mqtt;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "network")]
+pub mod prometheus;
+#[cfg(feature = "network")]
+pub mod statsd;
+#[cfg(unix)]
+pub mod syslog;
+#[cfg(feature = "network")]
+pub mod webhook;
+
+#[cfg(feature = "network")]
+use crate::{Metric, Sample};
+
+/// All metrics recorded in the sample together with their value.
+#[cfg(feature = "network")]
+pub(crate) fn values(sample: &Sample) -> impl Iterator<Item = (Metric, f64)> + '_ {
+    Metric::ALL
+        .iter()
+        .filter_map(move |&metric| metric.value(sample).map(|value| (metric, value)))
+}
+
+/// Parse an address where the host may be omitted, e.g. `:9555` meaning
+/// all interfaces.
+#[cfg(any(feature = "network", feature = "grpc"))]
+pub(crate) fn listen_addr(addr: &str) -> String {
+    if addr.starts_with(':') {
+        format!("0.0.0.0{}", addr)
+    } else {
+        addr.to_string()
+    }
+}
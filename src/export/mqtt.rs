@@ -0,0 +1,140 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Publishes the samples as JSON to an MQTT topic.
+//!
+//! Only the small part of MQTT 3.1.1 needed to publish with QoS 0 is
+//! implemented, without keep alive and TLS.
+
+use crate::output::Sink;
+use crate::Sample;
+use anyhow::{anyhow, bail, Result};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const DISCONNECT: u8 = 0xe0;
+
+/// Publishes every sample as JSON object to a topic, reconnecting after the
+/// connection broke.
+pub struct MqttPublisher {
+    addr: String,
+    topic: String,
+    stream: Option<TcpStream>,
+}
+
+impl MqttPublisher {
+    /// Connect to the broker at `addr`, the port defaults to 1883.
+    pub fn connect(addr: &str, topic: &str) -> Result<Self> {
+        let addr = if addr.contains(':') {
+            addr.to_string()
+        } else {
+            format!("{}:1883", addr)
+        };
+        let stream = open(&addr)?;
+        Ok(MqttPublisher {
+            addr,
+            topic: topic.to_string(),
+            stream: Some(stream),
+        })
+    }
+}
+
+/// Connect to the broker and wait for it to accept the session.
+fn open(addr: &str) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(addr)
+        .map_err(|e| anyhow!("Can not connect to MQTT broker '{}': {}", addr, e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+    let mut body = vec![];
+    write_string(&mut body, "MQTT");
+    // protocol level 4 (3.1.1), clean session, keep alive disabled
+    body.extend_from_slice(&[4, 0x02, 0, 0]);
+    write_string(&mut body, &format!("procrec-{}", std::process::id()));
+    write_packet(&mut stream, CONNECT, &body)?;
+
+    let mut connack = [0; 4];
+    stream.read_exact(&mut connack)?;
+    if connack[0] != CONNACK {
+        bail!("Unexpected answer of MQTT broker '{}'", addr);
+    }
+    if connack[3] != 0 {
+        bail!(
+            "MQTT broker '{}' refused the connection (code {})",
+            addr,
+            connack[3]
+        );
+    }
+    Ok(stream)
+}
+
+impl Sink for MqttPublisher {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        let mut body = vec![];
+        write_string(&mut body, &self.topic);
+        serde_json::to_writer(&mut body, sample)?;
+
+        if self.stream.is_none() {
+            self.stream = open(&self.addr).ok();
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            // keep recording while the broker is away, the samples are lost
+            // then
+            if let Err(err) = write_packet(stream, PUBLISH, &body) {
+                eprintln!("Warning: lost connection to MQTT broker: {}", err);
+                self.stream = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => write_packet(stream, DISCONNECT, &[]),
+            None => Ok(()),
+        }
+    }
+}
+
+/// UTF-8 string prefixed by its length.
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Packet with a fixed header, the remaining length is variable-length
+/// encoded with seven bits per byte.
+fn write_packet(out: &mut impl Write, kind: u8, body: &[u8]) -> Result<()> {
+    let mut packet = vec![kind];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    out.write_all(&packet)?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Inserts the samples into a Postgres or TimescaleDB table in batches.
+
+use crate::output::Sink;
+use crate::Sample;
+use anyhow::{anyhow, bail, Result};
+use postgres::{Client, NoTls};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Rows collected before they are inserted
+const BATCH_SIZE: usize = 500;
+/// Longest time a sample waits for its batch to be inserted
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// Rows kept while the database is unreachable, the oldest are dropped first
+const MAX_PENDING: usize = 100_000;
+
+/// Inserts the samples into `table`, which is created if missing. The table
+/// has a `time` column and no unique constraints, so it can be turned into
+/// a TimescaleDB hypertable with `create_hypertable('<table>', 'time')`.
+pub struct PostgresSink {
+    dsn: String,
+    table: String,
+    client: Client,
+    pending: VecDeque<(SystemTime, Sample)>,
+    last_flush: Instant,
+}
+
+impl PostgresSink {
+    /// Connect with a DSN like `host=localhost user=procrec dbname=perf` or
+    /// `postgresql://procrec@localhost/perf`.
+    pub fn connect(dsn: &str, table: &str) -> Result<Self> {
+        // the table name ends up in the statements, allow plain names only
+        if table.is_empty()
+            || !table
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            bail!("Invalid table name '{}'", table);
+        }
+        let mut client = Client::connect(dsn, NoTls)
+            .map_err(|e| anyhow!("Can not connect to Postgres: {}", e))?;
+        client.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                time TIMESTAMPTZ NOT NULL,
+                pid INTEGER NOT NULL,
+                cpu REAL NOT NULL,
+                rss BIGINT NOT NULL,
+                vsize BIGINT NOT NULL,
+                heap BIGINT,
+                stack BIGINT,
+                file BIGINT,
+                anon BIGINT
+            )",
+            table
+        ))?;
+        Ok(PostgresSink {
+            dsn: dsn.to_string(),
+            table: table.to_string(),
+            client,
+            pending: VecDeque::new(),
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Insert all pending rows in one transaction, the rows are kept if it
+    /// failed.
+    fn flush(&mut self) -> Result<()> {
+        self.last_flush = Instant::now();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        if self.client.is_closed() {
+            self.client = Client::connect(&self.dsn, NoTls)?;
+        }
+        let mut transaction = self.client.transaction()?;
+        let insert = transaction.prepare(&format!(
+            "INSERT INTO {} (time, pid, cpu, rss, vsize, heap, stack, file, anon)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            self.table
+        ))?;
+        for (time, sample) in &self.pending {
+            let smaps = sample.smaps;
+            transaction.execute(
+                &insert,
+                &[
+                    time,
+                    &(sample.pid as i32),
+                    &sample.cpu,
+                    &(sample.rss as i64),
+                    &(sample.vsize as i64),
+                    &smaps.map(|s| s.heap as i64),
+                    &smaps.map(|s| s.stack as i64),
+                    &smaps.map(|s| s.file as i64),
+                    &smaps.map(|s| s.anon as i64),
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl Sink for PostgresSink {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        self.pending.push_back((SystemTime::now(), sample.clone()));
+        if self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+        }
+        if self.pending.len() >= BATCH_SIZE || self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            // keep recording while the database is unreachable, retry with the next batch
+            if let Err(err) = self.flush() {
+                eprintln!("Warning: can not insert samples into Postgres: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
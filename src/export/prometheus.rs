@@ -0,0 +1,118 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Serves the latest values of all metrics for Prometheus to scrape.
+
+use super::listen_addr;
+use crate::http;
+use crate::output::Sink;
+use crate::{Metric, Sample};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Latest sample of every process
+type Latest = Arc<Mutex<BTreeMap<u32, Sample>>>;
+
+/// Serves `/metrics` in the Prometheus text format from a background thread.
+pub struct PrometheusExporter {
+    latest: Latest,
+}
+
+impl PrometheusExporter {
+    /// Listen on `addr`, e.g. `:9555` for port 9555 on all interfaces.
+    pub fn bind(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(listen_addr(addr))
+            .map_err(|e| anyhow!("Can not listen on '{}': {}", addr, e))?;
+        let latest = Latest::default();
+        let served = latest.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // a broken scrape must not end the exporter
+                let _ = serve(stream, &served);
+            }
+        });
+        Ok(PrometheusExporter { latest })
+    }
+}
+
+impl Sink for PrometheusExporter {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        self.latest
+            .lock()
+            .unwrap()
+            .insert(sample.pid, sample.clone());
+        Ok(())
+    }
+}
+
+/// Name of the metric, memory is exported in bytes as recommended by
+/// Prometheus.
+fn metric_name(metric: Metric) -> String {
+    if metric.is_memory() {
+        format!("procrec_{}_bytes", metric.name())
+    } else {
+        format!("procrec_{}_percent", metric.name())
+    }
+}
+
+fn render(latest: &BTreeMap<u32, Sample>) -> String {
+    let mut text = String::new();
+    for &metric in Metric::ALL.iter() {
+        let rows: Vec<(u32, f64)> = latest
+            .values()
+            .filter_map(|sample| metric.value(sample).map(|value| (sample.pid, value)))
+            .collect();
+        if rows.is_empty() {
+            continue;
+        }
+        let name = metric_name(metric);
+        let help = if metric.is_memory() {
+            format!("{} memory of the process in bytes", metric.title())
+        } else {
+            "CPU utilization of the process in percent".to_string()
+        };
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} gauge", name);
+        for (pid, value) in rows {
+            let value = if metric.is_memory() {
+                value * 1000.0
+            } else {
+                value
+            };
+            let _ = writeln!(text, "{}{{pid=\"{}\"}} {}", name, pid, value);
+        }
+    }
+    text
+}
+
+fn serve(stream: TcpStream, latest: &Latest) -> Result<()> {
+    let request = http::read_request(&stream)?;
+    let (status, body) = if request.path == "/metrics" {
+        ("200 OK", render(&latest.lock().unwrap()))
+    } else {
+        ("404 Not Found", "Not found, try /metrics\n".to_string())
+    };
+    http::respond(
+        &stream,
+        status,
+        "text/plain; version=0.0.4",
+        body.as_bytes(),
+    )
+}
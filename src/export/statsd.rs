@@ -0,0 +1,64 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pushes every sample as StatsD gauges over UDP.
+
+use super::values;
+use crate::output::Sink;
+use crate::Sample;
+use anyhow::{anyhow, Result};
+use std::net::UdpSocket;
+
+/// Sends the metrics of every sample as gauges `<prefix>.<metric>`, tagged
+/// with the PID in the DogStatsD format understood by Datadog and Telegraf.
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+}
+
+impl StatsdExporter {
+    /// Send to the StatsD server at `addr`, e.g. `localhost:8125`. The tags
+    /// are given as `key:value`.
+    pub fn connect(addr: &str, prefix: &str, tags: &[String]) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket
+            .connect(addr)
+            .map_err(|e| anyhow!("Can not reach StatsD at '{}': {}", addr, e))?;
+        Ok(StatsdExporter {
+            socket,
+            prefix: prefix.to_string(),
+            tags: tags.to_vec(),
+        })
+    }
+}
+
+impl Sink for StatsdExporter {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        let mut tags = vec![format!("pid:{}", sample.pid)];
+        tags.extend(self.tags.iter().cloned());
+        let tags = tags.join(",");
+        // all gauges of a sample in one datagram
+        let packet: Vec<String> = values(sample)
+            .map(|(metric, value)| {
+                format!("{}.{}:{}|g|#{}", self.prefix, metric.name(), value, tags)
+            })
+            .collect();
+        // UDP is fire and forget, an unreachable server must not end the recording
+        let _ = self.socket.send(packet.join("\n").as_bytes());
+        Ok(())
+    }
+}
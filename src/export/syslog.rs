@@ -0,0 +1,151 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Logs the samples and events as structured entries to journald or syslog.
+
+use crate::output::Sink;
+use crate::{Event, Sample};
+use anyhow::{anyhow, bail, Result};
+use std::os::unix::net::UnixDatagram;
+use std::str::FromStr;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Structured data ID, uses the enterprise number reserved for examples
+const SD_ID: &str = "procrec@32473";
+
+/// Severities of samples and events
+const INFO: u8 = 6;
+const NOTICE: u8 = 5;
+/// Facility `user`
+const FACILITY: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Native journald protocol with one field per value
+    Journald,
+    /// RFC 5424 message with the values as structured data
+    Syslog,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "journald" => Ok(LogFormat::Journald),
+            "syslog" => Ok(LogFormat::Syslog),
+            _ => bail!("Unknown log target '{}' (journald, syslog)", s),
+        }
+    }
+}
+
+/// Sends every sample and event to the local journald or syslog daemon.
+pub struct SystemLog {
+    format: LogFormat,
+    socket: UnixDatagram,
+}
+
+impl SystemLog {
+    pub fn connect(format: LogFormat) -> Result<Self> {
+        let path = match format {
+            LogFormat::Journald => JOURNALD_SOCKET,
+            LogFormat::Syslog => SYSLOG_SOCKET,
+        };
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(path)
+            .map_err(|e| anyhow!("Can not connect to '{}': {}", path, e))?;
+        Ok(SystemLog { format, socket })
+    }
+
+    /// Send a message with the given fields, the field names are upper case
+    /// for journald and lower case for syslog.
+    fn log(&self, severity: u8, message: &str, fields: &[(&str, String)]) -> Result<()> {
+        let entry = match self.format {
+            LogFormat::Journald => {
+                let mut entry = format!(
+                    "MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER=procrec\n",
+                    single_line(message),
+                    severity
+                );
+                for (name, value) in fields {
+                    entry += &format!("PROCREC_{}={}\n", name.to_uppercase(), single_line(value));
+                }
+                entry
+            }
+            LogFormat::Syslog => {
+                let params: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{}=\"{}\"", name, escape_param(value)))
+                    .collect();
+                // timestamp and host name are filled in by the daemon
+                format!(
+                    "<{}>1 - - procrec {} - [{} {}] {}",
+                    FACILITY * 8 + severity,
+                    std::process::id(),
+                    SD_ID,
+                    params.join(" "),
+                    single_line(message)
+                )
+            }
+        };
+        // keep recording while the daemon restarts, the entry is lost then
+        if let Err(err) = self.socket.send(entry.as_bytes()) {
+            eprintln!("Warning: can not write to the system log: {}", err);
+        }
+        Ok(())
+    }
+}
+
+/// Newlines would end the journald field, the values are short anyway.
+fn single_line(s: &str) -> String {
+    s.replace('\n', " ")
+}
+
+/// Escape a structured data parameter value as required by RFC 5424.
+fn escape_param(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+impl Sink for SystemLog {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        self.log(
+            INFO,
+            sample.to_string().trim_end(),
+            &[
+                ("ts", format!("{:.02}", sample.ts)),
+                ("pid", sample.pid.to_string()),
+                ("cpu", format!("{:.02}", sample.cpu)),
+                ("rss", sample.rss.to_string()),
+                ("vsize", sample.vsize.to_string()),
+            ],
+        )
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<()> {
+        self.log(
+            NOTICE,
+            &event.label,
+            &[
+                ("ts", format!("{:.02}", event.ts)),
+                ("event", event.label.clone()),
+            ],
+        )
+    }
+}
@@ -0,0 +1,114 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Notifies a webhook about events and the end of the recording.
+
+use crate::alert::Alert;
+use crate::output::Sink;
+use crate::{Event, Sample};
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Payload posted as JSON, `text` makes it usable with Slack incoming
+/// webhooks as is.
+#[derive(Serialize)]
+struct Notification<'a> {
+    /// `event`, `alert` or `complete`
+    kind: &'a str,
+    /// Relative time of the recording in seconds
+    ts: f32,
+    label: &'a str,
+    text: String,
+}
+
+/// POSTs a JSON notification for every event, like a process exiting, every
+/// alert and once the recording completed.
+pub struct Webhook {
+    url: String,
+    agent: ureq::Agent,
+    last_ts: f32,
+    samples: usize,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Self {
+        Webhook {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build(),
+            last_ts: 0.0,
+            samples: 0,
+        }
+    }
+
+    fn notify(&self, notification: Notification) {
+        let body = serde_json::to_string(&notification).unwrap_or_default();
+        let result = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+        // a broken webhook must not end the recording
+        if let Err(err) = result {
+            eprintln!("Warning: webhook '{}' failed: {}", self.url, err);
+        }
+    }
+}
+
+impl Sink for Webhook {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        self.last_ts = sample.ts;
+        self.samples += 1;
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<()> {
+        self.last_ts = self.last_ts.max(event.ts);
+        self.notify(Notification {
+            kind: "event",
+            ts: event.ts,
+            label: &event.label,
+            text: format!("procrec: {} after {:.0}s", event.label, event.ts),
+        });
+        Ok(())
+    }
+
+    fn write_alert(&mut self, alert: &Alert) -> Result<()> {
+        self.last_ts = self.last_ts.max(alert.ts);
+        self.notify(Notification {
+            kind: "alert",
+            ts: alert.ts,
+            label: &alert.event().label,
+            text: format!("procrec alert: {} after {:.0}s", alert, alert.ts),
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.notify(Notification {
+            kind: "complete",
+            ts: self.last_ts,
+            label: "recording complete",
+            text: format!(
+                "procrec: recording complete after {:.0}s with {} samples",
+                self.last_ts, self.samples
+            ),
+        });
+        Ok(())
+    }
+}
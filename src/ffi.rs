@@ -0,0 +1,160 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! C interface to drive a recording in-process, enabled by the `ffi` feature.
+//!
+//! The recording runs on a background thread, samples are fetched by polling.
+//! See `include/procrec.h` for the C declarations.
+
+use crate::output::Sink;
+use crate::{Recorder, RecorderOptions, Sample, TrackedProcess};
+use anyhow::Result;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A sample as handed out to C, memory in kB like `Sample`.
+#[repr(C)]
+pub struct ProcrecSample {
+    pub ts: f32,
+    pub pid: u32,
+    pub cpu: f32,
+    pub rss: u64,
+    pub vsize: u64,
+}
+
+impl From<&Sample> for ProcrecSample {
+    fn from(sample: &Sample) -> Self {
+        ProcrecSample {
+            ts: sample.ts,
+            pid: sample.pid,
+            cpu: sample.cpu,
+            rss: sample.rss,
+            vsize: sample.vsize,
+        }
+    }
+}
+
+/// A running recording, opaque to C.
+pub struct ProcrecHandle {
+    running: Arc<AtomicBool>,
+    samples: Receiver<Sample>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Passes the samples from the recording thread to the handle.
+struct ChannelSink(Sender<Sample>);
+
+impl Sink for ChannelSink {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        // nobody is polling anymore once the handle is gone
+        let _ = self.0.send(sample.clone());
+        Ok(())
+    }
+}
+
+/// Start recording the process `pid` every `interval_ms` milliseconds.
+///
+/// Returns NULL if the process can not be attached, otherwise a handle that
+/// must be released with `procrec_stop`.
+#[no_mangle]
+pub extern "C" fn procrec_start(pid: u32, interval_ms: u32) -> *mut ProcrecHandle {
+    let (sample_tx, samples) = mpsc::channel();
+    let (start_tx, start_rx) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        let process = match TrackedProcess::attach(pid) {
+            Ok(process) => process,
+            Err(_) => {
+                let _ = start_tx.send(None);
+                return;
+            }
+        };
+        let mut recorder = Recorder::new(
+            vec![process],
+            RecorderOptions {
+                interval: Duration::from_millis(interval_ms.into()),
+                ..RecorderOptions::default()
+            },
+        );
+        recorder.add_sink(Box::new(ChannelSink(sample_tx)));
+        let _ = start_tx.send(Some(recorder.stop_handle()));
+        let _ = recorder.run();
+    });
+
+    match start_rx.recv() {
+        Ok(Some(running)) => Box::into_raw(Box::new(ProcrecHandle {
+            running,
+            samples,
+            thread: Some(thread),
+        })),
+        _ => {
+            let _ = thread.join();
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Fetch the next sample without blocking.
+///
+/// Returns 1 if a sample was written to `out`, 0 if no sample is available
+/// yet and -1 once the recording finished and all samples were fetched.
+///
+/// # Safety
+///
+/// `handle` must be returned by `procrec_start` and not yet stopped, `out`
+/// must point to a writable `ProcrecSample`.
+#[no_mangle]
+pub unsafe extern "C" fn procrec_poll_sample(
+    handle: *mut ProcrecHandle,
+    out: *mut ProcrecSample,
+) -> i32 {
+    if handle.is_null() || out.is_null() {
+        return -1;
+    }
+    // SAFETY: both pointers are valid as required by the caller
+    match (*handle).samples.try_recv() {
+        Ok(sample) => {
+            *out = ProcrecSample::from(&sample);
+            1
+        }
+        Err(TryRecvError::Empty) => 0,
+        Err(TryRecvError::Disconnected) => -1,
+    }
+}
+
+/// Stop the recording and release the handle.
+///
+/// Blocks until the recording thread finished its current interval.
+///
+/// # Safety
+///
+/// `handle` must be returned by `procrec_start` or be NULL, it is invalid
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn procrec_stop(handle: *mut ProcrecHandle) {
+    if handle.is_null() {
+        return;
+    }
+    // SAFETY: the handle was created by `Box::into_raw` in `procrec_start`
+    let mut handle = Box::from_raw(handle);
+    handle.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = handle.thread.take() {
+        let _ = thread.join();
+    }
+}
@@ -0,0 +1,85 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of processes which are alive but make no progress.
+
+use crate::condition::{Comparison, Condition};
+use crate::{Metric, Sample};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// CPU utilization in percent below which a process counts as idle
+pub const HANG_CPU: f64 = 1.0;
+
+#[derive(Debug, Clone)]
+struct Idle {
+    since: f32,
+    io: Option<u64>,
+    fired: bool,
+}
+
+/// Watches for processes without CPU utilization and I/O for a period, e.g.
+/// deadlocked workers.
+///
+/// It fires once per hang and again only after the process made progress in
+/// between.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    pub period: Duration,
+    state: HashMap<u32, Idle>,
+}
+
+impl Watchdog {
+    pub fn new(period: Duration) -> Self {
+        Watchdog {
+            period,
+            state: HashMap::new(),
+        }
+    }
+
+    /// The CPU part of the hang as condition, used to report it as alert.
+    pub fn condition(&self) -> Condition {
+        Condition {
+            metric: Metric::Cpu,
+            comparison: Comparison::Less,
+            threshold: HANG_CPU,
+            duration: Some(self.period),
+        }
+    }
+
+    /// Update with a new sample and the bytes read and written by the
+    /// process so far if known, returns whether the process hangs.
+    pub fn update(&mut self, sample: &Sample, io: Option<u64>) -> bool {
+        let idle = self.state.entry(sample.pid).or_insert(Idle {
+            since: sample.ts,
+            io,
+            fired: false,
+        });
+        if f64::from(sample.cpu) >= HANG_CPU || idle.io != io {
+            *idle = Idle {
+                since: sample.ts,
+                io,
+                fired: false,
+            };
+            return false;
+        }
+        if idle.fired || sample.ts - idle.since < self.period.as_secs_f32() {
+            return false;
+        }
+        idle.fired = true;
+        true
+    }
+}
@@ -0,0 +1,103 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Distribution of a metric over a recording.
+
+use crate::summary;
+use crate::{Metric, Recording};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Number of bins of a histogram
+pub const BINS: usize = 10;
+/// Length of the longest bar in the terminal
+const BAR_WIDTH: usize = 40;
+
+/// Number of sampling rounds with a value in `[lower, upper)`, the last bin
+/// includes its upper bound.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bin {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+}
+
+/// Distribution of a metric, summed up over the processes per sampling
+/// round, in bins of equal width.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub metric: Metric,
+    pub bins: Vec<Bin>,
+}
+
+impl Histogram {
+    /// Histogram of the metric, `None` if it was not recorded.
+    pub fn of(recording: &Recording, metric: Metric, bins: usize) -> Option<Histogram> {
+        let rounds = summary::rounds(&recording.samples);
+        let values: Vec<f64> = summary::totals(&rounds, metric)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect();
+        let min = values.iter().copied().reduce(f64::min)?;
+        let max = values.iter().copied().reduce(f64::max)?;
+        // a constant metric fits into a single bin
+        let bins = if max > min { bins.max(1) } else { 1 };
+        let width = (max - min) / bins as f64;
+        let mut histogram = Histogram {
+            metric,
+            bins: (0..bins)
+                .map(|i| Bin {
+                    lower: min + width * i as f64,
+                    upper: min + width * (i + 1) as f64,
+                    count: 0,
+                })
+                .collect(),
+        };
+        for value in values {
+            let i = if width > 0.0 {
+                ((value - min) / width) as usize
+            } else {
+                0
+            };
+            histogram.bins[i.min(bins - 1)].count += 1;
+        }
+        Some(histogram)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = if self.metric.is_memory() { "kB" } else { "%" };
+        writeln!(f, "{} in {}", self.metric.name(), unit)?;
+        let most = self.bins.iter().map(|b| b.count).max().unwrap_or_default();
+        for bin in &self.bins {
+            let bar = if most > 0 {
+                (bin.count * BAR_WIDTH).div_ceil(most)
+            } else {
+                0
+            };
+            writeln!(
+                f,
+                "{:>12.1} - {:<12.1} {:>6} {}",
+                bin.lower,
+                bin.upper,
+                bin.count,
+                "#".repeat(bar)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hooks reacting on every new sample or alert while recording.
+
+use crate::alert::Alert;
+use crate::output::Sink;
+use crate::Sample;
+use anyhow::{anyhow, bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+
+/// Calls a closure with every new sample.
+pub struct Callback<F>(pub F);
+
+impl<F: FnMut(&Sample) -> Result<()> + Send> Sink for Callback<F> {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        (self.0)(sample)
+    }
+}
+
+/// Runs a shell command for every new sample and waits for it to finish.
+///
+/// The sample is passed in the environment variables `PROCREC_TS`,
+/// `PROCREC_PID`, `PROCREC_CPU`, `PROCREC_RSS` and `PROCREC_VSIZE`.
+pub struct ExecHook {
+    command: String,
+}
+
+impl ExecHook {
+    pub fn new(command: &str) -> Self {
+        ExecHook {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl Sink for ExecHook {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        // the exit status is up to the hook, only failing to run it is an error
+        Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("PROCREC_TS", format!("{:.02}", sample.ts))
+            .env("PROCREC_PID", sample.pid.to_string())
+            .env("PROCREC_CPU", format!("{:.02}", sample.cpu))
+            .env("PROCREC_RSS", sample.rss.to_string())
+            .env("PROCREC_VSIZE", sample.vsize.to_string())
+            .status()
+            .map_err(|e| anyhow!("Can not run hook '{}': {}", self.command, e))?;
+        Ok(())
+    }
+}
+
+/// Runs a shell command whenever an alert fires, e.g. to take a core dump at
+/// the moment of a spike.
+///
+/// The placeholders `{pid}`, `{metric}`, `{value}`, `{ts}` and `{condition}`
+/// are replaced by the quoted values of the alert. The recording goes on
+/// while the command runs, `finish` waits for it.
+pub struct AlertHook {
+    command: String,
+    running: Vec<Child>,
+}
+
+impl AlertHook {
+    pub fn new(command: &str) -> Self {
+        AlertHook {
+            command: command.to_string(),
+            running: vec![],
+        }
+    }
+}
+
+impl Sink for AlertHook {
+    fn write_sample(&mut self, _sample: &Sample) -> Result<()> {
+        // reap the commands that finished meanwhile
+        self.running
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        Ok(())
+    }
+
+    fn write_alert(&mut self, alert: &Alert) -> Result<()> {
+        let command = self
+            .command
+            .replace("{pid}", &quote(&alert.pid.to_string()))
+            .replace("{metric}", &quote(alert.condition.metric.name()))
+            .replace("{value}", &quote(&alert.value.to_string()))
+            .replace("{ts}", &quote(&format!("{:.02}", alert.ts)))
+            .replace("{condition}", &quote(&alert.condition.to_string()));
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .spawn()
+            .map_err(|e| anyhow!("Can not run alert hook '{}': {}", self.command, e))?;
+        self.running.push(child);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for mut child in self.running.drain(..) {
+            let _ = child.wait();
+        }
+        Ok(())
+    }
+}
+
+/// Files of `/proc/<pid>` copied by [`Snapshot`]
+const SNAPSHOT_FILES: [&str; 4] = ["status", "smaps", "stack", "limits"];
+
+/// Preserves the state of a process when an alert fires (linux only).
+///
+/// Copies `status`, `smaps`, `stack` and `limits` of `/proc/<pid>` and a
+/// listing of the open file descriptors to `<dir>/<pid>-<ts>s/`. Files which
+/// can not be read, like `stack` without privileges, are left out.
+pub struct Snapshot {
+    dir: PathBuf,
+}
+
+impl Snapshot {
+    pub fn new(dir: &Path) -> Self {
+        Snapshot {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn take(&self, alert: &Alert) -> Result<PathBuf> {
+        let proc_dir = Path::new("/proc").join(alert.pid.to_string());
+        let dir = self.dir.join(format!("{}-{:.0}s", alert.pid, alert.ts));
+        fs::create_dir_all(&dir)?;
+        for name in SNAPSHOT_FILES.iter() {
+            if let Ok(content) = fs::read(proc_dir.join(name)) {
+                fs::write(dir.join(name), content)?;
+            }
+        }
+        let mut fds = String::new();
+        if let Ok(entries) = fs::read_dir(proc_dir.join("fd")) {
+            let mut entries: Vec<_> = entries.flatten().collect();
+            entries.sort_by_key(|e| e.file_name().to_string_lossy().parse::<u32>().unwrap_or(0));
+            for entry in entries {
+                let target = fs::read_link(entry.path())
+                    .map(|t| t.display().to_string())
+                    .unwrap_or_else(|e| e.to_string());
+                fds.push_str(&format!(
+                    "{} -> {}\n",
+                    entry.file_name().to_string_lossy(),
+                    target
+                ));
+            }
+        }
+        fs::write(dir.join("fds"), fds)?;
+        Ok(dir)
+    }
+}
+
+impl Sink for Snapshot {
+    fn write_sample(&mut self, _sample: &Sample) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_alert(&mut self, alert: &Alert) -> Result<()> {
+        // a failed snapshot must not end the recording
+        match self.take(alert) {
+            Ok(dir) => eprintln!(
+                "Saved snapshot of process {} to '{}'",
+                alert.pid,
+                dir.display()
+            ),
+            Err(err) => eprintln!(
+                "Warning: can not save snapshot of process {}: {}",
+                alert.pid, err
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Sends a signal to the process an alert fired for, e.g. to trigger a heap
+/// dump by an allocator or profiler listening for `SIGUSR2`.
+#[cfg(unix)]
+pub struct AlertSignal {
+    signal: i32,
+}
+
+#[cfg(unix)]
+impl AlertSignal {
+    pub fn new(signal: i32) -> Self {
+        AlertSignal { signal }
+    }
+}
+
+#[cfg(unix)]
+impl Sink for AlertSignal {
+    fn write_sample(&mut self, _sample: &Sample) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_alert(&mut self, alert: &Alert) -> Result<()> {
+        if unsafe { libc::kill(alert.pid as libc::pid_t, self.signal) } != 0 {
+            eprintln!(
+                "Warning: can not signal process {}: {}",
+                alert.pid,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Parse a signal given by name, with or without `SIG` prefix, or number.
+pub fn parse_signal(s: &str) -> Result<i32> {
+    if let Ok(number) = s.parse() {
+        return Ok(number);
+    }
+    let name = s.to_uppercase();
+    Ok(match name.strip_prefix("SIG").unwrap_or(&name) {
+        #[cfg(unix)]
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        #[cfg(unix)]
+        "QUIT" => libc::SIGQUIT,
+        "ABRT" => libc::SIGABRT,
+        #[cfg(unix)]
+        "KILL" => libc::SIGKILL,
+        #[cfg(unix)]
+        "USR1" => libc::SIGUSR1,
+        #[cfg(unix)]
+        "USR2" => libc::SIGUSR2,
+        "TERM" => libc::SIGTERM,
+        #[cfg(unix)]
+        "CONT" => libc::SIGCONT,
+        #[cfg(unix)]
+        "STOP" => libc::SIGSTOP,
+        _ => bail!("Unknown signal '{}', expected e.g. SIGUSR2 or a number", s),
+    })
+}
+
+/// Quote for the shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
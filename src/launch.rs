@@ -0,0 +1,616 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! How procrec launches the recorded command.
+
+use crate::cgroup::Cgroup;
+use crate::TrackedProcess;
+use anyhow::{anyhow, bail, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Lines written by launched commands and the time they were read.
+#[derive(Debug, Clone, Default)]
+pub struct OutputLines(Arc<Mutex<Vec<(Instant, String)>>>);
+
+impl OutputLines {
+    /// Lines read since the last call.
+    pub(crate) fn take(&self) -> Vec<(Instant, String)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    /// Collect the lines of `input` while passing them on to `output`.
+    fn forward<R, W>(&self, input: R, mut output: W)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let lines = self.0.clone();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            let mut buf = Vec::new();
+            // the output is not necessarily UTF-8, the command would die
+            // writing to the closed pipe if reading stopped before EOF
+            loop {
+                buf.clear();
+                match input.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                // the command keeps running if its output gets lost
+                let _ = output.write_all(&buf).and_then(|_| output.flush());
+                let line = String::from_utf8_lossy(&buf);
+                if !line.trim().is_empty() {
+                    let line = line.trim_end().to_string();
+                    lines.lock().unwrap().push((Instant::now(), line));
+                }
+            }
+        });
+    }
+}
+
+/// The command to launch and the settings it is launched with.
+#[derive(Debug, Clone, Default)]
+pub struct Launch {
+    /// Program and its arguments
+    pub command: Vec<String>,
+    /// Directory to write the stdout and stderr of the command to, as
+    /// `<program>-<unix time>.stdout` and `.stderr`
+    pub capture_output: Option<PathBuf>,
+    /// Environment variables set in addition to the inherited ones
+    pub env: Vec<(String, String)>,
+    /// Working directory, procrec's own if omitted
+    pub cwd: Option<PathBuf>,
+    /// Control group the command joins, e.g. to limit its resources
+    pub cgroup: Option<Arc<Cgroup>>,
+    /// CPUs the command is pinned to (linux only)
+    pub cpuset: Option<CpuSet>,
+    /// Niceness of the command from -20 (highest priority) to 19
+    pub nice: Option<i32>,
+    /// I/O scheduling class and priority of the command (linux only)
+    pub ionice: Option<IoPriority>,
+    /// Name or UID of the user to run the command as, requires root (unix
+    /// only)
+    pub user: Option<String>,
+    /// Collect every line of stdout and stderr, which are still passed on
+    pub output_lines: Option<OutputLines>,
+    /// Run the command in a pseudo terminal, which gets the input of
+    /// procrec's terminal and passes on the output (unix only)
+    pub pty: bool,
+    /// Start the command in a session of its own, so it is not signaled by
+    /// procrec's terminal and can be left running (unix only)
+    pub detach: bool,
+}
+
+impl Launch {
+    pub fn new(command: Vec<String>) -> Launch {
+        Launch {
+            command,
+            ..Default::default()
+        }
+    }
+
+    /// The command ready to be spawned.
+    pub fn command(&self) -> Result<Command> {
+        if self.command.is_empty() {
+            bail!("Process to record must be provided as additional argument or via '--pid' parameter. For detailed information, execute with --help");
+        }
+        let mut cmd = Command::new(&self.command[0]);
+        cmd.args(&self.command[1..]);
+        cmd.envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(cwd) = &self.cwd {
+            if !cwd.is_dir() {
+                bail!("Working directory '{}' does not exist", cwd.display());
+            }
+            cmd.current_dir(cwd);
+        }
+        if self.detach {
+            unix::new_session(&mut cmd);
+        }
+        if let Some(CpuSet(cpus)) = &self.cpuset {
+            unix::pin(&mut cmd, cpus)?;
+        }
+        // raising the priority needs root
+        if let Some(nice) = self.nice {
+            unix::nice(&mut cmd, nice)?;
+        }
+        if let Some(priority) = self.ionice {
+            unix::ionice(&mut cmd, priority)?;
+        }
+        // joined while still privileged
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.join_on_exec(&mut cmd)?;
+        }
+        if let Some(user) = &self.user {
+            unix::run_as(&mut cmd, user)?;
+        }
+        Ok(cmd)
+    }
+
+    /// Launch the command and track the created process.
+    pub fn spawn(&self) -> Result<TrackedProcess> {
+        let mut cmd = self.command()?;
+        let files = match &self.capture_output {
+            Some(dir) => Some(self.output_files(dir)?),
+            None => None,
+        };
+        if self.pty {
+            return self.spawn_in_pty(cmd, files.map(|(stdout, _)| stdout));
+        }
+        let lines = match &self.output_lines {
+            Some(lines) => lines,
+            None => {
+                if let Some((stdout, stderr)) = files {
+                    cmd.stdout(stdout).stderr(stderr);
+                }
+                return TrackedProcess::spawn(cmd);
+            }
+        };
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = crate::process::spawn_child(&mut cmd)
+            .map_err(|e| anyhow!("Can not execute command: {}", e))?;
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        match files {
+            Some((out, err)) => {
+                lines.forward(stdout.unwrap(), out);
+                lines.forward(stderr.unwrap(), err);
+            }
+            None => {
+                lines.forward(stdout.unwrap(), io::stdout());
+                lines.forward(stderr.unwrap(), io::stderr());
+            }
+        }
+        TrackedProcess::from_child(child)
+    }
+
+    /// Launch the command in a pseudo terminal, its output goes to `output`
+    /// or procrec's stdout.
+    #[cfg(unix)]
+    fn spawn_in_pty(&self, mut cmd: Command, output: Option<File>) -> Result<TrackedProcess> {
+        let master = crate::pty::Pty::open()?.attach(&mut cmd)?;
+        let child = cmd
+            .spawn()
+            .map_err(|e| anyhow!("Can not execute command: {}", e))?;
+        // the command has to hold the only copies of the terminal, reading
+        // from it fails once the command ended
+        drop(cmd);
+        crate::pty::forward_input(master.try_clone()?);
+        let output: Box<dyn Write + Send> = match output {
+            Some(file) => Box::new(file),
+            None => Box::new(io::stdout()),
+        };
+        match &self.output_lines {
+            Some(lines) => lines.forward(master, output),
+            None => crate::pty::forward_output(master, output),
+        }
+        TrackedProcess::from_child(child)
+    }
+
+    #[cfg(not(unix))]
+    fn spawn_in_pty(&self, _cmd: Command, _output: Option<File>) -> Result<TrackedProcess> {
+        bail!("Pseudo terminals are only supported on unix")
+    }
+
+    /// Create the files capturing stdout and stderr in the directory. A
+    /// restart within the same second appends to them.
+    fn output_files(&self, dir: &Path) -> Result<(File, File)> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Can not create '{}': {}", dir.display(), e))?;
+        let program = Path::new(&self.command[0])
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.command[0].clone());
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let open = |stream: &str| {
+            let path = dir.join(format!("{}-{}.{}", program, since_epoch.as_secs(), stream));
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| anyhow!("Can not create '{}': {}", path.display(), e))
+        };
+        Ok((open("stdout")?, open("stderr")?))
+    }
+}
+
+/// A set of CPUs, parsed from a list like `0-3,6`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuSet(pub Vec<usize>);
+
+impl FromStr for CpuSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid CPU list '{}', expected e.g. '0-3,6'", s);
+        let mut cpus = vec![];
+        for part in s.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (first, last),
+                None => (part, part),
+            };
+            let first: usize = first.trim().parse().map_err(|_| invalid())?;
+            let last: usize = last.trim().parse().map_err(|_| invalid())?;
+            if first > last {
+                return Err(invalid());
+            }
+            cpus.extend(first..=last);
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuSet(cpus))
+    }
+}
+
+/// I/O scheduling class like the `-c` option of `ionice`, with the priority
+/// within the class from 0 (highest) to 7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    Realtime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl FromStr for IoPriority {
+    type Err = anyhow::Error;
+
+    /// Parse `idle`, `best-effort` or `realtime`, the latter two optionally
+    /// followed by the priority like `best-effort:7`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid I/O priority '{}', expected idle, best-effort[:0-7] or realtime[:0-7]",
+                s
+            )
+        };
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level.parse::<u8>().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        if level.is_some_and(|level| level > 7) {
+            return Err(invalid());
+        }
+        // 4 is the default of the kernel
+        Ok(match (class, level) {
+            ("realtime" | "1", _) => IoPriority::Realtime(level.unwrap_or(4)),
+            ("best-effort" | "2", _) => IoPriority::BestEffort(level.unwrap_or(4)),
+            ("idle" | "3", None) => IoPriority::Idle,
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+/// Parse an environment variable given as `KEY=VALUE`.
+pub fn parse_env(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => bail!("Invalid environment variable '{}', expected KEY=VALUE", s),
+    }
+}
+
+/// Read environment variables from a file with one `KEY=VALUE` per line.
+/// Empty lines and lines starting with `#` are skipped, quotes around a
+/// value are removed.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Can not read '{}': {}", path.display(), e))?;
+    let mut env = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) =
+            parse_env(line).map_err(|e| anyhow!("{}:{}: {}", path.display(), i + 1, e))?;
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&q| value.strip_prefix(q)?.strip_suffix(q))
+            .map(str::to_string)
+            .unwrap_or(value);
+        env.push((key, value));
+    }
+    Ok(env)
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::IoPriority;
+    use anyhow::{bail, Result};
+    use std::ffi::{CStr, CString};
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    pub fn new_session(cmd: &mut Command) {
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// An account from the user database.
+    struct User {
+        name: String,
+        uid: libc::uid_t,
+        gid: libc::gid_t,
+        home: String,
+        groups: Vec<libc::gid_t>,
+    }
+
+    impl User {
+        /// Look up the user by name or UID.
+        fn lookup(user: &str) -> Result<User> {
+            let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut result: *mut libc::passwd = std::ptr::null_mut();
+            let mut buf = vec![0 as libc::c_char; 16 * 1024];
+            let err = match user.parse::<libc::uid_t>() {
+                Ok(uid) => unsafe {
+                    libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+                },
+                Err(_) => {
+                    let name = CString::new(user)?;
+                    unsafe {
+                        libc::getpwnam_r(
+                            name.as_ptr(),
+                            &mut passwd,
+                            buf.as_mut_ptr(),
+                            buf.len(),
+                            &mut result,
+                        )
+                    }
+                }
+            };
+            if err != 0 || result.is_null() {
+                bail!("Unknown user '{}'", user);
+            }
+            let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+            let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+            // supplementary groups like initgroups() sets them
+            let mut groups = vec![0 as libc::gid_t; 256];
+            let mut count = groups.len() as libc::c_int;
+            #[cfg(not(target_os = "macos"))]
+            let found = unsafe {
+                libc::getgrouplist(
+                    passwd.pw_name,
+                    passwd.pw_gid,
+                    groups.as_mut_ptr(),
+                    &mut count,
+                )
+            };
+            #[cfg(target_os = "macos")]
+            let found = unsafe {
+                libc::getgrouplist(
+                    passwd.pw_name,
+                    passwd.pw_gid as libc::c_int,
+                    groups.as_mut_ptr() as *mut libc::c_int,
+                    &mut count,
+                )
+            };
+            if found < 0 {
+                bail!("User '{}' is in too many groups", user);
+            }
+            groups.truncate(count as usize);
+            Ok(User {
+                name: name.to_string_lossy().into_owned(),
+                uid: passwd.pw_uid,
+                gid: passwd.pw_gid,
+                home: home.to_string_lossy().into_owned(),
+                groups,
+            })
+        }
+    }
+
+    /// Pin the command to the CPUs.
+    #[cfg(target_os = "linux")]
+    pub fn pin(cmd: &mut Command, cpus: &[usize]) -> Result<()> {
+        let size = std::mem::size_of::<libc::cpu_set_t>();
+        let mut available: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        if unsafe { libc::sched_getaffinity(0, size, &mut available) } != 0 {
+            bail!("Can not read the available CPUs");
+        }
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for &cpu in cpus {
+            if cpu >= libc::CPU_SETSIZE as usize || !unsafe { libc::CPU_ISSET(cpu, &available) } {
+                bail!("CPU {} is not available", cpu);
+            }
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::sched_setaffinity(0, size, &set) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn pin(_cmd: &mut Command, _cpus: &[usize]) -> Result<()> {
+        bail!("Pinning the command to CPUs is only supported on linux")
+    }
+
+    /// Set the niceness of the command.
+    pub fn nice(cmd: &mut Command, nice: i32) -> Result<()> {
+        if !(-20..=19).contains(&nice) {
+            bail!("Niceness {} is out of range, expected -20 to 19", nice);
+        }
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Set the I/O scheduling class of the command.
+    #[cfg(target_os = "linux")]
+    pub fn ionice(cmd: &mut Command, priority: IoPriority) -> Result<()> {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        let (class, level) = match priority {
+            IoPriority::Realtime(level) => (1, level),
+            IoPriority::BestEffort(level) => (2, level),
+            IoPriority::Idle => (3, 0),
+        };
+        let value = class << IOPRIO_CLASS_SHIFT | libc::c_int::from(level);
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, value) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn ionice(_cmd: &mut Command, _priority: IoPriority) -> Result<()> {
+        bail!("Setting the I/O priority is only supported on linux")
+    }
+
+    /// Drop the privileges of the command to those of the user, procrec
+    /// keeps its own to sample it.
+    pub fn run_as(cmd: &mut Command, user: &str) -> Result<()> {
+        if unsafe { libc::geteuid() } != 0 {
+            bail!("Running the command as another user requires procrec to run as root");
+        }
+        let user = User::lookup(user)?;
+        // like su, unless set explicitly
+        for (key, value) in [
+            ("HOME", &user.home),
+            ("USER", &user.name),
+            ("LOGNAME", &user.name),
+        ] {
+            if !cmd.get_envs().any(|(k, _)| k == key) {
+                cmd.env(key, value);
+            }
+        }
+        let User {
+            uid, gid, groups, ..
+        } = user;
+        // the groups have to be set while still privileged, before the UID
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setgroups(groups.len() as _, groups.as_ptr()) != 0
+                    || libc::setgid(gid) != 0
+                    || libc::setuid(uid) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod unix {
+    use super::IoPriority;
+    use anyhow::{bail, Result};
+    use std::process::Command;
+
+    /// Children do not share the console signals with procrec anyway.
+    pub fn new_session(_cmd: &mut Command) {}
+
+    pub fn run_as(_cmd: &mut Command, _user: &str) -> Result<()> {
+        bail!("Running the command as another user is only supported on unix")
+    }
+
+    pub fn pin(_cmd: &mut Command, _cpus: &[usize]) -> Result<()> {
+        bail!("Pinning the command to CPUs is only supported on linux")
+    }
+
+    pub fn nice(_cmd: &mut Command, _nice: i32) -> Result<()> {
+        bail!("Setting the niceness is only supported on unix")
+    }
+
+    pub fn ionice(_cmd: &mut Command, _priority: IoPriority) -> Result<()> {
+        bail!("Setting the I/O priority is only supported on linux")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpuset_lists_and_ranges() {
+        assert_eq!("3".parse::<CpuSet>().unwrap(), CpuSet(vec![3]));
+        assert_eq!(
+            "0-3,6".parse::<CpuSet>().unwrap(),
+            CpuSet(vec![0, 1, 2, 3, 6])
+        );
+        // sorted and without duplicates
+        assert_eq!(
+            "6, 2-3 ,3".parse::<CpuSet>().unwrap(),
+            CpuSet(vec![2, 3, 6])
+        );
+    }
+
+    #[test]
+    fn cpuset_invalid() {
+        for s in ["", "a", "1,", "3-1", "1-", "-1", "0-3;6"] {
+            assert!(s.parse::<CpuSet>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn io_priority_classes() {
+        let parse = |s: &str| s.parse::<IoPriority>().unwrap();
+        assert_eq!(parse("idle"), IoPriority::Idle);
+        assert_eq!(parse("3"), IoPriority::Idle);
+        // the default level of the kernel
+        assert_eq!(parse("best-effort"), IoPriority::BestEffort(4));
+        assert_eq!(parse("best-effort:7"), IoPriority::BestEffort(7));
+        assert_eq!(parse("2:0"), IoPriority::BestEffort(0));
+        assert_eq!(parse("realtime"), IoPriority::Realtime(4));
+        assert_eq!(parse("1:2"), IoPriority::Realtime(2));
+    }
+
+    #[test]
+    fn io_priority_invalid() {
+        for s in [
+            "",
+            "fast",
+            "idle:1",
+            "best-effort:8",
+            "realtime:",
+            "2:-1",
+            "0",
+        ] {
+            assert!(s.parse::<IoPriority>().is_err(), "{:?} parsed", s);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Heuristic detection of memory leaks from a steadily growing RSS.
+
+use crate::output::Sink;
+use crate::{Recording, Sample};
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+/// Minimum number of samples to fit a trend to
+const MIN_SAMPLES: usize = 5;
+/// Minimum coefficient of determination of the linear trend, a leak grows
+/// steadily
+const MIN_FIT: f64 = 0.9;
+/// Minimum growth in kB per hour, ignores tiny drifts like caches warming up
+const MIN_RATE: f64 = 1000.0;
+
+/// RSS of a process growing steadily.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leak {
+    pub pid: u32,
+    /// Estimated growth in kB per hour
+    pub rate: f64,
+    /// Coefficient of determination of the linear trend, 1 for a perfect fit
+    pub fit: f64,
+    /// Time span of the samples the trend is fitted to, in seconds
+    pub span: f32,
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RSS grows by {:.1} MB/h over {:.0}s (fit {:.2})",
+            self.rate / 1000.0,
+            self.span,
+            self.fit
+        )
+    }
+}
+
+/// Fit a linear trend to the RSS over time, given as seconds and kB.
+///
+/// Reports a leak if the RSS grows steadily, that is the trend rises by at
+/// least 1 MB per hour and explains at least 90% of the variance.
+pub fn fit(pid: u32, points: &[(f32, u64)]) -> Option<Leak> {
+    if points.len() < MIN_SAMPLES {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|p| p.0 as f64).sum::<f64>() / n;
+    let mean_m = points.iter().map(|p| p.1 as f64).sum::<f64>() / n;
+    let (mut cov, mut var_t, mut var_m) = (0.0, 0.0, 0.0);
+    for &(t, m) in points {
+        let (dt, dm) = (t as f64 - mean_t, m as f64 - mean_m);
+        cov += dt * dm;
+        var_t += dt * dt;
+        var_m += dm * dm;
+    }
+    if var_t == 0.0 || var_m == 0.0 {
+        return None;
+    }
+    let slope = cov / var_t;
+    let fit = cov * cov / (var_t * var_m);
+    let rate = slope * 3600.0;
+    if rate < MIN_RATE || fit < MIN_FIT {
+        return None;
+    }
+    Some(Leak {
+        pid,
+        rate,
+        fit,
+        span: points[points.len() - 1].0 - points[0].0,
+    })
+}
+
+/// Check the RSS of every process over the whole recording.
+pub fn analyze(recording: &Recording) -> Vec<Leak> {
+    let mut pids: Vec<u32> = recording.samples.iter().map(|s| s.pid).collect();
+    pids.sort_unstable();
+    pids.dedup();
+    pids.into_iter()
+        .filter_map(|pid| {
+            let points: Vec<(f32, u64)> = recording
+                .samples
+                .iter()
+                .filter(|s| s.pid == pid)
+                .map(|s| (s.ts, s.rss))
+                .collect();
+            fit(pid, &points)
+        })
+        .collect()
+}
+
+/// Warns while recording when the RSS of a process grew steadily over a
+/// sliding window, once until the growth stops.
+pub struct LeakDetector {
+    window: f32,
+    points: HashMap<u32, VecDeque<(f32, u64)>>,
+    warned: HashMap<u32, bool>,
+}
+
+impl LeakDetector {
+    pub fn new(window: Duration) -> Self {
+        LeakDetector {
+            window: window.as_secs_f32(),
+            points: HashMap::new(),
+            warned: HashMap::new(),
+        }
+    }
+}
+
+impl Sink for LeakDetector {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        let window = self.window;
+        let points = self.points.entry(sample.pid).or_default();
+        points.push_back((sample.ts, sample.rss));
+        // keep the last point at or before the start of the window
+        while points.len() > 1 && sample.ts - points[1].0 >= window {
+            points.pop_front();
+        }
+        // judge full windows only
+        let filled = points.front().is_some_and(|p| sample.ts - p.0 >= window);
+        let leak = if filled {
+            fit(sample.pid, points.make_contiguous())
+        } else {
+            None
+        };
+        let warned = self.warned.entry(sample.pid).or_default();
+        if let Some(leak) = leak {
+            if !*warned {
+                eprintln!(
+                    "Warning: possible memory leak in process {}, {}",
+                    sample.pid, leak
+                );
+                *warned = true;
+            }
+        } else if filled {
+            *warned = false;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Record cpu and memory usage of processes.
+//!
+//! The [`Recorder`] samples a set of [`TrackedProcess`]es in a fixed interval
+//! and collects the results in a [`Recording`]. Recordings can be written
+//! with the writers in [`output`] and plotted with [`plot`].
+//!
+//! With the `async` feature, [`Recorder::into_stream`] turns a recorder into
+//! a stream of samples driven by a tokio timer.
+
+// The processes are sampled with psutil, which only supports processes on
+// linux and macos, with the Win32 API on windows, see src/windows.rs, and
+// from the kinfo_proc of the kernel on FreeBSD and OpenBSD behind the
+// features of the same names, see src/bsd.rs.
+#[cfg(all(target_os = "freebsd", not(feature = "freebsd")))]
+compile_error!("procrec needs the freebsd feature on FreeBSD, build it with --features freebsd");
+#[cfg(all(target_os = "openbsd", not(feature = "openbsd")))]
+compile_error!("procrec needs the openbsd feature on OpenBSD, build it with --features openbsd");
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    windows,
+    target_os = "freebsd",
+    target_os = "openbsd"
+)))]
+compile_error!("procrec only supports linux, macos, windows, FreeBSD and OpenBSD yet");
+
+pub mod alert;
+pub mod assertion;
+pub mod bench;
+#[cfg(any(
+    all(target_os = "freebsd", feature = "freebsd"),
+    all(target_os = "openbsd", feature = "openbsd")
+))]
+mod bsd;
+mod capture;
+pub mod cgroup;
+pub mod check;
+pub mod condition;
+pub mod config;
+pub mod control;
+pub mod correlation;
+pub mod counters;
+pub mod daemon;
+#[cfg(target_os = "macos")]
+mod darwin;
+pub mod diff;
+#[cfg(all(feature = "ebpf", target_os = "linux"))]
+mod ebpf;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod hang;
+pub mod histogram;
+pub mod hooks;
+#[cfg(feature = "network")]
+mod http;
+pub mod launch;
+pub mod leak;
+pub mod measure;
+pub mod outlier;
+pub mod output;
+pub mod peak;
+pub mod perf;
+pub mod plot;
+mod process;
+#[cfg(unix)]
+mod pty;
+#[cfg(feature = "python")]
+mod python;
+mod raw;
+mod recorder;
+mod recording;
+mod sample;
+#[cfg(feature = "network")]
+pub mod server;
+pub mod signals;
+pub mod significance;
+pub mod smaps;
+mod spill;
+#[cfg(feature = "async")]
+mod stream;
+pub mod summary;
+pub mod threads;
+#[cfg(feature = "network")]
+pub mod upload;
+#[cfg(windows)]
+mod windows;
+
+pub use process::{Backend, TrackedProcess};
+pub use recorder::{Recorder, RecorderBuilder, RecorderOptions};
+pub use recording::{CpuTime, Metadata, ProcessInfo, Recording};
+pub use sample::{Event, Metric, Sample, ThreadSample};
+#[cfg(feature = "async")]
+pub use stream::SampleStream;
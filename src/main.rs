@@ -15,33 +15,329 @@
 // this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use anyhow::{anyhow, bail, Result};
-use clap::{crate_authors, crate_version, Clap};
-use ctrlc;
-use psutil::process::Process;
-use std::convert::TryFrom;
-use std::fmt;
+use clap::{
+    crate_authors, crate_version, App, ArgGroup, ArgMatches, ArgSettings, Clap, ErrorKind,
+    FromArgMatches, IntoApp,
+};
+use clap_generate::generate;
+use clap_generate::generators::{Bash, Fish, Zsh};
+use procrec::alert::Alert;
+use procrec::assertion::Assertion;
+use procrec::bench::{self, Comparison, Run};
+use procrec::check::Report;
+use procrec::condition::{parse_duration, parse_memory, Condition};
+use procrec::config::{self, Config, OptionKind, Value};
+use procrec::control::{self, ControlSocket};
+use procrec::correlation;
+use procrec::daemon::{self, PidFile};
+#[cfg(feature = "network")]
+use procrec::export::grafana::GrafanaLive;
+#[cfg(feature = "network")]
+use procrec::export::graphite::GraphiteExporter;
+#[cfg(feature = "network")]
+use procrec::export::influx::{InfluxConfig, InfluxExporter};
+#[cfg(feature = "network")]
+use procrec::export::mqtt::MqttPublisher;
+#[cfg(feature = "network")]
+use procrec::export::prometheus::PrometheusExporter;
+#[cfg(feature = "network")]
+use procrec::export::statsd::StatsdExporter;
+#[cfg(unix)]
+use procrec::export::syslog::{LogFormat, SystemLog};
+#[cfg(feature = "network")]
+use procrec::export::webhook::Webhook;
+use procrec::histogram::{self, Histogram};
+#[cfg(unix)]
+use procrec::hooks::AlertSignal;
+use procrec::hooks::{parse_signal, AlertHook, ExecHook, Snapshot};
+use procrec::launch::{self, parse_env, CpuSet, IoPriority};
+use procrec::leak::{self, LeakDetector};
+use procrec::measure::Measure;
+use procrec::outlier::OutlierFilter;
+use procrec::output::{CsvWriter, JsonWriter, MovingAverage, Sink, TextWriter};
+use procrec::perf::PerfEvent;
+use procrec::plot::{self, AxisRange, PlotData, PlotOptions, Theme};
+use procrec::signals;
+use procrec::summary::{self, PhaseTable, RunSummary, Summary};
+#[cfg(feature = "network")]
+use procrec::upload::S3Location;
+use procrec::{Backend, Event, Metric, Recorder, RecorderBuilder, Recording, Sample};
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
 use std::io::{self, Write};
-use std::ops::Deref;
-use std::process::Command;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::SystemTime;
-use std::{thread, time};
-use tempfile::NamedTempFile;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
 /// Process recorder to log cpu utilization and memory consumption.
 #[derive(Clap)]
 #[clap(version = crate_version!(), author = crate_authors!())]
+#[clap(group = ArgGroup::new("alerts").multiple(true))]
 struct Opts {
-    /// Sampling interval in seconds
+    /// Sampling interval in seconds, e.g. '0.02' for 50 samples per second
     #[clap(short = 'i', long = "interval", default_value = "2")]
-    interval: u64,
+    interval: f64,
+    /// Read defaults of the options from this TOML file, options given on the command line take precedence [default: $XDG_CONFIG_HOME/procrec/config.toml]
+    #[clap(long = "config", value_name = "FILE", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Use the options of the profile, the table [profile.NAME] of the config file
+    #[clap(long = "profile", value_name = "NAME")]
+    profile: Option<String>,
+    /// Source of the CPU and memory usage: psutil (the Win32 API on windows, the kinfo_proc of the kernel on FreeBSD and OpenBSD), raw for reading /proc directly with less overhead at high sampling rates, or ebpf for the CPU time accounted by the scheduler and the counters accounted in the kernel (both linux only, ebpf requires root and the ebpf feature)
+    #[clap(long = "backend", default_value = "psutil", possible_values = &["psutil", "raw", "ebpf"])]
+    backend: Backend,
     /// Duration for observation
     #[clap(short = 'd', long = "duration")]
     duration: Option<u64>,
-    /// Process to be inspected. If omitted, a command to execute must be given.
-    #[clap(short = 'p', long = "pid", conflicts_with = "command")]
-    pid: Option<u32>,
+    /// Terminate the launched command with the stop signal if it runs longer, e.g. '90s' or '10m'. It is killed if still running after the grace period.
+    #[clap(long = "timeout", parse(try_from_str = parse_duration))]
+    timeout: Option<Duration>,
+    /// Signal asking the launched command to shut down on timeout or at the end of the recording, and processes breaching --kill-if
+    #[clap(long = "stop-signal", value_name = "SIGNAL", default_value = "SIGTERM", parse(try_from_str = parse_signal))]
+    stop_signal: i32,
+    /// Time a process gets to exit after the stop signal before it is killed, e.g. '30s'
+    #[clap(long = "stop-grace", value_name = "DURATION", default_value = "10s", parse(try_from_str = parse_duration))]
+    stop_grace: Duration,
+    /// Launch the command again when it fails, at most N times or without limit if N is omitted
+    #[clap(long = "restart-on-failure", value_name = "N", conflicts_with = "pid")]
+    restart_on_failure: Option<Option<u32>>,
+    /// Working directory of the launched command
+    #[clap(long = "cwd", parse(from_os_str), conflicts_with = "pid")]
+    cwd: Option<PathBuf>,
+    /// Run the launched command as this user (name or UID) with its groups, procrec itself has to run as root and keeps sampling it
+    #[clap(long = "as-user", value_name = "USER", conflicts_with = "pid")]
+    as_user: Option<String>,
+    /// Pin the launched command to these CPUs, e.g. '0-3' or '0,2' (linux only)
+    #[clap(long = "cpuset", value_name = "CPUS", conflicts_with = "pid")]
+    cpuset: Option<CpuSet>,
+    /// Niceness of the launched command from -20 (highest priority, requires root) to 19
+    #[clap(
+        long = "nice",
+        value_name = "N",
+        allow_hyphen_values = true,
+        conflicts_with = "pid"
+    )]
+    nice: Option<i32>,
+    /// I/O scheduling class of the launched command: idle, best-effort or realtime (requires root), the latter two optionally with a priority like 'best-effort:7' (linux only)
+    #[clap(long = "ionice", value_name = "CLASS", conflicts_with = "pid")]
+    ionice: Option<IoPriority>,
+    /// Limit the memory of the launched command, e.g. '2G', in a fresh cgroup (linux only, requires root)
+    #[clap(long = "limit-mem", value_name = "SIZE", conflicts_with = "pid", parse(try_from_str = parse_memory))]
+    limit_mem: Option<u64>,
+    /// Limit the launched command to the time of this many CPUs, e.g. '2' or '0.5', in a fresh cgroup (linux only, requires root)
+    #[clap(long = "limit-cpu", value_name = "CPUS", conflicts_with = "pid")]
+    limit_cpu: Option<f64>,
+    /// Launch the command in a fresh cgroup and record its CPU time and memory use, which covers every process it forks (linux only, requires root)
+    #[clap(long = "isolate-cgroup", conflicts_with = "pid")]
+    isolate_cgroup: bool,
+    /// Environment variable of the launched command, can be given multiple times
+    #[clap(long = "env", value_name = "KEY=VALUE", multiple_occurrences = true, conflicts_with = "pid", parse(try_from_str = parse_env))]
+    env: Vec<(String, String)>,
+    /// Read environment variables of the launched command from a file with one KEY=VALUE per line, --env takes precedence
+    #[clap(long = "env-file", parse(from_os_str), conflicts_with = "pid")]
+    env_file: Option<PathBuf>,
+    /// Write the stdout and stderr of the launched command to files named after it and its start time in this directory instead of the terminal
+    #[clap(
+        long = "capture-output",
+        value_name = "DIR",
+        parse(from_os_str),
+        conflicts_with = "pid"
+    )]
+    capture_output: Option<PathBuf>,
+    /// Signals passed on to the launched command. After SIGINT (Ctrl-C) or SIGTERM procrec records until the command exited and kills it after the grace period, a second one stops recording right away.
+    #[clap(long = "forward-signals", value_name = "SIGNALS", use_delimiter = true, default_value = "SIGINT,SIGTERM", parse(try_from_str = parse_signal))]
+    forward_signals: Vec<i32>,
+    /// Stop the recording on Ctrl-C and kill the launched command instead of passing the signals on
+    #[clap(long = "no-forward-signals")]
+    no_forward_signals: bool,
+    /// Leave the launched command running when the recording is stopped, e.g. by Ctrl-C, instead of killing it. It is started in a session of its own (unix only)
+    #[clap(long = "detach", alias = "no-kill", conflicts_with_all = &["pid", "pty", "output-events"])]
+    detach: bool,
+    /// Run the launched command in a pseudo terminal for progress bars and interactive prompts, the terminal of procrec is passed through (unix only)
+    #[clap(long = "pty", conflicts_with = "pid")]
+    pty: bool,
+    /// Record every line the launched command writes as event with its time, drawn as annotation in the graph
+    #[clap(long = "output-events", conflicts_with = "pid")]
+    output_events: bool,
+    /// Process to be inspected, can be given multiple times. If omitted, a command to execute must be given.
+    #[clap(
+        short = 'p',
+        long = "pid",
+        multiple_occurrences = true,
+        use_delimiter = true,
+        conflicts_with = "command"
+    )]
+    pid: Vec<u32>,
+    /// Record the breakdown of resident memory into heap, stack, file-backed and anonymous (linux only)
+    #[clap(long = "smaps")]
+    smaps: bool,
+    /// Record the bytes read and written and the page faults (linux only), their totals and rates are part of the --summary
+    #[clap(long = "counters")]
+    counters: bool,
+    /// Count performance events on the processes, e.g. 'instructions,cycles,cache-misses,branch-misses', recorded as increase per sample (linux only), instructions with cycles give the IPC in the --summary
+    #[clap(long = "perf", value_name = "EVENTS", use_delimiter = true)]
+    perf: Vec<PerfEvent>,
+    /// Keep at most this many samples in memory and move older ones to a temporary file until the recording ended, so long recordings don't grow procrec; 0 keeps all in memory. --save, the output, --summary and the graph read them from there, --graph-live shows the samples in memory and --filter-outliers, --histogram, --assert, --measure, --correlate and --detect-leaks load them back.
+    #[clap(long = "memory-samples", value_name = "N", default_value = "100000")]
+    memory_samples: usize,
+    /// Divide the CPU utilization by the number of CPUs available to procrec (affinity and cgroup quota), so 100% means all of them are busy
+    #[clap(long = "cpu-normalize")]
+    cpu_normalize: bool,
+    /// Record the CPU utilization of every thread (linux only)
+    #[clap(long = "threads")]
+    threads: bool,
+    /// Record the child processes as well, marking forks, execs and exits in the recording
+    #[clap(long = "follow-children")]
+    follow_children: bool,
+    /// Additional output as FORMAT[:PATH] with the formats text, csv and json, can be given multiple times. Without a path or with '-' stdout is used.
+    #[clap(short = 'o', long = "output", multiple_occurrences = true)]
+    output: Vec<OutputSpec>,
+    /// Append a simple moving average over this number of samples as additional columns to the csv and json outputs
+    #[clap(long = "sma", value_name = "N")]
+    sma: Option<usize>,
+    /// Metrics to average for --sma
+    #[clap(long = "sma-metrics", use_delimiter = true, default_value = "cpu")]
+    sma_metrics: Vec<Metric>,
+    /// Serve the latest values at /metrics for Prometheus while recording, e.g. ':9555'
+    #[cfg(feature = "network")]
+    #[clap(long = "prometheus")]
+    prometheus: Option<String>,
+    /// Push every sample as StatsD gauges to HOST:PORT, e.g. 'localhost:8125'
+    #[cfg(feature = "network")]
+    #[clap(long = "statsd")]
+    statsd: Option<String>,
+    /// Prefix of the StatsD metric names
+    #[cfg(feature = "network")]
+    #[clap(long = "statsd-prefix", default_value = "procrec")]
+    statsd_prefix: String,
+    /// Additional StatsD tags as key:value, separated by commas
+    #[cfg(feature = "network")]
+    #[clap(long = "statsd-tags", use_delimiter = true)]
+    statsd_tags: Vec<String>,
+    /// Stream the samples to Graphite at HOST:PORT, e.g. 'localhost:2003'
+    #[cfg(feature = "network")]
+    #[clap(long = "graphite")]
+    graphite: Option<String>,
+    /// Prefix of the Graphite metric paths
+    #[cfg(feature = "network")]
+    #[clap(long = "graphite-prefix", default_value = "procrec")]
+    graphite_prefix: String,
+    /// Publish every sample as JSON to the MQTT broker at HOST[:PORT]
+    #[cfg(feature = "network")]
+    #[clap(long = "mqtt", requires = "topic")]
+    mqtt: Option<String>,
+    /// MQTT topic to publish the samples to, e.g. 'procrec/myjob'
+    #[cfg(feature = "network")]
+    #[clap(long = "topic")]
+    topic: Option<String>,
+    /// Write the samples to the InfluxDB v2 at this URL, e.g. 'http://localhost:8086'
+    #[cfg(feature = "network")]
+    #[clap(long = "influx-url", requires_all = &["influx-token", "influx-org", "influx-bucket"])]
+    influx_url: Option<String>,
+    /// API token for --influx-url
+    #[cfg(feature = "network")]
+    #[clap(long = "influx-token")]
+    influx_token: Option<String>,
+    /// Organization of the InfluxDB bucket
+    #[cfg(feature = "network")]
+    #[clap(long = "influx-org")]
+    influx_org: Option<String>,
+    /// InfluxDB bucket to write the samples to
+    #[cfg(feature = "network")]
+    #[clap(long = "influx-bucket")]
+    influx_bucket: Option<String>,
+    /// Push the samples to a Grafana Live endpoint for real-time dashboards, e.g. 'ws://localhost:3000/api/live/push/procrec'
+    #[cfg(feature = "network")]
+    #[clap(long = "grafana-live")]
+    grafana_live: Option<String>,
+    /// Service account token for --grafana-live
+    #[cfg(feature = "network")]
+    #[clap(long = "grafana-token")]
+    grafana_token: Option<String>,
+    /// POST a JSON notification to this URL on events, like a process exiting, and when the recording completed
+    #[cfg(feature = "network")]
+    #[clap(long = "webhook")]
+    webhook: Option<String>,
+    /// Log samples and events as structured entries to the system log (journald, syslog)
+    #[cfg(unix)]
+    #[clap(long = "log-to")]
+    log_to: Option<LogFormat>,
+    /// Insert the samples into Postgres, e.g. 'host=localhost user=procrec dbname=perf'
+    #[cfg(feature = "postgres")]
+    #[clap(long = "pg-dsn")]
+    pg_dsn: Option<String>,
+    /// Table for --pg-dsn, created if missing
+    #[cfg(feature = "postgres")]
+    #[clap(long = "pg-table", default_value = "procrec_samples")]
+    pg_table: String,
+    /// Stream the samples to gRPC clients connecting to this address, e.g. ':50051'
+    #[cfg(feature = "grpc")]
+    #[clap(long = "grpc")]
+    grpc: Option<String>,
+    /// Accept the commands pause, resume, marker <label> and stop on this unix socket, e.g. $XDG_RUNTIME_DIR/procrec.sock
+    #[clap(long = "control-socket", parse(from_os_str))]
+    control_socket: Option<PathBuf>,
+    /// Run in the background, e.g. for recordings over days. 'procrec stop' and 'procrec status' reach it over the --control-socket, which defaults to $XDG_RUNTIME_DIR/procrec.sock.
+    #[clap(long = "daemonize", conflicts_with_all = &["graph", "graph-live", "pty"])]
+    daemonize: bool,
+    /// Write the PID of the background procrec to this file, it is removed at the end of the recording
+    #[clap(long = "pidfile", parse(from_os_str), requires = "daemonize")]
+    pidfile: Option<PathBuf>,
+    /// Append the output of the background procrec to this file instead of discarding it
+    #[clap(long = "log-file", parse(from_os_str), requires = "daemonize")]
+    log_file: Option<PathBuf>,
+    /// Add a marker labeled with the content of this file whenever SIGHUP is received
+    #[clap(long = "marker-file", parse(from_os_str))]
+    marker_file: Option<PathBuf>,
+    /// Shell command to run for every new sample, which is passed in the PROCREC_TS, PROCREC_PID, PROCREC_CPU, PROCREC_RSS and PROCREC_VSIZE environment variables
+    #[clap(long = "exec-on-sample")]
+    exec_on_sample: Option<String>,
+    /// Alert when a condition is breached, e.g. 'rss>1.5G' or 'cpu>90 for 30s', can be given multiple times. Alerts are printed, marked in the recording and posted to --webhook.
+    #[clap(long = "alert", multiple_occurrences = true, group = "alerts")]
+    alert: Vec<Condition>,
+    /// Alert when a process shows no CPU utilization and no I/O for this period while still alive, e.g. '2m' to catch deadlocked workers
+    #[clap(long = "hang-after", group = "alerts", parse(try_from_str = parse_duration))]
+    hang_after: Option<Duration>,
+    /// Shell command to run when an alert fires, with the placeholders {pid}, {metric}, {value}, {ts} and {condition}, e.g. 'gcore -o core {pid}'
+    #[clap(long = "on-alert", requires = "alerts")]
+    on_alert: Option<String>,
+    /// Save the state of the process from /proc (status, smaps, stack, limits, open files) to this directory when an alert fires
+    #[clap(long = "snapshot-dir", requires = "alerts", parse(from_os_str))]
+    snapshot_dir: Option<PathBuf>,
+    /// Send this signal to the process when an alert fires, e.g. SIGUSR2 to trigger a heap dump of the application
+    #[cfg(unix)]
+    #[clap(long = "signal-on-alert", requires = "alerts", parse(try_from_str = parse_signal))]
+    signal_on_alert: Option<i32>,
+    /// Terminate a process breaching the condition with the stop signal, e.g. 'rss>4G', can be given multiple times. It is killed if still running after the grace period.
+    #[clap(long = "kill-if", multiple_occurrences = true)]
+    kill_if: Vec<Condition>,
+    /// End the recording once a process fulfills the condition, e.g. 'cpu<1 for 60s' after the busy phase of a job
+    #[clap(long = "stop-when", multiple_occurrences = true)]
+    stop_when: Vec<Condition>,
+    /// Keep only the samples while the condition holds, e.g. 'cpu>50', to record just the bursts of long-running services
+    #[clap(long = "capture-when")]
+    capture_when: Option<Condition>,
+    /// Seconds of samples kept before the condition of --capture-when holds
+    #[clap(long = "capture-pre", default_value = "10")]
+    capture_pre: u64,
+    /// Seconds of samples kept after the condition of --capture-when stopped holding
+    #[clap(long = "capture-post", default_value = "10")]
+    capture_post: u64,
+    /// Warn about steadily growing memory over the --leak-window while recording and over the whole recording afterwards
+    #[clap(long = "detect-leaks")]
+    detect_leaks: bool,
+    /// Seconds of samples checked for a leak while recording
+    #[clap(long = "leak-window", default_value = "300")]
+    leak_window: u64,
+    /// Sample once and check the conditions, e.g. 'rss<2G,cpu<90', exiting as monitoring plugin with OK (0) or CRITICAL (2) and performance data
+    #[clap(long = "check", use_delimiter = true)]
+    check: Vec<Condition>,
+    /// Conditions for --check resulting in WARNING (1) if violated
+    #[clap(long = "check-warn", use_delimiter = true)]
+    check_warn: Vec<Condition>,
     /// A level of verbosity, and can be used multiple times
     #[clap(short = 'v', long = "verbose", parse(from_occurrences))]
     verbose: i32,
@@ -49,242 +345,1315 @@ struct Opts {
     /// Display graph using gnuplot
     #[clap(short = 'g', long = "graph")]
     graph: bool,
+    /// Display graph using gnuplot and refresh it while recording
+    #[clap(long = "graph-live")]
+    graph_live: bool,
+    #[clap(flatten)]
+    plot: PlotArgs,
+    /// Save the recording to a file, which can be plotted again later
+    #[clap(long = "save", parse(from_os_str))]
+    save: Option<PathBuf>,
+    /// Upload the finished recording and the graph of --graph-output to S3, e.g. 's3://bucket/ci/', credentials are taken from the AWS_* environment variables
+    #[cfg(feature = "network")]
+    #[clap(long = "upload")]
+    upload: Option<S3Location>,
+    /// Print summary statistics (min, mean, max, standard deviation and percentiles) of every metric after recording
+    #[clap(long = "summary")]
+    summary: bool,
+    /// Number of the highest CPU and memory peaks listed by --summary and --summary-json
+    #[clap(long = "peaks", value_name = "K", default_value = "5")]
+    peaks: usize,
+    /// Print a histogram of the distribution of these metrics after recording, e.g. 'cpu', also written to --summary-json
+    #[clap(long = "histogram", use_delimiter = true)]
+    histogram: Vec<Metric>,
+    /// Report series which correlate strongly after recording, e.g. RSS growth with major page faults (with --counters)
+    #[clap(long = "correlate")]
+    correlate: bool,
+    /// Write the summary statistics, metadata of the run and all alerts as JSON to this file
+    #[clap(long = "summary-json", parse(from_os_str))]
+    summary_json: Option<PathBuf>,
+    /// Resource budget checked after recording, e.g. 'max_rss<512M' or 'p95_cpu<150', can be given multiple times. Fails if one is violated.
+    #[clap(long = "assert", multiple_occurrences = true)]
+    assert: Vec<Assertion>,
+    /// Report a measurement after recording, e.g. 'time_until rss>1G' for the time until the condition first held, can be given multiple times
+    #[clap(long = "measure", multiple_occurrences = true)]
+    measure: Vec<Measure>,
+    /// Save the summary statistics as JSON, to be used as --baseline of later runs
+    #[clap(long = "save-baseline", parse(from_os_str))]
+    save_baseline: Option<PathBuf>,
+    /// Compare the summary statistics with a baseline saved by --save-baseline and fail on a regression
+    #[clap(long = "baseline", parse(from_os_str))]
+    baseline: Option<PathBuf>,
+    /// Increase of a mean or maximum over the --baseline considered a regression, e.g. '10%'
+    #[clap(long = "fail-threshold", default_value = "10%", parse(try_from_str = parse_percent))]
+    fail_threshold: f64,
     /// Just print gnuplot script
     #[clap(short = 't', long = "print-gnuplot")]
     script_dump: bool,
 
+    /// Run this command line under 'sh -c' and record the shell with all processes it starts, e.g. for pipelines and redirections
+    #[clap(short = 'c', long = "shell", value_name = "COMMAND", conflicts_with_all = &["pid", "command"])]
+    shell: Option<String>,
     /// The command to execute and record. If omitted, then --pid must be provided.
     #[clap(index = 1, multiple = true, conflicts_with = "pid")]
     command: Vec<String>,
+
+    #[clap(subcommand)]
+    subcmd: Option<SubCommand>,
+}
+
+// The structs of the subcommands have plain comments, clap takes a doc
+// comment on them as the about text of the whole application instead of the
+// doc comments of the variants here.
+#[derive(Clap)]
+enum SubCommand {
+    /// Plot a previously saved recording
+    Plot(PlotCmd),
+    /// Serve an HTTP API to start and stop recordings remotely
+    #[cfg(feature = "network")]
+    Serve(ServeCmd),
+    /// Add a marker to a recording running with --control-socket
+    Mark(MarkCmd),
+    /// End a recording running with --control-socket or --daemonize and wait until it finished
+    Stop(SocketArgs),
+    /// Print whether a recording runs with --control-socket or --daemonize, exits with 3 if not
+    Status(SocketArgs),
+    /// Compare two recordings, e.g. before and after a change
+    Diff(DiffCmd),
+    /// Run a command several times and report statistics over the runs
+    Bench(BenchCmd),
+    /// Cut a saved recording to a time window and optionally downsample it
+    Trim(TrimCmd),
+    /// Print the completion script for the shell, e.g. to /etc/bash_completion.d/procrec
+    Completions(CompletionsCmd),
+}
+
+// Benchmark a command by recording repeated runs.
+#[derive(Clap)]
+struct BenchCmd {
+    /// Number of runs
+    #[clap(short = 'n', long = "runs", default_value = "10")]
+    runs: u32,
+    /// Count the first runs as cold, e.g. with empty caches, and report them
+    /// separately from the warm ones
+    #[clap(long = "cold", default_value = "0")]
+    cold: u32,
+    /// Sampling interval in seconds, also the resolution of the wall time
+    #[clap(short = 'i', long = "interval", default_value = "0.05")]
+    interval: f64,
+    /// Test the runs for significant differences to the runs saved to this
+    /// directory by an earlier 'bench -o'
+    #[clap(long = "against", parse(from_os_str))]
+    against: Option<PathBuf>,
+    /// Save the recording of every run to this directory as run-N.rec
+    #[clap(short = 'o', long = "output-dir", parse(from_os_str))]
+    output_dir: Option<PathBuf>,
+    /// The command to benchmark
+    #[clap(required = true, multiple = true)]
+    command: Vec<String>,
 }
 
-#[derive(Debug)]
-struct Sample {
-    ts: f32,
-    pid: u32,
-    //num_threads: u64, // currently not supported in psutil crate
-    cpu: f32,
-    vsize: u64,
-    rss: u64,
+// Compare the statistics and curves of two saved recordings.
+#[derive(Clap)]
+struct DiffCmd {
+    /// Recording before the change, or a directory of runs saved by 'bench -o'
+    #[clap(parse(from_os_str))]
+    before: PathBuf,
+    /// Recording after the change, or a directory of runs saved by 'bench -o'
+    #[clap(parse(from_os_str))]
+    after: PathBuf,
+    /// Plot both recordings in one graph, aligned by their relative time
+    #[clap(short = 'g', long = "graph")]
+    graph: bool,
+    #[clap(flatten)]
+    plot: PlotArgs,
+}
+
+// Cut a saved recording to a time window.
+#[derive(Clap)]
+struct TrimCmd {
+    /// Recording to trim
+    #[clap(parse(from_os_str))]
+    input: PathBuf,
+    /// Start of the window, e.g. '30s', the trimmed recording starts here
+    #[clap(long = "from", parse(try_from_str = parse_duration))]
+    from: Option<Duration>,
+    /// End of the window, e.g. '5m' [default: end of the recording]
+    #[clap(long = "to", parse(try_from_str = parse_duration))]
+    to: Option<Duration>,
+    /// Keep only samples at least this far apart, e.g. '10s'
+    #[clap(long = "downsample", parse(try_from_str = parse_duration))]
+    downsample: Option<Duration>,
+    /// File to save the trimmed recording to
+    #[clap(short = 'o', long = "output", parse(from_os_str))]
+    output: PathBuf,
 }
 
-impl fmt::Display for Sample {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{:.02} PID {} CPU% {:.02} RSS {} VSIZE {} ",
-            self.ts, self.pid, self.cpu, self.rss, self.vsize
-        )
+// Send a marker to the control socket of a running recording.
+#[derive(Clap)]
+struct MarkCmd {
+    /// Label of the marker, e.g. 'started load test'
+    #[clap(required = true)]
+    label: Vec<String>,
+    /// Control socket of the recording [default: $XDG_RUNTIME_DIR/procrec.sock]
+    #[clap(short = 's', long = "socket", parse(from_os_str))]
+    socket: Option<PathBuf>,
+}
+
+// Generate shell completions.
+#[derive(Clap)]
+struct CompletionsCmd {
+    /// Shell to complete for
+    #[clap(possible_values = &["bash", "zsh", "fish"])]
+    shell: Shell,
+}
+
+/// Shells to generate completions for.
+#[derive(Debug, Clone, Copy)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => bail!("Unknown shell '{}' (bash, zsh, fish)", s),
+        }
     }
 }
 
-/// Define a struct to carry the information about the process
-/// to track. The process can be either external or internal.
-///
-/// This enum dereferences to the psutil::Process to gather information
-/// about system usage.
-pub enum TrackedProcess {
-    /// An external process was started outside of this program and
-    /// submitted using the --pid parameter.
-    External(Process),
-    /// An internal process is started by procrec as a fork and requires
-    /// joining the forked process.
-    Internal(Process, std::process::Child),
-}
-
-impl<'a> TryFrom<&'a Opts> for TrackedProcess {
-    type Error = anyhow::Error;
-
-    fn try_from(opts: &'a Opts) -> Result<Self, Self::Error> {
-        match opts.pid {
-            Some(pid) => match Process::new(pid) {
-                Ok(p) => Ok(TrackedProcess::External(p)),
-                Err(e) => Err(anyhow!("Failed accessing process: {}", e)),
-            },
-            None => {
-                let cl = &opts.command;
-                if cl.len() == 0 {
-                    bail!("Process to record must be provided as additional argument or via '--pid' parameter. For detailed information, execute with --help");
-                }
+// Control socket of a running recording, shared by stop and status
+#[derive(Clap)]
+struct SocketArgs {
+    /// Control socket of the recording [default: $XDG_RUNTIME_DIR/procrec.sock]
+    #[clap(short = 's', long = "socket", parse(from_os_str))]
+    socket: Option<PathBuf>,
+}
 
-                // Create the command line for the process to be executed
-                let mut cmd = Command::new(cl[0].clone());
-                if cl.len() > 1 {
-                    cmd.args(&cl[1..]);
-                }
+impl SocketArgs {
+    fn path(&self) -> PathBuf {
+        self.socket
+            .clone()
+            .unwrap_or_else(control::default_socket_path)
+    }
+}
 
-                match cmd.spawn() {
-                    Ok(c) => match Process::new(c.id()) {
-                        Ok(p) => Ok(TrackedProcess::Internal(p, c)),
-                        Err(e) => Err(anyhow!("Failed access created process: {}", e)),
-                    },
-                    Err(e) => {
-                        bail!("Can not execute command: {}", e);
-                    }
-                }
+// Control recordings over HTTP, see the server module for the API.
+#[cfg(feature = "network")]
+#[derive(Clap)]
+struct ServeCmd {
+    /// Address to listen on, e.g. ':7070' for all interfaces
+    #[clap(long = "listen", default_value = ":7070")]
+    listen: String,
+}
+
+// Plot a recording saved with --save or from the output of procrec.
+#[derive(Clap)]
+struct PlotCmd {
+    /// Recordings to plot, several recordings require --overlay
+    #[clap(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+    /// Draw several recordings in one graph, aligned by their relative time
+    #[clap(long = "overlay")]
+    overlay: bool,
+    #[clap(flatten)]
+    plot: PlotArgs,
+}
+
+// Options controlling the generated graph, shared by recording and replotting
+#[derive(Clap)]
+struct PlotArgs {
+    /// Write graph to an image file instead of opening a window (.png, .svg or .pdf)
+    #[clap(long = "graph-output", parse(from_os_str))]
+    graph_output: Option<PathBuf>,
+    /// Metrics to include in the graph (cpu, rss, vsize, heap, stack, file, anon, or the groups mem, smaps)
+    #[clap(long = "plot-metrics", use_delimiter = true, default_value = "cpu,rss")]
+    plot_metrics: Vec<String>,
+    /// Draw the memory breakdown (heap, stack, file, anon) as stacked areas
+    #[clap(long = "plot-stacked")]
+    plot_stacked: bool,
+    /// Metrics to draw on the right y-axis (default: memory if CPU is plotted)
+    #[clap(long = "plot-y2", use_delimiter = true)]
+    plot_y2: Option<Vec<Metric>>,
+    /// Range of the left y-axis as MIN:MAX, either bound may be omitted
+    #[clap(long = "plot-y1-range")]
+    plot_y1_range: Option<AxisRange>,
+    /// Range of the right y-axis as MIN:MAX, either bound may be omitted
+    #[clap(long = "plot-y2-range")]
+    plot_y2_range: Option<AxisRange>,
+    /// Metrics to plot on a logarithmic axis (cpu, mem, rss, vsize)
+    #[clap(long = "log-scale", use_delimiter = true)]
+    log_scale: Vec<String>,
+    /// Overlay a moving average over the given number of samples on the CPU graph
+    #[clap(long = "smooth")]
+    smooth: Option<usize>,
+    /// Number of busiest threads to plot in their own panel, if threads were recorded
+    #[clap(long = "plot-threads", default_value = "4")]
+    plot_threads: usize,
+    /// Color scheme of the graph (light, dark, paper)
+    #[clap(long = "plot-theme", default_value = "light")]
+    plot_theme: Theme,
+    /// Remove anomalous samples before the summary statistics and plotting, 'zscore:K' beyond K standard deviations or 'iqr:K' beyond K interquartile ranges
+    #[clap(long = "filter-outliers")]
+    filter_outliers: Option<OutlierFilter>,
+}
+
+impl PlotArgs {
+    /// Remove the outliers from the recording if enabled, which must not
+    /// have moved samples to disk.
+    fn filter(&self, recording: &mut Recording) {
+        if let Some(filter) = self.filter_outliers {
+            let removed = filter.apply(recording);
+            if !removed.is_empty() {
+                eprintln!("Removed {} outlier samples", removed.len());
             }
         }
     }
+
+    fn options(&self) -> Result<PlotOptions> {
+        Ok(PlotOptions {
+            metrics: parse_metric_groups(&self.plot_metrics)?,
+            stacked: self.plot_stacked,
+            output: self.graph_output.clone(),
+            y2_metrics: self.plot_y2.clone(),
+            y1_range: self.plot_y1_range,
+            y2_range: self.plot_y2_range,
+            theme: self.plot_theme,
+            log_scale: parse_metric_groups(&self.log_scale)?,
+            smooth: self.smooth,
+            threads: self.plot_threads,
+        })
+    }
 }
 
-impl TrackedProcess {
-    /// Wraps around the internal process.cpu_percent() because
-    /// value needs to be mutable.
-    pub fn cpu_percent(&mut self) -> psutil::process::ProcessResult<psutil::Percent> {
-        match self {
-            TrackedProcess::External(p) => p.cpu_percent(),
-            TrackedProcess::Internal(p, _) => p.cpu_percent(),
-        }
+/// Format of an output given by `--output`.
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+/// An output given as `FORMAT[:PATH]`, no path or `-` meaning stdout.
+struct OutputSpec {
+    format: OutputFormat,
+    path: Option<PathBuf>,
+}
+
+impl FromStr for OutputSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (format, path) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let format = match format {
+            "text" => OutputFormat::Text,
+            "csv" => OutputFormat::Csv,
+            "json" => OutputFormat::Json,
+            _ => bail!("Unknown output format '{}' (text, csv, json)", format),
+        };
+        let path = path.filter(|p| *p != "-").map(PathBuf::from);
+        Ok(OutputSpec { format, path })
     }
+}
 
-    /// Check if the tracked process is still running
-    pub fn is_running(&mut self) -> bool {
-        match self {
-            // For an internal process, check if we can join the child-process
-            // Unless the child-process is joined, it will be reported as "running"
-            TrackedProcess::Internal(_, ref mut c) => match c.try_wait() {
-                Err(e) => panic!("Can not check if child process can be joined: {}", e),
-                Ok(Some(_exit_status)) => false, // exit status is irrelevant for the tracking
-                Ok(None) => true,
+impl OutputSpec {
+    fn sink(&self, sma: Option<MovingAverage>) -> Result<Box<dyn Sink>> {
+        let out: Box<dyn Write + Send> = match &self.path {
+            Some(path) => Box::new(
+                File::create(path)
+                    .map_err(|e| anyhow!("Can not create output '{}': {}", path.display(), e))?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+        Ok(match self.format {
+            OutputFormat::Text => Box::new(TextWriter::new(out)),
+            OutputFormat::Csv => match sma {
+                Some(sma) => Box::new(CsvWriter::new(out).with_sma(sma)),
+                None => Box::new(CsvWriter::new(out)),
             },
-            // For external process, rely on psutils to check process status
-            TrackedProcess::External(p) => p.is_running(),
+            OutputFormat::Json => match sma {
+                Some(sma) => Box::new(JsonWriter::new(out).with_sma(sma)),
+                None => Box::new(JsonWriter::new(out)),
+            },
+        })
+    }
+}
+
+impl Opts {
+    /// The command to launch, the shell running the -c command line.
+    fn command(&self) -> Vec<String> {
+        match &self.shell {
+            Some(line) => vec!["sh".to_string(), "-c".to_string(), line.clone()],
+            None => self.command.clone(),
         }
     }
+
+    /// Whether an analysis after the recording works on all samples at
+    /// once, rather than reading them one by one.
+    fn analyzes_all_samples(&self) -> bool {
+        self.plot.filter_outliers.is_some()
+            || !self.histogram.is_empty()
+            || !self.assert.is_empty()
+            || !self.measure.is_empty()
+            || self.correlate
+            || self.detect_leaks
+    }
+
+    /// Configure the recorder from the command line options.
+    fn recorder(&self) -> Result<RecorderBuilder> {
+        // conditions on the memory breakdown require smaps
+        let smaps = self
+            .alert
+            .iter()
+            .chain(&self.kill_if)
+            .chain(&self.stop_when)
+            .chain(&self.capture_when)
+            .chain(&self.check)
+            .chain(&self.check_warn)
+            .any(|c| c.metric.is_smaps())
+            || self.assert.iter().any(|a| a.metric.is_smaps());
+        let interval = Duration::try_from_secs_f64(self.interval)
+            .ok()
+            .filter(|interval| !interval.is_zero())
+            .ok_or_else(|| anyhow!("Invalid interval {}", self.interval))?;
+        if self.backend != Backend::Psutil && !cfg!(target_os = "linux") {
+            bail!("The raw and ebpf backends are only supported on linux");
+        }
+        let mut builder = Recorder::builder()
+            .interval(interval)
+            .backend(self.backend)
+            .smaps(self.smaps || smaps)
+            .counters(self.counters)
+            .perf(self.perf.clone())
+            .memory_samples(Some(self.memory_samples).filter(|&n| n > 0))
+            .cpu_normalize(self.cpu_normalize)
+            .threads(self.threads)
+            // the processes of a pipeline are children of the shell
+            .follow_children(self.follow_children || self.shell.is_some())
+            .output_events(self.output_events)
+            .pty(self.pty)
+            .command(self.command());
+        if let Some(duration) = self.duration {
+            builder = builder.duration(Duration::from_secs(duration));
+        }
+        builder = builder
+            .stop_signal(self.stop_signal)
+            .stop_grace(self.stop_grace);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(cwd) = &self.cwd {
+            builder = builder.cwd(cwd.clone());
+        }
+        if let Some(cpus) = &self.cpuset {
+            builder = builder.cpuset(cpus.clone());
+        }
+        if let Some(nice) = self.nice {
+            builder = builder.nice(nice);
+        }
+        if let Some(priority) = self.ionice {
+            builder = builder.ionice(priority);
+        }
+        if let Some(kb) = self.limit_mem {
+            builder = builder.limit_memory(kb);
+        }
+        if let Some(cpus) = self.limit_cpu {
+            if cpus <= 0.0 {
+                bail!("--limit-cpu must be positive");
+            }
+            builder = builder.limit_cpus(cpus);
+        }
+        if self.isolate_cgroup {
+            builder = builder.isolate_cgroup(true);
+        }
+        if self.detach {
+            builder = builder.detach(true);
+        }
+        if let Some(user) = &self.as_user {
+            builder = builder.user(user.clone());
+        }
+        if let Some(path) = &self.env_file {
+            for (key, value) in launch::read_env_file(path)? {
+                builder = builder.env(key, value);
+            }
+        }
+        for (key, value) in &self.env {
+            builder = builder.env(key.clone(), value.clone());
+        }
+        if let Some(dir) = &self.capture_output {
+            builder = builder.capture_output(dir.clone());
+        }
+        if let Some(restarts) = self.restart_on_failure {
+            builder = builder.restart_on_failure(restarts.unwrap_or(u32::MAX));
+        }
+        for &pid in &self.pid {
+            builder = builder.pid(pid);
+        }
+        for condition in &self.kill_if {
+            builder = builder.kill_if(condition.clone());
+        }
+        for condition in &self.stop_when {
+            builder = builder.stop_when(condition.clone());
+        }
+        if let Some(condition) = &self.capture_when {
+            builder = builder.capture_when(
+                condition.clone(),
+                Duration::from_secs(self.capture_pre),
+                Duration::from_secs(self.capture_post),
+            );
+        }
+        if self.detect_leaks {
+            builder = builder.sink(Box::new(LeakDetector::new(Duration::from_secs(
+                self.leak_window,
+            ))));
+        }
+        if !self.alert.is_empty() || self.hang_after.is_some() {
+            for condition in &self.alert {
+                builder = builder.alert(condition.clone());
+            }
+            if let Some(period) = self.hang_after {
+                builder = builder.hang_after(period);
+            }
+            builder = builder.sink(Box::new(AlertLog));
+            if let Some(command) = &self.on_alert {
+                builder = builder.sink(Box::new(AlertHook::new(command)));
+            }
+            #[cfg(unix)]
+            if let Some(signal) = self.signal_on_alert {
+                builder = builder.sink(Box::new(AlertSignal::new(signal)));
+            }
+            if let Some(dir) = &self.snapshot_dir {
+                builder = builder.sink(Box::new(Snapshot::new(dir)));
+            }
+        }
+        for spec in &self.output {
+            let sma = self
+                .sma
+                .map(|window| MovingAverage::new(window, &self.sma_metrics));
+            builder = builder.sink(spec.sink(sma)?);
+        }
+        #[cfg(feature = "network")]
+        if let Some(addr) = &self.prometheus {
+            builder = builder.sink(Box::new(PrometheusExporter::bind(addr)?));
+        }
+        #[cfg(feature = "network")]
+        if let Some(addr) = &self.statsd {
+            builder = builder.sink(Box::new(StatsdExporter::connect(
+                addr,
+                &self.statsd_prefix,
+                &self.statsd_tags,
+            )?));
+        }
+        #[cfg(feature = "network")]
+        if let Some(addr) = &self.graphite {
+            builder = builder.sink(Box::new(GraphiteExporter::connect(
+                addr,
+                &self.graphite_prefix,
+            )?));
+        }
+        #[cfg(feature = "network")]
+        if let (Some(addr), Some(topic)) = (&self.mqtt, &self.topic) {
+            builder = builder.sink(Box::new(MqttPublisher::connect(addr, topic)?));
+        }
+        #[cfg(feature = "network")]
+        if let (Some(url), Some(token), Some(org), Some(bucket)) = (
+            &self.influx_url,
+            &self.influx_token,
+            &self.influx_org,
+            &self.influx_bucket,
+        ) {
+            builder = builder.sink(Box::new(InfluxExporter::new(InfluxConfig {
+                url: url.clone(),
+                token: token.clone(),
+                org: org.clone(),
+                bucket: bucket.clone(),
+            })));
+        }
+        #[cfg(feature = "network")]
+        if let Some(url) = &self.grafana_live {
+            builder = builder.sink(Box::new(GrafanaLive::connect(
+                url,
+                self.grafana_token.as_deref(),
+            )?));
+        }
+        #[cfg(feature = "network")]
+        if let Some(url) = &self.webhook {
+            builder = builder.sink(Box::new(Webhook::new(url)));
+        }
+        #[cfg(unix)]
+        if let Some(format) = self.log_to {
+            builder = builder.sink(Box::new(SystemLog::connect(format)?));
+        }
+        #[cfg(feature = "postgres")]
+        if let Some(dsn) = &self.pg_dsn {
+            builder = builder.sink(Box::new(procrec::export::postgres::PostgresSink::connect(
+                dsn,
+                &self.pg_table,
+            )?));
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(addr) = &self.grpc {
+            builder = builder.sink(Box::new(procrec::export::grpc::GrpcServer::bind(addr)?));
+        }
+        if let Some(command) = &self.exec_on_sample {
+            builder = builder.sink(Box::new(ExecHook::new(command)));
+        }
+        Ok(builder)
+    }
 }
 
-impl Deref for TrackedProcess {
-    type Target = Process;
+/// Number of entries of a recording already printed.
+#[derive(Default)]
+struct Printed {
+    samples: usize,
+    threads: usize,
+    events: usize,
+}
 
-    fn deref(&self) -> &Self::Target {
-        match self {
-            TrackedProcess::Internal(p, _) => &p,
-            TrackedProcess::External(p) => &p,
+impl Printed {
+    /// Print everything added to the recording since the last call.
+    fn print_new<W: Write + Send>(
+        &mut self,
+        out: &mut TextWriter<W>,
+        recording: &Recording,
+    ) -> Result<()> {
+        for i in recording.samples_since(self.samples) {
+            out.write_sample(i)?;
         }
+        for i in recording.threads_since(self.threads) {
+            out.write_thread(i)?;
+        }
+        for i in recording.events_since(self.events) {
+            out.write_event(i)?;
+        }
+        self.samples = recording.sample_count();
+        self.threads = recording.thread_count();
+        self.events = recording.event_count();
+        Ok(())
     }
 }
 
-// Implement a custom handler to clean up the child-process of an internal process
-impl Drop for TrackedProcess {
-    fn drop(&mut self) {
-        // If we have forked a child process, we need to kill and clean up
-        if self.is_running() {
-            if let TrackedProcess::Internal(_, ref mut c) = self {
-                if let Err(e) = c.kill() {
-                    eprintln!("Warning: can not kill child process: {}", e);
-                } else if let Err(e) = c.wait() {
-                    eprintln!(
-                        "Warning: Can not join the child process after killing it: {}",
-                        e
-                    );
-                }
+/// Prints alerts to stderr as they fire.
+struct AlertLog;
+
+impl Sink for AlertLog {
+    fn write_sample(&mut self, _sample: &Sample) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_alert(&mut self, alert: &Alert) -> Result<()> {
+        eprintln!("[{:.02}s] ALERT {}", alert.ts, alert);
+        Ok(())
+    }
+}
+
+/// Minimum time between two refreshes of the live graph
+const LIVE_REFRESH: Duration = Duration::from_secs(5);
+
+/// Parse a list of metric names and groups, see `Metric::parse_group`.
+fn parse_metric_groups(names: &[String]) -> Result<Vec<Metric>> {
+    let mut metrics = vec![];
+    for name in names {
+        for metric in Metric::parse_group(name)? {
+            if !metrics.contains(&metric) {
+                metrics.push(metric);
             }
         }
     }
+    Ok(metrics)
+}
+
+/// Parse a percentage like '10%' as fraction.
+fn parse_percent(s: &str) -> Result<f64> {
+    let percent: f64 = s
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| anyhow!("Invalid percentage '{}', expected e.g. '10%'", s))?;
+    Ok(percent / 100.0)
+}
+
+/// Plot recordings previously saved with --save or from the output of procrec.
+fn replot(cmd: &PlotCmd) -> Result<()> {
+    let plot_opts = cmd.plot.options()?;
+    if cmd.files.len() > 1 && !cmd.overlay {
+        bail!("Plotting several recordings requires --overlay");
+    }
+
+    let data = if cmd.files.len() == 1 {
+        load(&cmd.files[0], &cmd.plot)?.plot_data(&plot_opts)?
+    } else {
+        overlay(&cmd.files, &cmd.plot)?
+    };
+    plot::gnuplot_recording(&data, &plot_opts)
+        .map_err(|e| anyhow!("Fatal error calling gnuplot: {}", e))
 }
 
-fn delay(millis: u64) {
-    let timeout = time::Duration::from_millis(millis);
-    thread::sleep(timeout);
+/// Load a recording which has samples, without outliers if enabled.
+fn load(file: &Path, plot: &PlotArgs) -> Result<Recording> {
+    let mut recording = Recording::load(file)?;
+    plot.filter(&mut recording);
+    if recording.samples.is_empty() {
+        bail!("No samples found in '{}'", file.display());
+    }
+    Ok(recording)
 }
-fn gnuplot_recording(recording: &[Sample]) -> io::Result<()> {
-    let gnuplot_script_content = include_str!("../recording.plot");
-    let mut gnuplot_file = NamedTempFile::new()?;
-    gnuplot_file.write_all(gnuplot_script_content.as_bytes())?;
 
-    let mut data_file = NamedTempFile::new()?;
-    for i in recording {
-        data_file.write_all(format!("{}\n", i).as_bytes())?;
+/// Plot data of several recordings in one graph.
+fn overlay(files: &[PathBuf], plot: &PlotArgs) -> Result<PlotData> {
+    let plot_opts = plot.options()?;
+    let mut data = PlotData::default();
+    for file in files {
+        let mut recording = load(file, plot)?;
+
+        // Align the recordings by their relative time and tell them apart
+        // by the file name
+        let name = file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| file.display().to_string());
+        recording.shift(-recording.samples[0].ts);
+        let file_data = recording.plot_data(&plot_opts)?;
+        let single = file_data.groups.len() == 1;
+        data.groups
+            .extend(file_data.groups.into_iter().map(|mut g| {
+                g.label = if single {
+                    name.clone()
+                } else {
+                    format!("{} ({})", name, g.label)
+                };
+                g
+            }));
+        data.threads
+            .extend(file_data.threads.into_iter().map(|mut t| {
+                t.label = format!("{}: {}", name, t.label);
+                t
+            }));
+        data.events
+            .extend(file_data.events.into_iter().map(|e| Event {
+                ts: e.ts,
+                label: format!("{}: {}", name, e.label),
+                marker: e.marker,
+            }));
     }
-    data_file.flush()?;
-    let fname_param = format!("filename={:?};", data_file.path().display());
+    Ok(data)
+}
 
-    let output = Command::new("gnuplot")
-        .arg("-e")
-        .arg(fname_param)
-        .arg("-p")
-        .arg(gnuplot_file.path())
-        .output()?;
+/// Record repeated runs of a command and report statistics over them.
+fn bench(cmd: &BenchCmd) -> Result<()> {
+    if let Some(dir) = &cmd.output_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Can not create '{}': {}", dir.display(), e))?;
+    }
+    let interval = Duration::try_from_secs_f64(cmd.interval)
+        .map_err(|_| anyhow!("Invalid interval {}", cmd.interval))?;
+    let baseline = cmd.against.as_deref().map(bench::load).transpose()?;
+    if cmd.cold >= cmd.runs {
+        bail!("{} cold runs leave no warm run of {}", cmd.cold, cmd.runs);
+    }
+    let mut runs = vec![];
+    for i in 1..=cmd.runs {
+        let start = Instant::now();
+        let mut recorder = Recorder::builder()
+            .interval(interval)
+            .command(cmd.command.clone())
+            .build()?;
+        recorder.run()?;
+        let wall = start.elapsed();
+        let exit_status = recorder.exit_status();
+        let mut recording = recorder.into_recording();
+        recording.metadata.wall = Some(wall.as_secs_f64());
+        recording.metadata.cold = i <= cmd.cold;
+        if let Some(dir) = &cmd.output_dir {
+            recording.save(&dir.join(format!("run-{}.rec", i)))?;
+        }
+        let run = Run::new(&recording, Some(wall), exit_status.map(exit_code));
+        let tag = if run.cold { " (cold)" } else { "" };
+        eprintln!("run {}/{}{}: {}", i, cmd.runs, tag, run);
+        runs.push(run);
+    }
+    if cmd.cold > 0 {
+        let (cold, warm) = runs.split_at(cmd.cold as usize);
+        println!("cold ({} runs)", cold.len());
+        print!("{}", bench::Report::new(cold));
+        println!("\nwarm ({} runs)", warm.len());
+        print!("{}", bench::Report::new(warm));
+        println!("\ncold vs. warm (Mann-Whitney U test)");
+        print!("{}", Comparison::new(cold, warm));
+    } else {
+        print!("{}", bench::Report::new(&runs));
+    }
+    if let (Some(baseline), Some(dir)) = (baseline, &cmd.against) {
+        println!("\nagainst {} (Mann-Whitney U test)", dir.display());
+        print!("{}", Comparison::new(&warm(&baseline), &warm(&runs)));
+    }
+    let failed = runs.iter().filter(|r| r.failed()).count();
+    if failed > 0 {
+        bail!("{} of {} runs failed", failed, runs.len());
+    }
+    Ok(())
+}
+
+/// The runs which are not cold, saved runs are compared without the cold
+/// ones.
+fn warm(runs: &[Run]) -> Vec<Run> {
+    runs.iter().filter(|r| !r.cold).copied().collect()
+}
+
+/// Cut a saved recording to a time window and save it.
+fn trim(cmd: &TrimCmd) -> Result<()> {
+    let from = cmd.from.unwrap_or_default().as_secs_f32();
+    let to = cmd.to.map(|to| to.as_secs_f32());
+    if to.is_some_and(|to| to <= from) {
+        bail!("The end of the window must be after its start");
+    }
+    let mut recording = Recording::load(&cmd.input)?;
+    recording.trim(from, to);
+    if let Some(interval) = cmd.downsample {
+        recording.downsample(interval.as_secs_f32());
+    }
+    if recording.samples.is_empty() {
+        bail!(
+            "No samples between {}s and {}s",
+            from,
+            to.unwrap_or(f32::INFINITY)
+        );
+    }
+    recording.save(&cmd.output)
+}
 
-    if !output.status.success() {
-        println!("status: {}", output.status);
-        io::stdout().write_all(&output.stdout)?;
-        io::stderr().write_all(&output.stderr)?;
+/// Compare two saved recordings and optionally plot them together, or two
+/// directories of benchmark runs.
+fn diff(cmd: &DiffCmd) -> Result<()> {
+    if cmd.before.is_dir() && cmd.after.is_dir() {
+        let comparison = Comparison::new(
+            &warm(&bench::load(&cmd.before)?),
+            &warm(&bench::load(&cmd.after)?),
+        );
+        print!("{}", comparison);
+        return Ok(());
+    }
+    let plot_opts = cmd.plot.options()?;
+    let diff = procrec::diff::diff(
+        &load(&cmd.before, &cmd.plot)?,
+        &load(&cmd.after, &cmd.plot)?,
+    )?;
+    print!("{}", diff);
+    if cmd.graph || cmd.plot.graph_output.is_some() {
+        let data = overlay(&[cmd.before.clone(), cmd.after.clone()], &cmd.plot)?;
+        plot::gnuplot_recording(&data, &plot_opts)
+            .map_err(|e| anyhow!("Fatal error calling gnuplot: {}", e))?;
     }
     Ok(())
 }
 
+/// Sample the processes once and check the conditions of --check.
+fn check(opts: &Opts) -> Result<Report> {
+    let mut recorder = opts.recorder()?.build()?;
+    recorder.sample()?;
+    recorder.finish()?;
+    if recorder.recording().samples.is_empty() {
+        return Ok(Report::unknown("Process ended before it was sampled"));
+    }
+    Ok(Report::new(
+        recorder.recording(),
+        &opts.check,
+        &opts.check_warn,
+    ))
+}
+
+/// Arguments setting each of the options which are missing on the command
+/// line and not negated with `--no-<flag>`.
+fn config_args(
+    app: &App,
+    matches: &ArgMatches,
+    negated: &[String],
+    options: &[(String, Value)],
+) -> Result<Vec<Vec<String>>> {
+    let mut args = vec![];
+    for (long, value) in options {
+        let arg = match app
+            .get_arguments()
+            .find(|a| a.get_long() == Some(long.as_str()))
+        {
+            Some(arg) => arg,
+            None => bail!("Unknown option '{}' in config", long),
+        };
+        if matches.occurrences_of(arg.get_name()) > 0 || negated.contains(long) {
+            continue;
+        }
+        let kind = OptionKind {
+            takes_value: arg.is_set(ArgSettings::TakesValue),
+            multiple: arg.is_set(ArgSettings::MultipleOccurrences),
+        };
+        args.push(config::to_args(long, value, kind)?);
+    }
+    Ok(args)
+}
+
+/// Remove the `--no-<flag>` arguments, which keep a flag set by the config
+/// or the environment off, and return the names of the flags.
+fn negations(app: &App, args: &mut Vec<OsString>) -> Vec<String> {
+    let arguments: Vec<_> = app
+        .get_arguments()
+        .chain(app.get_subcommands().flat_map(|s| s.get_arguments()))
+        .collect();
+    let is_flag = |long: &str| {
+        arguments
+            .iter()
+            .any(|a| a.get_long() == Some(long) && !a.is_set(ArgSettings::TakesValue))
+    };
+    let is_option = |long: &str| arguments.iter().any(|a| a.get_long() == Some(long));
+    let mut negated = vec![];
+    // the arguments of the recorded command are left alone
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let mut i = 0;
+    args.retain(|arg| {
+        i += 1;
+        let flag = match arg.to_str().and_then(|a| a.strip_prefix("--no-")) {
+            Some(flag) if i <= end && is_flag(flag) && !is_option(&format!("no-{}", flag)) => flag,
+            _ => return true,
+        };
+        negated.push(flag.to_string());
+        false
+    });
+    negated
+}
+
+/// Options of the main command or the subcommand from the `PROCREC_*`
+/// environment variables, `PROCREC_BENCH_RUNS` for `bench --runs`.
+fn env_defaults(app: &App, sub: Option<&str>) -> Vec<(String, Value)> {
+    let (cmd, prefix) = match sub {
+        Some(name) => (
+            app.find_subcommand(name).expect("parsed subcommand"),
+            format!("PROCREC_{}_", name.to_uppercase()),
+        ),
+        None => (app, "PROCREC_".to_string()),
+    };
+    let mut options = config::env_options(&prefix);
+    options.retain(|(long, _)| {
+        if cmd
+            .get_arguments()
+            .any(|a| a.get_long() == Some(long.as_str()))
+        {
+            return true;
+        }
+        let for_subcommand = sub.is_none()
+            && app
+                .get_subcommands()
+                .any(|s| long.starts_with(&format!("{}-", s.get_name())));
+        if !for_subcommand {
+            eprintln!(
+                "Warning: ignoring {}{}, there is no option --{}",
+                prefix,
+                long.to_uppercase().replace('-', "_"),
+                long
+            );
+        }
+        false
+    });
+    options
+}
+
+/// Insert the defaults for the options of the main command, or of the
+/// subcommand after its name, and parse the arguments again.
+///
+/// Defaults conflicting with the arguments given so far are left out, e.g.
+/// `graph = true` when `--daemonize` was given.
+fn with_defaults<F>(
+    app: &App,
+    args: &mut Vec<OsString>,
+    matches: &ArgMatches,
+    negated: &[String],
+    defaults: F,
+) -> Result<ArgMatches>
+where
+    F: FnOnce(Option<&str>) -> Result<Vec<(String, Value)>>,
+{
+    let (options, at) = match matches.subcommand() {
+        Some((name, sub_matches)) => {
+            let sub = app.find_subcommand(name).expect("parsed subcommand");
+            let options = config_args(sub, sub_matches, negated, &defaults(Some(name))?)?;
+            let at = args
+                .iter()
+                .position(|a| a == name)
+                .map_or(args.len(), |i| i + 1);
+            (options, at)
+        }
+        None => (config_args(app, matches, negated, &defaults(None)?)?, 1),
+    };
+    let mut extra = vec![];
+    for option in options {
+        let mut tried = args.clone();
+        tried.splice(at..at, option.iter().map(OsString::from));
+        match app.clone().try_get_matches_from(&tried) {
+            Err(e) if e.kind == ErrorKind::ArgumentConflict => {}
+            _ => extra.extend(option),
+        }
+    }
+    args.splice(at..at, extra.into_iter().map(OsString::from));
+    Ok(app.clone().get_matches_from(args.iter()))
+}
+
+/// Parse the command line, with the defaults of the environment and then of
+/// the config file.
+fn parse_opts() -> Result<Opts> {
+    let app = Opts::into_app();
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let negated = negations(&app, &mut args);
+    let matches = app.clone().get_matches_from(&args);
+    let matches = with_defaults(&app, &mut args, &matches, &negated, |sub| {
+        Ok(env_defaults(&app, sub))
+    })?;
+    let opts = Opts::from_arg_matches(&matches);
+    let config = match Config::find(opts.config.as_deref())? {
+        Some(config) => config,
+        None if opts.profile.is_some() => bail!("--profile needs a config file"),
+        None => return Ok(opts),
+    };
+    for section in config.sections() {
+        if app.find_subcommand(section).is_none() {
+            bail!(
+                "Unknown command '{}' in config '{}'",
+                section,
+                config.path.display()
+            );
+        }
+    }
+    // the main options only apply to recordings, the ones of a subcommand
+    // go after its name
+    let matches = with_defaults(&app, &mut args, &matches, &negated, |sub| {
+        match (sub, &opts.profile) {
+            (Some(name), _) => config.section(name),
+            (None, Some(profile)) => config.profile(profile),
+            (None, None) => Ok(config.options()),
+        }
+    })?;
+    Ok(Opts::from_arg_matches(&matches))
+}
+
 fn main() -> Result<()> {
-    let opts: Opts = Opts::parse();
+    let opts = parse_opts()?;
+
+    match &opts.subcmd {
+        Some(SubCommand::Plot(cmd)) => return replot(cmd),
+        Some(SubCommand::Diff(cmd)) => return diff(cmd),
+        Some(SubCommand::Bench(cmd)) => return bench(cmd),
+        Some(SubCommand::Trim(cmd)) => return trim(cmd),
+        Some(SubCommand::Completions(cmd)) => {
+            let mut app = Opts::into_app();
+            let out = &mut io::stdout();
+            match cmd.shell {
+                Shell::Bash => generate::<Bash, _>(&mut app, "procrec", out),
+                Shell::Zsh => generate::<Zsh, _>(&mut app, "procrec", out),
+                Shell::Fish => generate::<Fish, _>(&mut app, "procrec", out),
+            }
+            return Ok(());
+        }
+        #[cfg(feature = "network")]
+        Some(SubCommand::Serve(cmd)) => return procrec::server::serve(&cmd.listen),
+        Some(SubCommand::Mark(cmd)) => {
+            let socket = cmd
+                .socket
+                .clone()
+                .unwrap_or_else(control::default_socket_path);
+            control::send_command(&socket, &format!("marker {}", cmd.label.join(" ")))?;
+            return Ok(());
+        }
+        Some(SubCommand::Stop(args)) => {
+            let socket = args.path();
+            control::send_command(&socket, "stop")?;
+            // the socket is gone once the recording is saved and evaluated
+            while control::send_command(&socket, "status").is_ok() {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            return Ok(());
+        }
+        Some(SubCommand::Status(args)) => match control::send_command(&args.path(), "status") {
+            Ok(state) => {
+                println!("{}", state);
+                return Ok(());
+            }
+            Err(_) => {
+                println!("not running");
+                std::process::exit(3);
+            }
+        },
+        None => {}
+    }
+
+    let plot_opts = opts.plot.options()?;
+    // Fail early on an unusable plot configuration instead of after a long recording
+    let gnuplot_script = plot::gnuplot_script(&plot_opts, &PlotData::default())?;
 
     if opts.script_dump {
-        let gnuplot_script_content = include_str!("../recording.plot");
-        println!("{}", gnuplot_script_content);
+        println!("{}", gnuplot_script);
         std::process::exit(0);
     }
+    if (opts.graph || opts.plot.graph_output.is_some()) && !cfg!(feature = "plot") {
+        bail!("procrec was built without the plot feature, plot a recording saved with --save elsewhere");
+    }
 
-    // Initialize the tracking process
-    let mut pid_proc = TrackedProcess::try_from(&opts)?;
-
-    // Fetch the CPU one time set the "baseline"
-    let _percent_cpu = pid_proc.cpu_percent();
-    let sample_rate = opts.interval * 1000;
+    if !opts.check.is_empty() || !opts.check_warn.is_empty() {
+        let report = check(&opts).unwrap_or_else(|e| Report::unknown(&e.to_string()));
+        println!("{}", report);
+        std::process::exit(report.status.exit_code());
+    }
 
-    let mut recording = vec![];
-    let mut start: Option<SystemTime> = None;
+    // before any thread is started, only the forking one survives
+    let startup = if opts.daemonize {
+        if let Some(path) = &opts.pidfile {
+            PidFile::check(path)?;
+        }
+        Some(daemon::daemonize(opts.log_file.as_deref())?)
+    } else {
+        None
+    };
+    // a daemon reports failures up to here to the process which started it
+    let started = (|| -> Result<_> {
+        let pidfile = opts.pidfile.as_deref().map(PidFile::create).transpose()?;
 
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
-    })
-    .expect("Error setting Ctrl-C handler");
+        // Initialize the tracking processes
+        let recorder = opts.recorder()?.build()?;
 
-    // MAIN phase
-    while running.load(Ordering::SeqCst) {
-        delay(sample_rate);
+        let control_socket = match &opts.control_socket {
+            Some(path) => Some(ControlSocket::bind(path, recorder.control())?),
+            None if opts.daemonize => Some(ControlSocket::bind(
+                &control::default_socket_path(),
+                recorder.control(),
+            )?),
+            None => None,
+        };
+        if let Some(path) = &opts.marker_file {
+            control::marker_on_sighup(path.clone(), recorder.control())?;
+        }
 
-        if !pid_proc.is_running() {
-            running.store(false, Ordering::SeqCst);
+        // a detached command is not bothered with the end of the recording
+        let mut forwarded = if opts.command().is_empty() || opts.no_forward_signals || opts.detach {
+            vec![]
         } else {
-            let percent_cpu = pid_proc.cpu_percent()?;
-            let cur_mem = pid_proc.memory_info()?;
-            let time_since_start = if let Some(time) = start {
-                time.elapsed()?.as_secs_f32()
-            } else {
-                start = Some(time::SystemTime::now());
-                0.0
-            };
-            let data = Sample {
-                ts: time_since_start,
-                pid: pid_proc.pid(),
-                cpu: percent_cpu,
-                rss: cur_mem.rss() / 1000,
-                vsize: cur_mem.vms() / 1000,
-                //num_threads: pid_proc.num_threads(),
-            };
-            if opts.verbose > 0 {
-                println!("{}", data);
-            }
-            recording.push(data);
-            if let Some(dur) = opts.duration {
-                if time_since_start > dur as f32 {
-                    break;
+            opts.forward_signals.clone()
+        };
+        // a daemon ends like the recording on SIGTERM instead of right away
+        if opts.daemonize && !opts.detach && !forwarded.contains(&libc::SIGTERM) {
+            forwarded.push(libc::SIGTERM);
+        }
+        #[cfg(unix)]
+        if opts.marker_file.is_some() && forwarded.contains(&libc::SIGHUP) {
+            bail!("SIGHUP can not be forwarded when it adds markers (--marker-file)");
+        }
+        signals::forward(&forwarded)?;
+        if !forwarded.contains(&libc::SIGINT) {
+            let running = recorder.stop_handle();
+            ctrlc::set_handler(move || {
+                running.store(false, Ordering::SeqCst);
+            })
+            .expect("Error setting Ctrl-C handler");
+        }
+        Ok((pidfile, recorder, control_socket))
+    })();
+    if let Some(startup) = startup {
+        startup.report(&started);
+    }
+    let (pidfile, mut recorder, control_socket) = started?;
+    // the plain text on stdout is replaced by any output to stdout
+    let print_stdout = opts.output.iter().all(|o| o.path.is_some());
+
+    let mut live_plot = if opts.graph_live {
+        Some(plot::LivePlot::start()?)
+    } else {
+        None
+    };
+    let mut last_refresh: Option<Instant> = None;
+
+    // MAIN phase
+    let mut out = TextWriter::new(io::stdout());
+    let mut printed = Printed::default();
+    while recorder.sample()? {
+        let recording = recorder.recording();
+        if print_stdout && opts.verbose > 0 {
+            printed.print_new(&mut out, recording)?;
+        }
+        if let Some(live) = live_plot.as_mut() {
+            if last_refresh.is_none_or(|t| t.elapsed() >= LIVE_REFRESH) {
+                last_refresh = Some(Instant::now());
+                let data = recording.recent_plot_data(&plot_opts);
+                if let Err(err) = live.update(&data, &plot_opts) {
+                    eprintln!("Warning: stopped refreshing live graph: {}", err);
+                    live_plot = None;
                 }
             }
         }
     }
+    recorder.finish()?;
+    let exit_status = recorder.exit_status();
+    let alerts = recorder.alerts().to_vec();
+    let mut recording = recorder.into_recording();
 
     // POST phase
-    if opts.verbose == 0 {
-        for i in &recording {
-            println!("{}", i);
+    // prints everything in non-verbose mode, otherwise the remains of the
+    // final round
+    if print_stdout && opts.verbose == 0 {
+        recording.replay(&mut out)?;
+    } else if print_stdout {
+        printed.print_new(&mut out, &recording)?;
+    }
+    if let Some(path) = &opts.save {
+        recording.save(path)?;
+    }
+    // the saving, printing, summary and plots read the samples moved to
+    // disk one by one, the other analyses need them all at once
+    if opts.analyzes_all_samples() {
+        recording.load_spilled()?;
+    }
+    // the saved recording keeps the outliers
+    opts.plot.filter(&mut recording);
+    let summary = Summary::with_peaks(&recording, opts.peaks)?;
+    let phases = summary::phases(&recording)?;
+    let histograms: Vec<Histogram> = opts
+        .histogram
+        .iter()
+        .filter_map(|&metric| Histogram::of(&recording, metric, histogram::BINS))
+        .collect();
+    for histogram in &histograms {
+        eprint!("{}", histogram);
+    }
+    if opts.summary {
+        eprint!("{}", summary);
+        if !phases.is_empty() {
+            eprint!("{}", PhaseTable(&phases));
+        }
+    }
+    if let Some(path) = &opts.summary_json {
+        RunSummary {
+            metadata: recording.metadata.clone(),
+            exit_code: exit_status.map(exit_code),
+            summary: summary.clone(),
+            alerts,
+            phases,
+            histograms,
+        }
+        .save(path)?;
+    }
+    if let Some(path) = &opts.save_baseline {
+        summary.save(path)?;
+    }
+    // the reports, the plot and the upload are needed most when a check
+    // failed, so its error is only returned once they are done
+    let mut failures = vec![];
+    if let Some(path) = &opts.baseline {
+        let comparison = summary.compare(&Summary::load(path)?, opts.fail_threshold);
+        eprint!("{}", comparison);
+        if comparison.has_regression() {
+            failures.push(format!(
+                "Regression of more than {}% compared to the baseline",
+                opts.fail_threshold * 100.0
+            ));
+        }
+    }
+    if !opts.assert.is_empty() {
+        let outcomes: Vec<_> = opts.assert.iter().map(|a| a.check(&recording)).collect();
+        for outcome in &outcomes {
+            eprintln!("{}", outcome);
+        }
+        let failed = outcomes.iter().filter(|o| !o.passed).count();
+        if failed > 0 {
+            failures.push(format!(
+                "{} of {} assertions failed",
+                failed,
+                outcomes.len()
+            ));
+        }
+    }
+    for measure in &opts.measure {
+        match measure.measure(&recording) {
+            Some(ts) => eprintln!("{}: {:.2}s", measure, ts),
+            None => eprintln!("{}: never", measure),
+        }
+    }
+    if opts.correlate {
+        let correlations = correlation::analyze(&recording);
+        if correlations.is_empty() {
+            eprintln!("No strong correlations found");
+        }
+        for correlation in correlations {
+            eprintln!("{}", correlation);
         }
     }
-    if opts.graph {
-        if let Err(err) = gnuplot_recording(&recording) {
+    if opts.detect_leaks {
+        let leaks = leak::analyze(&recording);
+        if leaks.is_empty() {
+            eprintln!("No steady memory growth detected");
+        }
+        for leak in leaks {
+            let label = recording
+                .metadata
+                .processes
+                .iter()
+                .find(|p| p.pid == leak.pid)
+                .map(|p| p.label.clone())
+                .unwrap_or_else(|| leak.pid.to_string());
+            eprintln!("Possible memory leak in {}: {}", label, leak);
+        }
+    }
+    let data = recording.plot_data(&plot_opts)?;
+    // whether this run wrote the graph, a live plot draws it in the
+    // background
+    #[cfg_attr(not(feature = "network"), allow(unused_variables))]
+    let graph_drawn = if let Some(mut live) = live_plot {
+        if let Err(err) = live.update(&data, &plot_opts) {
             println!("Fatal error calling gnuplot: {}", err);
         }
+        false
+    } else if opts.graph || opts.plot.graph_output.is_some() {
+        match plot::gnuplot_recording(&data, &plot_opts) {
+            Ok(()) => true,
+            Err(err) => {
+                println!("Fatal error calling gnuplot: {}", err);
+                false
+            }
+        }
+    } else {
+        false
+    };
+    #[cfg(feature = "network")]
+    if let Some(location) = &opts.upload {
+        // an image left by an earlier run must not be uploaded if gnuplot
+        // failed
+        let graph = opts.plot.graph_output.as_deref().filter(|_| graph_drawn);
+        let prefix = location.upload(&recording, graph)?;
+        eprintln!("Uploaded to s3://{}/{}", location.bucket, prefix);
+    }
+    if !failures.is_empty() {
+        bail!("{}", failures.join(", "));
+    }
+    // exit like the launched command to be transparent to scripts
+    if let Some(code) = exit_status.map(exit_code).filter(|&code| code != 0) {
+        drop(recording);
+        drop(control_socket);
+        drop(pidfile);
+        std::process::exit(code);
     }
     Ok(())
 }
+
+/// Exit code of a shell for the status, 128 plus the number of a signal.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
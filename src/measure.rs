@@ -0,0 +1,76 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Measurements of a recording like the time until a condition first held.
+
+use crate::condition::Condition;
+use crate::{summary, Recording};
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// A quantity measured after recording, e.g. `time_until rss>1G`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Measure {
+    /// Time until the condition first held for the sum over all processes,
+    /// for its whole duration if it has one
+    TimeUntil(Condition),
+}
+
+impl Measure {
+    /// Seconds since the start of the recording, `None` if it never held.
+    pub fn measure(&self, recording: &Recording) -> Option<f32> {
+        match self {
+            Measure::TimeUntil(condition) => {
+                let rounds = summary::rounds(&recording.samples);
+                let duration = condition.duration.unwrap_or_default().as_secs_f32();
+                let mut since = None;
+                for (ts, total) in summary::totals(&rounds, condition.metric) {
+                    if !condition.comparison.compare(total, condition.threshold) {
+                        since = None;
+                        continue;
+                    }
+                    if ts - *since.get_or_insert(ts) >= duration {
+                        return Some(ts);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+impl FromStr for Measure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().split_once(char::is_whitespace) {
+            Some(("time_until", condition)) => Ok(Measure::TimeUntil(condition.trim().parse()?)),
+            _ => Err(anyhow!(
+                "Invalid measure '{}', expected e.g. 'time_until rss>1G'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Measure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Measure::TimeUntil(condition) => write!(f, "time_until {}", condition),
+        }
+    }
+}
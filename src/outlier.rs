@@ -0,0 +1,114 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Removal of anomalous samples, e.g. the bogus first CPU reading after
+//! attaching to a process.
+
+use crate::summary::{self, Stats};
+use crate::{Metric, Recording, Sample};
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Minimum number of samples of a process to tell outliers apart
+const MIN_SAMPLES: usize = 3;
+
+/// Method deciding whether a value is an outlier among the values of a
+/// metric of one process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutlierFilter {
+    /// More than this many standard deviations away from the mean
+    ZScore(f64),
+    /// More than this many interquartile ranges outside of the quartiles
+    Iqr(f64),
+}
+
+impl FromStr for OutlierFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (method, k) = s.split_once(':').unwrap_or((s, ""));
+        let k: Option<f64> =
+            if k.is_empty() {
+                None
+            } else {
+                Some(k.parse().map_err(|_| {
+                    anyhow!("Invalid outlier filter '{}', expected e.g. 'zscore:3'", s)
+                })?)
+            };
+        match method {
+            "zscore" => Ok(OutlierFilter::ZScore(k.unwrap_or(3.0))),
+            "iqr" => Ok(OutlierFilter::Iqr(k.unwrap_or(1.5))),
+            _ => bail!("Unknown outlier filter '{}' (zscore:K, iqr:K)", method),
+        }
+    }
+}
+
+impl OutlierFilter {
+    /// Range of values which are not outliers.
+    fn bounds(self, values: &[f64]) -> Option<(f64, f64)> {
+        let stats = Stats::of(values)?;
+        match self {
+            OutlierFilter::ZScore(k) => {
+                Some((stats.mean - k * stats.stddev, stats.mean + k * stats.stddev))
+            }
+            OutlierFilter::Iqr(k) => {
+                let mut sorted = values.to_vec();
+                sorted.sort_by(f64::total_cmp);
+                let q1 = summary::percentile(&sorted, 25.0);
+                let q3 = summary::percentile(&sorted, 75.0);
+                Some((q1 - k * (q3 - q1), q3 + k * (q3 - q1)))
+            }
+        }
+    }
+
+    /// Remove the samples with an outlying value in any metric, compared to
+    /// the other samples of the same process. Returns the removed samples.
+    pub fn apply(self, recording: &mut Recording) -> Vec<Sample> {
+        let mut by_pid: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        for (i, sample) in recording.samples.iter().enumerate() {
+            by_pid.entry(sample.pid).or_default().push(i);
+        }
+        let mut outlier = vec![false; recording.samples.len()];
+        for indices in by_pid.values().filter(|i| i.len() >= MIN_SAMPLES) {
+            for metric in Metric::ALL.iter() {
+                let values: Option<Vec<f64>> = indices
+                    .iter()
+                    .map(|&i| metric.value(&recording.samples[i]))
+                    .collect();
+                let values = match values {
+                    Some(values) => values,
+                    None => continue,
+                };
+                if let Some((low, high)) = self.bounds(&values) {
+                    for (&i, value) in indices.iter().zip(values) {
+                        outlier[i] |= value < low || value > high;
+                    }
+                }
+            }
+        }
+        let mut removed = vec![];
+        let mut flags = outlier.into_iter();
+        recording.samples.retain(|sample| {
+            let outlier = flags.next().unwrap_or_default();
+            if outlier {
+                removed.push(sample.clone());
+            }
+            !outlier
+        });
+        removed
+    }
+}
@@ -0,0 +1,364 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sinks receiving the samples and events of a recording.
+//!
+//! The textual format written by [`TextWriter`] holds one sample, thread
+//! sample or event per line as written by their `Display` implementations.
+
+use crate::alert::Alert;
+use crate::smaps::SmapsBreakdown;
+use crate::{Event, Metric, Recording, Sample, ThreadSample};
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+
+/// Receiver of the data collected by a recording.
+///
+/// Several sinks can be attached to a [`Recorder`](crate::Recorder), each
+/// gets every sample and event as soon as it was collected.
+pub trait Sink: Send {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()>;
+
+    fn write_thread(&mut self, _thread: &ThreadSample) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_event(&mut self, _event: &Event) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when an alert fired, records it as event by default.
+    fn write_alert(&mut self, alert: &Alert) -> Result<()> {
+        self.write_event(&alert.event())
+    }
+
+    /// Called once after the recording ended.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Simple moving average of metrics over the last samples of each process,
+/// appended as additional columns by the CSV and JSON writers.
+#[derive(Debug, Clone)]
+pub struct MovingAverage {
+    window: usize,
+    metrics: Vec<Metric>,
+    values: HashMap<(u32, usize), VecDeque<f64>>,
+}
+
+impl MovingAverage {
+    /// Average over `window` samples, at least one.
+    pub fn new(window: usize, metrics: &[Metric]) -> Self {
+        MovingAverage {
+            window: window.max(1),
+            metrics: metrics.to_vec(),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Names of the columns, e.g. `cpu_sma10`.
+    pub fn columns(&self) -> Vec<String> {
+        self.metrics
+            .iter()
+            .map(|m| format!("{}_sma{}", m.name(), self.window))
+            .collect()
+    }
+
+    /// Add the sample, returns the averages in the order of the columns,
+    /// `None` for metrics not recorded.
+    pub fn update(&mut self, sample: &Sample) -> Vec<Option<f64>> {
+        let mut averages = vec![];
+        for (i, metric) in self.metrics.iter().enumerate() {
+            let value = match metric.value(sample) {
+                Some(value) => value,
+                None => {
+                    averages.push(None);
+                    continue;
+                }
+            };
+            let values = self.values.entry((sample.pid, i)).or_default();
+            if values.len() == self.window {
+                values.pop_front();
+            }
+            values.push_back(value);
+            averages.push(Some(values.iter().sum::<f64>() / values.len() as f64));
+        }
+        averages
+    }
+}
+
+/// Writes samples and events in the textual output format.
+pub struct TextWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write + Send> TextWriter<W> {
+    pub fn new(out: W) -> Self {
+        TextWriter { out }
+    }
+
+    /// Write a complete recording, samples first, then threads and events.
+    pub fn write_recording(&mut self, recording: &Recording) -> Result<()> {
+        for i in &recording.samples {
+            self.write_sample(i)?;
+        }
+        for i in &recording.threads {
+            self.write_thread(i)?;
+        }
+        for i in &recording.events {
+            self.write_event(i)?;
+        }
+        self.finish()
+    }
+}
+
+impl<W: Write + Send> Sink for TextWriter<W> {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        writeln!(self.out, "{}", sample)?;
+        Ok(())
+    }
+
+    fn write_thread(&mut self, thread: &ThreadSample) -> Result<()> {
+        writeln!(self.out, "{}", thread)?;
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<()> {
+        writeln!(self.out, "{}", event)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the samples as CSV with one column per metric, thread samples and
+/// events are skipped.
+pub struct CsvWriter<W: Write> {
+    out: W,
+    header_written: bool,
+    sma: Option<MovingAverage>,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(out: W) -> Self {
+        CsvWriter {
+            out,
+            header_written: false,
+            sma: None,
+        }
+    }
+
+    /// Append the moving averages as columns.
+    pub fn with_sma(mut self, sma: MovingAverage) -> Self {
+        self.sma = Some(sma);
+        self
+    }
+}
+
+impl<W: Write + Send> Sink for CsvWriter<W> {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        if !self.header_written {
+            write!(self.out, "ts,pid")?;
+            for metric in Metric::ALL.iter() {
+                write!(self.out, ",{}", metric.name())?;
+            }
+            for column in self.sma.iter().flat_map(|sma| sma.columns()) {
+                write!(self.out, ",{}", column)?;
+            }
+            writeln!(self.out)?;
+            self.header_written = true;
+        }
+        write!(self.out, "{:.02},{}", sample.ts, sample.pid)?;
+        for metric in Metric::ALL.iter() {
+            match metric.value(sample) {
+                Some(value) => write!(self.out, ",{}", value)?,
+                None => write!(self.out, ",")?,
+            }
+        }
+        if let Some(sma) = self.sma.as_mut() {
+            for average in sma.update(sample) {
+                match average {
+                    Some(average) => write!(self.out, ",{:.2}", average)?,
+                    None => write!(self.out, ",")?,
+                }
+            }
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// One line of the JSON output, tagged by its type.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum JsonEntry<'a> {
+    Sample(&'a Sample),
+    Thread(&'a ThreadSample),
+    Event(&'a Event),
+    #[serde(rename = "sample")]
+    Averaged(Averaged<'a>),
+}
+
+/// A sample with its moving averages by column name.
+#[derive(Serialize)]
+pub(crate) struct Averaged<'a> {
+    #[serde(flatten)]
+    sample: &'a Sample,
+    #[serde(flatten)]
+    averages: BTreeMap<String, f64>,
+}
+
+/// Writes every sample and event as JSON object on its own line, e.g. to be
+/// consumed live by another program.
+pub struct JsonWriter<W: Write> {
+    out: W,
+    sma: Option<MovingAverage>,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(out: W) -> Self {
+        JsonWriter { out, sma: None }
+    }
+
+    /// Add the moving averages as fields of the samples.
+    pub fn with_sma(mut self, sma: MovingAverage) -> Self {
+        self.sma = Some(sma);
+        self
+    }
+
+    fn write_entry(&mut self, entry: JsonEntry) -> Result<()> {
+        serde_json::to_writer(&mut self.out, &entry)?;
+        writeln!(self.out)?;
+        // consumers are expected to read along, do not hold back lines
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Sink for JsonWriter<W> {
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        match self.sma.as_mut() {
+            Some(sma) => {
+                let averages = sma
+                    .columns()
+                    .into_iter()
+                    .zip(sma.update(sample))
+                    .filter_map(|(column, average)| Some((column, average?)))
+                    .collect();
+                self.write_entry(JsonEntry::Averaged(Averaged { sample, averages }))
+            }
+            None => self.write_entry(JsonEntry::Sample(sample)),
+        }
+    }
+
+    fn write_thread(&mut self, thread: &ThreadSample) -> Result<()> {
+        self.write_entry(JsonEntry::Thread(thread))
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<()> {
+        self.write_entry(JsonEntry::Event(event))
+    }
+}
+
+/// Read a recording in the textual output format.
+///
+/// Lines that are neither samples, thread samples nor events are skipped.
+pub fn read_text<R: BufRead>(input: R) -> io::Result<Recording> {
+    let mut recording = Recording::default();
+    for line in input.lines() {
+        let line = line?;
+        if let Ok(sample) = line.parse() {
+            recording.samples.push(sample);
+        } else if let Ok(thread) = line.parse() {
+            recording.threads.push(thread);
+        } else if let Ok(event) = line.parse() {
+            recording.events.push(event);
+        }
+    }
+    Ok(recording)
+}
+
+/// Read the samples written by [`CsvWriter`], the moving averages are
+/// skipped.
+pub fn read_csv<R: BufRead>(input: R) -> Result<Recording> {
+    let mut lines = input.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns: Vec<&str> = header.trim().split(',').collect();
+    let column = |name: &str| columns.iter().position(|c| *c == name);
+    let (ts, pid) = match (column("ts"), column("pid")) {
+        (Some(ts), Some(pid)) => (ts, pid),
+        _ => bail!("Missing ts and pid columns in the CSV header"),
+    };
+    let metrics: Vec<(Metric, Option<usize>)> =
+        Metric::ALL.iter().map(|m| (*m, column(m.name()))).collect();
+
+    let mut recording = Recording::default();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        let invalid = || anyhow!("Invalid CSV line {}: '{}'", i + 2, line);
+        let field = |index: usize| fields.get(index).map(|f| f.trim()).unwrap_or_default();
+        let value = |metric: Metric| -> Result<Option<f64>> {
+            match metrics.iter().find(|(m, _)| *m == metric) {
+                Some((_, Some(index))) if !field(*index).is_empty() => {
+                    Ok(Some(field(*index).parse().map_err(|_| invalid())?))
+                }
+                _ => Ok(None),
+            }
+        };
+        let kb = |metric: Metric| value(metric).map(|v| v.unwrap_or(0.0) as u64);
+        let smaps = match (
+            value(Metric::Heap)?,
+            value(Metric::Stack)?,
+            value(Metric::File)?,
+            value(Metric::Anon)?,
+        ) {
+            (Some(heap), Some(stack), Some(file), Some(anon)) => Some(SmapsBreakdown {
+                heap: heap as u64,
+                stack: stack as u64,
+                file: file as u64,
+                anon: anon as u64,
+            }),
+            _ => None,
+        };
+        recording.samples.push(Sample {
+            ts: field(ts).parse().map_err(|_| invalid())?,
+            pid: field(pid).parse().map_err(|_| invalid())?,
+            cpu: value(Metric::Cpu)?.unwrap_or(0.0) as f32,
+            rss: kb(Metric::Rss)?,
+            vsize: kb(Metric::Vsize)?,
+            smaps,
+            counters: None,
+            footprint: None,
+            perf: BTreeMap::new(),
+        });
+    }
+    Ok(recording)
+}
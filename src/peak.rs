@@ -0,0 +1,96 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of CPU and memory peaks in a recording.
+
+use crate::summary::Stats;
+use crate::Metric;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A stretch of time in which a metric was more than one standard deviation
+/// above its mean.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Peak {
+    pub metric: Metric,
+    /// Time of the highest value in seconds
+    pub ts: f32,
+    /// Highest value, summed up over all processes
+    pub value: f64,
+    /// Time in seconds the metric stayed above the threshold, 0 for a
+    /// single sample
+    pub duration: f32,
+}
+
+impl fmt::Display for Peak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} peak of ", self.metric.name())?;
+        if self.metric.is_memory() {
+            write!(f, "{}kB", self.value)?;
+        } else {
+            write!(f, "{:.1}%", self.value)?;
+        }
+        write!(f, " at {:.2}s lasting {:.2}s", self.ts, self.duration)
+    }
+}
+
+/// Peaks of the CPU utilization and RSS, given as their totals over time,
+/// the `count` highest of each.
+pub fn top(cpu: &[(f32, f64)], rss: &[(f32, f64)], count: usize) -> Vec<Peak> {
+    let mut peaks = vec![];
+    for (metric, curve) in [(Metric::Cpu, cpu), (Metric::Rss, rss)] {
+        let mut found = find(metric, curve);
+        found.sort_by(|a, b| b.value.total_cmp(&a.value));
+        found.truncate(count);
+        peaks.extend(found);
+    }
+    peaks
+}
+
+/// Find the peaks of the curve, given as time and value.
+fn find(metric: Metric, curve: &[(f32, f64)]) -> Vec<Peak> {
+    let values: Vec<f64> = curve.iter().map(|&(_, v)| v).collect();
+    let stats = match Stats::of(&values) {
+        Some(stats) if stats.stddev > 0.0 => stats,
+        _ => return vec![],
+    };
+    let threshold = stats.mean + stats.stddev;
+    let mut peaks = vec![];
+    // start of the current stretch above the threshold and its peak
+    let mut current: Option<(f32, Peak)> = None;
+    for &(ts, value) in curve {
+        if value <= threshold {
+            peaks.extend(current.take().map(|(_, peak)| peak));
+            continue;
+        }
+        let (start, peak) = current.get_or_insert((
+            ts,
+            Peak {
+                metric,
+                ts,
+                value,
+                duration: 0.0,
+            },
+        ));
+        if value > peak.value {
+            peak.ts = ts;
+            peak.value = value;
+        }
+        peak.duration = ts - *start;
+    }
+    peaks.extend(current.map(|(_, peak)| peak));
+    peaks
+}
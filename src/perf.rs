@@ -0,0 +1,385 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hardware and software performance counters of processes with
+//! `perf_event_open` (linux only).
+//!
+//! The counters are opened on every thread of a process and inherited by
+//! the threads and children started later, unless the children are followed
+//! and counted on their own. Every sample holds the increase since the
+//! previous one.
+
+// the events are only opened on unix, elsewhere they are just parsed
+#![cfg_attr(not(unix), allow(dead_code))]
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(unix)]
+use std::fs;
+use std::io;
+use std::mem;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::str::FromStr;
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+#[cfg(target_os = "linux")]
+pub(crate) const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 2;
+const INHERIT: u64 = 1 << 1;
+const EXCLUDE_KERNEL: u64 = 1 << 5;
+const EXCLUDE_HV: u64 = 1 << 6;
+
+/// Leading part of `perf_event_attr` up to `PERF_ATTR_SIZE_VER5`.
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct PerfEventAttr {
+    pub kind: u32,
+    pub size: u32,
+    pub config: u64,
+    pub sample_period: u64,
+    pub sample_type: u64,
+    pub read_format: u64,
+    pub flags: u64,
+    pub wakeup_events: u32,
+    pub bp_type: u32,
+    pub config1: u64,
+    pub config2: u64,
+    pub branch_sample_type: u64,
+    pub sample_regs_user: u64,
+    pub sample_stack_user: u32,
+    pub clockid: i32,
+    pub sample_regs_intr: u64,
+    pub aux_watermark: u32,
+    pub sample_max_stack: u16,
+    pub reserved: u16,
+}
+
+impl PerfEventAttr {
+    pub fn new(kind: u32, config: u64) -> Self {
+        PerfEventAttr {
+            kind,
+            size: mem::size_of::<PerfEventAttr>() as u32,
+            config,
+            ..Default::default()
+        }
+    }
+}
+
+/// Open an event on a thread or, with `pid` -1, on a CPU.
+#[cfg(target_os = "linux")]
+pub(crate) fn open_event(attr: &mut PerfEventAttr, pid: i32, cpu: i32) -> io::Result<OwnedFd> {
+    use std::os::fd::FromRawFd;
+
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            attr as *mut PerfEventAttr,
+            pid,
+            cpu,
+            -1,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn open_event(_attr: &mut PerfEventAttr, _pid: i32, _cpu: i32) -> io::Result<OwnedFd> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// A countable event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PerfEvent {
+    Cycles,
+    Instructions,
+    CacheReferences,
+    CacheMisses,
+    BranchInstructions,
+    BranchMisses,
+    /// CPU time in ns
+    TaskClock,
+    PageFaults,
+    ContextSwitches,
+    CpuMigrations,
+}
+
+impl PerfEvent {
+    pub const ALL: [PerfEvent; 10] = [
+        PerfEvent::Cycles,
+        PerfEvent::Instructions,
+        PerfEvent::CacheReferences,
+        PerfEvent::CacheMisses,
+        PerfEvent::BranchInstructions,
+        PerfEvent::BranchMisses,
+        PerfEvent::TaskClock,
+        PerfEvent::PageFaults,
+        PerfEvent::ContextSwitches,
+        PerfEvent::CpuMigrations,
+    ];
+
+    /// Name as used by `perf stat` and accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            PerfEvent::Cycles => "cycles",
+            PerfEvent::Instructions => "instructions",
+            PerfEvent::CacheReferences => "cache-references",
+            PerfEvent::CacheMisses => "cache-misses",
+            PerfEvent::BranchInstructions => "branch-instructions",
+            PerfEvent::BranchMisses => "branch-misses",
+            PerfEvent::TaskClock => "task-clock",
+            PerfEvent::PageFaults => "page-faults",
+            PerfEvent::ContextSwitches => "context-switches",
+            PerfEvent::CpuMigrations => "cpu-migrations",
+        }
+    }
+
+    /// Type and config of the event for `perf_event_open`
+    fn config(self) -> (u32, u64) {
+        match self {
+            PerfEvent::Cycles => (PERF_TYPE_HARDWARE, 0),
+            PerfEvent::Instructions => (PERF_TYPE_HARDWARE, 1),
+            PerfEvent::CacheReferences => (PERF_TYPE_HARDWARE, 2),
+            PerfEvent::CacheMisses => (PERF_TYPE_HARDWARE, 3),
+            PerfEvent::BranchInstructions => (PERF_TYPE_HARDWARE, 4),
+            PerfEvent::BranchMisses => (PERF_TYPE_HARDWARE, 5),
+            PerfEvent::TaskClock => (PERF_TYPE_SOFTWARE, 1),
+            PerfEvent::PageFaults => (PERF_TYPE_SOFTWARE, 2),
+            PerfEvent::ContextSwitches => (PERF_TYPE_SOFTWARE, 3),
+            PerfEvent::CpuMigrations => (PERF_TYPE_SOFTWARE, 4),
+        }
+    }
+}
+
+impl fmt::Display for PerfEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for PerfEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_lowercase();
+        match PerfEvent::ALL.iter().find(|e| e.name() == name) {
+            Some(event) => Ok(*event),
+            // aliases of perf
+            None => match name.as_str() {
+                "cpu-cycles" => Ok(PerfEvent::Cycles),
+                "branches" => Ok(PerfEvent::BranchInstructions),
+                "faults" => Ok(PerfEvent::PageFaults),
+                "cs" => Ok(PerfEvent::ContextSwitches),
+                "migrations" => Ok(PerfEvent::CpuMigrations),
+                _ => bail!(
+                    "Unknown perf event '{}', expected one of {}",
+                    s,
+                    PerfEvent::ALL.map(PerfEvent::name).join(", ")
+                ),
+            },
+        }
+    }
+}
+
+/// Instructions per cycle, if both were counted.
+pub fn ipc(counts: &BTreeMap<PerfEvent, u64>) -> Option<f64> {
+    match (
+        counts.get(&PerfEvent::Instructions),
+        counts.get(&PerfEvent::Cycles),
+    ) {
+        (Some(instructions), Some(cycles)) if *cycles > 0 => {
+            Some(*instructions as f64 / *cycles as f64)
+        }
+        _ => None,
+    }
+}
+
+/// Counters of the events on all threads of a process.
+#[cfg(unix)]
+pub struct PerfCounters {
+    pid: u32,
+    /// Whether threads and children started later are counted by the
+    /// kernel, otherwise new threads are looked for with every sample
+    inherit: bool,
+    tids: Vec<i32>,
+    /// Counters of every thread by event
+    counters: Vec<(PerfEvent, PerfEventAttr, Vec<OwnedFd>)>,
+    last: BTreeMap<PerfEvent, u64>,
+}
+
+#[cfg(unix)]
+impl PerfCounters {
+    /// Open the events on all threads of the process, counting starts now.
+    ///
+    /// With `inherit` the counts of the threads and children started later
+    /// are included, children followed on their own must not inherit them
+    /// to not be counted twice.
+    ///
+    /// Counting in the kernel is skipped where perf_event_paranoid
+    /// only permits user space.
+    pub fn open(pid: u32, events: &[PerfEvent], inherit: bool) -> io::Result<Self> {
+        let mut tids = task_ids(pid)?;
+        if tids.is_empty() {
+            tids.push(pid as i32);
+        }
+        let mut counters = vec![];
+        for event in events {
+            let (kind, config) = event.config();
+            let mut attr = PerfEventAttr::new(kind, config);
+            attr.read_format = PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING;
+            attr.flags = EXCLUDE_HV;
+            if inherit {
+                attr.flags |= INHERIT;
+            }
+            let mut fds = vec![];
+            for tid in &tids {
+                let fd = match open_event(&mut attr, *tid, -1) {
+                    Err(e) if e.raw_os_error() == Some(libc::EACCES) => {
+                        attr.flags |= EXCLUDE_KERNEL;
+                        open_event(&mut attr, *tid, -1)
+                    }
+                    fd => fd,
+                };
+                match fd {
+                    Ok(fd) => fds.push(fd),
+                    // the thread ended in between
+                    Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
+                        return Err(io::Error::new(
+                            e.kind(),
+                            format!("{} is not supported by this CPU or VM", event),
+                        ))
+                    }
+                    Err(e) => {
+                        return Err(io::Error::new(
+                            e.kind(),
+                            format!("Can not count {}: {}", event, e),
+                        ))
+                    }
+                }
+            }
+            counters.push((*event, attr, fds));
+        }
+        Ok(PerfCounters {
+            pid,
+            inherit,
+            tids,
+            counters,
+            last: BTreeMap::new(),
+        })
+    }
+
+    /// Open the events on the threads started since the last call, threads
+    /// which can not be counted are skipped.
+    fn open_new_threads(&mut self) {
+        // the process ended, its threads are counted already
+        let tids = match task_ids(self.pid) {
+            Ok(tids) => tids,
+            Err(_) => return,
+        };
+        for tid in tids {
+            if self.tids.contains(&tid) {
+                continue;
+            }
+            self.tids.push(tid);
+            for (_, attr, fds) in &mut self.counters {
+                if let Ok(fd) = open_event(attr, tid, -1) {
+                    fds.push(fd);
+                }
+            }
+        }
+    }
+
+    /// Process whose threads are counted
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Increase of every event since the previous call or the opening.
+    ///
+    /// Counts are scaled up if the kernel had to multiplex the counters.
+    pub fn sample(&mut self) -> io::Result<BTreeMap<PerfEvent, u64>> {
+        if !self.inherit {
+            self.open_new_threads();
+        }
+        let mut deltas = BTreeMap::new();
+        for (event, _, fds) in &self.counters {
+            let mut total = 0;
+            for fd in fds {
+                // count, time enabled and time running
+                let mut values = [0u64; 3];
+                let len = mem::size_of_val(&values);
+                let read = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        values.as_mut_ptr() as *mut libc::c_void,
+                        len,
+                    )
+                };
+                if read != len as isize {
+                    return Err(io::Error::last_os_error());
+                }
+                let [count, enabled, running] = values;
+                total += if running > 0 && running < enabled {
+                    (count as f64 * enabled as f64 / running as f64) as u64
+                } else {
+                    count
+                };
+            }
+            let last = self.last.insert(*event, total).unwrap_or(0);
+            deltas.insert(*event, total.saturating_sub(last));
+        }
+        Ok(deltas)
+    }
+}
+
+/// Counters of the events on all threads of a process (linux only).
+#[cfg(not(unix))]
+pub struct PerfCounters {
+    pid: u32,
+}
+
+#[cfg(not(unix))]
+impl PerfCounters {
+    pub fn open(_pid: u32, _events: &[PerfEvent], _inherit: bool) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn sample(&mut self) -> io::Result<BTreeMap<PerfEvent, u64>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// IDs of the threads of a process.
+#[cfg(unix)]
+fn task_ids(pid: u32) -> io::Result<Vec<i32>> {
+    Ok(fs::read_dir(format!("/proc/{}/task", pid))?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
@@ -0,0 +1,800 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of gnuplot scripts and plotting of recordings.
+
+#[cfg(feature = "plot")]
+use crate::recording::Entry;
+#[cfg(feature = "plot")]
+use crate::spill;
+use crate::{Event, Metric, Sample, ThreadSample};
+use anyhow::{anyhow, bail, Result};
+#[cfg(feature = "plot")]
+use std::collections::VecDeque;
+use std::fmt::{self, Write as FmtWrite};
+use std::fs::File;
+#[cfg(feature = "plot")]
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "plot")]
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+#[cfg(feature = "plot")]
+use tempfile::NamedTempFile;
+
+/// Reason plotting fails without the plot feature
+#[cfg(not(feature = "plot"))]
+const NO_PLOT: &str =
+    "procrec was built without the plot feature, --print-gnuplot still prints the script";
+
+/// Settings controlling how a recording gets plotted.
+pub struct PlotOptions {
+    /// Metrics to draw, in legend order
+    pub metrics: Vec<Metric>,
+    /// Draw the memory breakdown metrics as stacked areas instead of lines
+    pub stacked: bool,
+    /// Image file to write, an interactive window is opened if omitted
+    pub output: Option<PathBuf>,
+    /// Metrics drawn on the right y-axis, chosen automatically if omitted
+    pub y2_metrics: Option<Vec<Metric>>,
+    /// Range of the left y-axis, chosen by the metrics if omitted
+    pub y1_range: Option<AxisRange>,
+    /// Range of the right y-axis, chosen by the metrics if omitted
+    pub y2_range: Option<AxisRange>,
+    /// Color scheme of the plot
+    pub theme: Theme,
+    /// Metrics whose axis uses a logarithmic scale
+    pub log_scale: Vec<Metric>,
+    /// Window (in samples) of the moving average drawn over the CPU usage
+    pub smooth: Option<usize>,
+    /// Number of busiest threads drawn in their own panel
+    pub threads: usize,
+}
+
+/// Everything drawn in a graph.
+#[derive(Default)]
+pub struct PlotData {
+    pub groups: Vec<Group>,
+    /// Threads drawn in their own panels below the main graph
+    pub threads: Vec<ThreadSeries>,
+    pub events: Vec<Event>,
+    /// File with the samples a recorder moved to disk, the ones of the
+    /// groups and threads are read from it in front of theirs in memory
+    pub spilled: Option<File>,
+}
+
+/// CPU utilization over time of a single thread.
+pub struct ThreadSeries {
+    pub label: String,
+    pub pid: u32,
+    pub tid: u32,
+    /// Timestamp and CPU utilization
+    pub samples: Vec<(f32, f32)>,
+}
+
+/// Select the `count` threads with the highest total CPU utilization.
+pub fn top_threads(threads: &[ThreadSample], count: usize) -> Vec<ThreadSeries> {
+    let mut ids: Vec<(u32, u32)> = threads.iter().map(|t| (t.pid, t.tid)).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let mut series: Vec<(f32, ThreadSeries)> = ids
+        .into_iter()
+        .map(|(pid, tid)| {
+            let samples: Vec<&ThreadSample> = threads
+                .iter()
+                .filter(|t| t.pid == pid && t.tid == tid)
+                .collect();
+            let total = samples.iter().map(|t| t.cpu).sum();
+            let name = samples.last().map(|t| t.name.as_str()).unwrap_or_default();
+            let series = ThreadSeries {
+                label: format!("{} ({})", name, tid),
+                pid,
+                tid,
+                samples: samples.iter().map(|t| (t.ts, t.cpu)).collect(),
+            };
+            (total, series)
+        })
+        .collect();
+    series.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    series.into_iter().take(count).map(|(_, s)| s).collect()
+}
+
+/// Samples drawn as one group of series, e.g. all samples of one process.
+pub struct Group {
+    /// Legend label, used when several groups are plotted
+    pub label: String,
+    pub pid: u32,
+    pub samples: Vec<Sample>,
+}
+
+/// Split a recording into one group per process.
+///
+/// `labels` maps the PIDs to their legend label, unknown PIDs are labeled by
+/// their number.
+pub fn group_by_process(recording: &[Sample], labels: &[(u32, String)]) -> Vec<Group> {
+    let mut pids: Vec<u32> = recording.iter().map(|s| s.pid).collect();
+    pids.sort_unstable();
+    pids.dedup();
+    pids.into_iter()
+        .map(|pid| Group {
+            label: process_label(labels, pid),
+            pid,
+            samples: recording.iter().filter(|s| s.pid == pid).cloned().collect(),
+        })
+        .collect()
+}
+
+/// Legend label of the process, its number if unknown.
+pub(crate) fn process_label(labels: &[(u32, String)], pid: u32) -> String {
+    labels
+        .iter()
+        .find(|(p, _)| *p == pid)
+        .map(|(_, label)| label.clone())
+        .unwrap_or_else(|| pid.to_string())
+}
+
+/// Column of the moving average appended by `write_data`
+const SMOOTH_COLUMN: usize = Metric::ALL.len() + 2;
+
+/// Column of the metric in the data file written by `write_data`
+fn data_column(metric: Metric) -> usize {
+    Metric::ALL
+        .iter()
+        .position(|m| *m == metric)
+        .unwrap_or_default()
+        + 2
+}
+
+/// Color of a plotted series or label.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Color {
+    /// One of the gnuplot default line types
+    LineType(u32),
+    /// Explicit color as `#rrggbb`
+    Rgb(&'static str),
+}
+
+impl Color {
+    /// Line color specification, e.g. for `plot ... with l`
+    fn line(self) -> String {
+        match self {
+            Color::LineType(lt) => format!("lt {}", lt),
+            Color::Rgb(rgb) => format!("lc rgb {:?}", rgb),
+        }
+    }
+
+    /// Text color specification, e.g. for `set ylabel ... tc`
+    fn text(self) -> String {
+        match self {
+            Color::LineType(lt) => format!("lt {}", lt),
+            Color::Rgb(rgb) => format!("rgb {:?}", rgb),
+        }
+    }
+}
+
+/// Presets for the look of the generated plot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Theme {
+    /// gnuplot defaults, good for interactive use
+    Light,
+    /// Dark background for terminals and slides
+    Dark,
+    /// Thin lines and print-friendly colors for documents
+    Paper,
+}
+
+impl Theme {
+    /// Script lines setting up background, border and text colors
+    fn preamble(self) -> &'static str {
+        match self {
+            Theme::Light => "",
+            Theme::Dark => concat!(
+                "set object 1 rectangle from screen 0,0 to screen 1,1 fillcolor rgb \"#1e1e1e\" fillstyle solid noborder behind\n",
+                "set border lc rgb \"#d0d0d0\"\n",
+                "set xtics textcolor rgb \"#d0d0d0\"\n",
+                "set xlabel textcolor rgb \"#d0d0d0\"\n",
+                "set key textcolor rgb \"#d0d0d0\"\n",
+                "set grid lc rgb \"#505050\"\n",
+            ),
+            Theme::Paper => concat!(
+                "set border lw 0.5\n",
+                "set grid lc rgb \"#d0d0d0\" lw 0.5\n",
+                "set xlabel font \"Times,12\"\n",
+            ),
+        }
+    }
+
+    /// Color of event markers
+    fn marker_color(self) -> Color {
+        match self {
+            Theme::Light | Theme::Paper => Color::Rgb("#808080"),
+            Theme::Dark => Color::Rgb("#a0a0a0"),
+        }
+    }
+
+    /// Options appended to the y-axis tics
+    fn tics(self) -> &'static str {
+        match self {
+            Theme::Dark => " textcolor rgb \"#d0d0d0\"",
+            Theme::Light | Theme::Paper => "",
+        }
+    }
+
+    fn line_width(self) -> f32 {
+        match self {
+            Theme::Light | Theme::Dark => 2.0,
+            Theme::Paper => 1.0,
+        }
+    }
+
+    /// Font of the axis labels
+    fn font(self) -> &'static str {
+        match self {
+            Theme::Light | Theme::Dark => ",12",
+            Theme::Paper => "Times,12",
+        }
+    }
+
+    fn metric_color(self, metric: Metric) -> Color {
+        match (self, metric) {
+            (Theme::Light, m) => Color::LineType(m.line_type()),
+            (Theme::Dark, Metric::Cpu) => Color::Rgb("#ff6e6e"),
+            (Theme::Dark, Metric::Rss) => Color::Rgb("#6ec8ff"),
+            (Theme::Dark, Metric::Vsize) => Color::Rgb("#9cff6e"),
+            (Theme::Dark, Metric::Heap) => Color::Rgb("#ffd36e"),
+            (Theme::Dark, Metric::Stack) => Color::Rgb("#d38cff"),
+            (Theme::Dark, Metric::File) => Color::Rgb("#6effd3"),
+            (Theme::Dark, Metric::Anon) => Color::Rgb("#ff9c6e"),
+            (Theme::Paper, Metric::Cpu) => Color::Rgb("#b2182b"),
+            (Theme::Paper, Metric::Rss) => Color::Rgb("#2166ac"),
+            (Theme::Paper, Metric::Vsize) => Color::Rgb("#4d4d4d"),
+            (Theme::Paper, Metric::Heap) => Color::Rgb("#e08214"),
+            (Theme::Paper, Metric::Stack) => Color::Rgb("#762a83"),
+            (Theme::Paper, Metric::File) => Color::Rgb("#1b7837"),
+            (Theme::Paper, Metric::Anon) => Color::Rgb("#8c510a"),
+        }
+    }
+
+    /// Color of the n-th group when comparing several processes or recordings
+    fn process_color(self, n: usize) -> Color {
+        const DARK: [&str; 6] = [
+            "#ff6e6e", "#6ec8ff", "#9cff6e", "#ffd36e", "#d38cff", "#6effd3",
+        ];
+        const PAPER: [&str; 6] = [
+            "#b2182b", "#2166ac", "#1b7837", "#e08214", "#762a83", "#4d4d4d",
+        ];
+        match self {
+            Theme::Light => Color::LineType(n as u32 + 1),
+            Theme::Dark => Color::Rgb(DARK[n % DARK.len()]),
+            Theme::Paper => Color::Rgb(PAPER[n % PAPER.len()]),
+        }
+    }
+}
+
+impl FromStr for Theme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            "paper" => Ok(Theme::Paper),
+            _ => bail!("Unknown plot theme '{}', expected light, dark or paper", s),
+        }
+    }
+}
+
+/// Range of a plot axis, an omitted bound is autoscaled by gnuplot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl FromStr for AxisRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = match s.split_once(':') {
+            Some(bounds) => bounds,
+            None => bail!(
+                "Invalid axis range '{}', expected MIN:MAX (e.g. 0:100 or 0:)",
+                s
+            ),
+        };
+        let bound = |b: &str| -> Result<Option<f64>> {
+            let b = b.trim();
+            if b.is_empty() || b == "*" {
+                Ok(None)
+            } else {
+                b.parse()
+                    .map(Some)
+                    .map_err(|_| anyhow!("Invalid axis bound '{}' in range '{}'", b, s))
+            }
+        };
+        Ok(AxisRange {
+            min: bound(min)?,
+            max: bound(max)?,
+        })
+    }
+}
+
+impl fmt::Display for AxisRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bound = |b: Option<f64>| b.map(|v| v.to_string()).unwrap_or_default();
+        write!(f, "[{}:{}]", bound(self.min), bound(self.max))
+    }
+}
+
+/// Label of an axis showing the given metrics.
+fn axis_label(metrics: &[Metric]) -> String {
+    if metrics.iter().all(|m| *m == Metric::Cpu) {
+        "CPU %".to_string()
+    } else if metrics.iter().all(|m| m.is_memory()) {
+        "Memory (kB)".to_string()
+    } else {
+        let titles: Vec<&str> = metrics.iter().map(|m| m.title()).collect();
+        titles.join(" / ")
+    }
+}
+
+/// Default range of an axis showing the given metrics.
+fn default_range(metrics: &[Metric], log: bool) -> AxisRange {
+    let max = if metrics.iter().all(|m| *m == Metric::Cpu) {
+        Some(100.0)
+    } else {
+        None
+    };
+    // zero can not be shown on a logarithmic axis
+    let min = if log { None } else { Some(0.0) };
+    AxisRange { min, max }
+}
+
+/// Write label, range, scale and tics of the y-axis `axis` ("y" or "y2").
+fn write_axis(
+    script: &mut String,
+    axis: &str,
+    metrics: &[Metric],
+    range: Option<AxisRange>,
+    opts: &PlotOptions,
+) -> Result<()> {
+    let theme = opts.theme;
+    let offset = if axis == "y2" { " offset 1,0" } else { "" };
+    let log = metrics.iter().any(|m| opts.log_scale.contains(m));
+    writeln!(
+        script,
+        "set {}label {:?} tc {} font {:?}{}",
+        axis,
+        axis_label(metrics),
+        theme.metric_color(metrics[0]).text(),
+        theme.font(),
+        offset
+    )?;
+    let range = range.unwrap_or_else(|| default_range(metrics, log));
+    if log {
+        if let Some(min) = range.min.filter(|min| *min <= 0.0) {
+            bail!(
+                "Range of logarithmic {}-axis must start above zero, got {}",
+                axis,
+                min
+            );
+        }
+        writeln!(script, "set logscale {}", axis)?;
+    }
+    writeln!(script, "set {}r{}", axis, range)?;
+    writeln!(script, "set {}tic auto{}", axis, theme.tics())?;
+    Ok(())
+}
+
+/// Select the gnuplot terminal matching the extension of the output file.
+pub fn gnuplot_terminal(output: &Path) -> Result<&'static str> {
+    let ext = output
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match ext.as_deref() {
+        Some("png") => Ok("pngcairo size 1280,720"),
+        Some("svg") => Ok("svg size 1280,720 dynamic"),
+        Some("pdf") => Ok("pdfcairo size 8in,4.5in"),
+        _ => bail!(
+            "Unsupported graph output '{}', use a .png, .svg or .pdf file",
+            output.display()
+        ),
+    }
+}
+
+/// Build the gnuplot script for the given options.
+///
+/// The data file is passed in as gnuplot variable `filename` with one data
+/// block per group followed by one per thread, events are drawn as labeled
+/// vertical lines.
+pub fn gnuplot_script(opts: &PlotOptions, data: &PlotData) -> Result<String> {
+    let groups = data.groups.as_slice();
+    if opts.metrics.is_empty() {
+        bail!("At least one metric must be selected for plotting");
+    }
+    let stacked: Vec<Metric> = opts
+        .metrics
+        .iter()
+        .copied()
+        .filter(|m| opts.stacked && m.is_smaps())
+        .collect();
+    if opts.stacked && stacked.is_empty() {
+        bail!("Stacked plots require memory breakdown metrics, e.g. --plot-metrics cpu,smaps");
+    }
+    if !stacked.is_empty() && groups.len() > 1 {
+        bail!("Stacked plots are only supported for a single process");
+    }
+    let mut script = String::new();
+    if let Some(output) = &opts.output {
+        writeln!(script, "set terminal {}", gnuplot_terminal(output)?)?;
+        writeln!(script, "set output {:?}", output.display().to_string())?;
+    }
+    script.push_str(include_str!("../recording.plot"));
+    writeln!(script, "set datafile missing \"?\"")?;
+    script.push_str(opts.theme.preamble());
+    let theme = opts.theme;
+
+    // By default CPU is drawn on the left axis, memory on the right one.
+    // Without CPU the memory series move to the left axis to avoid an empty
+    // scale.
+    let has_cpu = opts.metrics.contains(&Metric::Cpu);
+    let on_y2 = |m: &Metric| match &opts.y2_metrics {
+        Some(y2) => y2.contains(m),
+        None => has_cpu && m.is_memory(),
+    };
+    let y1: Vec<Metric> = opts.metrics.iter().copied().filter(|m| !on_y2(m)).collect();
+    let y2: Vec<Metric> = opts.metrics.iter().copied().filter(|m| on_y2(m)).collect();
+    if !y1.is_empty() {
+        write_axis(&mut script, "y", &y1, opts.y1_range, opts)?;
+    }
+    if !y2.is_empty() {
+        write_axis(&mut script, "y2", &y2, opts.y2_range, opts)?;
+    }
+    let multi_group = groups.len() > 1;
+    if opts.metrics.len() > 1 || multi_group {
+        writeln!(script, "set key right center # legend placement")?;
+    }
+
+    // Every group is stored as its own data block in the data file, see
+    // `write_data`. With several groups each one gets its own color while
+    // the metrics are told apart by the dash type.
+    let mut series = vec![];
+    // without groups, e.g. when only dumping the script, one block is assumed
+    let labels: Vec<&str> = match groups {
+        [] => vec![""],
+        _ => groups.iter().map(|g| g.label.as_str()).collect(),
+    };
+    for (block, label) in labels.into_iter().enumerate() {
+        for (i, m) in opts.metrics.iter().enumerate() {
+            if stacked.contains(m) {
+                continue;
+            }
+            let axes = if on_y2(m) { " axes x1y2" } else { "" };
+            let file = if series.is_empty() {
+                "filename"
+            } else {
+                "\"\""
+            };
+            let style = if multi_group {
+                format!("{} dt {}", theme.process_color(block).line(), i + 1)
+            } else {
+                theme.metric_color(*m).line()
+            };
+            let title = if multi_group {
+                format!("{} {}", label, m.title())
+            } else {
+                m.title().to_string()
+            };
+            // the raw samples are drawn thinner below their moving average
+            let smoothed = *m == Metric::Cpu && opts.smooth.is_some();
+            let line_width = if smoothed {
+                theme.line_width() / 2.0
+            } else {
+                theme.line_width()
+            };
+            series.push(format!(
+                "{} index {} using 1:{} title {:?} with l {} lw {}{}",
+                file,
+                block,
+                data_column(*m),
+                title,
+                style,
+                line_width,
+                axes
+            ));
+            if let Some(window) = opts.smooth.filter(|_| smoothed) {
+                series.push(format!(
+                    "\"\" index {} using 1:{} title \"{} (avg {})\" with l {} lw {}{}",
+                    block,
+                    SMOOTH_COLUMN,
+                    title,
+                    window,
+                    style,
+                    theme.line_width() + 1.0,
+                    axes
+                ));
+            }
+        }
+
+        // Stacked areas are drawn from the top, every area covers the sum of
+        // its own and all following metrics.
+        for n in (0..stacked.len()).rev() {
+            let m = stacked[n];
+            let sum: Vec<String> = stacked[..=n]
+                .iter()
+                .map(|m| format!("${}", data_column(*m)))
+                .collect();
+            let file = if series.is_empty() {
+                "filename"
+            } else {
+                "\"\""
+            };
+            let axes = if on_y2(&m) { " axes x1y2" } else { "" };
+            series.push(format!(
+                "{} index {} using 1:({}) title {:?} with filledcurves x1 {} fs solid 0.6{}",
+                file,
+                block,
+                sum.join("+"),
+                m.title(),
+                theme.metric_color(m).line(),
+                axes
+            ));
+        }
+    }
+    for event in &data.events {
+        writeln!(
+            script,
+            "set arrow from {ts}, graph 0 to {ts}, graph 1 nohead {} dt 2",
+            theme.marker_color().line(),
+            ts = event.ts
+        )?;
+        writeln!(
+            script,
+            "set label {:?} at {}, graph 1 rotate by 90 right offset -1,-0.5 tc {} font \",8\"",
+            event.label,
+            event.ts,
+            theme.marker_color().text()
+        )?;
+    }
+    if !data.threads.is_empty() {
+        writeln!(script, "set multiplot layout {},1", data.threads.len() + 1)?;
+    }
+    writeln!(script, "plot    {}", series.join(", \\\n        "))?;
+
+    // One panel per thread with the CPU utilization only
+    if !data.threads.is_empty() {
+        writeln!(
+            script,
+            "unset y2label; unset y2tics; unset logscale; unset key"
+        )?;
+        writeln!(
+            script,
+            "set ylabel \"CPU %\" tc {} font {:?}",
+            theme.metric_color(Metric::Cpu).text(),
+            theme.font()
+        )?;
+        writeln!(script, "set yr[0:*]")?;
+        writeln!(script, "set ytic auto{}", theme.tics())?;
+        for (n, thread) in data.threads.iter().enumerate() {
+            writeln!(
+                script,
+                "set title {:?} font {:?}",
+                thread.label,
+                theme.font()
+            )?;
+            writeln!(
+                script,
+                "plot filename index {} using 1:2 with l {} lw {}",
+                groups.len().max(1) + n,
+                theme.process_color(n).line(),
+                theme.line_width()
+            )?;
+        }
+        writeln!(script, "unset multiplot")?;
+    }
+    Ok(script)
+}
+
+/// Write the samples as gnuplot data file with one data block per group and
+/// one per thread.
+///
+/// Every line of a group holds the timestamp followed by all metrics in the order of
+/// `Metric::ALL`, metrics that were not recorded are written as missing.
+/// With smoothing enabled the moving average of the CPU usage is appended as
+/// additional column.
+#[cfg(feature = "plot")]
+fn write_data<W: Write>(out: &mut W, data: &PlotData, opts: &PlotOptions) -> Result<()> {
+    for group in &data.groups {
+        let mut smooth = opts.smooth.map(RunningAverage::new);
+        let mut write = |sample: &Sample| -> io::Result<()> {
+            write!(out, "{}", sample.ts)?;
+            for metric in Metric::ALL.iter() {
+                match metric.value(sample) {
+                    Some(value) => write!(out, " {}", value)?,
+                    None => write!(out, " ?")?,
+                }
+            }
+            if let Some(smooth) = smooth.as_mut() {
+                write!(out, " {}", smooth.push(sample.cpu))?;
+            }
+            writeln!(out)
+        };
+        if let Some(file) = &data.spilled {
+            for entry in spill::read(file)? {
+                match entry? {
+                    Entry::Sample(sample) if sample.pid == group.pid => write(&sample)?,
+                    _ => {}
+                }
+            }
+        }
+        for sample in &group.samples {
+            write(sample)?;
+        }
+        // two blank lines separate gnuplot data blocks
+        writeln!(out, "\n")?;
+    }
+    for thread in &data.threads {
+        if let Some(file) = &data.spilled {
+            for entry in spill::read(file)? {
+                match entry? {
+                    Entry::Thread(t) if t.pid == thread.pid && t.tid == thread.tid => {
+                        writeln!(out, "{} {}", t.ts, t.cpu)?
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (ts, cpu) in &thread.samples {
+            writeln!(out, "{} {}", ts, cpu)?;
+        }
+        writeln!(out, "\n")?;
+    }
+    Ok(())
+}
+
+/// Trailing simple moving average over a window of values.
+#[cfg(feature = "plot")]
+struct RunningAverage {
+    window: usize,
+    values: VecDeque<f32>,
+    sum: f32,
+}
+
+#[cfg(feature = "plot")]
+impl RunningAverage {
+    fn new(window: usize) -> Self {
+        RunningAverage {
+            window: window.max(1),
+            values: VecDeque::new(),
+            sum: 0.0,
+        }
+    }
+
+    /// Add the next value and return the average of the window.
+    fn push(&mut self, value: f32) -> f32 {
+        self.sum += value;
+        self.values.push_back(value);
+        if self.values.len() > self.window {
+            self.sum -= self.values.pop_front().unwrap_or_default();
+        }
+        self.sum / self.values.len() as f32
+    }
+}
+
+/// Plot the recording with gnuplot.
+#[cfg(feature = "plot")]
+pub fn gnuplot_recording(data: &PlotData, opts: &PlotOptions) -> Result<()> {
+    let mut gnuplot_file = NamedTempFile::new()?;
+    gnuplot_file.write_all(gnuplot_script(opts, data)?.as_bytes())?;
+    gnuplot_file.flush()?;
+
+    let mut data_file = NamedTempFile::new()?;
+    write_data(&mut data_file, data, opts)?;
+    data_file.flush()?;
+    let fname_param = format!("filename={:?};", data_file.path().display());
+
+    let output = Command::new("gnuplot")
+        .arg("-e")
+        .arg(fname_param)
+        .arg("-p")
+        .arg(gnuplot_file.path())
+        .output()?;
+
+    if !output.status.success() {
+        io::stdout().write_all(&output.stdout)?;
+        io::stderr().write_all(&output.stderr)?;
+        bail!("gnuplot failed with {}", output.status);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "plot"))]
+pub fn gnuplot_recording(_data: &PlotData, _opts: &PlotOptions) -> Result<()> {
+    bail!(NO_PLOT)
+}
+
+/// gnuplot instance showing a graph that is refreshed during the recording.
+#[cfg(feature = "plot")]
+pub struct LivePlot {
+    gnuplot: Child,
+    stdin: Option<ChildStdin>,
+    data_file: NamedTempFile,
+}
+
+#[cfg(feature = "plot")]
+impl LivePlot {
+    /// Start gnuplot reading its commands from a pipe.
+    pub fn start() -> Result<Self> {
+        let mut gnuplot = Command::new("gnuplot")
+            .arg("-p")
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Can not start gnuplot: {}", e))?;
+        let stdin = gnuplot.stdin.take();
+        Ok(LivePlot {
+            gnuplot,
+            stdin,
+            data_file: NamedTempFile::new()?,
+        })
+    }
+
+    /// Redraw the graph with the samples and events collected so far.
+    pub fn update(&mut self, data: &PlotData, opts: &PlotOptions) -> Result<()> {
+        let script = gnuplot_script(opts, data)?;
+        let file = self.data_file.as_file_mut();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write_data(file, data, opts)?;
+        file.flush()?;
+
+        let stdin = match self.stdin.as_mut() {
+            Some(stdin) => stdin,
+            None => bail!("gnuplot is not running anymore"),
+        };
+        // labels and markers of the previous update must not pile up
+        writeln!(stdin, "reset; unset arrow; unset label; unset object")?;
+        writeln!(stdin, "filename={:?}", self.data_file.path().display())?;
+        stdin.write_all(script.as_bytes())?;
+        stdin.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "plot")]
+impl Drop for LivePlot {
+    fn drop(&mut self) {
+        // closing stdin ends gnuplot, the window persists due to -p
+        self.stdin.take();
+        if let Err(e) = self.gnuplot.wait() {
+            eprintln!("Warning: can not join gnuplot: {}", e);
+        }
+    }
+}
+
+#[cfg(not(feature = "plot"))]
+pub struct LivePlot;
+
+#[cfg(not(feature = "plot"))]
+impl LivePlot {
+    pub fn start() -> Result<Self> {
+        bail!(NO_PLOT)
+    }
+
+    pub fn update(&mut self, _data: &PlotData, _opts: &PlotOptions) -> Result<()> {
+        bail!(NO_PLOT)
+    }
+}
@@ -0,0 +1,505 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Processes tracked by a recording.
+
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+use crate::bsd::{Process, ProcessError, ProcessResult};
+use crate::launch::Launch;
+use crate::recording::ResourceUsage;
+#[cfg(windows)]
+use crate::windows::{Process, ProcessError, ProcessResult};
+use crate::CpuTime;
+use anyhow::{anyhow, bail, Result};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use psutil::process::{Process, ProcessError, ProcessResult};
+use std::io;
+use std::ops::Deref;
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time between two checks whether a stopped process exited.
+const JOIN_POLL: Duration = Duration::from_millis(50);
+
+/// Source of the CPU and memory usage of the processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// psutil on linux and macos, the Win32 API on windows, the kinfo_proc
+    /// of the kernel on FreeBSD and OpenBSD
+    #[default]
+    Psutil,
+    /// `/proc` files opened once and read without allocations, for high
+    /// sampling rates (linux only)
+    Raw,
+    /// CPU time accounted by the scheduler with eBPF, exact for bursts
+    /// shorter than a clock tick, memory is polled with psutil (linux only,
+    /// requires root and the `ebpf` feature)
+    #[cfg(all(feature = "ebpf", target_os = "linux"))]
+    Ebpf,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "psutil" => Ok(Backend::Psutil),
+            "raw" => Ok(Backend::Raw),
+            #[cfg(all(feature = "ebpf", target_os = "linux"))]
+            "ebpf" => Ok(Backend::Ebpf),
+            #[cfg(not(all(feature = "ebpf", target_os = "linux")))]
+            "ebpf" => {
+                bail!("The ebpf backend requires linux and procrec built with the ebpf feature")
+            }
+            _ => bail!("Invalid backend '{}', expected psutil, raw or ebpf", s),
+        }
+    }
+}
+
+/// Whether the error stems from the process having ended in the meantime.
+#[cfg(not(windows))]
+pub(crate) fn is_gone(e: &ProcessError) -> bool {
+    matches!(
+        e,
+        ProcessError::NoSuchProcess { .. } | ProcessError::ZombieProcess { .. }
+    )
+}
+
+/// Whether the error stems from the process having ended in the meantime.
+#[cfg(windows)]
+pub(crate) fn is_gone(e: &ProcessError) -> bool {
+    matches!(e, ProcessError::NoSuchProcess { .. })
+}
+
+/// Whether reading a `/proc` file of the process failed as it ended.
+pub(crate) fn is_gone_io(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    let gone = e.raw_os_error() == Some(libc::ESRCH);
+    #[cfg(not(unix))]
+    let gone = false;
+    e.kind() == io::ErrorKind::NotFound || gone
+}
+
+#[cfg(target_os = "linux")]
+fn is_zombie(p: &Process) -> bool {
+    matches!(p.status(), Ok(psutil::process::Status::Zombie))
+}
+
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+fn is_zombie(p: &Process) -> bool {
+    p.is_zombie()
+}
+
+// psutil lacks the status on macos, the process is gone once reaped, and
+// windows has no zombies
+#[cfg(any(target_os = "macos", windows))]
+fn is_zombie(_p: &Process) -> bool {
+    false
+}
+
+/// Printed when the metrics of a process are denied.
+pub(crate) const PERMISSION_HINT: &str = "Hint: processes of other users can only be inspected with CAP_SYS_PTRACE, e.g. with sudo, perf counters on them also need CAP_PERFMON or kernel.perf_event_paranoid <= 1";
+
+/// Define a struct to carry the information about the process
+/// to track. The process can be either external or internal.
+///
+/// This enum dereferences to the psutil::Process, or its counterparts in
+/// `windows` and `bsd`, to gather information about system usage.
+pub enum TrackedProcess {
+    /// An external process was started outside of this program and
+    /// submitted using the --pid parameter.
+    External(Process),
+    /// An internal process is started by procrec as a fork and requires
+    /// joining the forked process.
+    Internal(Process, std::process::Child),
+}
+
+impl TrackedProcess {
+    /// Attach to an already running process.
+    pub fn attach(pid: u32) -> Result<Self> {
+        match Process::new(pid) {
+            Ok(p) => Ok(TrackedProcess::External(p)),
+            Err(e @ ProcessError::AccessDenied { .. }) => Err(anyhow!(
+                "Failed accessing process: {}\n{}",
+                e,
+                PERMISSION_HINT
+            )),
+            Err(e) => Err(anyhow!("Failed accessing process: {}", e)),
+        }
+    }
+
+    /// Execute the given command line and track the created process.
+    pub fn launch(cl: &[String]) -> Result<Self> {
+        Launch::new(cl.to_vec()).spawn()
+    }
+
+    /// Spawn the command and track the created process.
+    pub fn spawn(mut cmd: Command) -> Result<Self> {
+        match spawn_child(&mut cmd) {
+            Ok(c) => TrackedProcess::from_child(c),
+            Err(e) => {
+                bail!("Can not execute command: {}", e);
+            }
+        }
+    }
+
+    /// Track a spawned child process.
+    pub fn from_child(c: std::process::Child) -> Result<Self> {
+        #[cfg(windows)]
+        let process = Process::launched(&c);
+        #[cfg(not(windows))]
+        let process = Process::new(c.id());
+        match process {
+            Ok(p) => Ok(TrackedProcess::Internal(p, c)),
+            Err(e) => Err(anyhow!("Failed access created process: {}", e)),
+        }
+    }
+
+    /// Name used to label the process in plots, falls back to the PID.
+    pub fn label(&self) -> String {
+        match self.name() {
+            Ok(name) => format!("{} ({})", name, self.pid()),
+            Err(_) => self.pid().to_string(),
+        }
+    }
+    /// Wraps around the internal process.cpu_percent() because
+    /// value needs to be mutable.
+    pub fn cpu_percent(&mut self) -> ProcessResult<f32> {
+        let percent = match self {
+            TrackedProcess::External(p) => p.cpu_percent(),
+            TrackedProcess::Internal(p, _) => p.cpu_percent(),
+        };
+        // psutil takes the ticks for nanoseconds
+        #[cfg(target_os = "macos")]
+        let percent = percent.map(|percent| percent * crate::darwin::ns_per_tick() as f32);
+        percent
+    }
+
+    /// Command line of the process, empty if not accessible.
+    pub fn cmdline(&self) -> Vec<String> {
+        #[cfg(target_os = "macos")]
+        let cmdline = crate::darwin::cmdline(self.pid());
+        #[cfg(not(target_os = "macos"))]
+        let cmdline = self.cmdline_vec().ok().flatten();
+        cmdline.unwrap_or_default()
+    }
+
+    /// PID of the parent process.
+    pub fn parent(&self) -> Option<u32> {
+        #[cfg(target_os = "macos")]
+        {
+            crate::darwin::ppid(self.pid())
+        }
+        #[cfg(not(target_os = "macos"))]
+        {
+            self.ppid().ok().flatten()
+        }
+    }
+
+    /// Physical footprint in kB, the memory the process is charged for
+    /// including compressed and swapped out pages (macos only).
+    pub fn footprint(&self) -> Option<u64> {
+        #[cfg(target_os = "macos")]
+        {
+            crate::darwin::footprint(self.pid()).map(|bytes| bytes / 1000)
+        }
+        #[cfg(not(target_os = "macos"))]
+        None
+    }
+
+    /// CPU time consumed so far, optionally including the children the
+    /// process waited for.
+    pub fn cpu_time(&self, with_children: bool) -> Option<CpuTime> {
+        // psutil gets neither the units nor the children right on macos
+        #[cfg(target_os = "macos")]
+        {
+            crate::darwin::cpu_time(self.pid(), with_children)
+        }
+        #[cfg(not(target_os = "macos"))]
+        {
+            let times = self.cpu_times().ok()?;
+            let mut cpu_time = CpuTime {
+                user: times.user().as_secs_f64(),
+                system: times.system().as_secs_f64(),
+            };
+            if with_children {
+                cpu_time.user += times.children_user().as_secs_f64();
+                cpu_time.system += times.children_system().as_secs_f64();
+            }
+            Some(cpu_time)
+        }
+    }
+
+    /// Highest RSS and virtual memory size in kB so far, from VmHWM and
+    /// VmPeak on linux, the peak working set and private bytes on windows.
+    pub fn memory_peaks(&self) -> Option<(u64, u64)> {
+        #[cfg(windows)]
+        {
+            let info = self.memory_info().ok()?;
+            Some((info.peak_rss() / 1000, info.peak_vms() / 1000))
+        }
+        #[cfg(not(windows))]
+        status_peaks(self.pid())
+    }
+
+    /// Whether the process was launched by procrec.
+    pub fn is_launched(&self) -> bool {
+        matches!(self, TrackedProcess::Internal(..))
+    }
+
+    /// Check if the tracked process is still running
+    pub fn is_running(&mut self) -> bool {
+        match self {
+            // For an internal process, check if we can join the child-process
+            // Unless the child-process is joined, it will be reported as "running"
+            TrackedProcess::Internal(_, ref mut c) => match c.try_wait() {
+                // it can not be waited for anymore, e.g. reaped elsewhere
+                Err(_) => false,
+                Ok(Some(_exit_status)) => false, // exit status is irrelevant for the tracking
+                Ok(None) => true,
+            },
+            // For external process, rely on psutils to check process status,
+            // a zombie has ended and only waits for its parent
+            TrackedProcess::External(p) => p.is_running() && !is_zombie(p),
+        }
+    }
+
+    /// Bytes read and written by the process so far, including pipes and
+    /// sockets (linux and windows).
+    pub fn io_bytes(&self) -> Option<u64> {
+        #[cfg(windows)]
+        return self.deref().io_bytes();
+        #[cfg(not(windows))]
+        if cfg!(target_os = "linux") {
+            let io = std::fs::read_to_string(format!("/proc/{}/io", self.pid())).ok()?;
+            io.lines()
+                .filter_map(|line| match line.split_once(':') {
+                    Some(("rchar", value)) | Some(("wchar", value)) => value.trim().parse().ok(),
+                    _ => None,
+                })
+                .reduce(|a: u64, b| a + b)
+        } else {
+            None
+        }
+    }
+
+    /// Exit status of a launched process once it ended, unknown for attached
+    /// processes.
+    pub fn exit_status(&mut self) -> Option<ExitStatus> {
+        match self {
+            TrackedProcess::Internal(_, ref mut c) => c.try_wait().ok().flatten(),
+            TrackedProcess::External(_) => None,
+        }
+    }
+
+    /// Send a signal to the process, on windows any signal terminates it
+    /// and the job of a launched process.
+    pub fn signal(&self, signal: i32) -> Result<()> {
+        #[cfg(unix)]
+        {
+            if unsafe { libc::kill(self.pid() as i32, signal) } != 0 {
+                bail!(
+                    "Can not signal process {}: {}",
+                    self.pid(),
+                    std::io::Error::last_os_error()
+                );
+            }
+            Ok(())
+        }
+        #[cfg(windows)]
+        {
+            let _ = signal;
+            if let Err(e) = self.kill() {
+                bail!("Can not terminate process {}: {}", self.pid(), e);
+            }
+            Ok(())
+        }
+    }
+
+    /// Wait for a launched process to exit until the deadline and kill it
+    /// if it is still running then.
+    pub fn join_until(&mut self, deadline: Instant) {
+        if let TrackedProcess::Internal(_, ref mut c) = self {
+            while Instant::now() < deadline {
+                match c.try_wait() {
+                    Ok(None) => thread::sleep(JOIN_POLL),
+                    _ => return,
+                }
+            }
+            if let Err(e) = c.kill() {
+                eprintln!("Warning: can not kill child process: {}", e);
+            } else if let Err(e) = c.wait() {
+                eprintln!(
+                    "Warning: Can not join the child process after killing it: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Stop managing a launched process, it is neither killed nor joined
+    /// when dropped and keeps running after procrec exited.
+    pub fn disown(&mut self) {
+        if let TrackedProcess::Internal(p, _) = self {
+            #[cfg(windows)]
+            p.disown_job();
+            let external = TrackedProcess::External(p.clone());
+            // dropping it would kill the child, the forgotten handle only
+            // holds its PID
+            std::mem::forget(std::mem::replace(self, external));
+        }
+    }
+
+    /// Resource usage of a launched process which exited but was not joined
+    /// yet, including the descendants it waited for (linux only).
+    ///
+    /// Unlike the memory peaks of a running process it covers a burst right
+    /// before the exit.
+    pub(crate) fn exited_usage(&self) -> Option<ResourceUsage> {
+        #[cfg(target_os = "linux")]
+        {
+            if !self.is_launched() {
+                return None;
+            }
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+            // the waitid syscall, unlike the libc wrapper, reports the rusage
+            // and WNOWAIT leaves the child to be joined
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_waitid,
+                    libc::P_PID,
+                    self.pid(),
+                    &mut info as *mut libc::siginfo_t,
+                    libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+                    &mut usage as *mut libc::rusage,
+                )
+            };
+            if res != 0 || unsafe { info.si_pid() } == 0 {
+                return None;
+            }
+            let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+            Some(ResourceUsage {
+                max_rss: kib_to_kb(usage.ru_maxrss as u64),
+                user: seconds(usage.ru_utime),
+                system: seconds(usage.ru_stime),
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        None
+    }
+}
+
+/// Spawn the command, on windows suspended until it is in its job, see
+/// `TrackedProcess::from_child`.
+pub(crate) fn spawn_child(cmd: &mut Command) -> io::Result<std::process::Child> {
+    #[cfg(windows)]
+    return crate::windows::spawn_suspended(cmd);
+    #[cfg(not(windows))]
+    cmd.spawn()
+}
+
+/// Highest RSS of procrec itself in kB, which the RSS of a launched process
+/// starts from.
+pub(crate) fn own_peak_rss() -> Option<u64> {
+    status_peaks(std::process::id()).map(|(rss, _)| rss)
+}
+
+/// VmHWM and VmPeak of a process in kB.
+fn status_peaks(pid: u32) -> Option<(u64, u64)> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            .map(kib_to_kb)
+    };
+    Some((field("VmHWM:")?, field("VmPeak:")?))
+}
+
+/// The kernel counts in units of 1024 bytes, the samples in 1000 bytes.
+pub(crate) fn kib_to_kb(kib: u64) -> u64 {
+    kib * 1024 / 1000
+}
+
+/// PIDs of all descendants of the processes which are not among them.
+pub(crate) fn descendants(pids: &[u32]) -> Vec<u32> {
+    #[cfg(target_os = "macos")]
+    let parents = crate::darwin::parents();
+    #[cfg(windows)]
+    let parents = crate::windows::parents();
+    #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+    let parents = crate::bsd::parents();
+    #[cfg(target_os = "linux")]
+    let parents: Vec<(u32, u32)> = psutil::process::processes()
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| Some((p.pid(), p.ppid().ok()??)))
+        .collect();
+    let mut tree = pids.to_vec();
+    let mut found = vec![];
+    // grandchildren may precede their parents in the list
+    loop {
+        let new: Vec<u32> = parents
+            .iter()
+            .filter(|(pid, ppid)| tree.contains(ppid) && !tree.contains(pid))
+            .map(|(pid, _)| *pid)
+            .collect();
+        if new.is_empty() {
+            return found;
+        }
+        tree.extend(&new);
+        found.extend(new);
+    }
+}
+
+impl Deref for TrackedProcess {
+    type Target = Process;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            TrackedProcess::Internal(p, _) => p,
+            TrackedProcess::External(p) => p,
+        }
+    }
+}
+
+// Implement a custom handler to clean up the child-process of an internal process
+impl Drop for TrackedProcess {
+    fn drop(&mut self) {
+        // If we have forked a child process, we need to kill and clean up,
+        // the recorder gave it the chance to shut down gracefully already
+        if self.is_running() {
+            if let TrackedProcess::Internal(_, ref mut c) = self {
+                if let Err(e) = c.kill() {
+                    eprintln!("Warning: can not kill child process: {}", e);
+                } else if let Err(e) = c.wait() {
+                    eprintln!(
+                        "Warning: Can not join the child process after killing it: {}",
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
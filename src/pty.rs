@@ -0,0 +1,179 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pseudo terminals for launched commands which need a TTY (unix only).
+
+use anyhow::{anyhow, bail, Result};
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, Once};
+use std::thread;
+
+/// Settings of procrec's terminal before it was switched to raw mode.
+static SAVED: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+/// The controlling side of a pseudo terminal.
+pub(crate) struct Pty {
+    pub master: File,
+    slave: File,
+}
+
+impl Pty {
+    /// Open a pseudo terminal with the window size of procrec's terminal.
+    pub fn open() -> Result<Pty> {
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+        if fd < 0 {
+            bail!(
+                "Can not open a pseudo terminal: {}",
+                io::Error::last_os_error()
+            );
+        }
+        let master = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            bail!(
+                "Can not unlock the pseudo terminal: {}",
+                io::Error::last_os_error()
+            );
+        }
+        let name = slave_name(fd)?;
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&name)
+            .map_err(|e| anyhow!("Can not open '{}': {}", name, e))?;
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 {
+            unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) };
+        }
+        Ok(Pty { master, slave })
+    }
+
+    /// Make the terminal the stdin, stdout, stderr and controlling terminal
+    /// of the command in a new session.
+    pub fn attach(self, cmd: &mut Command) -> Result<File> {
+        cmd.stdin(Stdio::from(self.slave.try_clone()?))
+            .stdout(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave));
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(self.master)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn slave_name(fd: libc::c_int) -> Result<String> {
+    let mut buf = [0 as libc::c_char; 128];
+    if unsafe { libc::ptsname_r(fd, buf.as_mut_ptr(), buf.len()) } != 0 {
+        bail!(
+            "Can not name the pseudo terminal: {}",
+            io::Error::last_os_error()
+        );
+    }
+    Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn slave_name(fd: libc::c_int) -> Result<String> {
+    // not thread safe, but only procrec opens terminals
+    let name = unsafe { libc::ptsname(fd) };
+    if name.is_null() {
+        bail!(
+            "Can not name the pseudo terminal: {}",
+            io::Error::last_os_error()
+        );
+    }
+    Ok(unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// Pass on what is typed in procrec's terminal, which is switched to raw
+/// mode so keys like Ctrl-C reach the command unchanged.
+pub(crate) fn forward_input(mut master: File) {
+    let stdin = libc::STDIN_FILENO;
+    if unsafe { libc::isatty(stdin) } == 1 {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(stdin, &mut termios) } == 0 {
+            SAVED.lock().unwrap().get_or_insert(termios);
+            let mut raw = termios;
+            unsafe {
+                libc::cfmakeraw(&mut raw);
+                libc::tcsetattr(stdin, libc::TCSANOW, &raw);
+            }
+            static RESTORE_AT_EXIT: Once = Once::new();
+            RESTORE_AT_EXIT.call_once(|| {
+                extern "C" fn restore() {
+                    restore_terminal();
+                }
+                unsafe { libc::atexit(restore) };
+            });
+        }
+    }
+    thread::spawn(move || {
+        let mut buf = [0; 1024];
+        loop {
+            match io::stdin().read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if master.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Pass on the output of the command unbuffered, progress bars redraw a
+/// line without ending it.
+pub(crate) fn forward_output<W: Write + Send + 'static>(mut master: File, mut output: W) {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        // reading fails with EIO once the command closed the terminal
+        while let Ok(n) = master.read(&mut buf) {
+            if n == 0
+                || output
+                    .write_all(&buf[..n])
+                    .and_then(|_| output.flush())
+                    .is_err()
+            {
+                break;
+            }
+        }
+        restore_terminal();
+    });
+}
+
+/// Switch procrec's terminal back from raw mode.
+pub(crate) fn restore_terminal() {
+    if let Ok(mut saved) = SAVED.try_lock() {
+        if let Some(termios) = saved.take() {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+        }
+    }
+}
@@ -0,0 +1,166 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Python bindings, enabled by the `python` feature.
+//!
+//! ```python
+//! import procrec
+//!
+//! for sample in procrec.record_iter(1234, interval=0.5):
+//!     print(sample.ts, sample.cpu, sample.rss)
+//! ```
+
+// the code generated by #[pyfunction] triggers this lint
+#![allow(clippy::useless_conversion)]
+
+use crate::{Recorder, RecorderOptions, TrackedProcess};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A single sample, memory in kB.
+#[pyclass(name = "Sample", module = "procrec", frozen)]
+#[derive(Clone)]
+pub struct PySample {
+    #[pyo3(get)]
+    ts: f32,
+    #[pyo3(get)]
+    pid: u32,
+    #[pyo3(get)]
+    cpu: f32,
+    #[pyo3(get)]
+    rss: u64,
+    #[pyo3(get)]
+    vsize: u64,
+}
+
+impl From<&crate::Sample> for PySample {
+    fn from(sample: &crate::Sample) -> Self {
+        PySample {
+            ts: sample.ts,
+            pid: sample.pid,
+            cpu: sample.cpu,
+            rss: sample.rss,
+            vsize: sample.vsize,
+        }
+    }
+}
+
+#[pymethods]
+impl PySample {
+    fn __repr__(&self) -> String {
+        format!(
+            "Sample(ts={:.02}, pid={}, cpu={:.02}, rss={}, vsize={})",
+            self.ts, self.pid, self.cpu, self.rss, self.vsize
+        )
+    }
+}
+
+/// Iterator over the samples of a running recording.
+#[pyclass(name = "SampleIter", module = "procrec")]
+pub struct SampleIter {
+    recorder: Recorder,
+    pending: VecDeque<PySample>,
+    done: bool,
+}
+
+#[pymethods]
+impl SampleIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PySample>> {
+        while self.pending.is_empty() && !self.done {
+            let known = self.recorder.recording().sample_count();
+            // don't block other python threads while waiting for the interval
+            let recorder = &mut self.recorder;
+            let running = py.allow_threads(|| recorder.sample())?;
+            self.done = !running;
+            self.pending.extend(
+                self.recorder
+                    .recording()
+                    .samples_since(known)
+                    .iter()
+                    .map(PySample::from),
+            );
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+fn recorder(pid: u32, interval: f32, duration: Option<f32>) -> PyResult<Recorder> {
+    let interval = Duration::try_from_secs_f32(interval)
+        .ok()
+        .filter(|interval| !interval.is_zero())
+        .ok_or_else(|| PyValueError::new_err(format!("invalid interval {}", interval)))?;
+    let duration = duration
+        .map(|duration| {
+            Duration::try_from_secs_f32(duration)
+                .map_err(|_| PyValueError::new_err(format!("invalid duration {}", duration)))
+        })
+        .transpose()?;
+    let process = TrackedProcess::attach(pid)?;
+    Ok(Recorder::new(
+        vec![process],
+        RecorderOptions {
+            interval,
+            duration,
+            ..RecorderOptions::default()
+        },
+    ))
+}
+
+/// Record the process `pid` until it ended or the duration in seconds passed
+/// and return the list of samples.
+#[pyfunction]
+#[pyo3(signature = (pid, interval = 2.0, duration = None))]
+fn record(
+    py: Python<'_>,
+    pid: u32,
+    interval: f32,
+    duration: Option<f32>,
+) -> PyResult<Vec<PySample>> {
+    let mut recorder = recorder(pid, interval, duration)?;
+    py.allow_threads(|| recorder.run())?;
+    Ok(recorder
+        .recording()
+        .samples
+        .iter()
+        .map(PySample::from)
+        .collect())
+}
+
+/// Like `record`, but yield every sample as soon as it was collected.
+#[pyfunction]
+#[pyo3(signature = (pid, interval = 2.0, duration = None))]
+fn record_iter(pid: u32, interval: f32, duration: Option<f32>) -> PyResult<SampleIter> {
+    Ok(SampleIter {
+        recorder: recorder(pid, interval, duration)?,
+        pending: VecDeque::new(),
+        done: false,
+    })
+}
+
+#[pymodule]
+fn procrec(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySample>()?;
+    m.add_class::<SampleIter>()?;
+    m.add_function(wrap_pyfunction!(record, m)?)?;
+    m.add_function(wrap_pyfunction!(record_iter, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,231 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sampling straight from `/proc/<pid>/stat`, `statm` and `status` (linux
+//! only).
+//!
+//! The files are opened once and read again from the start for every
+//! sample into a fixed buffer, so sampling at a high rate neither allocates
+//! nor resolves paths.
+
+use crate::process::kib_to_kb;
+use crate::CpuTime;
+use std::fs::File;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use std::time::Instant;
+
+/// Large enough for the 52 fields of `stat` even with the longest name.
+const BUF_SIZE: usize = 1024;
+/// Enough of `status` for the memory peaks, which follow the ids and groups.
+const STATUS_BUF_SIZE: usize = 4096;
+
+/// CPU and memory usage of a process since the previous sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawUsage {
+    /// CPU utilization in percent
+    pub cpu: f32,
+    /// Resident set size in kB
+    pub rss: u64,
+    /// Virtual memory size in kB
+    pub vsize: u64,
+}
+
+/// Samples a process from its open `stat`, `statm` and `status` files.
+pub struct RawSampler {
+    pid: u32,
+    stat: File,
+    statm: File,
+    status: File,
+    buf: [u8; BUF_SIZE],
+    status_buf: [u8; STATUS_BUF_SIZE],
+    /// utime, stime, cutime and cstime in clock ticks from the last read of
+    /// `stat`
+    times: [u64; 4],
+    /// Consumed CPU time in clock ticks at the previous sample
+    last_ticks: u64,
+    last_time: Instant,
+    ticks_per_sec: f32,
+    page_kb: f32,
+}
+
+impl RawSampler {
+    /// Open the files of the process, the CPU utilization of the first
+    /// sample is measured from here.
+    pub fn open(pid: u32) -> io::Result<Self> {
+        // SAFETY: sysconf has no preconditions
+        #[cfg(unix)]
+        let (ticks, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        // the files are missing there anyway
+        #[cfg(not(unix))]
+        let (ticks, page_size) = (0, 0);
+        let mut sampler = RawSampler {
+            pid,
+            stat: File::open(format!("/proc/{}/stat", pid))?,
+            statm: File::open(format!("/proc/{}/statm", pid))?,
+            status: File::open(format!("/proc/{}/status", pid))?,
+            buf: [0; BUF_SIZE],
+            status_buf: [0; STATUS_BUF_SIZE],
+            times: [0; 4],
+            last_ticks: 0,
+            last_time: Instant::now(),
+            ticks_per_sec: if ticks > 0 { ticks as f32 } else { 100.0 },
+            page_kb: if page_size > 0 {
+                page_size as f32 / 1000.0
+            } else {
+                4.096
+            },
+        };
+        sampler.read_stat()?;
+        sampler.last_ticks = sampler.times[0] + sampler.times[1];
+        Ok(sampler)
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Read the CPU times again, returns whether the process is still
+    /// running rather than gone or a zombie.
+    pub fn refresh(&mut self) -> bool {
+        matches!(self.read_stat(), Ok(state) if state != b'Z' && state != b'X')
+    }
+
+    /// CPU time consumed up to the last sample or refresh, optionally
+    /// including the children the process waited for.
+    pub fn cpu_time(&self, with_children: bool) -> CpuTime {
+        let [user, system, children_user, children_system] = self.times;
+        let seconds = |ticks: u64| ticks as f64 / f64::from(self.ticks_per_sec);
+        if with_children {
+            CpuTime {
+                user: seconds(user + children_user),
+                system: seconds(system + children_system),
+            }
+        } else {
+            CpuTime {
+                user: seconds(user),
+                system: seconds(system),
+            }
+        }
+    }
+
+    /// VmHWM and VmPeak in kB.
+    pub fn memory_peaks(&mut self) -> Option<(u64, u64)> {
+        let len = read_start(&self.status, &mut self.status_buf).ok()?;
+        let status = &self.status_buf[..len];
+        let field = |name: &[u8]| {
+            let line = status
+                .split(|b| *b == b'\n')
+                .find_map(|line| line.strip_prefix(name))?;
+            // the value is right aligned and followed by the unit
+            let digits = line
+                .split(|b| b.is_ascii_whitespace())
+                .find(|field| !field.is_empty())?;
+            parse_u64(digits).map(kib_to_kb)
+        };
+        Some((field(b"VmHWM:")?, field(b"VmPeak:")?))
+    }
+
+    /// Usage since the previous call, fails once the process is gone.
+    pub fn sample(&mut self) -> io::Result<RawUsage> {
+        self.read_stat()?;
+        let ticks = self.times[0] + self.times[1];
+        let elapsed = self.last_time.elapsed().as_secs_f32();
+        self.last_time = Instant::now();
+        let used = ticks.saturating_sub(self.last_ticks) as f32 / self.ticks_per_sec;
+        self.last_ticks = ticks;
+
+        let len = read_start(&self.statm, &mut self.buf)?;
+        // size and resident pages are the first two fields
+        let mut fields = self.buf[..len]
+            .split(|b| b.is_ascii_whitespace())
+            .map(parse_u64);
+        let (size, resident) = match (fields.next().flatten(), fields.next().flatten()) {
+            (Some(size), Some(resident)) => (size, resident),
+            _ => return Err(invalid(self.pid, "statm")),
+        };
+        Ok(RawUsage {
+            cpu: if elapsed > 0.0 {
+                used / elapsed * 100.0
+            } else {
+                0.0
+            },
+            rss: (resident as f32 * self.page_kb) as u64,
+            vsize: (size as f32 * self.page_kb) as u64,
+        })
+    }
+
+    /// Read utime, stime, cutime and cstime from `stat`, returns the state.
+    fn read_stat(&mut self) -> io::Result<u8> {
+        let len = read_start(&self.stat, &mut self.buf)?;
+        let stat = &self.buf[..len];
+        // the name is enclosed in parentheses and may contain spaces itself
+        let end = match stat.iter().rposition(|b| *b == b')') {
+            Some(end) => end,
+            None => return Err(invalid(self.pid, "stat")),
+        };
+        // fields start with the state (3rd field), utime, stime, cutime and
+        // cstime are 14th to 17th
+        let mut fields = stat[end + 1..]
+            .split(|b| *b == b' ')
+            .filter(|field| !field.is_empty());
+        let state = match fields.next() {
+            Some([state]) => *state,
+            _ => return Err(invalid(self.pid, "stat")),
+        };
+        let mut fields = fields.skip(10).map(parse_u64);
+        for time in self.times.iter_mut() {
+            *time = match fields.next().flatten() {
+                Some(ticks) => ticks,
+                None => return Err(invalid(self.pid, "stat")),
+            };
+        }
+        Ok(state)
+    }
+}
+
+/// Read the file from the start, on unix without moving its offset.
+fn read_start(file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    #[cfg(unix)]
+    return file.read_at(buf, 0);
+    #[cfg(windows)]
+    return file.seek_read(buf, 0);
+}
+
+fn parse_u64(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |n, b| {
+        let digit = (*b as char).to_digit(10)?;
+        n.checked_mul(10)?.checked_add(u64::from(digit))
+    })
+}
+
+fn invalid(pid: u32, file: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unexpected format of /proc/{}/{}", pid, file),
+    )
+}
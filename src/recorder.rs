@@ -0,0 +1,1349 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The recording loop sampling the tracked processes.
+
+use crate::alert::{Alert, Trigger};
+use crate::capture::Capture;
+use crate::cgroup::{Cgroup, Limits};
+use crate::condition::Condition;
+use crate::control::Control;
+#[cfg(all(feature = "ebpf", target_os = "linux"))]
+use crate::ebpf::SchedSampler;
+use crate::hang::Watchdog;
+use crate::hooks::Callback;
+use crate::launch::{CpuSet, IoPriority, Launch, OutputLines};
+use crate::output::Sink;
+use crate::perf::{PerfCounters, PerfEvent};
+use crate::process::{self, Backend};
+use crate::raw::RawSampler;
+use crate::recording::{Metadata, ProcessInfo, Termination};
+use crate::signals;
+use crate::spill::Spill;
+use crate::threads::ThreadSampler;
+use crate::{counters, smaps, Event, Recording, Sample, ThreadSample, TrackedProcess};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Settings of a recording.
+pub struct RecorderOptions {
+    /// Time between two samples
+    pub interval: Duration,
+    /// Stop after this time, record until all processes ended if omitted
+    pub duration: Option<Duration>,
+    /// Terminate the launched command if it runs longer
+    pub timeout: Option<Duration>,
+    /// Record the memory breakdown from smaps (linux only)
+    pub smaps: bool,
+    /// Record the I/O and page fault counters (linux only)
+    pub counters: bool,
+    /// Divide the CPU utilization by the number of CPUs available to
+    /// procrec, see [`Metadata::cpus`]
+    pub cpu_normalize: bool,
+    /// Record the CPU utilization of every thread (linux only)
+    pub threads: bool,
+    /// Record the descendants of the processes as well, marking forks and
+    /// execs
+    pub follow_children: bool,
+    /// Source of the CPU and memory usage
+    pub backend: Backend,
+    /// Signal asking a launched process to shut down, on timeout, a kill
+    /// condition or at the end of the recording
+    pub stop_signal: i32,
+    /// Time a launched process gets to exit after the stop signal before it
+    /// is killed forcibly
+    pub stop_grace: Duration,
+    /// Performance counters recorded as increase per sample (linux only)
+    pub perf: Vec<PerfEvent>,
+    /// Move older samples to a temporary file once more are in memory,
+    /// bounding the memory of long recordings
+    pub memory_samples: Option<usize>,
+}
+
+impl Default for RecorderOptions {
+    fn default() -> Self {
+        RecorderOptions {
+            interval: Duration::from_secs(2),
+            duration: None,
+            timeout: None,
+            smaps: false,
+            counters: false,
+            cpu_normalize: false,
+            threads: false,
+            follow_children: false,
+            backend: Backend::default(),
+            stop_signal: libc::SIGTERM,
+            stop_grace: Duration::from_secs(10),
+            perf: vec![],
+            memory_samples: None,
+        }
+    }
+}
+
+/// Configures a [`Recorder`], the processes are attached or launched by
+/// `build`.
+///
+/// ```no_run
+/// use procrec::{Recorder, output::CsvWriter};
+/// use std::{fs::File, time::Duration};
+///
+/// let mut recorder = Recorder::builder()
+///     .pid(1234)
+///     .interval(Duration::from_millis(500))
+///     .duration(Duration::from_secs(60))
+///     .sink(Box::new(CsvWriter::new(File::create("recording.csv")?)))
+///     .build()?;
+/// recorder.run()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Default)]
+pub struct RecorderBuilder {
+    pids: Vec<u32>,
+    launch: Launch,
+    limits: Limits,
+    isolate_cgroup: bool,
+    procs: Vec<TrackedProcess>,
+    opts: RecorderOptions,
+    sinks: Vec<Box<dyn Sink>>,
+    alerts: Vec<Condition>,
+    hang_after: Option<Duration>,
+    kill_if: Vec<Condition>,
+    stop_when: Vec<Condition>,
+    capture: Option<(Condition, Duration, Duration)>,
+    restarts: Option<u32>,
+}
+
+impl RecorderBuilder {
+    /// Attach to a running process, can be called several times.
+    pub fn pid(mut self, pid: u32) -> Self {
+        self.pids.push(pid);
+        self
+    }
+
+    /// Launch the command with its arguments and record it.
+    pub fn command(mut self, command: Vec<String>) -> Self {
+        self.launch.command = command;
+        self
+    }
+
+    /// Write the stdout and stderr of the command to files in the directory,
+    /// see [`Launch::capture_output`].
+    pub fn capture_output(mut self, dir: PathBuf) -> Self {
+        self.launch.capture_output = Some(dir);
+        self
+    }
+
+    /// Set an environment variable of the command.
+    pub fn env(mut self, key: String, value: String) -> Self {
+        self.launch.env.push((key, value));
+        self
+    }
+
+    /// Run the command in this working directory.
+    pub fn cwd(mut self, cwd: PathBuf) -> Self {
+        self.launch.cwd = Some(cwd);
+        self
+    }
+
+    /// Run the command as this user, see [`Launch::user`].
+    pub fn user(mut self, user: String) -> Self {
+        self.launch.user = Some(user);
+        self
+    }
+
+    /// Pin the command to the CPUs (linux only).
+    pub fn cpuset(mut self, cpus: CpuSet) -> Self {
+        self.launch.cpuset = Some(cpus);
+        self
+    }
+
+    /// Run the command with this niceness, -20 to 19.
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.launch.nice = Some(nice);
+        self
+    }
+
+    /// Run the command in this I/O scheduling class (linux only).
+    pub fn ionice(mut self, priority: IoPriority) -> Self {
+        self.launch.ionice = Some(priority);
+        self
+    }
+
+    /// Limit the memory of the command to `kb` in a fresh cgroup (linux
+    /// only).
+    pub fn limit_memory(mut self, kb: u64) -> Self {
+        self.limits.memory = Some(kb);
+        self
+    }
+
+    /// Limit the command to the time of `cpus` CPUs in a fresh cgroup (linux
+    /// only).
+    pub fn limit_cpus(mut self, cpus: f64) -> Self {
+        self.limits.cpus = Some(cpus);
+        self
+    }
+
+    /// Launch the command in a fresh cgroup and record its CPU time and
+    /// memory use, which covers every process it started (linux only).
+    pub fn isolate_cgroup(mut self, isolate: bool) -> Self {
+        self.isolate_cgroup = isolate;
+        self
+    }
+
+    /// Leave the command running when the recording ends instead of killing
+    /// it, it is started in a session of its own (unix only).
+    pub fn detach(mut self, detach: bool) -> Self {
+        self.launch.detach = detach;
+        self
+    }
+
+    /// Run the command in a pseudo terminal (unix only).
+    pub fn pty(mut self, pty: bool) -> Self {
+        self.launch.pty = pty;
+        self
+    }
+
+    /// Record every line the command writes as event, see
+    /// [`Launch::output_lines`].
+    pub fn output_events(mut self, output_events: bool) -> Self {
+        self.launch.output_lines = output_events.then(OutputLines::default);
+        self
+    }
+
+    /// Record an already tracked process.
+    pub fn process(mut self, process: TrackedProcess) -> Self {
+        self.procs.push(process);
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.opts.interval = interval;
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.opts.duration = Some(duration);
+        self
+    }
+
+    /// Terminate the launched command after this time, like
+    /// [`Recorder::add_kill_if`] does.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.opts.timeout = Some(timeout);
+        self
+    }
+
+    /// Shut the launched command down with this signal, see
+    /// [`RecorderOptions::stop_signal`].
+    pub fn stop_signal(mut self, signal: i32) -> Self {
+        self.opts.stop_signal = signal;
+        self
+    }
+
+    /// Time the launched command gets to exit after the stop signal.
+    pub fn stop_grace(mut self, grace: Duration) -> Self {
+        self.opts.stop_grace = grace;
+        self
+    }
+
+    /// Sample with this backend, see [`Backend`].
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.opts.backend = backend;
+        self
+    }
+
+    pub fn smaps(mut self, smaps: bool) -> Self {
+        self.opts.smaps = smaps;
+        self
+    }
+
+    pub fn counters(mut self, counters: bool) -> Self {
+        self.opts.counters = counters;
+        self
+    }
+
+    /// Count these events on the processes, see [`PerfEvent`].
+    pub fn perf(mut self, events: Vec<PerfEvent>) -> Self {
+        self.opts.perf = events;
+        self
+    }
+
+    /// Keep at most about this many samples, thread samples and events in
+    /// memory, the older ones stay on disk, see `Recording::spilled_samples`.
+    pub fn memory_samples(mut self, samples: Option<usize>) -> Self {
+        self.opts.memory_samples = samples;
+        self
+    }
+
+    pub fn threads(mut self, threads: bool) -> Self {
+        self.opts.threads = threads;
+        self
+    }
+
+    pub fn cpu_normalize(mut self, cpu_normalize: bool) -> Self {
+        self.opts.cpu_normalize = cpu_normalize;
+        self
+    }
+
+    pub fn follow_children(mut self, follow_children: bool) -> Self {
+        self.opts.follow_children = follow_children;
+        self
+    }
+
+    pub fn sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Raise an alert when a process hangs, see [`Recorder::hang_after`].
+    pub fn hang_after(mut self, period: Duration) -> Self {
+        self.hang_after = Some(period);
+        self
+    }
+
+    /// Raise an alert when the condition is breached, see
+    /// [`Recorder::add_alert`].
+    pub fn alert(mut self, condition: Condition) -> Self {
+        self.alerts.push(condition);
+        self
+    }
+
+    /// Terminate a process breaching the condition, see
+    /// [`Recorder::add_kill_if`].
+    pub fn kill_if(mut self, condition: Condition) -> Self {
+        self.kill_if.push(condition);
+        self
+    }
+
+    /// End the recording on the condition, see [`Recorder::add_stop_when`].
+    pub fn stop_when(mut self, condition: Condition) -> Self {
+        self.stop_when.push(condition);
+        self
+    }
+
+    /// Record only around the condition, see [`Recorder::capture_when`].
+    pub fn capture_when(mut self, condition: Condition, pre: Duration, post: Duration) -> Self {
+        self.capture = Some((condition, pre, post));
+        self
+    }
+
+    /// Launch the command again when it failed, at most `restarts` times.
+    pub fn restart_on_failure(mut self, restarts: u32) -> Self {
+        self.restarts = Some(restarts);
+        self
+    }
+
+    /// Call `callback` with every new sample, see [`Recorder::on_sample`].
+    pub fn on_sample<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&Sample) -> Result<()> + Send + 'static,
+    {
+        self.sink(Box::new(Callback(callback)))
+    }
+
+    /// Attach to the processes and launch the command.
+    pub fn build(mut self) -> Result<Recorder> {
+        let mut procs = self.procs;
+        for pid in self.pids {
+            procs.push(TrackedProcess::attach(pid)?);
+        }
+        // without any process the command is required, launch reports it
+        let cgroup = if self.limits.is_empty() && !self.isolate_cgroup {
+            None
+        } else {
+            Some(Arc::new(Cgroup::create(&self.limits, self.isolate_cgroup)?))
+        };
+        self.launch.cgroup = cgroup.clone();
+        if !self.launch.command.is_empty() || procs.is_empty() {
+            procs.push(self.launch.spawn()?);
+        }
+        let mut recorder = Recorder::new(procs, self.opts);
+        recorder.cgroup = cgroup;
+        recorder.detach = self.launch.detach;
+        recorder.output_lines = self.launch.output_lines.clone();
+        if let Some(restarts) = self.restarts {
+            recorder.restart = Some((self.launch, restarts));
+        }
+        recorder.sinks = self.sinks;
+        if let Some(period) = self.hang_after {
+            recorder.hang_after(period);
+        }
+        for condition in self.alerts {
+            recorder.add_alert(condition);
+        }
+        for condition in self.kill_if {
+            recorder.add_kill_if(condition);
+        }
+        for condition in self.stop_when {
+            recorder.add_stop_when(condition);
+        }
+        if let Some((condition, pre, post)) = self.capture {
+            recorder.capture_when(condition, pre, post);
+        }
+        Ok(recorder)
+    }
+}
+
+/// Samples a set of processes in a fixed interval.
+///
+/// ```no_run
+/// use procrec::{Recorder, RecorderOptions, TrackedProcess};
+///
+/// let process = TrackedProcess::attach(1234)?;
+/// let mut recorder = Recorder::new(vec![process], RecorderOptions::default());
+/// recorder.run()?;
+/// for sample in &recorder.recording().samples {
+///     println!("{}", sample);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Recorder {
+    procs: Vec<TrackedProcess>,
+    thread_samplers: Vec<ThreadSampler>,
+    raw_samplers: Vec<RawSampler>,
+    perf_counters: Vec<PerfCounters>,
+    /// The hint on missing permissions was printed
+    hinted: bool,
+    /// Started with the first sample of the ebpf backend
+    #[cfg(all(feature = "ebpf", target_os = "linux"))]
+    sched: Option<SchedSampler>,
+    opts: RecorderOptions,
+    recording: Recording,
+    sinks: Vec<Box<dyn Sink>>,
+    start: Option<Instant>,
+    control: Control,
+    alerts: Vec<Trigger>,
+    // alerts fired in the current round, passed on to the sinks
+    fired: Vec<Alert>,
+    // all alerts passed on so far
+    raised: Vec<Alert>,
+    hang: Option<Watchdog>,
+    kill_if: Vec<Trigger>,
+    // processes sent SIGTERM, killed once the grace period passed
+    terminated: Vec<(u32, Instant)>,
+    stop_when: Vec<Trigger>,
+    capture: Option<Capture>,
+    timed_out: bool,
+    // command to launch again on failure and the restarts left
+    restart: Option<(Launch, u32)>,
+    // lines written by the launched command, recorded as events
+    output_lines: Option<OutputLines>,
+    // whether SIGINT or SIGTERM was passed on to the launched command
+    signaled: bool,
+    // launched processes are left running at the end
+    detach: bool,
+    // removed once the processes above are dropped
+    cgroup: Option<Arc<Cgroup>>,
+    // exit status of the launched command once it ended
+    exit_status: Option<ExitStatus>,
+}
+
+impl Recorder {
+    pub fn builder() -> RecorderBuilder {
+        RecorderBuilder::default()
+    }
+
+    pub fn new(mut procs: Vec<TrackedProcess>, opts: RecorderOptions) -> Self {
+        // Fetch the CPU one time set the "baseline"
+        for pid_proc in procs.iter_mut() {
+            let _percent_cpu = pid_proc.cpu_percent();
+        }
+        let mut thread_samplers: Vec<ThreadSampler> = if opts.threads {
+            procs.iter().map(|p| ThreadSampler::new(p.pid())).collect()
+        } else {
+            vec![]
+        };
+        for sampler in thread_samplers.iter_mut() {
+            let _usage = sampler.sample();
+        }
+        let raw_samplers = match opts.backend {
+            Backend::Raw => procs
+                .iter()
+                .filter_map(|p| RawSampler::open(p.pid()).ok())
+                .collect(),
+            _ => vec![],
+        };
+        let pids: Vec<u32> = procs.iter().map(|p| p.pid()).collect();
+        // The name is gone together with the process, fetch it at startup
+        let processes = procs
+            .iter()
+            .map(|p| ProcessInfo {
+                pid: p.pid(),
+                label: p.label(),
+                cmdline: p.cmdline(),
+                ..Default::default()
+            })
+            .collect();
+        let metadata = Metadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            start_time: None,
+            interval: opts.interval.as_secs_f32(),
+            cpus: thread::available_parallelism().ok().map(|n| n.get()),
+            cpu_normalized: opts.cpu_normalize,
+            processes,
+            cgroup: None,
+            wall: None,
+            cold: false,
+        };
+
+        let mut recorder = Recorder {
+            procs,
+            thread_samplers,
+            raw_samplers,
+            perf_counters: vec![],
+            hinted: false,
+            #[cfg(all(feature = "ebpf", target_os = "linux"))]
+            sched: None,
+            opts,
+            recording: Recording {
+                metadata,
+                ..Recording::default()
+            },
+            sinks: vec![],
+            start: None,
+            control: Control::default(),
+            alerts: vec![],
+            fired: vec![],
+            raised: vec![],
+            hang: None,
+            kill_if: vec![],
+            terminated: vec![],
+            stop_when: vec![],
+            capture: None,
+            timed_out: false,
+            restart: None,
+            output_lines: None,
+            signaled: false,
+            detach: false,
+            cgroup: None,
+            exit_status: None,
+        };
+        for pid in pids {
+            recorder.open_metrics(pid);
+        }
+        recorder
+    }
+
+    /// Attach a sink receiving every sample and event once collected.
+    pub fn add_sink(&mut self, sink: Box<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Call `callback` with every new sample as soon as it was collected, an
+    /// error aborts the recording.
+    pub fn on_sample<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Sample) -> Result<()> + Send + 'static,
+    {
+        self.add_sink(Box::new(Callback(callback)));
+    }
+
+    /// Raise an alert whenever a process breaches the condition, which is
+    /// recorded as event and passed to the sinks by `Sink::write_alert`.
+    pub fn add_alert(&mut self, condition: Condition) {
+        self.alerts.push(Trigger::new(condition));
+    }
+
+    /// Raise an alert when a process shows no CPU utilization and no I/O for
+    /// the period while still alive.
+    pub fn hang_after(&mut self, period: Duration) {
+        self.hang = Some(Watchdog::new(period));
+    }
+
+    /// Terminate a process once it breaches the condition, which is
+    /// recorded as event. Processes still running after a grace period of
+    /// 10 seconds are killed.
+    pub fn add_kill_if(&mut self, condition: Condition) {
+        self.kill_if.push(Trigger::new(condition));
+    }
+
+    /// End the recording once any process fulfills the condition, e.g. when
+    /// the busy phase of a job is over with `cpu<1 for 60s`.
+    pub fn add_stop_when(&mut self, condition: Condition) {
+        self.stop_when.push(Trigger::new(condition));
+    }
+
+    /// Keep only the samples while the condition holds for any process,
+    /// along with those of the time `pre` before and `post` after. Alerts
+    /// and other conditions still see every sample.
+    pub fn capture_when(&mut self, condition: Condition, pre: Duration, post: Duration) {
+        let rounds =
+            |d: Duration| (d.as_secs_f64() / self.opts.interval.as_secs_f64()).ceil() as usize;
+        self.capture = Some(Capture::new(condition, rounds(pre), rounds(post)));
+    }
+
+    /// Flag to stop the recording from another thread or a signal handler by
+    /// setting it to `false`.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.control.running.clone()
+    }
+
+    /// Handle to stop, pause and resume the recording and to add markers.
+    pub fn control(&self) -> Control {
+        self.control.clone()
+    }
+
+    /// Time between two samples.
+    pub fn interval(&self) -> Duration {
+        self.opts.interval
+    }
+
+    /// Exit status of the launched command if it ended during the
+    /// recording.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+    }
+
+    /// All alerts raised so far.
+    pub fn alerts(&self) -> &[Alert] {
+        &self.raised
+    }
+
+    /// The data collected so far.
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn into_recording(mut self) -> Recording {
+        std::mem::take(&mut self.recording)
+    }
+
+    /// Record until all processes ended, the duration passed or the
+    /// recording got stopped, then finish the sinks.
+    pub fn run(&mut self) -> Result<()> {
+        while self.sample()? {}
+        self.finish()
+    }
+
+    /// Finish all sinks, call once after `sample` returned `false`.
+    pub fn finish(&mut self) -> Result<()> {
+        // the last lines of the command are read after its exit
+        let events = self.recording.events.len();
+        self.stop_launched();
+        self.record_output_lines();
+        self.record_cgroup();
+        for sink in self.sinks.iter_mut() {
+            for event in &self.recording.events[events..] {
+                sink.write_event(event)?;
+            }
+        }
+        for sink in self.sinks.iter_mut() {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the next interval and sample all processes still running.
+    ///
+    /// Returns `false` once the recording is finished.
+    pub fn sample(&mut self) -> Result<bool> {
+        thread::sleep(self.opts.interval);
+        self.sample_now()
+    }
+
+    /// Sample all processes still running without waiting, the caller is
+    /// responsible for keeping the interval.
+    pub fn sample_now(&mut self) -> Result<bool> {
+        if !self.control.is_running() {
+            return Ok(false);
+        }
+        self.limit_memory()?;
+        let (samples, threads, events) = (
+            self.recording.samples.len(),
+            self.recording.threads.len(),
+            self.recording.events.len(),
+        );
+        let running = self.sample_processes()?;
+        self.record_cgroup();
+
+        // pass on everything collected in this round
+        for sink in self.sinks.iter_mut() {
+            for i in &self.recording.samples[samples..] {
+                sink.write_sample(i)?;
+            }
+            for i in &self.recording.threads[threads..] {
+                sink.write_thread(i)?;
+            }
+            for i in &self.recording.events[events..] {
+                sink.write_event(i)?;
+            }
+        }
+        for alert in self.fired.drain(..) {
+            self.recording.events.push(alert.event());
+            for sink in self.sinks.iter_mut() {
+                sink.write_alert(&alert)?;
+            }
+            self.raised.push(alert);
+        }
+        Ok(running)
+    }
+
+    /// Move all but the newest half of the samples to the spill file of the
+    /// recording once there are more than `memory_samples` in memory.
+    fn limit_memory(&mut self) -> Result<()> {
+        let limit = match self.opts.memory_samples {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        if self.recording.samples.len() <= limit
+            && self.recording.threads.len() <= limit
+            && self.recording.events.len() <= limit
+        {
+            return Ok(());
+        }
+        let mut spill = match self.recording.spill.take() {
+            Some(spill) => spill,
+            None => Spill::create()?,
+        };
+        let moved = spill.write(&mut self.recording, limit / 2);
+        self.recording.spill = Some(spill);
+        moved
+    }
+
+    /// Leave the launched processes still running with `detach`, otherwise
+    /// send them the stop signal and kill them once the grace period passed.
+    fn stop_launched(&mut self) {
+        let mut stopping = vec![];
+        for p in self.procs.iter_mut().filter(|p| p.is_launched()) {
+            if self.detach {
+                p.disown();
+                continue;
+            }
+            if !p.is_running() {
+                continue;
+            }
+            // the grace period of a process stopped before keeps running
+            let since = match self.terminated.iter().find(|(pid, _)| *pid == p.pid()) {
+                Some((_, since)) => *since,
+                None => {
+                    if let Err(err) = p.signal(self.opts.stop_signal) {
+                        eprintln!("Warning: {}", err);
+                    }
+                    Instant::now()
+                }
+            };
+            stopping.push((p.pid(), since));
+        }
+        for (pid, since) in stopping {
+            if let Some(p) = self.procs.iter_mut().find(|p| p.pid() == pid) {
+                p.join_until(since + self.opts.stop_grace);
+            }
+        }
+    }
+
+    /// Keep the counters of an isolating cgroup in the metadata.
+    fn record_cgroup(&mut self) {
+        if let Some(cgroup) = &self.cgroup {
+            self.recording.metadata.cgroup = cgroup.usage();
+        }
+    }
+
+    fn sample_processes(&mut self) -> Result<bool> {
+        let time_since_start = match self.start {
+            Some(start) => start.elapsed().as_secs_f32(),
+            None => {
+                self.start = Some(Instant::now());
+                self.recording.metadata.start_time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_secs_f64());
+                0.0
+            }
+        };
+        if let Some(start) = self.start {
+            for (time, label) in self.control.take_markers() {
+                self.recording.events.push(Event {
+                    ts: time.saturating_duration_since(start).as_secs_f32(),
+                    label,
+                    marker: true,
+                });
+            }
+        }
+        self.record_output_lines();
+        self.forward_signals(time_since_start);
+
+        let processes = &mut self.recording.metadata.processes;
+        let events = &mut self.recording.events;
+        let exit_status = &mut self.exit_status;
+        let terminated = &self.terminated;
+        // followed children are accounted for on their own
+        let with_children = !self.opts.follow_children;
+        let raw_samplers = &mut self.raw_samplers;
+        let mut failed = false;
+        self.procs.retain_mut(|p| {
+            // the raw backend reads from the open files instead of psutil
+            let raw = raw_samplers
+                .iter_mut()
+                .find(|s| s.pid() == p.pid())
+                .map(|sampler| {
+                    let running = sampler.refresh();
+                    let cpu_time = sampler.cpu_time(with_children);
+                    (Some(cpu_time), sampler.memory_peaks(), running)
+                });
+            // a launched process keeps its final CPU time until it is joined
+            let (cpu_time, peaks) = match raw {
+                Some((cpu_time, peaks, _)) => (cpu_time, peaks),
+                None => (p.cpu_time(with_children), p.memory_peaks()),
+            };
+            let usage = p.exited_usage();
+            let alive = match raw {
+                // a launched process is still to be joined
+                Some((_, _, running)) if !p.is_launched() => running,
+                _ => p.is_running(),
+            };
+            if let Some(info) = processes.iter_mut().find(|info| info.pid == p.pid()) {
+                info.cpu_time = cpu_time.or(info.cpu_time);
+                if let Some((rss, vsize)) = peaks {
+                    info.peak_rss = Some(rss);
+                    info.peak_vsize = Some(vsize);
+                }
+                // peaks up to procrec's own may stem from the copy of procrec
+                // the process started as
+                let own_rss = || process::own_peak_rss();
+                if let Some(usage) = usage.filter(|u| Some(u.max_rss) > own_rss()) {
+                    info.peak_rss = info.peak_rss.max(Some(usage.max_rss));
+                }
+            }
+            if !alive {
+                let mut termination = Termination {
+                    ts: time_since_start,
+                    end_time: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .ok()
+                        .map(|d| d.as_secs_f64()),
+                    usage,
+                    ..Default::default()
+                };
+                if let Some(status) = p.exit_status() {
+                    *exit_status = Some(status);
+                    // processes ended on purpose are not restarted
+                    let killed = terminated.iter().any(|(pid, _)| *pid == p.pid());
+                    failed |= !status.success() && !killed;
+                    termination.exit_code = status.code();
+                    #[cfg(unix)]
+                    {
+                        use std::os::unix::process::ExitStatusExt;
+                        termination.signal = status.signal();
+                        termination.core_dumped = status.core_dumped();
+                    }
+                }
+                if let Some(info) = processes.iter_mut().find(|info| info.pid == p.pid()) {
+                    info.termination = Some(termination);
+                }
+                events.push(Event {
+                    ts: time_since_start,
+                    label: format!("{} exited", process_label(processes, p.pid())),
+                    marker: false,
+                });
+            }
+            alive
+        });
+        if failed {
+            self.restart(time_since_start)?;
+        }
+        if self.procs.is_empty() {
+            self.control.stop();
+            return Ok(false);
+        }
+        if self.opts.follow_children {
+            self.follow_children(time_since_start);
+        }
+        if let Some(timeout) = self.opts.timeout {
+            if !self.timed_out && time_since_start >= timeout.as_secs_f32() {
+                self.timed_out = true;
+                self.terminate_launched(time_since_start);
+            }
+        }
+        let procs = &self.procs;
+        let grace = self.opts.stop_grace;
+        self.terminated.retain(|(pid, time)| {
+            let process = match procs.iter().find(|p| p.pid() == *pid) {
+                Some(process) => process,
+                None => return false,
+            };
+            if time.elapsed() < grace {
+                return true;
+            }
+            if let Err(err) = process.kill() {
+                eprintln!("Warning: can not kill process {}: {}", pid, err);
+            }
+            false
+        });
+
+        if self.control.is_paused() {
+            // keep the CPU baselines fresh for the first sample after resuming
+            for pid_proc in self.procs.iter_mut() {
+                let _percent_cpu = pid_proc.cpu_percent();
+            }
+            for sampler in self.thread_samplers.iter_mut() {
+                let _usage = sampler.sample();
+            }
+            for counters in self.perf_counters.iter_mut() {
+                let _counts = counters.sample();
+            }
+        } else {
+            self.push_samples(time_since_start)?;
+            if !self.control.is_running() {
+                return Ok(false);
+            }
+        }
+
+        if let Some(dur) = self.opts.duration {
+            if time_since_start > dur.as_secs_f32() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Pass on the signals caught by [`signals::forward`] to the launched
+    /// command. SIGINT and SIGTERM stop the recording right away if there is
+    /// none or they were passed on before.
+    fn forward_signals(&mut self, time_since_start: f32) {
+        for received in signals::take() {
+            let terminating = matches!(received.signal, libc::SIGINT | libc::SIGTERM);
+            let launched: Vec<u32> = self
+                .procs
+                .iter()
+                .filter(|p| p.is_launched())
+                .map(|p| p.pid())
+                .collect();
+            if terminating && (launched.is_empty() || self.signaled) {
+                self.control.stop();
+                continue;
+            }
+            self.signaled |= terminating;
+            for pid in launched {
+                // the terminal signaled the command itself already
+                #[cfg(unix)]
+                if received.sent && unsafe { libc::kill(pid as i32, received.signal) } != 0 {
+                    eprintln!(
+                        "Warning: can not signal process {}: {}",
+                        pid,
+                        std::io::Error::last_os_error()
+                    );
+                    continue;
+                }
+                if terminating && !self.terminated.iter().any(|(p, _)| *p == pid) {
+                    self.terminated.push((pid, Instant::now()));
+                }
+                self.recording.events.push(Event {
+                    ts: time_since_start,
+                    label: format!(
+                        "{} to {}",
+                        signals::name(received.signal),
+                        process_label(&self.recording.metadata.processes, pid)
+                    ),
+                    marker: false,
+                });
+            }
+        }
+    }
+
+    /// Add the lines the command wrote since the last call as events.
+    fn record_output_lines(&mut self) {
+        let (lines, start) = match (&self.output_lines, self.start) {
+            (Some(lines), Some(start)) => (lines, start),
+            _ => return,
+        };
+        for (time, line) in lines.take() {
+            self.recording.events.push(Event {
+                ts: time.saturating_duration_since(start).as_secs_f32(),
+                label: line,
+                marker: false,
+            });
+        }
+    }
+
+    /// Launch the failed command again if restarts are left.
+    fn restart(&mut self, time_since_start: f32) -> Result<()> {
+        let (launch, left) = match self.restart.as_mut() {
+            Some((launch, left)) if *left > 0 => (launch, left),
+            _ => return Ok(()),
+        };
+        *left -= 1;
+        let process = launch.spawn()?;
+        let label = self.track(process);
+        self.recording.events.push(Event {
+            ts: time_since_start,
+            label: format!("restarted as {}", label),
+            marker: false,
+        });
+        Ok(())
+    }
+
+    /// Mark execs of the processes and start recording new descendants.
+    fn follow_children(&mut self, time_since_start: f32) {
+        for p in self.procs.iter() {
+            let info = self
+                .recording
+                .metadata
+                .processes
+                .iter_mut()
+                .find(|info| info.pid == p.pid());
+            if let Some(info) = info {
+                let label = p.label();
+                if label != info.label {
+                    self.recording.events.push(Event {
+                        ts: time_since_start,
+                        label: format!("{} exec {}", info.label, label),
+                        marker: false,
+                    });
+                    info.label = label;
+                    info.cmdline = p.cmdline();
+                }
+            }
+        }
+        let pids: Vec<u32> = self.procs.iter().map(|p| p.pid()).collect();
+        for pid in process::descendants(&pids) {
+            // the child may be gone already
+            let process = match TrackedProcess::attach(pid) {
+                Ok(process) => process,
+                Err(_) => continue,
+            };
+            let parent = process
+                .parent()
+                .map(|ppid| process_label(&self.recording.metadata.processes, ppid))
+                .unwrap_or_default();
+            let label = self.track(process);
+            self.recording.events.push(Event {
+                ts: time_since_start,
+                label: format!("{} forked {}", parent, label),
+                marker: false,
+            });
+        }
+    }
+
+    /// Start recording another process, returns its label.
+    fn track(&mut self, mut process: TrackedProcess) -> String {
+        let _percent_cpu = process.cpu_percent();
+        if self.opts.backend == Backend::Raw {
+            // the child may be gone already, it is dropped with the next
+            // sample then
+            if let Ok(sampler) = RawSampler::open(process.pid()) {
+                self.raw_samplers.push(sampler);
+            }
+        }
+        if self.opts.threads {
+            let mut sampler = ThreadSampler::new(process.pid());
+            let _usage = sampler.sample();
+            self.thread_samplers.push(sampler);
+        }
+        let label = process.label();
+        self.recording.metadata.processes.push(ProcessInfo {
+            pid: process.pid(),
+            label: label.clone(),
+            cmdline: process.cmdline(),
+            ..Default::default()
+        });
+        self.open_metrics(process.pid());
+        self.procs.push(process);
+        label
+    }
+
+    /// Check the optional metrics of a process and open its perf counters.
+    /// The ones it does not grant access to are reported and noted in the
+    /// metadata, the recording goes on without them.
+    fn open_metrics(&mut self, pid: u32) {
+        let mut unavailable: Vec<(&str, io::Error)> = vec![];
+        if self.opts.smaps {
+            if let Err(e) = smaps::read(pid) {
+                unavailable.push(("smaps", e));
+            }
+        }
+        if self.opts.counters {
+            if let Err(e) = counters::read(pid) {
+                unavailable.push(("counters", e));
+            }
+        }
+        if !self.opts.perf.is_empty() {
+            // followed children are counted on their own
+            let inherit = !self.opts.follow_children;
+            match PerfCounters::open(pid, &self.opts.perf, inherit) {
+                Ok(counters) => self.perf_counters.push(counters),
+                Err(e) => unavailable.push(("perf", e)),
+            }
+        }
+        // a process gone already is dropped with the next sample
+        unavailable.retain(|(_, e)| e.kind() != io::ErrorKind::NotFound);
+        if unavailable.is_empty() {
+            return;
+        }
+        let label = process_label(&self.recording.metadata.processes, pid);
+        for (metric, e) in &unavailable {
+            eprintln!("Warning: no {} for {}: {}", metric, label, e);
+        }
+        if !self.hinted
+            && unavailable
+                .iter()
+                .any(|(_, e)| e.kind() == io::ErrorKind::PermissionDenied)
+        {
+            eprintln!("{}", process::PERMISSION_HINT);
+            self.hinted = true;
+        }
+        if let Some(info) = self
+            .recording
+            .metadata
+            .processes
+            .iter_mut()
+            .find(|info| info.pid == pid)
+        {
+            info.unavailable = unavailable
+                .iter()
+                .map(|(metric, _)| metric.to_string())
+                .collect();
+        }
+    }
+
+    /// Terminate the launched command on timeout.
+    fn terminate_launched(&mut self, time_since_start: f32) {
+        for process in self.procs.iter() {
+            // stopped already by --kill-if or a forwarded signal
+            if self.terminated.iter().any(|(p, _)| *p == process.pid()) {
+                continue;
+            }
+            if let TrackedProcess::Internal(..) = process {
+                if let Err(err) = process.signal(self.opts.stop_signal) {
+                    eprintln!("Warning: {}", err);
+                    continue;
+                }
+                self.terminated.push((process.pid(), Instant::now()));
+                self.recording.events.push(Event {
+                    ts: time_since_start,
+                    label: format!(
+                        "timeout, terminated {}",
+                        process_label(&self.recording.metadata.processes, process.pid())
+                    ),
+                    marker: false,
+                });
+            }
+        }
+    }
+
+    fn push_samples(&mut self, time_since_start: f32) -> Result<()> {
+        let first = self.recording.samples.len();
+        let first_thread = self.recording.threads.len();
+        let mut io = vec![];
+        let cpus = match self.recording.metadata.cpus {
+            Some(cpus) if self.opts.cpu_normalize => cpus as f32,
+            _ => 1.0,
+        };
+        let procs = &self.procs;
+        self.raw_samplers
+            .retain(|sampler| procs.iter().any(|p| p.pid() == sampler.pid()));
+        self.perf_counters
+            .retain(|counters| procs.iter().any(|p| p.pid() == counters.pid()));
+        #[cfg(all(feature = "ebpf", target_os = "linux"))]
+        if let Some(sched) = self.sched.as_mut() {
+            sched.retain(|pid| procs.iter().any(|p| p.pid() == pid));
+        }
+        for pid_proc in self.procs.iter_mut() {
+            let (percent_cpu, rss, vsize) = match self.opts.backend {
+                Backend::Psutil => {
+                    let usage = pid_proc
+                        .cpu_percent()
+                        .and_then(|percent_cpu| Ok((percent_cpu, pid_proc.memory_info()?)));
+                    match usage {
+                        Ok((percent_cpu, cur_mem)) => {
+                            (percent_cpu, cur_mem.rss() / 1000, cur_mem.vms() / 1000)
+                        }
+                        // ended since the check, it is marked as exited with
+                        // the next sample
+                        Err(e) if process::is_gone(&e) => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Backend::Raw => {
+                    let pid = pid_proc.pid();
+                    let sampler = match self.raw_samplers.iter_mut().find(|s| s.pid() == pid) {
+                        Some(sampler) => sampler,
+                        None => continue,
+                    };
+                    match sampler.sample() {
+                        Ok(usage) => (usage.cpu, usage.rss, usage.vsize),
+                        Err(e) if process::is_gone_io(&e) => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                #[cfg(all(feature = "ebpf", target_os = "linux"))]
+                Backend::Ebpf => {
+                    let sched = match &mut self.sched {
+                        Some(sched) => sched,
+                        None => self.sched.insert(SchedSampler::start(self.opts.counters)?),
+                    };
+                    let usage = match sched.cpu_percent(pid_proc.pid()) {
+                        Some(percent_cpu) => Ok(percent_cpu),
+                        // the map is full
+                        None => pid_proc.cpu_percent(),
+                    }
+                    .and_then(|percent_cpu| Ok((percent_cpu, pid_proc.memory_info()?)));
+                    match usage {
+                        Ok((percent_cpu, cur_mem)) => {
+                            (percent_cpu, cur_mem.rss() / 1000, cur_mem.vms() / 1000)
+                        }
+                        Err(e) if process::is_gone(&e) => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            };
+            if self.hang.is_some() {
+                io.push(pid_proc.io_bytes());
+            }
+            // counted in the kernel by the ebpf backend
+            #[cfg(all(feature = "ebpf", target_os = "linux"))]
+            let counted = self
+                .sched
+                .as_ref()
+                .and_then(|sched| sched.counters(pid_proc.pid()));
+            #[cfg(not(all(feature = "ebpf", target_os = "linux")))]
+            let counted = None;
+            self.recording.samples.push(Sample {
+                ts: time_since_start,
+                pid: pid_proc.pid(),
+                cpu: percent_cpu / cpus,
+                rss,
+                vsize,
+                smaps: if self.opts.smaps {
+                    smaps::read(pid_proc.pid()).ok()
+                } else {
+                    None
+                },
+                counters: if self.opts.counters {
+                    counted.or_else(|| counters::read(pid_proc.pid()).ok())
+                } else {
+                    None
+                },
+                footprint: pid_proc.footprint(),
+                perf: match self
+                    .perf_counters
+                    .iter_mut()
+                    .find(|c| c.pid() == pid_proc.pid())
+                {
+                    Some(counters) => counters.sample().unwrap_or_default(),
+                    None => BTreeMap::new(),
+                },
+                //num_threads: pid_proc.num_threads(),
+            });
+        }
+        for (i, sample) in self.recording.samples[first..].iter().enumerate() {
+            if let Some(watchdog) = self.hang.as_mut() {
+                if watchdog.update(sample, io[i]) {
+                    self.fired.push(Alert {
+                        ts: sample.ts,
+                        pid: sample.pid,
+                        label: process_label(&self.recording.metadata.processes, sample.pid),
+                        condition: watchdog.condition(),
+                        value: f64::from(sample.cpu),
+                    });
+                }
+            }
+            for trigger in self.alerts.iter_mut() {
+                if let Some(value) = trigger.update(sample) {
+                    self.fired.push(Alert {
+                        ts: sample.ts,
+                        pid: sample.pid,
+                        label: process_label(&self.recording.metadata.processes, sample.pid),
+                        condition: trigger.condition.clone(),
+                        value,
+                    });
+                }
+            }
+            for trigger in self.kill_if.iter_mut() {
+                let value = match trigger.update(sample) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let process = match self.procs.iter().find(|p| p.pid() == sample.pid) {
+                    Some(process) => process,
+                    None => continue,
+                };
+                if let Err(err) = process.signal(self.opts.stop_signal) {
+                    eprintln!("Warning: {}", err);
+                    continue;
+                }
+                self.terminated.push((sample.pid, Instant::now()));
+                let breach = Alert {
+                    ts: sample.ts,
+                    pid: sample.pid,
+                    label: process_label(&self.recording.metadata.processes, sample.pid),
+                    condition: trigger.condition.clone(),
+                    value,
+                };
+                self.recording.events.push(Event {
+                    ts: sample.ts,
+                    label: format!("killed {}", breach),
+                    marker: false,
+                });
+            }
+            for trigger in self.stop_when.iter_mut() {
+                if trigger.update(sample).is_some() && self.control.is_running() {
+                    let label = process_label(&self.recording.metadata.processes, sample.pid);
+                    self.recording.events.push(Event {
+                        ts: sample.ts,
+                        label: format!("stopped, {} fulfills {}", label, trigger.condition),
+                        marker: false,
+                    });
+                    self.control.stop();
+                }
+            }
+        }
+        for sampler in self.thread_samplers.iter_mut() {
+            // the process may have ended, its threads are gone then as well
+            let usage = sampler.sample().unwrap_or_default();
+            for thread in usage {
+                self.recording.threads.push(ThreadSample {
+                    ts: time_since_start,
+                    pid: sampler.pid(),
+                    tid: thread.tid,
+                    cpu: thread.cpu / cpus,
+                    name: thread.name,
+                });
+            }
+        }
+        if let Some(capture) = self.capture.as_mut() {
+            capture.filter(
+                &mut self.recording.samples,
+                &mut self.recording.threads,
+                first,
+                first_thread,
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // also after an error, finish stopped them already otherwise
+        self.stop_launched();
+    }
+}
+
+/// Label of the process recorded in the metadata, the PID if unknown.
+fn process_label(processes: &[ProcessInfo], pid: u32) -> String {
+    processes
+        .iter()
+        .find(|info| info.pid == pid)
+        .map(|info| info.label.clone())
+        .unwrap_or_else(|| pid.to_string())
+}
@@ -0,0 +1,628 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Container of everything collected during a recording.
+
+use crate::cgroup::CgroupUsage;
+use crate::output::{self, JsonEntry, Sink};
+use crate::plot::{self, Group, PlotData, PlotOptions, ThreadSeries};
+use crate::signals;
+use crate::spill::Spill;
+use crate::{Event, Sample, ThreadSample};
+use anyhow::{anyhow, bail, Result};
+use serde::ser::{self, SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// First word of a saved recording, followed by the format version.
+const FORMAT_MAGIC: &str = "PROCREC-RECORDING";
+/// Version of the format written by `Recording::save`. Older versions must
+/// stay readable, bump it on incompatible changes only.
+const FORMAT_VERSION: u32 = 1;
+
+/// Owned counterpart of `JsonEntry` for reading.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Entry {
+    Sample(Sample),
+    Thread(ThreadSample),
+    Event(Event),
+}
+
+impl Entry {
+    fn as_json(&self) -> JsonEntry<'_> {
+        match self {
+            Entry::Sample(sample) => JsonEntry::Sample(sample),
+            Entry::Thread(thread) => JsonEntry::Thread(thread),
+            Entry::Event(event) => JsonEntry::Event(event),
+        }
+    }
+}
+
+/// Information about a recorded process.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Label used in plots and events, name and PID of the process
+    pub label: String,
+    /// Command line of the process, empty if not accessible
+    #[serde(default)]
+    pub cmdline: Vec<String>,
+    /// CPU time consumed by the process, at its exit if it ended during the
+    /// recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time: Option<CpuTime>,
+    /// Highest RSS in kB reported by the operating system, catches bursts
+    /// between the samples
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss: Option<u64>,
+    /// Highest virtual memory size in kB reported by the operating system
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_vsize: Option<u64>,
+    /// How the process ended, if it did during the recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub termination: Option<Termination>,
+    /// Optional metrics which could not be read, e.g. smaps of a process
+    /// of another user
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unavailable: Vec<String>,
+}
+
+/// End of a process. The exit status and resource usage are only known for
+/// launched processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Termination {
+    /// Relative time of the recording in seconds
+    pub ts: f32,
+    /// Time in seconds since the unix epoch
+    pub end_time: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Signal which terminated the process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub core_dumped: bool,
+    /// Resource usage of the process and the descendants it waited for
+    /// (linux only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ResourceUsage>,
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => write!(f, "exited with code {}", code)?,
+            (None, Some(signal)) => write!(f, "killed by {}", signals::name(signal))?,
+            (None, None) => write!(f, "ended")?,
+        }
+        if self.core_dumped {
+            write!(f, " (core dumped)")?;
+        }
+        write!(f, " at {:.2}s", self.ts)?;
+        if let Some(usage) = self.usage {
+            write!(
+                f,
+                ", user {:.2}s, system {:.2}s, max RSS {}kB",
+                usage.user, usage.system, usage.max_rss
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Resource usage reported by the kernel when a process ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// Highest RSS in kB, including the copy of procrec a launched process
+    /// started as
+    pub max_rss: u64,
+    /// CPU time in seconds
+    pub user: f64,
+    pub system: f64,
+}
+
+/// CPU time in seconds spent in user and kernel mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuTime {
+    pub user: f64,
+    pub system: f64,
+}
+
+impl CpuTime {
+    pub fn total(&self) -> f64 {
+        self.user + self.system
+    }
+}
+
+/// Description of how and when a recording was made.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Version of procrec which made the recording
+    pub version: String,
+    /// Start of the recording in seconds since the unix epoch
+    pub start_time: Option<f64>,
+    /// Sampling interval in seconds
+    pub interval: f32,
+    /// Number of CPUs procrec could run on, the logical CPUs of the host
+    /// limited by its affinity mask and cgroup CPU quota
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<usize>,
+    /// CPU utilization divided by the number of CPUs, 100% are all CPUs
+    /// available to procrec
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cpu_normalized: bool,
+    #[serde(default)]
+    pub processes: Vec<ProcessInfo>,
+    /// Counters of the cgroup the command was isolated in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<CgroupUsage>,
+    /// Wall time of a run of `procrec bench` in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wall: Option<f64>,
+    /// Whether the run of `procrec bench` counted as cold
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cold: bool,
+}
+
+/// Everything collected during a recording. It serializes with the entries
+/// moved to disk, which are streamed from there like `write` does.
+#[derive(Debug, Default, Deserialize)]
+pub struct Recording {
+    #[serde(default)]
+    pub metadata: Metadata,
+    pub samples: Vec<Sample>,
+    /// Per-thread samples, only recorded with --threads
+    #[serde(default)]
+    pub threads: Vec<ThreadSample>,
+    #[serde(default)]
+    pub events: Vec<Event>,
+    /// Number of the first samples moved to disk by a recorder with a
+    /// memory limit, `save`, `replay`, serializing, the summary and the plot
+    /// data read them from there and `load_spilled` brings them back
+    #[serde(skip)]
+    pub spilled_samples: usize,
+    /// Number of the first thread samples moved to disk
+    #[serde(skip)]
+    pub spilled_threads: usize,
+    /// Number of the first events moved to disk
+    #[serde(skip)]
+    pub spilled_events: usize,
+    #[serde(skip)]
+    pub(crate) spill: Option<Spill>,
+}
+
+impl Serialize for Recording {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let spill = self.spill.as_ref();
+        let mut state = serializer.serialize_struct("Recording", 4)?;
+        state.serialize_field("metadata", &self.metadata)?;
+        state.serialize_field(
+            "samples",
+            &Spilled {
+                spill,
+                pick: |entry| match entry {
+                    Entry::Sample(sample) => Some(sample),
+                    _ => None,
+                },
+                tail: &self.samples,
+            },
+        )?;
+        state.serialize_field(
+            "threads",
+            &Spilled {
+                spill,
+                pick: |entry| match entry {
+                    Entry::Thread(thread) => Some(thread),
+                    _ => None,
+                },
+                tail: &self.threads,
+            },
+        )?;
+        state.serialize_field(
+            "events",
+            &Spilled {
+                spill,
+                pick: |entry| match entry {
+                    Entry::Event(event) => Some(event),
+                    _ => None,
+                },
+                tail: &self.events,
+            },
+        )?;
+        state.end()
+    }
+}
+
+/// The entries of one kind moved to disk followed by the ones in memory,
+/// serialized as one sequence.
+struct Spilled<'a, T> {
+    spill: Option<&'a Spill>,
+    pick: fn(Entry) -> Option<T>,
+    tail: &'a [T],
+}
+
+impl<T: Serialize> Serialize for Spilled<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        if let Some(spill) = self.spill {
+            for entry in spill.entries().map_err(ser::Error::custom)? {
+                if let Some(item) = (self.pick)(entry.map_err(ser::Error::custom)?) {
+                    seq.serialize_element(&item)?;
+                }
+            }
+        }
+        for item in self.tail {
+            seq.serialize_element(item)?;
+        }
+        seq.end()
+    }
+}
+
+impl Recording {
+    /// Load a recording saved by `save`. The JSON lines, CSV and textual
+    /// output of procrec are read as well, though without metadata.
+    pub fn load(path: &Path) -> Result<Recording> {
+        let file = File::open(path)
+            .map_err(|e| anyhow!("Can not read recording '{}': {}", path.display(), e))?;
+        Recording::read(BufReader::new(file))
+            .map_err(|e| anyhow!("Can not read recording '{}': {}", path.display(), e))
+    }
+
+    /// Save the recording in the versioned recording format.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Can not write recording '{}': {}", path.display(), e))?;
+        self.write(BufWriter::new(file))
+    }
+
+    /// Write the versioned recording format: a line with the magic word and
+    /// the format version, a line with the metadata as JSON and finally one
+    /// JSON line per sample, thread sample and event.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        writeln!(out, "{} {}", FORMAT_MAGIC, FORMAT_VERSION)?;
+        serde_json::to_writer(&mut out, &self.metadata)?;
+        writeln!(out)?;
+        if let Some(spill) = &self.spill {
+            spill.copy_to(&mut out)?;
+        }
+        let entries = self
+            .samples
+            .iter()
+            .map(JsonEntry::Sample)
+            .chain(self.threads.iter().map(JsonEntry::Thread))
+            .chain(self.events.iter().map(JsonEntry::Event));
+        for entry in entries {
+            serde_json::to_writer(&mut out, &entry)?;
+            writeln!(out)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Read any recording format, see `load`.
+    pub fn read<R: BufRead>(mut input: R) -> Result<Recording> {
+        let mut first = String::new();
+        input.read_line(&mut first)?;
+        let mut words = first.split_whitespace();
+        let mut recording = Recording::default();
+        if words.next() == Some(FORMAT_MAGIC) {
+            let version: u32 = words
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| anyhow!("Missing format version"))?;
+            if version > FORMAT_VERSION {
+                bail!(
+                    "Recording format version {} is newer than the supported version {}, update procrec",
+                    version,
+                    FORMAT_VERSION
+                );
+            }
+            let mut metadata = String::new();
+            input.read_line(&mut metadata)?;
+            recording.metadata = serde_json::from_str(&metadata)?;
+        } else if first.starts_with('{') {
+            // JSON lines as written by `JsonWriter`
+            recording.push_entry(&first)?;
+        } else if first.starts_with("ts,pid") {
+            return output::read_csv(first.as_bytes().chain(input));
+        } else {
+            return Ok(output::read_text(first.as_bytes().chain(input))?);
+        }
+
+        for line in input.lines() {
+            recording.push_entry(&line?)?;
+        }
+        Ok(recording)
+    }
+
+    fn push_entry(&mut self, line: &str) -> Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        match serde_json::from_str(line)? {
+            Entry::Sample(sample) => self.samples.push(sample),
+            Entry::Thread(thread) => self.threads.push(thread),
+            Entry::Event(event) => self.events.push(event),
+        }
+        Ok(())
+    }
+
+    /// Whether entries were moved to disk, see `spilled_samples`.
+    pub fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    /// Pass all samples, thread samples and events to the sink, the ones
+    /// moved to disk first, without loading them into memory.
+    pub fn replay(&self, sink: &mut dyn Sink) -> Result<()> {
+        self.for_each_entry(|entry| match entry {
+            JsonEntry::Sample(sample) => sink.write_sample(sample),
+            JsonEntry::Thread(thread) => sink.write_thread(thread),
+            JsonEntry::Event(event) => sink.write_event(event),
+            JsonEntry::Averaged(_) => Ok(()),
+        })
+    }
+
+    /// Visit the entries one by one, the ones moved to disk first.
+    pub(crate) fn for_each_entry(
+        &self,
+        mut visit: impl FnMut(JsonEntry<'_>) -> Result<()>,
+    ) -> Result<()> {
+        if let Some(spill) = &self.spill {
+            for entry in spill.entries()? {
+                visit(entry?.as_json())?;
+            }
+        }
+        let entries = self
+            .samples
+            .iter()
+            .map(JsonEntry::Sample)
+            .chain(self.threads.iter().map(JsonEntry::Thread))
+            .chain(self.events.iter().map(JsonEntry::Event));
+        for entry in entries {
+            visit(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Visit the samples one by one, the ones moved to disk first.
+    pub(crate) fn for_each_sample(&self, mut visit: impl FnMut(&Sample)) -> Result<()> {
+        self.for_each_entry(|entry| {
+            if let JsonEntry::Sample(sample) = entry {
+                visit(sample);
+            }
+            Ok(())
+        })
+    }
+
+    /// Load the entries moved to disk back in front of the ones in memory,
+    /// which the analyses working on the whole recording at once need.
+    pub fn load_spilled(&mut self) -> Result<()> {
+        let spill = match self.spill.take() {
+            Some(spill) => spill,
+            None => return Ok(()),
+        };
+        let mut spilled = Recording::default();
+        for entry in spill.entries()? {
+            match entry? {
+                Entry::Sample(sample) => spilled.samples.push(sample),
+                Entry::Thread(thread) => spilled.threads.push(thread),
+                Entry::Event(event) => spilled.events.push(event),
+            }
+        }
+        spilled.samples.append(&mut self.samples);
+        spilled.threads.append(&mut self.threads);
+        spilled.events.append(&mut self.events);
+        self.samples = spilled.samples;
+        self.threads = spilled.threads;
+        self.events = spilled.events;
+        self.spilled_samples = 0;
+        self.spilled_threads = 0;
+        self.spilled_events = 0;
+        Ok(())
+    }
+
+    /// Number of samples so far, including the ones moved to disk.
+    pub fn sample_count(&self) -> usize {
+        self.spilled_samples + self.samples.len()
+    }
+
+    /// Samples after the first `count`, e.g. the new ones since an earlier
+    /// `sample_count`.
+    pub fn samples_since(&self, count: usize) -> &[Sample] {
+        &self.samples[count
+            .saturating_sub(self.spilled_samples)
+            .min(self.samples.len())..]
+    }
+
+    /// Number of thread samples so far, including the ones moved to disk.
+    pub fn thread_count(&self) -> usize {
+        self.spilled_threads + self.threads.len()
+    }
+
+    /// Thread samples after the first `count`.
+    pub fn threads_since(&self, count: usize) -> &[ThreadSample] {
+        &self.threads[count
+            .saturating_sub(self.spilled_threads)
+            .min(self.threads.len())..]
+    }
+
+    /// Number of events so far, including the ones moved to disk.
+    pub fn event_count(&self) -> usize {
+        self.spilled_events + self.events.len()
+    }
+
+    /// Events after the first `count`.
+    pub fn events_since(&self, count: usize) -> &[Event] {
+        &self.events[count
+            .saturating_sub(self.spilled_events)
+            .min(self.events.len())..]
+    }
+
+    /// Move the recording by `offset` seconds on the time axis.
+    pub fn shift(&mut self, offset: f32) {
+        self.samples.iter_mut().for_each(|s| s.ts += offset);
+        self.threads.iter_mut().for_each(|s| s.ts += offset);
+        self.events.iter_mut().for_each(|e| e.ts += offset);
+        for termination in self
+            .metadata
+            .processes
+            .iter_mut()
+            .filter_map(|p| p.termination.as_mut())
+        {
+            termination.ts += offset;
+        }
+    }
+
+    /// Cut the recording to the time window from `from` to `to` seconds and
+    /// move its start to `from`.
+    ///
+    /// Processes which ended before the window are dropped and ends after it
+    /// are cleared. The CPU time, memory peaks and cgroup counters cover the
+    /// whole run and are cleared as well.
+    pub fn trim(&mut self, from: f32, to: Option<f32>) {
+        let inside = |ts: f32| ts >= from && to.is_none_or(|to| ts <= to);
+        self.samples.retain(|s| inside(s.ts));
+        self.threads.retain(|s| inside(s.ts));
+        self.events.retain(|e| inside(e.ts));
+        self.metadata
+            .processes
+            .retain(|p| p.termination.is_none_or(|t| t.ts >= from));
+        for process in &mut self.metadata.processes {
+            if process.termination.is_some_and(|t| !inside(t.ts)) {
+                process.termination = None;
+            }
+            if let Some(termination) = process.termination.as_mut() {
+                termination.usage = None;
+            }
+            process.cpu_time = None;
+            process.peak_rss = None;
+            process.peak_vsize = None;
+        }
+        self.metadata.cgroup = None;
+        self.shift(-from);
+        if let Some(start) = self.metadata.start_time.as_mut() {
+            *start += f64::from(from);
+        }
+    }
+
+    /// Keep only the sampling rounds at least `interval` seconds apart, give
+    /// or take half the sampling interval. Events are kept.
+    pub fn downsample(&mut self, interval: f32) {
+        let min_gap = interval - self.metadata.interval / 2.0;
+        let mut last: Option<f32> = None;
+        let mut kept = vec![];
+        for sample in &self.samples {
+            if last == Some(sample.ts) || last.is_none_or(|ts| sample.ts - ts >= min_gap) {
+                last = Some(sample.ts);
+                kept.push(sample.ts);
+            }
+        }
+        kept.dedup();
+        let keep = |ts: f32| kept.binary_search_by(|k| k.total_cmp(&ts)).is_ok();
+        self.samples.retain(|s| keep(s.ts));
+        self.threads.retain(|s| keep(s.ts));
+        self.metadata.interval = self.metadata.interval.max(interval);
+    }
+
+    /// Collect the data plotted for the recording, the samples moved to
+    /// disk are read again when plotting rather than loaded.
+    pub fn plot_data(&self, opts: &PlotOptions) -> Result<PlotData> {
+        let spill = match &self.spill {
+            Some(spill) => spill,
+            None => return Ok(self.recent_plot_data(opts)),
+        };
+        let labels = self.labels();
+        // the samples on disk are only read again while plotting
+        let mut pids = BTreeSet::new();
+        let mut threads: BTreeMap<(u32, u32), (f32, String)> = BTreeMap::new();
+        let mut events = vec![];
+        self.for_each_entry(|entry| {
+            match entry {
+                JsonEntry::Sample(sample) => {
+                    pids.insert(sample.pid);
+                }
+                JsonEntry::Thread(thread) => {
+                    let (total, name) = threads.entry((thread.pid, thread.tid)).or_default();
+                    *total += thread.cpu;
+                    name.clone_from(&thread.name);
+                }
+                JsonEntry::Event(event) => events.push(event.clone()),
+                JsonEntry::Averaged(_) => {}
+            }
+            Ok(())
+        })?;
+        let mut busiest: Vec<((u32, u32), (f32, String))> = threads.into_iter().collect();
+        busiest.sort_by(|a, b| b.1 .0.total_cmp(&a.1 .0));
+        busiest.truncate(opts.threads);
+        Ok(PlotData {
+            groups: pids
+                .into_iter()
+                .map(|pid| Group {
+                    label: plot::process_label(&labels, pid),
+                    pid,
+                    samples: self
+                        .samples
+                        .iter()
+                        .filter(|s| s.pid == pid)
+                        .cloned()
+                        .collect(),
+                })
+                .collect(),
+            threads: busiest
+                .into_iter()
+                .map(|((pid, tid), (_, name))| ThreadSeries {
+                    label: format!("{} ({})", name, tid),
+                    pid,
+                    tid,
+                    samples: self
+                        .threads
+                        .iter()
+                        .filter(|t| t.pid == pid && t.tid == tid)
+                        .map(|t| (t.ts, t.cpu))
+                        .collect(),
+                })
+                .collect(),
+            events,
+            spilled: Some(spill.open()?),
+        })
+    }
+
+    /// Collect the data plotted for the entries in memory only, e.g. to
+    /// refresh a live graph without reading the ones moved to disk.
+    pub fn recent_plot_data(&self, opts: &PlotOptions) -> PlotData {
+        PlotData {
+            groups: plot::group_by_process(&self.samples, &self.labels()),
+            threads: plot::top_threads(&self.threads, opts.threads),
+            events: self.events.clone(),
+            spilled: None,
+        }
+    }
+
+    /// Legend labels of the processes by PID.
+    fn labels(&self) -> Vec<(u32, String)> {
+        self.metadata
+            .processes
+            .iter()
+            .map(|p| (p.pid, p.label.clone()))
+            .collect()
+    }
+}
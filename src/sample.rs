@@ -0,0 +1,354 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Data model of a recording: samples, thread samples, events and metrics.
+
+use crate::counters::Counters;
+use crate::perf::PerfEvent;
+use crate::smaps::SmapsBreakdown;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Resource usage of a process at one point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    /// Seconds since the start of the recording
+    pub ts: f32,
+    pub pid: u32,
+    //num_threads: u64, // currently not supported in psutil crate
+    /// CPU utilization in percent, may exceed 100 on multiple cores
+    pub cpu: f32,
+    /// Virtual memory size in kB
+    pub vsize: u64,
+    /// Resident set size in kB
+    pub rss: u64,
+    /// Breakdown of the resident memory, only recorded with --smaps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smaps: Option<SmapsBreakdown>,
+    /// I/O and page fault counters, only recorded with --counters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counters: Option<Counters>,
+    /// Physical footprint in kB, the memory the process is charged for
+    /// including compressed and swapped out pages (macos only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub footprint: Option<u64>,
+    /// Increase of the performance counters since the previous sample, only
+    /// recorded with --perf
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub perf: BTreeMap<PerfEvent, u64>,
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.02} PID {} CPU% {:.02} RSS {} VSIZE {} ",
+            self.ts, self.pid, self.cpu, self.rss, self.vsize
+        )?;
+        if let Some(smaps) = &self.smaps {
+            write!(
+                f,
+                "HEAP {} STACK {} FILE {} ANON {} ",
+                smaps.heap, smaps.stack, smaps.file, smaps.anon
+            )?;
+        }
+        if let Some(c) = &self.counters {
+            write!(
+                f,
+                "READ {} WRITE {} MINFLT {} MAJFLT {} ",
+                c.read_bytes, c.write_bytes, c.minor_faults, c.major_faults
+            )?;
+        }
+        if let Some(footprint) = self.footprint {
+            write!(f, "FOOTPRINT {} ", footprint)?;
+        }
+        if !self.perf.is_empty() {
+            write!(f, "PERF ")?;
+            for (event, count) in &self.perf {
+                write!(f, "{}={} ", event, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Sample {
+    type Err = anyhow::Error;
+
+    /// Parse a sample in the format written by its `Display` implementation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        match fields.as_slice() {
+            [ts, "PID", pid, "CPU%", cpu, "RSS", rss, "VSIZE", vsize, rest @ ..] => {
+                let (smaps, rest) = match rest {
+                    ["HEAP", heap, "STACK", stack, "FILE", file, "ANON", anon, rest @ ..] => (
+                        Some(SmapsBreakdown {
+                            heap: heap.parse()?,
+                            stack: stack.parse()?,
+                            file: file.parse()?,
+                            anon: anon.parse()?,
+                        }),
+                        rest,
+                    ),
+                    _ => (None, rest),
+                };
+                let (counters, rest) = match rest {
+                    ["READ", read, "WRITE", write, "MINFLT", minor, "MAJFLT", major, rest @ ..] => {
+                        (
+                            Some(Counters {
+                                read_bytes: read.parse()?,
+                                write_bytes: write.parse()?,
+                                minor_faults: minor.parse()?,
+                                major_faults: major.parse()?,
+                            }),
+                            rest,
+                        )
+                    }
+                    _ => (None, rest),
+                };
+                let (footprint, rest) = match rest {
+                    ["FOOTPRINT", footprint, rest @ ..] => (Some(footprint.parse()?), rest),
+                    _ => (None, rest),
+                };
+                let mut perf = BTreeMap::new();
+                if let ["PERF", counts @ ..] = rest {
+                    for count in counts {
+                        match count.split_once('=') {
+                            Some((event, count)) => perf.insert(event.parse()?, count.parse()?),
+                            None => bail!("Invalid perf count '{}'", count),
+                        };
+                    }
+                }
+                Ok(Sample {
+                    ts: ts.parse()?,
+                    pid: pid.parse()?,
+                    cpu: cpu.parse()?,
+                    rss: rss.parse()?,
+                    vsize: vsize.parse()?,
+                    smaps,
+                    counters,
+                    footprint,
+                    perf,
+                })
+            }
+            _ => bail!("Invalid sample '{}'", s),
+        }
+    }
+}
+
+/// CPU utilization of a single thread of a recorded process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadSample {
+    pub ts: f32,
+    pub pid: u32,
+    pub tid: u32,
+    pub cpu: f32,
+    pub name: String,
+}
+
+impl fmt::Display for ThreadSample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.02} TID {} PID {} CPU% {:.02} NAME {}",
+            self.ts, self.tid, self.pid, self.cpu, self.name
+        )
+    }
+}
+
+impl FromStr for ThreadSample {
+    type Err = anyhow::Error;
+
+    /// Parse a thread sample in the format written by its `Display`
+    /// implementation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the name is last as it may contain spaces
+        match s.splitn(8, ' ').collect::<Vec<_>>().as_slice() {
+            [ts, "TID", tid, "PID", pid, "CPU%", cpu, rest] => match rest.strip_prefix("NAME ") {
+                Some(name) => Ok(ThreadSample {
+                    ts: ts.parse()?,
+                    pid: pid.parse()?,
+                    tid: tid.parse()?,
+                    cpu: cpu.parse()?,
+                    name: name.to_string(),
+                }),
+                None => bail!("Invalid thread sample '{}'", s),
+            },
+            _ => bail!("Invalid thread sample '{}'", s),
+        }
+    }
+}
+
+/// Something noteworthy happening during a recording, e.g. a process exiting.
+///
+/// Events are drawn as labeled markers in the graph.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub ts: f32,
+    pub label: String,
+    /// Added by the user, e.g. over the control socket, rather than by
+    /// procrec itself. Markers separate the phases of a recording.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub marker: bool,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.02} EVENT {}", self.ts, self.label)
+    }
+}
+
+impl FromStr for Event {
+    type Err = anyhow::Error;
+
+    /// Parse an event in the format written by its `Display` implementation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.splitn(3, ' ').collect::<Vec<_>>().as_slice() {
+            [ts, "EVENT", label] => Ok(Event {
+                ts: ts.parse()?,
+                label: label.to_string(),
+                marker: false,
+            }),
+            _ => bail!("Invalid event '{}'", s),
+        }
+    }
+}
+
+/// A metric collected for every sample.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    Cpu,
+    Rss,
+    Vsize,
+    Heap,
+    Stack,
+    File,
+    Anon,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 7] = [
+        Metric::Cpu,
+        Metric::Rss,
+        Metric::Vsize,
+        Metric::Heap,
+        Metric::Stack,
+        Metric::File,
+        Metric::Anon,
+    ];
+
+    /// Identifier of the metric as accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Cpu => "cpu",
+            Metric::Rss => "rss",
+            Metric::Vsize => "vsize",
+            Metric::Heap => "heap",
+            Metric::Stack => "stack",
+            Metric::File => "file",
+            Metric::Anon => "anon",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Metric::Cpu => "CPU",
+            Metric::Rss => "RSS",
+            Metric::Vsize => "VSIZE",
+            Metric::Heap => "Heap",
+            Metric::Stack => "Stack",
+            Metric::File => "File-backed",
+            Metric::Anon => "Anonymous",
+        }
+    }
+
+    /// gnuplot line type used to draw the metric
+    pub fn line_type(self) -> u32 {
+        match self {
+            Metric::Cpu => 1,
+            Metric::Rss => 20,
+            Metric::Vsize => 3,
+            Metric::Heap => 4,
+            Metric::Stack => 5,
+            Metric::File => 6,
+            Metric::Anon => 7,
+        }
+    }
+
+    pub fn is_memory(self) -> bool {
+        self != Metric::Cpu
+    }
+
+    /// Metrics of the memory breakdown, only recorded with --smaps
+    pub fn is_smaps(self) -> bool {
+        matches!(
+            self,
+            Metric::Heap | Metric::Stack | Metric::File | Metric::Anon
+        )
+    }
+
+    /// Value of the metric in the given sample, if it was recorded
+    pub fn value(self, sample: &Sample) -> Option<f64> {
+        match self {
+            Metric::Cpu => Some(sample.cpu as f64),
+            Metric::Rss => Some(sample.rss as f64),
+            Metric::Vsize => Some(sample.vsize as f64),
+            Metric::Heap => sample.smaps.map(|s| s.heap as f64),
+            Metric::Stack => sample.smaps.map(|s| s.stack as f64),
+            Metric::File => sample.smaps.map(|s| s.file as f64),
+            Metric::Anon => sample.smaps.map(|s| s.anon as f64),
+        }
+    }
+
+    /// Parse a metric name or one of the groups `mem` (rss and vsize),
+    /// `smaps` (memory breakdown) and `all`.
+    pub fn parse_group(s: &str) -> Result<Vec<Metric>> {
+        match s.trim().to_lowercase().as_str() {
+            "mem" | "memory" => Ok(vec![Metric::Rss, Metric::Vsize]),
+            "smaps" => Ok(Metric::ALL
+                .iter()
+                .copied()
+                .filter(|m| m.is_smaps())
+                .collect()),
+            "all" => Ok(Metric::ALL.to_vec()),
+            _ => Ok(vec![s.parse()?]),
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "cpu" => Ok(Metric::Cpu),
+            "rss" => Ok(Metric::Rss),
+            "vsize" | "vsz" => Ok(Metric::Vsize),
+            "heap" => Ok(Metric::Heap),
+            "stack" => Ok(Metric::Stack),
+            "file" => Ok(Metric::File),
+            "anon" => Ok(Metric::Anon),
+            _ => bail!(
+                "Unknown metric '{}', expected one of cpu, rss, vsize, heap, stack, file, anon",
+                s
+            ),
+        }
+    }
+}
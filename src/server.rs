@@ -0,0 +1,242 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! HTTP control server to start and stop recordings remotely.
+//!
+//! | Request                        | Action                                  |
+//! |--------------------------------|-----------------------------------------|
+//! | `POST /recordings`             | start recording, body `{"pids": [..]}`  |
+//! | `GET /recordings`              | status of all recordings                |
+//! | `GET /recordings/<id>`         | status of a recording                   |
+//! | `POST /recordings/<id>/stop`   | stop a recording                        |
+//! | `GET /recordings/<id>/data`    | recording in the format of `--save`     |
+
+use crate::http::{self, Request};
+use crate::{Recorder, TrackedProcess};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Body of `POST /recordings`
+#[derive(Deserialize)]
+struct StartRequest {
+    pids: Vec<u32>,
+    /// Sampling interval in seconds
+    #[serde(default = "default_interval")]
+    interval: f32,
+    /// Duration in seconds, record until the processes ended if omitted
+    duration: Option<f32>,
+    #[serde(default)]
+    smaps: bool,
+    #[serde(default)]
+    threads: bool,
+}
+
+fn default_interval() -> f32 {
+    2.0
+}
+
+#[derive(Serialize)]
+struct Status {
+    id: usize,
+    pids: Vec<u32>,
+    running: bool,
+    samples: usize,
+    error: Option<String>,
+}
+
+struct Job {
+    pids: Vec<u32>,
+    recorder: Mutex<Recorder>,
+    stop: Arc<AtomicBool>,
+    finished: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+type Jobs = Arc<Mutex<Vec<Arc<Job>>>>;
+
+/// Serve the control API on `addr`, e.g. `:7070`, until the process is
+/// terminated.
+pub fn serve(addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(crate::export::listen_addr(addr))
+        .map_err(|e| anyhow!("Can not listen on '{}': {}", addr, e))?;
+    let jobs = Jobs::default();
+    for stream in listener.incoming().flatten() {
+        let jobs = jobs.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(&stream, &jobs) {
+                let body = error_body(&err.to_string());
+                let _ = http::respond(&stream, "400 Bad Request", "application/json", &body);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn error_body(message: &str) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({ "error": message })).unwrap_or_default()
+}
+
+fn handle(stream: &TcpStream, jobs: &Jobs) -> Result<()> {
+    let request = http::read_request(stream)?;
+    let parts: Vec<&str> = request
+        .path
+        .trim_matches('/')
+        .split('/')
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    match (request.method.as_str(), parts.as_slice()) {
+        ("POST", ["recordings"]) => {
+            let id = start(&request, jobs)?;
+            json(
+                stream,
+                "201 Created",
+                &status(id, &jobs.lock().unwrap()[id]),
+            )
+        }
+        ("GET", ["recordings"]) => {
+            let all: Vec<Status> = jobs
+                .lock()
+                .unwrap()
+                .iter()
+                .enumerate()
+                .map(|(id, job)| status(id, job))
+                .collect();
+            json(stream, "200 OK", &all)
+        }
+        (method, ["recordings", id, rest @ ..]) => {
+            let job = id
+                .parse::<usize>()
+                .ok()
+                .and_then(|id| jobs.lock().unwrap().get(id).cloned().map(|job| (id, job)));
+            let (id, job) = match job {
+                Some(job) => job,
+                None => {
+                    let body = error_body("No such recording");
+                    return http::respond(stream, "404 Not Found", "application/json", &body);
+                }
+            };
+            match (method, rest) {
+                ("GET", []) => json(stream, "200 OK", &status(id, &job)),
+                ("POST", ["stop"]) => {
+                    job.stop.store(false, Ordering::SeqCst);
+                    json(stream, "200 OK", &status(id, &job))
+                }
+                ("GET", ["data"]) => {
+                    let mut data = vec![];
+                    job.recorder.lock().unwrap().recording().write(&mut data)?;
+                    http::respond(stream, "200 OK", "text/plain", &data)
+                }
+                _ => not_found(stream),
+            }
+        }
+        _ => not_found(stream),
+    }
+}
+
+fn json<T: Serialize>(stream: &TcpStream, status: &str, value: &T) -> Result<()> {
+    http::respond(
+        stream,
+        status,
+        "application/json",
+        &serde_json::to_vec(value)?,
+    )
+}
+
+fn not_found(stream: &TcpStream) -> Result<()> {
+    http::respond(
+        stream,
+        "404 Not Found",
+        "application/json",
+        &error_body("Unknown request, see the documentation of procrec serve"),
+    )
+}
+
+fn status(id: usize, job: &Job) -> Status {
+    Status {
+        id,
+        pids: job.pids.clone(),
+        running: !job.finished.load(Ordering::SeqCst),
+        samples: job.recorder.lock().unwrap().recording().sample_count(),
+        error: job.error.lock().unwrap().clone(),
+    }
+}
+
+/// Start a recording on its own thread and return its id.
+fn start(request: &Request, jobs: &Jobs) -> Result<usize> {
+    let start: StartRequest = serde_json::from_slice(&request.body)?;
+    let interval = Duration::try_from_secs_f32(start.interval)
+        .ok()
+        .filter(|interval| !interval.is_zero())
+        .ok_or_else(|| anyhow!("Invalid interval {}", start.interval))?;
+    let mut builder = Recorder::builder()
+        .interval(interval)
+        .smaps(start.smaps)
+        .threads(start.threads);
+    if let Some(duration) = start.duration {
+        let duration = Duration::try_from_secs_f32(duration)
+            .map_err(|_| anyhow!("Invalid duration {}", duration))?;
+        builder = builder.duration(duration);
+    }
+    for &pid in &start.pids {
+        builder = builder.process(TrackedProcess::attach(pid)?);
+    }
+    let recorder = builder.build()?;
+    let job = Arc::new(Job {
+        pids: start.pids,
+        stop: recorder.stop_handle(),
+        recorder: Mutex::new(recorder),
+        finished: AtomicBool::new(false),
+        error: Mutex::new(None),
+    });
+
+    let id = {
+        let mut jobs = jobs.lock().unwrap();
+        jobs.push(job.clone());
+        jobs.len() - 1
+    };
+    thread::spawn(move || record(&job));
+    Ok(id)
+}
+
+/// Sampling loop, the recorder is locked while sampling only so the status
+/// can be queried in between.
+fn record(job: &Job) {
+    let interval = job.recorder.lock().unwrap().interval();
+    loop {
+        thread::sleep(interval);
+        let mut recorder = job.recorder.lock().unwrap();
+        match recorder.sample_now() {
+            Ok(true) => {}
+            Ok(false) => {
+                if let Err(err) = recorder.finish() {
+                    *job.error.lock().unwrap() = Some(err.to_string());
+                }
+                break;
+            }
+            Err(err) => {
+                *job.error.lock().unwrap() = Some(err.to_string());
+                break;
+            }
+        }
+    }
+    job.finished.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,151 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Signals received by procrec and forwarded to the launched command.
+//!
+//! The handlers only note the signals, the recorder passes them on in its
+//! next round.
+
+use anyhow::Result;
+
+/// A signal received since the last call of `take`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Received {
+    pub signal: i32,
+    /// Sent by another process rather than the terminal, which signals the
+    /// whole foreground process group including the command
+    pub sent: bool,
+}
+
+#[cfg(unix)]
+mod imp {
+    use super::Received;
+    use anyhow::{bail, Result};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // one bit per signal number
+    static RECEIVED: AtomicU64 = AtomicU64::new(0);
+    static SENT: AtomicU64 = AtomicU64::new(0);
+
+    extern "C" fn handle(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+        let bit = 1u64 << signal;
+        // kill() and sigqueue() report a code of 0 or below
+        if !info.is_null() && unsafe { (*info).si_code } <= 0 {
+            SENT.fetch_or(bit, Ordering::SeqCst);
+        }
+        RECEIVED.fetch_or(bit, Ordering::SeqCst);
+    }
+
+    pub fn forward(signals: &[i32]) -> Result<()> {
+        for &signal in signals {
+            if !(1..64).contains(&signal) {
+                bail!("Can not forward signal {}", signal);
+            }
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            let handler =
+                handle as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void);
+            action.sa_sigaction = handler as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+                bail!("Can not install the handler of signal {}", signal);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn take() -> Vec<Received> {
+        let received = RECEIVED.swap(0, Ordering::SeqCst);
+        let sent = SENT.swap(0, Ordering::SeqCst);
+        (1..64)
+            .filter(|signal| received & (1 << signal) != 0)
+            .map(|signal| Received {
+                signal,
+                sent: sent & (1 << signal) != 0,
+            })
+            .collect()
+    }
+}
+
+// Ctrl-C reaches every process of the console including the command, so it
+// is only noted like SIGINT from the terminal. There is no SIGTERM to receive.
+#[cfg(not(unix))]
+mod imp {
+    use super::Received;
+    use anyhow::{anyhow, bail, Result};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    pub fn forward(signals: &[i32]) -> Result<()> {
+        if let Some(signal) = signals
+            .iter()
+            .find(|s| !matches!(**s, libc::SIGINT | libc::SIGTERM))
+        {
+            bail!("Can not forward signal {}, only SIGINT (Ctrl-C)", signal);
+        }
+        if signals.contains(&libc::SIGINT) {
+            ctrlc::set_handler(|| RECEIVED.store(true, Ordering::SeqCst))
+                .map_err(|e| anyhow!("Can not install the Ctrl-C handler: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn take() -> Vec<Received> {
+        if !RECEIVED.swap(false, Ordering::SeqCst) {
+            return vec![];
+        }
+        vec![Received {
+            signal: libc::SIGINT,
+            sent: false,
+        }]
+    }
+}
+
+/// Catch the signals to pass them on to the launched command. SIGINT and
+/// SIGTERM end the recording once the command exited, another one ends it
+/// right away.
+pub fn forward(signals: &[i32]) -> Result<()> {
+    imp::forward(signals)
+}
+
+/// Signals received since the last call.
+pub(crate) fn take() -> Vec<Received> {
+    imp::take()
+}
+
+/// Name of the signal like SIGTERM, the number for uncommon ones.
+pub(crate) fn name(signal: i32) -> String {
+    let name = match signal {
+        #[cfg(unix)]
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        #[cfg(unix)]
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGSEGV => "SIGSEGV",
+        #[cfg(unix)]
+        libc::SIGKILL => "SIGKILL",
+        #[cfg(unix)]
+        libc::SIGPIPE => "SIGPIPE",
+        #[cfg(unix)]
+        libc::SIGUSR1 => "SIGUSR1",
+        #[cfg(unix)]
+        libc::SIGUSR2 => "SIGUSR2",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
@@ -0,0 +1,220 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Significance tests telling real differences between repeated runs from
+//! noise.
+
+/// Significance level below which a difference counts as real.
+pub const ALPHA: f64 = 0.05;
+
+/// Samples up to this size without ties get the exact distribution of U,
+/// larger ones the normal approximation.
+const EXACT_LIMIT: usize = 40;
+
+/// Result of a two-sided Mann-Whitney U test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MannWhitney {
+    /// The smaller of the two U statistics
+    pub u: f64,
+    /// Probability of a difference at least this large by chance
+    pub p: f64,
+}
+
+impl MannWhitney {
+    pub fn significant(&self) -> bool {
+        self.p < ALPHA
+    }
+}
+
+/// Test whether the values of `a` and `b` come from the same distribution,
+/// without assuming it is normal. NaN values are left out, `None` if one of
+/// them has no other values.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> Option<MannWhitney> {
+    // NaN has no rank
+    let a: Vec<f64> = a.iter().copied().filter(|v| !v.is_nan()).collect();
+    let b: Vec<f64> = b.iter().copied().filter(|v| !v.is_nan()).collect();
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let (m, n) = (a.len(), b.len());
+    let mut all: Vec<(f64, bool)> = a
+        .iter()
+        .map(|&v| (v, true))
+        .chain(b.iter().map(|&v| (v, false)))
+        .collect();
+    all.sort_by(|x, y| x.0.total_cmp(&y.0));
+    // tied values share their mean rank
+    let mut rank_sum = 0.0;
+    let mut ties = vec![];
+    let mut i = 0;
+    while i < all.len() {
+        let j = i + all[i..].iter().take_while(|v| v.0 == all[i].0).count();
+        let rank = (i + j + 1) as f64 / 2.0;
+        rank_sum += rank * all[i..j].iter().filter(|v| v.1).count() as f64;
+        if j - i > 1 {
+            ties.push((j - i) as f64);
+        }
+        i = j;
+    }
+    let mn = (m * n) as f64;
+    let u1 = rank_sum - (m * (m + 1)) as f64 / 2.0;
+    let u = u1.min(mn - u1);
+    let p = if ties.is_empty() && m + n <= EXACT_LIMIT {
+        exact_p(m, n, u as usize)
+    } else {
+        let total = (m + n) as f64;
+        let correction = ties.iter().map(|t| t * t * t - t).sum::<f64>() / (total * (total - 1.0));
+        let sigma = (mn / 12.0 * (total + 1.0 - correction)).sqrt();
+        if sigma == 0.0 {
+            1.0
+        } else {
+            // continuity correction towards the mean
+            let z = ((mn / 2.0 - u - 0.5).max(0.0)) / sigma;
+            erfc(z / std::f64::consts::SQRT_2)
+        }
+    };
+    Some(MannWhitney { u, p: p.min(1.0) })
+}
+
+/// Two-sided p-value of U in the exact distribution for samples of size `m`
+/// and `n`.
+fn exact_p(m: usize, n: usize, u: usize) -> f64 {
+    // counts[j][k]: orderings of i values of a and j of b with U = k,
+    // built up for growing i
+    let max_u = m * n;
+    let mut counts = vec![vec![0.0f64; max_u + 1]; n + 1];
+    for row in counts.iter_mut() {
+        row[0] = 1.0;
+    }
+    for _ in 1..=m {
+        let mut next = vec![vec![0.0f64; max_u + 1]; n + 1];
+        next[0][0] = 1.0;
+        for j in 1..=n {
+            for k in 0..=max_u {
+                // the largest value is from b, or from a and above all j values of b
+                let from_b = next[j - 1][k];
+                let from_a = if k >= j { counts[j][k - j] } else { 0.0 };
+                next[j][k] = from_b + from_a;
+            }
+        }
+        counts = next;
+    }
+    let total: f64 = counts[n].iter().sum();
+    let below: f64 = counts[n][..=u].iter().sum();
+    2.0 * below / total
+}
+
+/// Complementary error function, with a relative error below 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398
+                                    + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn exact_without_overlap() {
+        let test = mann_whitney(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]).unwrap();
+        assert_eq!(test.u, 0.0);
+        // 2 of the 20 orderings are as extreme
+        assert_close(test.p, 0.1);
+        let test = mann_whitney(&[6.0, 7.0, 8.0, 9.0, 10.0], &[1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        assert_eq!(test.u, 0.0);
+        assert_close(test.p, 2.0 / 252.0);
+        assert!(test.significant());
+    }
+
+    #[test]
+    fn exact_interleaved() {
+        let test = mann_whitney(&[1.0, 3.0, 5.0], &[2.0, 4.0, 6.0]).unwrap();
+        assert_eq!(test.u, 3.0);
+        // U <= 3 in 1 + 1 + 2 + 3 of the 20 orderings
+        assert_close(test.p, 0.7);
+        assert!(!test.significant());
+    }
+
+    #[test]
+    fn ties_use_the_corrected_normal_approximation() {
+        let test = mann_whitney(&[1.0, 2.0, 2.0], &[2.0, 3.0, 4.0]).unwrap();
+        assert_eq!(test.u, 1.0);
+        assert_close(test.p, 0.16415972847851523);
+    }
+
+    #[test]
+    fn all_tied() {
+        let test = mann_whitney(&[1.0, 1.0], &[1.0, 1.0]).unwrap();
+        assert_eq!(test.u, 2.0);
+        assert_eq!(test.p, 1.0);
+    }
+
+    #[test]
+    fn large_samples() {
+        let a: Vec<f64> = (1..=25).map(f64::from).collect();
+        let b: Vec<f64> = (26..=50).map(f64::from).collect();
+        let test = mann_whitney(&a, &b).unwrap();
+        assert_eq!(test.u, 0.0);
+        assert!(test.p < 1e-8);
+        let test = mann_whitney(&a, &a).unwrap();
+        assert_eq!(test.p, 1.0);
+    }
+
+    #[test]
+    fn nan_is_left_out() {
+        let test = mann_whitney(&[1.0, f64::NAN, 2.0, 3.0], &[4.0, 5.0, f64::NAN, 6.0]).unwrap();
+        assert_eq!(test.u, 0.0);
+        assert_close(test.p, 0.1);
+        assert_eq!(mann_whitney(&[f64::NAN], &[1.0]), None);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(mann_whitney(&[], &[1.0]), None);
+        assert_eq!(mann_whitney(&[1.0], &[]), None);
+    }
+
+    #[test]
+    fn erfc_values() {
+        assert_close(erfc(0.0), 1.0);
+        assert_close(erfc(1.0), 0.157299207050285);
+        assert_close(erfc(-1.0), 1.842700792949715);
+    }
+}
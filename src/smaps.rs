@@ -0,0 +1,74 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Breakdown of the resident memory by mapping type from `/proc/<pid>/smaps`.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+
+/// Resident memory of a process split up by the kind of mapping, in kB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SmapsBreakdown {
+    /// The `[heap]` mapping
+    pub heap: u64,
+    /// `[stack]` mappings of the process and its threads
+    pub stack: u64,
+    /// Mappings backed by a file, e.g. binaries and shared libraries
+    pub file: u64,
+    /// All other anonymous mappings, e.g. allocations via mmap
+    pub anon: u64,
+}
+
+/// Read the memory breakdown of the given process, only available on linux.
+pub fn read(pid: u32) -> io::Result<SmapsBreakdown> {
+    let content = fs::read_to_string(format!("/proc/{}/smaps", pid))?;
+    Ok(parse(&content))
+}
+
+fn parse(content: &str) -> SmapsBreakdown {
+    let mut breakdown = SmapsBreakdown::default();
+    let mut current: Option<&mut u64> = None;
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let first = fields.next().unwrap_or_default();
+        if first.ends_with(':') {
+            // attribute of the current mapping, e.g. "Rss:  1234 kB"
+            if first == "Rss:" {
+                if let (Some(kib), Some(counter)) = (
+                    fields.next().and_then(|v| v.parse::<u64>().ok()),
+                    current.as_mut(),
+                ) {
+                    // smaps reports KiB while procrec uses kB
+                    **counter += kib * 1024 / 1000;
+                }
+            }
+        } else {
+            // header of a new mapping: address perms offset dev inode [path]
+            let path = fields.nth(4).unwrap_or_default();
+            current = Some(if path == "[heap]" {
+                &mut breakdown.heap
+            } else if path.starts_with("[stack") {
+                &mut breakdown.stack
+            } else if path.starts_with('/') {
+                &mut breakdown.file
+            } else {
+                &mut breakdown.anon
+            });
+        }
+    }
+    breakdown
+}
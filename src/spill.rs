@@ -0,0 +1,118 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Temporary file taking the older samples of long recordings to bound the
+//! memory of the recorder.
+
+use crate::output::JsonEntry;
+use crate::recording::Entry;
+use crate::Recording;
+use anyhow::{anyhow, Result};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the files of several recorders in one process.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Samples, thread samples and events moved out of a recording as JSON
+/// lines, the file is removed when dropped.
+#[derive(Debug)]
+pub(crate) struct Spill {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl Spill {
+    pub(crate) fn create() -> Result<Spill> {
+        let path = env::temp_dir().join(format!(
+            "procrec-{}-{}.spill",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| anyhow!("Can not create '{}': {}", path.display(), e))?;
+        Ok(Spill {
+            path,
+            out: BufWriter::new(file),
+        })
+    }
+
+    /// Move all but the last `keep` samples, thread samples and events of
+    /// the recording to the file.
+    pub(crate) fn write(&mut self, recording: &mut Recording, keep: usize) -> Result<()> {
+        let samples = recording.samples.len().saturating_sub(keep);
+        let threads = recording.threads.len().saturating_sub(keep);
+        let events = recording.events.len().saturating_sub(keep);
+        let entries = recording.samples[..samples]
+            .iter()
+            .map(JsonEntry::Sample)
+            .chain(recording.threads[..threads].iter().map(JsonEntry::Thread))
+            .chain(recording.events[..events].iter().map(JsonEntry::Event));
+        for entry in entries {
+            serde_json::to_writer(&mut self.out, &entry)?;
+            writeln!(self.out)?;
+        }
+        self.out.flush()?;
+        recording.samples.drain(..samples);
+        recording.threads.drain(..threads);
+        recording.events.drain(..events);
+        recording.spilled_samples += samples;
+        recording.spilled_threads += threads;
+        recording.spilled_events += events;
+        Ok(())
+    }
+
+    /// Open the file for reading, it stays readable through the handle
+    /// once removed.
+    pub(crate) fn open(&self) -> Result<File> {
+        File::open(&self.path).map_err(|e| anyhow!("Can not read '{}': {}", self.path.display(), e))
+    }
+
+    /// Read the entries back one by one, in the order they were moved.
+    pub(crate) fn entries(&self) -> Result<impl Iterator<Item = Result<Entry>>> {
+        read(&self.open()?)
+    }
+
+    /// Copy the lines of the file, which are in the format of the entries
+    /// of a saved recording.
+    pub(crate) fn copy_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        io::copy(&mut self.open()?, out)?;
+        Ok(())
+    }
+}
+
+/// Read the entries of an opened spill file one by one from its start.
+pub(crate) fn read(file: &File) -> Result<impl Iterator<Item = Result<Entry>>> {
+    let mut file = file.try_clone()?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(BufReader::new(file).lines().map(|line| -> Result<Entry> {
+        serde_json::from_str(&line?)
+            .map_err(|e| anyhow!("Can not read the samples moved to disk: {}", e))
+    }))
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
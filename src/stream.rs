@@ -0,0 +1,123 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Async recording yielding the samples as a stream.
+
+use crate::{Recorder, Recording, Sample};
+use anyhow::Result;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+
+/// Stream of the samples of a [`Recorder`], sampling on a tokio timer
+/// instead of a blocking sleep.
+///
+/// ```no_run
+/// use procrec::{Recorder, RecorderOptions, TrackedProcess};
+///
+/// # async fn record() -> anyhow::Result<()> {
+/// let process = TrackedProcess::attach(1234)?;
+/// let recorder = Recorder::new(vec![process], RecorderOptions::default());
+/// let mut samples = recorder.into_stream();
+/// while let Some(sample) = samples.next().await {
+///     println!("{}", sample?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SampleStream {
+    recorder: Recorder,
+    interval: Interval,
+    pending: VecDeque<Sample>,
+    done: bool,
+}
+
+impl Recorder {
+    /// Record asynchronously, must be called within a tokio runtime.
+    pub fn into_stream(self) -> SampleStream {
+        let period = self.interval();
+        // the first sample needs a full interval for the CPU utilization
+        let mut interval = time::interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        SampleStream {
+            recorder: self,
+            interval,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl SampleStream {
+    /// The next sample, `None` once the recording is finished.
+    pub async fn next(&mut self) -> Option<Result<Sample>> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// The data collected so far, including threads and events.
+    pub fn recording(&self) -> &Recording {
+        self.recorder.recording()
+    }
+
+    pub fn into_recording(self) -> Recording {
+        self.recorder.into_recording()
+    }
+}
+
+impl Stream for SampleStream {
+    type Item = Result<Sample>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(sample) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(sample)));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+            if self.interval.poll_tick(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            let this = &mut *self;
+            let known = this.recorder.recording().sample_count();
+            let result = this.recorder.sample_now();
+            this.pending.extend(
+                this.recorder
+                    .recording()
+                    .samples_since(known)
+                    .iter()
+                    .cloned(),
+            );
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    this.done = true;
+                    if let Err(err) = this.recorder.finish() {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                }
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,688 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Summary statistics of a recording and their comparison with a baseline.
+
+use crate::alert::Alert;
+use crate::cgroup::CgroupUsage;
+use crate::counters::Counters;
+use crate::histogram::Histogram;
+use crate::output::JsonEntry;
+use crate::peak::{self, Peak};
+use crate::perf::{self, PerfEvent};
+use crate::recording::Termination;
+use crate::{CpuTime, Metadata, Metric, Recording, Sample};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// Number of peaks of each metric listed by default
+pub const PEAKS: usize = 5;
+/// Smaller increases of CPU usage, in percent, are noise rather than
+/// regressions
+const NOISE_CPU: f64 = 1.0;
+/// Smaller increases of memory, in kB, are noise rather than regressions
+const NOISE_MEMORY: f64 = 1000.0;
+
+/// Statistics of one metric.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    /// Population standard deviation
+    #[serde(default)]
+    pub stddev: f64,
+    /// Median
+    #[serde(default)]
+    pub p50: f64,
+    #[serde(default)]
+    pub p90: f64,
+    #[serde(default)]
+    pub p99: f64,
+}
+
+impl Stats {
+    /// Statistics of the values, `None` if there are none.
+    pub fn of(values: &[f64]) -> Option<Stats> {
+        if values.is_empty() {
+            return None;
+        }
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        Some(Stats {
+            min: sorted[0],
+            mean,
+            max: sorted[sorted.len() - 1],
+            stddev: (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt(),
+            p50: percentile(&sorted, 50.0),
+            p90: percentile(&sorted, 90.0),
+            p99: percentile(&sorted, 99.0),
+        })
+    }
+}
+
+/// Amount of the high-water mark of every process above its highest
+/// sampled RSS, processes which ended at different times can not be summed
+/// up.
+fn missed_rss(metadata: &Metadata, sampled: &BTreeMap<u32, u64>) -> BTreeMap<String, u64> {
+    metadata
+        .processes
+        .iter()
+        .filter_map(|p| {
+            let missed = p.peak_rss?.checked_sub(*sampled.get(&p.pid)?)?;
+            (missed > 0).then(|| (p.label.clone(), missed))
+        })
+        .collect()
+}
+
+/// Percentile of sorted values by the nearest-rank method.
+pub(crate) fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Statistics of a whole recording. With several processes the metrics are
+/// summed up per sampling round first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    /// Recorded time in seconds
+    pub duration: f32,
+    /// Number of sampling rounds
+    pub samples: usize,
+    /// Statistics by metric name
+    pub metrics: BTreeMap<String, Stats>,
+    /// Integral of the CPU utilization over time, in seconds
+    #[serde(default)]
+    pub cpu_seconds: f64,
+    /// CPU time reported by the operating system for all processes, at their
+    /// exit if they ended during the recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time: Option<CpuTime>,
+    /// Highest RSS in kB reported by the operating system, summed up over
+    /// the processes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss: Option<u64>,
+    /// Highest virtual memory size in kB reported by the operating system,
+    /// summed up over the processes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_vsize: Option<u64>,
+    /// How far the highest sampled RSS of a process stayed below its
+    /// high-water mark, in kB by label
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub missed_rss: BTreeMap<String, u64>,
+    /// Counters of the cgroup the command was isolated in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<CgroupUsage>,
+    /// Increase and rate of the I/O and page fault counters and the perf
+    /// events by name, summed up over the processes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub counters: BTreeMap<String, Growth>,
+    /// Instructions per cycle, if both were counted with --perf
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipc: Option<f64>,
+    /// Highest CPU and memory peaks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peaks: Vec<Peak>,
+    /// How processes ended during the recording, by label
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub terminations: BTreeMap<String, Termination>,
+    /// Metrics which could not be read, by label
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unavailable: BTreeMap<String, Vec<String>>,
+}
+
+impl Summary {
+    pub fn new(recording: &Recording) -> Result<Summary> {
+        Summary::with_peaks(recording, PEAKS)
+    }
+
+    /// Summary listing the `peaks` highest peaks of the CPU utilization and
+    /// RSS. The samples moved to disk are read one by one.
+    pub fn with_peaks(recording: &Recording, peaks: usize) -> Result<Summary> {
+        let mut builder = Builder::default();
+        recording.for_each_sample(|sample| builder.push(sample))?;
+        Ok(builder.build(&recording.metadata, peaks))
+    }
+
+    pub fn load(path: &Path) -> Result<Summary> {
+        let file = File::open(path)
+            .map_err(|e| anyhow!("Can not read summary '{}': {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| anyhow!("Can not read summary '{}': {}", path.display(), e))
+    }
+
+    /// Save as JSON, e.g. as baseline of later runs.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Can not write summary '{}': {}", path.display(), e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Compare the mean and maximum of every metric in both summaries, a
+    /// value higher than the baseline by more than `threshold` (e.g. 0.1 for
+    /// 10%) is a regression unless it is less than 1% CPU or 1 MB.
+    pub fn compare(&self, baseline: &Summary, threshold: f64) -> Comparison {
+        let mut deltas = vec![];
+        for (name, stats) in &self.metrics {
+            let base = match baseline.metrics.get(name) {
+                Some(base) => base,
+                None => continue,
+            };
+            let noise = if name == Metric::Cpu.name() {
+                NOISE_CPU
+            } else {
+                NOISE_MEMORY
+            };
+            for (stat, value, base) in [
+                ("mean", stats.mean, base.mean),
+                ("max", stats.max, base.max),
+            ] {
+                let change = if base > 0.0 {
+                    (value - base) / base
+                } else {
+                    0.0
+                };
+                deltas.push(Delta {
+                    metric: name.clone(),
+                    stat,
+                    value,
+                    base,
+                    change,
+                    regression: change > threshold && value - base > noise,
+                });
+            }
+        }
+        Comparison { deltas }
+    }
+}
+
+/// Increase of a cumulative counter during the recording.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Growth {
+    pub total: u64,
+    /// Average increase per second
+    pub rate: f64,
+}
+
+impl Growth {
+    fn new(total: u64, duration: f32) -> Self {
+        let rate = if duration > 0.0 {
+            total as f64 / f64::from(duration)
+        } else {
+            0.0
+        };
+        Growth { total, rate }
+    }
+}
+
+/// Growth of the counters between the first and last sample of every
+/// process.
+fn growth(
+    first: &BTreeMap<u32, Counters>,
+    last: &BTreeMap<u32, Counters>,
+    duration: f32,
+) -> BTreeMap<String, Growth> {
+    let mut totals = [0; 4];
+    for (pid, last) in last {
+        let first = first[pid].values();
+        for (i, value) in last.values().iter().enumerate() {
+            totals[i] += value.saturating_sub(first[i]);
+        }
+    }
+    if last.is_empty() {
+        return BTreeMap::new();
+    }
+    Counters::NAMES
+        .iter()
+        .zip(totals)
+        .map(|(name, total)| (name.to_string(), Growth::new(total, duration)))
+        .collect()
+}
+
+/// Collects what a summary needs sample by sample, the metrics summed up
+/// per round rather than the samples.
+#[derive(Default)]
+struct Builder {
+    /// Time of every round and the totals in the order of `Metric::ALL`,
+    /// `None` if a process lacks the metric
+    rounds: Vec<(f32, [Option<f64>; Metric::ALL.len()])>,
+    first_counters: BTreeMap<u32, Counters>,
+    last_counters: BTreeMap<u32, Counters>,
+    perf: BTreeMap<PerfEvent, u64>,
+    /// Highest sampled RSS by process
+    sampled_rss: BTreeMap<u32, u64>,
+}
+
+impl Builder {
+    fn push(&mut self, sample: &Sample) {
+        match self.rounds.last_mut() {
+            Some((ts, totals)) if *ts == sample.ts => {
+                for (total, metric) in totals.iter_mut().zip(Metric::ALL) {
+                    *total = total.and_then(|total| Some(total + metric.value(sample)?));
+                }
+            }
+            _ => self
+                .rounds
+                .push((sample.ts, Metric::ALL.map(|metric| metric.value(sample)))),
+        }
+        if let Some(counters) = sample.counters {
+            self.first_counters.entry(sample.pid).or_insert(counters);
+            self.last_counters.insert(sample.pid, counters);
+        }
+        for (event, count) in &sample.perf {
+            *self.perf.entry(*event).or_insert(0) += count;
+        }
+        let max = self.sampled_rss.entry(sample.pid).or_default();
+        *max = (*max).max(sample.rss);
+    }
+
+    /// Sum of the metric over all processes per round, skipping rounds
+    /// without the metric.
+    fn totals(&self, metric: Metric) -> Vec<(f32, f64)> {
+        let i = Metric::ALL
+            .iter()
+            .position(|m| *m == metric)
+            .unwrap_or_default();
+        self.rounds
+            .iter()
+            .filter_map(|(ts, totals)| Some((*ts, totals[i]?)))
+            .collect()
+    }
+
+    fn build(self, metadata: &Metadata, peaks: usize) -> Summary {
+        let mut metrics = BTreeMap::new();
+        for metric in Metric::ALL.iter() {
+            let totals: Vec<f64> = self
+                .totals(*metric)
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect();
+            if let Some(stats) = Stats::of(&totals) {
+                metrics.insert(metric.name().to_string(), stats);
+            }
+        }
+        let duration = match (self.rounds.first(), self.rounds.last()) {
+            (Some(first), Some(last)) => last.0 - first.0,
+            _ => 0.0,
+        };
+        let mut counters = growth(&self.first_counters, &self.last_counters, duration);
+        for (event, total) in &self.perf {
+            counters.insert(event.name().to_string(), Growth::new(*total, duration));
+        }
+        let cpu = self.totals(Metric::Cpu);
+        Summary {
+            duration,
+            samples: self.rounds.len(),
+            metrics,
+            cpu_seconds: cpu_seconds(&cpu, metadata.interval),
+            cpu_time: metadata
+                .processes
+                .iter()
+                .filter_map(|p| p.cpu_time)
+                .reduce(|a, b| CpuTime {
+                    user: a.user + b.user,
+                    system: a.system + b.system,
+                }),
+            peak_rss: metadata.processes.iter().map(|p| p.peak_rss).sum(),
+            peak_vsize: metadata.processes.iter().map(|p| p.peak_vsize).sum(),
+            missed_rss: missed_rss(metadata, &self.sampled_rss),
+            cgroup: metadata.cgroup,
+            counters,
+            ipc: perf::ipc(&self.perf),
+            peaks: peak::top(&cpu, &self.totals(Metric::Rss), peaks),
+            terminations: metadata
+                .processes
+                .iter()
+                .filter_map(|p| Some((p.label.clone(), p.termination?)))
+                .collect(),
+            unavailable: metadata
+                .processes
+                .iter()
+                .filter(|p| !p.unavailable.is_empty())
+                .map(|p| (p.label.clone(), p.unavailable.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// Integral of the CPU utilization in percent over time. A sample holds the
+/// utilization since the previous one, so the first one is skipped.
+///
+/// A step covers at most one interval, the gaps left by pausing, capture
+/// windows, dropped outliers and downsampling were not measured.
+fn cpu_seconds(cpu: &[(f32, f64)], interval: f32) -> f64 {
+    cpu.windows(2)
+        .map(|w| {
+            let step = w[1].0 - w[0].0;
+            let step = if interval > 0.0 {
+                step.min(interval)
+            } else {
+                step
+            };
+            f64::from(step) * w[1].1 / 100.0
+        })
+        .sum()
+}
+
+/// Group the samples by sampling round, the samples of one round share the
+/// timestamp.
+pub(crate) fn rounds(samples: &[Sample]) -> Vec<(f32, Vec<&Sample>)> {
+    let mut rounds: Vec<(f32, Vec<&Sample>)> = vec![];
+    for sample in samples {
+        match rounds.last_mut() {
+            Some((ts, samples)) if *ts == sample.ts => samples.push(sample),
+            _ => rounds.push((sample.ts, vec![sample])),
+        }
+    }
+    rounds
+}
+
+/// Sum of the metric over all processes per round, skipping rounds without
+/// the metric.
+pub(crate) fn totals(rounds: &[(f32, Vec<&Sample>)], metric: Metric) -> Vec<(f32, f64)> {
+    rounds
+        .iter()
+        .filter_map(|(ts, samples)| {
+            let total = samples
+                .iter()
+                .map(|s| metric.value(s))
+                .sum::<Option<f64>>()?;
+            Some((*ts, total))
+        })
+        .collect()
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} samples over {:.2}s, CPU in %, memory in kB",
+            self.samples, self.duration
+        )?;
+        writeln!(
+            f,
+            "{:<8} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}",
+            "metric", "min", "mean", "max", "stddev", "p50", "p90", "p99"
+        )?;
+        // in the order of the metrics rather than by name
+        for metric in Metric::ALL.iter() {
+            if let Some(s) = self.metrics.get(metric.name()) {
+                writeln!(
+                    f,
+                    "{:<8} {:>12.2} {:>12.2} {:>12.2} {:>12.2} {:>12.2} {:>12.2} {:>12.2}",
+                    metric.name(),
+                    s.min,
+                    s.mean,
+                    s.max,
+                    s.stddev,
+                    s.p50,
+                    s.p90,
+                    s.p99
+                )?;
+            }
+        }
+        write!(f, "CPU time {:.2}s from the samples", self.cpu_seconds)?;
+        if let Some(cpu_time) = self.cpu_time {
+            write!(
+                f,
+                ", {:.2}s measured (user {:.2}s, system {:.2}s)",
+                cpu_time.total(),
+                cpu_time.user,
+                cpu_time.system
+            )?;
+        }
+        writeln!(f)?;
+        if let (Some(rss), Some(vsize)) = (self.peak_rss, self.peak_vsize) {
+            writeln!(f, "High-water mark RSS {}kB, VSIZE {}kB", rss, vsize)?;
+            for (label, missed) in &self.missed_rss {
+                writeln!(
+                    f,
+                    "The samples missed {}kB of the RSS peak of {}",
+                    missed, label
+                )?;
+            }
+        }
+        if let Some(cgroup) = self.cgroup {
+            writeln!(f, "{}", cgroup)?;
+        }
+        for (name, growth) in &self.counters {
+            writeln!(f, "{} {} total, {:.1}/s", name, growth.total, growth.rate)?;
+        }
+        if let Some(ipc) = self.ipc {
+            writeln!(f, "{:.2} instructions per cycle", ipc)?;
+        }
+        for peak in &self.peaks {
+            writeln!(f, "{}", peak)?;
+        }
+        for (label, termination) in &self.terminations {
+            writeln!(f, "{} {}", label, termination)?;
+        }
+        for (label, metrics) in &self.unavailable {
+            writeln!(f, "{} without {}", label, metrics.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Part of a recording between two markers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Phase {
+    /// Label of the marker starting the phase, `start` for the first one
+    pub label: String,
+    /// Start of the phase in seconds
+    pub start: f32,
+    /// Until the next marker or the last sample
+    pub duration: f32,
+    pub summary: Summary,
+}
+
+/// Split the recording at its markers and summarize every phase, empty
+/// without markers.
+pub fn phases(recording: &Recording) -> Result<Vec<Phase>> {
+    let mut markers = vec![];
+    recording.for_each_entry(|entry| {
+        if let JsonEntry::Event(event) = entry {
+            if event.marker {
+                markers.push((event.label.clone(), event.ts));
+            }
+        }
+        Ok(())
+    })?;
+    if markers.is_empty() {
+        return Ok(vec![]);
+    }
+    // the first phase starts with the first sample, the others with their
+    // marker
+    let mut parts: Vec<Builder> = (0..=markers.len()).map(|_| Builder::default()).collect();
+    let mut first = None;
+    let mut last = 0.0;
+    recording.for_each_sample(|sample| {
+        first.get_or_insert(sample.ts);
+        last = sample.ts;
+        let part = markers
+            .iter()
+            .rposition(|(_, ts)| sample.ts >= *ts)
+            .map_or(0, |i| i + 1);
+        parts[part].push(sample);
+    })?;
+    let mut bounds = vec![("start".to_string(), first.unwrap_or_default())];
+    bounds.extend(markers);
+    let mut phases = vec![];
+    for (i, ((label, start), part)) in bounds.iter().zip(parts).enumerate() {
+        if part.rounds.is_empty() {
+            continue;
+        }
+        let end = bounds.get(i + 1).map(|b| b.1);
+        phases.push(Phase {
+            label: label.clone(),
+            start: *start,
+            duration: end.unwrap_or(last).min(last) - start,
+            summary: part.build(&Metadata::default(), 0),
+        });
+    }
+    Ok(phases)
+}
+
+/// Table of the CPU and RSS of the phases.
+pub struct PhaseTable<'a>(pub &'a [Phase]);
+
+impl fmt::Display for PhaseTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>9} {:>9} {:>9} {:>9} {:>9} {:>12} {:>12}",
+            "phase", "start", "duration", "cpu-s", "cpu mean", "cpu max", "rss mean", "rss max"
+        )?;
+        for phase in self.0 {
+            let s = &phase.summary;
+            let stats = |metric: Metric| s.metrics.get(metric.name()).copied().unwrap_or_default();
+            let (cpu, rss) = (stats(Metric::Cpu), stats(Metric::Rss));
+            writeln!(
+                f,
+                "{:<20} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>12.0} {:>12.0}",
+                phase.label,
+                phase.start,
+                phase.duration,
+                s.cpu_seconds,
+                cpu.mean,
+                cpu.max,
+                rss.mean,
+                rss.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Summary of a run for programmatic consumption, e.g. by CI or dashboards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub metadata: Metadata,
+    /// Exit code of the launched command if it ended during the recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(flatten)]
+    pub summary: Summary,
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
+    /// Statistics between the markers, if there are any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<Phase>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub histograms: Vec<Histogram>,
+}
+
+impl RunSummary {
+    /// Save as JSON.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Can not write summary '{}': {}", path.display(), e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+/// Change of one statistic compared to the baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub metric: String,
+    /// `mean` or `max`
+    pub stat: &'static str,
+    pub value: f64,
+    pub base: f64,
+    /// Relative change, 0.1 for 10% more than the baseline
+    pub change: f64,
+    pub regression: bool,
+}
+
+/// Result of comparing a summary with a baseline, displayed as table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub deltas: Vec<Delta>,
+}
+
+impl Comparison {
+    pub fn has_regression(&self) -> bool {
+        self.deltas.iter().any(|d| d.regression)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>14} {:>14} {:>9}",
+            "metric", "baseline", "current", "change"
+        )?;
+        for d in &self.deltas {
+            writeln!(
+                f,
+                "{:<12} {:>14.2} {:>14.2} {:>+8.1}%{}",
+                format!("{} {}", d.metric, d.stat),
+                d.base,
+                d.value,
+                d.change * 100.0,
+                if d.regression { "  REGRESSION" } else { "" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_of_nothing() {
+        assert_eq!(Stats::of(&[]), None);
+    }
+
+    #[test]
+    fn stats_of_one_value() {
+        let s = Stats::of(&[7.0]).unwrap();
+        assert_eq!((s.min, s.mean, s.max, s.stddev), (7.0, 7.0, 7.0, 0.0));
+        assert_eq!((s.p50, s.p90, s.p99), (7.0, 7.0, 7.0));
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        // unsorted on purpose
+        let s = Stats::of(&[40.0, 15.0, 50.0, 35.0, 20.0]).unwrap();
+        assert_eq!((s.min, s.max, s.mean), (15.0, 50.0, 32.0));
+        // ranks 3 (of 2.5), 5 (of 4.5) and 5 (of 4.95)
+        assert_eq!((s.p50, s.p90, s.p99), (35.0, 50.0, 50.0));
+    }
+
+    #[test]
+    fn percentiles_of_a_hundred_values() {
+        let values: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+        let s = Stats::of(&values).unwrap();
+        assert_eq!((s.p50, s.p90, s.p99), (50.0, 90.0, 99.0));
+        assert_eq!(s.mean, 50.5);
+        assert!((s.stddev - (9999.0f64 / 12.0).sqrt()).abs() < 1e-9);
+        assert_eq!(percentile(&[1.0, 2.0], 0.0), 1.0);
+        assert_eq!(percentile(&[1.0, 2.0], 100.0), 2.0);
+    }
+}
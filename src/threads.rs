@@ -0,0 +1,111 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Per-thread CPU utilization from `/proc/<pid>/task/<tid>/stat`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::time::Instant;
+
+/// CPU utilization of a single thread since the previous sample.
+pub struct ThreadUsage {
+    pub tid: u32,
+    pub name: String,
+    pub cpu: f32,
+}
+
+/// Samples the CPU utilization of all threads of a process, only available
+/// on linux.
+pub struct ThreadSampler {
+    pid: u32,
+    /// Consumed CPU time in clock ticks of every thread seen in the previous
+    /// sample
+    last_ticks: HashMap<u32, u64>,
+    last_time: Instant,
+    ticks_per_sec: f32,
+}
+
+impl ThreadSampler {
+    /// Create a sampler, the first call of `sample` sets the baseline.
+    pub fn new(pid: u32) -> Self {
+        // SAFETY: sysconf has no preconditions
+        #[cfg(unix)]
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        // there are no threads in /proc to sample anyway
+        #[cfg(not(unix))]
+        let ticks = 0;
+        ThreadSampler {
+            pid,
+            last_ticks: HashMap::new(),
+            last_time: Instant::now(),
+            ticks_per_sec: if ticks > 0 { ticks as f32 } else { 100.0 },
+        }
+    }
+
+    /// Process whose threads are sampled
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// CPU utilization of every thread since the previous call. Threads seen
+    /// for the first time are reported with 0%.
+    pub fn sample(&mut self) -> io::Result<Vec<ThreadUsage>> {
+        let elapsed = self.last_time.elapsed().as_secs_f32();
+        self.last_time = Instant::now();
+
+        let mut usage = vec![];
+        let mut ticks = HashMap::new();
+        for entry in fs::read_dir(format!("/proc/{}/task", self.pid))? {
+            let entry = entry?;
+            let tid: u32 = match entry.file_name().to_string_lossy().parse() {
+                Ok(tid) => tid,
+                Err(_) => continue,
+            };
+            // threads may end at any time, those are skipped silently
+            let (name, cur) = match fs::read_to_string(entry.path().join("stat"))
+                .ok()
+                .and_then(|stat| parse_stat(&stat))
+            {
+                Some(thread) => thread,
+                None => continue,
+            };
+            let cpu = match self.last_ticks.get(&tid) {
+                Some(last) if elapsed > 0.0 => {
+                    cur.saturating_sub(*last) as f32 / self.ticks_per_sec / elapsed * 100.0
+                }
+                _ => 0.0,
+            };
+            ticks.insert(tid, cur);
+            usage.push(ThreadUsage { tid, name, cpu });
+        }
+        self.last_ticks = ticks;
+        Ok(usage)
+    }
+}
+
+/// Extract name and consumed CPU time (user + system) in clock ticks.
+fn parse_stat(stat: &str) -> Option<(String, u64)> {
+    // the name is enclosed in parentheses and may contain spaces itself
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let name = stat.get(start + 1..end)?.to_string();
+    let fields: Vec<&str> = stat.get(end + 1..)?.split_whitespace().collect();
+    // fields start with the state (3rd field), utime and stime are 14th/15th
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((name, utime + stime))
+}
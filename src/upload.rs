@@ -0,0 +1,264 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Upload of finished recordings to S3 or compatible object storage.
+//!
+//! The credentials are taken from the usual environment variables
+//! `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN` and
+//! `AWS_REGION`. `AWS_ENDPOINT_URL` selects another S3-compatible service
+//! like MinIO, which is addressed path-style.
+
+use crate::Recording;
+use anyhow::{anyhow, bail, Result};
+use ring::{digest, hmac};
+use std::env;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bucket and key prefix of an `s3://bucket/prefix/` URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl FromStr for S3Location {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rest = s.strip_prefix("s3://").ok_or_else(|| {
+            anyhow!(
+                "Invalid upload location '{}', expected s3://BUCKET/PREFIX/",
+                s
+            )
+        })?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            bail!("Missing bucket in upload location '{}'", s);
+        }
+        Ok(S3Location {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+impl S3Location {
+    /// Prefix of the objects of a recording, made of its start time and the
+    /// first process, e.g. `ci/20240131T120000Z-server-4730/`.
+    pub fn run_prefix(&self, recording: &Recording) -> String {
+        let start = recording.metadata.start_time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        });
+        let mut name = String::new();
+        if let Some(process) = recording.metadata.processes.first() {
+            for c in process.label.chars() {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                    name.push(c);
+                } else if !name.ends_with('-') {
+                    name.push('-');
+                }
+            }
+        }
+        let (date, time) = utc_timestamp(start as u64);
+        format!(
+            "{}{}T{}Z-{}/",
+            self.prefix,
+            date,
+            time,
+            name.trim_matches('-')
+        )
+    }
+
+    /// Store `body` at `key` below the bucket, signed with AWS Signature
+    /// Version 4.
+    pub fn put(&self, key: &str, body: &[u8], content_type: &str) -> Result<()> {
+        let access_key = env::var("AWS_ACCESS_KEY_ID")
+            .map_err(|_| anyhow!("AWS_ACCESS_KEY_ID must be set for uploading"))?;
+        let secret_key = env::var("AWS_SECRET_ACCESS_KEY")
+            .map_err(|_| anyhow!("AWS_SECRET_ACCESS_KEY must be set for uploading"))?;
+        let region = env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let (base, host, path) = match env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/').to_string();
+                let host = endpoint
+                    .split_once("://")
+                    .map(|(_, host)| host)
+                    .unwrap_or(&endpoint)
+                    .to_string();
+                (
+                    endpoint,
+                    host,
+                    format!("/{}/{}", self.bucket, uri_encode(key)),
+                )
+            }
+            Err(_) => {
+                let host = format!("{}.s3.{}.amazonaws.com", self.bucket, region);
+                (
+                    format!("https://{}", host),
+                    host,
+                    format!("/{}", uri_encode(key)),
+                )
+            }
+        };
+        let url = format!("{}{}", base, path);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (date, time) = utc_timestamp(now);
+        let amz_date = format!("{}T{}Z", date, time);
+        let payload_hash = hex(digest::digest(&digest::SHA256, body).as_ref());
+        let token = env::var("AWS_SESSION_TOKEN").ok();
+
+        let mut headers = vec![
+            ("content-type", content_type.to_string()),
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = token {
+            headers.push(("x-amz-security-token", token));
+        }
+        let mut canonical_headers = String::new();
+        for (name, value) in &headers {
+            writeln!(canonical_headers, "{}:{}", name, value.trim())?;
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "PUT\n{}\n\n{}\n{}\n{}",
+            path, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+        );
+        let mut signing_key = format!("AWS4{}", secret_key).into_bytes();
+        for part in [date.as_str(), region.as_str(), "s3", "aws4_request"] {
+            signing_key = sign(&signing_key, part.as_bytes());
+        }
+        let signature = hex(&sign(&signing_key, string_to_sign.as_bytes()));
+
+        let mut request = ureq::put(&url).set(
+            "Authorization",
+            &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                access_key, scope, signed_headers, signature
+            ),
+        );
+        for (name, value) in &headers {
+            // ureq derives the host header from the URL
+            if *name != "host" {
+                request = request.set(name, value);
+            }
+        }
+        match request.send_bytes(body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, response)) => bail!(
+                "Upload to s3://{}/{} failed with status {}: {}",
+                self.bucket,
+                key,
+                code,
+                response.into_string().unwrap_or_default()
+            ),
+            Err(err) => bail!("Upload to s3://{}/{} failed: {}", self.bucket, key, err),
+        }
+    }
+
+    /// Upload the recording in the format of `Recording::save` and the
+    /// rendered graph if given, returns the prefix of the objects.
+    pub fn upload(&self, recording: &Recording, graph: Option<&Path>) -> Result<String> {
+        let prefix = self.run_prefix(recording);
+        let mut body = vec![];
+        recording.write(&mut body)?;
+        self.put(&format!("{}recording.procrec", prefix), &body, "text/plain")?;
+        if let Some(graph) = graph {
+            let name = graph
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("graph");
+            let content_type = match graph.extension().and_then(|e| e.to_str()) {
+                Some("png") => "image/png",
+                Some("svg") => "image/svg+xml",
+                Some("pdf") => "application/pdf",
+                _ => "application/octet-stream",
+            };
+            let body = std::fs::read(graph)
+                .map_err(|e| anyhow!("Can not read graph '{}': {}", graph.display(), e))?;
+            self.put(&format!("{}{}", prefix, name), &body, content_type)?;
+        }
+        Ok(prefix)
+    }
+}
+
+fn sign(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data)
+        .as_ref()
+        .to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encode everything but the unreserved characters and slashes.
+fn uri_encode(key: &str) -> String {
+    let mut encoded = String::new();
+    for b in key.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", b);
+        }
+    }
+    encoded
+}
+
+/// Date as `YYYYMMDD` and time as `HHMMSS` in UTC.
+fn utc_timestamp(secs: u64) -> (String, String) {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // civil date from days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (
+        format!("{:04}{:02}{:02}", year, month, day),
+        format!("{:02}{:02}{:02}", rem / 3600, rem / 60 % 60, rem % 60),
+    )
+}
@@ -0,0 +1,525 @@
+// procrec - record/plot cpu and memory usage of processes
+// Copyright (C) 2020 Lars Baumgaertner
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of  MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Process information on windows from the Win32 API.
+//!
+//! psutil does not support processes on windows, `Process` provides the part
+//! of its interface procrec uses. The RSS is the working set and the virtual
+//! memory size the private bytes, like psutil reports them for windows in
+//! python.
+//!
+//! A launched command is started suspended and resumed once it is in a job
+//! object, which holds all its descendants. They are accounted as its
+//! children and ended with it.
+
+use std::ffi::{c_void, OsString};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::windows::ffi::OsStringExt;
+use std::os::windows::io::AsRawHandle;
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use windows_sys::Wdk::System::Threading::{
+    NtQueryInformationProcess, ProcessCommandLineInformation,
+};
+use windows_sys::Win32::Foundation::{
+    CloseHandle, LocalFree, ERROR_ACCESS_DENIED, ERROR_INVALID_PARAMETER, FILETIME, HANDLE,
+    INVALID_HANDLE_VALUE, UNICODE_STRING, WAIT_TIMEOUT,
+};
+use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, Thread32First, Thread32Next,
+    PROCESSENTRY32W, TH32CS_SNAPPROCESS, TH32CS_SNAPTHREAD, THREADENTRY32,
+};
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectBasicAccountingInformation,
+    JobObjectExtendedLimitInformation, QueryInformationJobObject, SetInformationJobObject,
+    TerminateJobObject, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+use windows_sys::Win32::System::ProcessStatus::{
+    GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS, PROCESS_MEMORY_COUNTERS_EX,
+};
+use windows_sys::Win32::System::Threading::{
+    GetProcessIoCounters, GetProcessTimes, OpenProcess, OpenThread, QueryFullProcessImageNameW,
+    ResumeThread, TerminateProcess, WaitForSingleObject, CREATE_SUSPENDED, IO_COUNTERS,
+    PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE, PROCESS_TERMINATE,
+    PROCESS_VM_READ, THREAD_SUSPEND_RESUME,
+};
+use windows_sys::Win32::UI::Shell::CommandLineToArgvW;
+
+/// Access to the processes, reading the memory counters needs PROCESS_VM_READ
+/// and waiting for the exit SYNCHRONIZE.
+const PROCESS_ACCESS: u32 =
+    PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ | PROCESS_SYNCHRONIZE;
+/// Exit code of processes terminated by procrec.
+const TERMINATED: u32 = 1;
+/// Longest path and command line in UTF-16 units.
+const MAX_WIDE: usize = 32768;
+
+/// Failure to inspect a process, named like the errors of psutil.
+#[derive(Debug)]
+pub enum ProcessError {
+    NoSuchProcess { pid: u32 },
+    AccessDenied { pid: u32 },
+    Os { pid: u32, source: io::Error },
+}
+
+impl ProcessError {
+    fn new(pid: u32, source: io::Error) -> Self {
+        match source.raw_os_error().map(|code| code as u32) {
+            Some(ERROR_ACCESS_DENIED) => ProcessError::AccessDenied { pid },
+            // the PID is unknown
+            Some(ERROR_INVALID_PARAMETER) => ProcessError::NoSuchProcess { pid },
+            _ => ProcessError::Os { pid, source },
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::NoSuchProcess { pid } => write!(f, "Process {} does not exist", pid),
+            ProcessError::AccessDenied { pid } => write!(f, "Access denied for process {}", pid),
+            ProcessError::Os { pid, source } => write!(f, "Process {}: {}", pid, source),
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+pub type ProcessResult<T> = Result<T, ProcessError>;
+
+/// Closed when dropped.
+#[derive(Debug)]
+struct Handle(HANDLE);
+
+impl Handle {
+    fn new(handle: HANDLE) -> io::Result<Self> {
+        if handle == 0 || handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Handle(handle))
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Working set and private bytes of a process.
+#[derive(Clone, Copy)]
+pub struct MemoryInfo(PROCESS_MEMORY_COUNTERS_EX);
+
+impl MemoryInfo {
+    /// Working set in bytes.
+    pub fn rss(&self) -> u64 {
+        self.0.WorkingSetSize as u64
+    }
+
+    /// Private bytes, the memory committed for the process alone.
+    pub fn vms(&self) -> u64 {
+        self.0.PrivateUsage as u64
+    }
+
+    /// Highest working set so far in bytes.
+    pub fn peak_rss(&self) -> u64 {
+        self.0.PeakWorkingSetSize as u64
+    }
+
+    /// Highest private bytes so far, the commit charge of the page file.
+    pub fn peak_vms(&self) -> u64 {
+        self.0.PeakPagefileUsage as u64
+    }
+}
+
+/// CPU times of a process and its job.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTimes {
+    user: Duration,
+    system: Duration,
+    children_user: Duration,
+    children_system: Duration,
+}
+
+impl CpuTimes {
+    pub fn user(&self) -> Duration {
+        self.user
+    }
+
+    pub fn system(&self) -> Duration {
+        self.system
+    }
+
+    /// Time of the other processes in the job of a launched process,
+    /// including the ones which ended.
+    pub fn children_user(&self) -> Duration {
+        self.children_user
+    }
+
+    pub fn children_system(&self) -> Duration {
+        self.children_system
+    }
+}
+
+/// A process opened for querying its usage.
+#[derive(Debug, Clone)]
+pub struct Process {
+    pid: u32,
+    handle: Arc<Handle>,
+    /// Job of a launched process, holding its descendants
+    job: Option<Arc<Handle>>,
+    /// CPU time and time of the previous `cpu_percent`
+    last_cpu: (Duration, Instant),
+}
+
+impl Process {
+    pub fn new(pid: u32) -> ProcessResult<Process> {
+        let handle = unsafe { OpenProcess(PROCESS_ACCESS, 0, pid) };
+        let handle = Handle::new(handle).map_err(|e| ProcessError::new(pid, e))?;
+        let mut process = Process {
+            pid,
+            handle: Arc::new(handle),
+            job: None,
+            last_cpu: (Duration::ZERO, Instant::now()),
+        };
+        // like psutil, the first call of cpu_percent covers the time since
+        let times = process.cpu_times()?;
+        process.last_cpu.0 = times.user + times.system;
+        Ok(process)
+    }
+
+    /// Track a child spawned with `spawn_suspended`, which is put into a new
+    /// job and resumed.
+    pub fn launched(child: &Child) -> ProcessResult<Process> {
+        let job = match create_job(child) {
+            Ok(job) => Some(Arc::new(job)),
+            Err(e) => {
+                eprintln!(
+                    "Warning: can not put the command into a job object, its descendants are neither accounted nor ended with it: {}",
+                    e
+                );
+                None
+            }
+        };
+        if let Err(e) = resume(child.id()) {
+            // it would never start
+            unsafe { TerminateProcess(child.as_raw_handle() as HANDLE, TERMINATED) };
+            return Err(ProcessError::new(child.id(), e));
+        }
+        let mut process = Process::new(child.id())?;
+        process.job = job;
+        Ok(process)
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// File name of the executable, e.g. `cargo.exe`.
+    pub fn name(&self) -> ProcessResult<String> {
+        let mut buf = vec![0u16; MAX_WIDE];
+        let mut len = buf.len() as u32;
+        let res = unsafe {
+            QueryFullProcessImageNameW(
+                self.handle.0,
+                PROCESS_NAME_WIN32,
+                buf.as_mut_ptr(),
+                &mut len,
+            )
+        };
+        if res == 0 {
+            return Err(self.last_error());
+        }
+        let path = OsString::from_wide(&buf[..len as usize]);
+        Ok(Path::new(&path)
+            .file_name()
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned())
+    }
+
+    /// PID of the parent process, which may have ended.
+    pub fn ppid(&self) -> ProcessResult<Option<u32>> {
+        Ok(parents()
+            .into_iter()
+            .find(|(pid, _)| *pid == self.pid)
+            .map(|(_, ppid)| ppid))
+    }
+
+    /// Arguments of the process including the program, as the C runtime
+    /// splits its command line.
+    pub fn cmdline_vec(&self) -> ProcessResult<Option<Vec<String>>> {
+        // aligned for the UNICODE_STRING at its start
+        let mut buf = vec![0u64; (mem::size_of::<UNICODE_STRING>() + MAX_WIDE * 2) / 8];
+        let mut len = 0;
+        let status = unsafe {
+            NtQueryInformationProcess(
+                self.handle.0,
+                ProcessCommandLineInformation,
+                buf.as_mut_ptr() as *mut c_void,
+                (buf.len() * 8) as u32,
+                &mut len,
+            )
+        };
+        if status < 0 {
+            return Ok(None);
+        }
+        // the string points into the buffer behind it
+        let line = unsafe { &*(buf.as_ptr() as *const UNICODE_STRING) };
+        let mut wide: Vec<u16> =
+            unsafe { slice::from_raw_parts(line.Buffer, line.Length as usize / 2) }.to_vec();
+        if wide.is_empty() {
+            return Ok(Some(vec![]));
+        }
+        wide.push(0);
+        let mut argc = 0;
+        let argv = unsafe { CommandLineToArgvW(wide.as_ptr(), &mut argc) };
+        if argv.is_null() {
+            return Ok(None);
+        }
+        let args = unsafe { slice::from_raw_parts(argv, argc as usize) }
+            .iter()
+            .map(|arg| {
+                let len = (0..).take_while(|i| unsafe { *arg.add(*i) } != 0).count();
+                let arg = unsafe { slice::from_raw_parts(*arg, len) };
+                OsString::from_wide(arg).to_string_lossy().into_owned()
+            })
+            .collect();
+        unsafe { LocalFree(argv as *mut c_void) };
+        Ok(Some(args))
+    }
+
+    /// CPU utilization since the previous call in percent of one CPU.
+    pub fn cpu_percent(&mut self) -> ProcessResult<f32> {
+        if !self.is_running() {
+            return Err(ProcessError::NoSuchProcess { pid: self.pid });
+        }
+        let times = self.cpu_times()?;
+        let used = times.user + times.system;
+        let (last_used, last_time) = self.last_cpu;
+        let elapsed = last_time.elapsed().as_secs_f32();
+        self.last_cpu = (used, Instant::now());
+        if elapsed <= 0.0 {
+            return Ok(0.0);
+        }
+        Ok(used.saturating_sub(last_used).as_secs_f32() / elapsed * 100.0)
+    }
+
+    /// CPU times so far, available until the process is joined.
+    pub fn cpu_times(&self) -> ProcessResult<CpuTimes> {
+        let zero = FILETIME {
+            dwLowDateTime: 0,
+            dwHighDateTime: 0,
+        };
+        let (mut created, mut exited, mut kernel, mut user) = (zero, zero, zero, zero);
+        let res = unsafe {
+            GetProcessTimes(
+                self.handle.0,
+                &mut created,
+                &mut exited,
+                &mut kernel,
+                &mut user,
+            )
+        };
+        if res == 0 {
+            return Err(self.last_error());
+        }
+        let (user, system) = (duration(filetime(user)), duration(filetime(kernel)));
+        let (children_user, children_system) = match self.job.as_deref().and_then(accounting) {
+            // the job includes the process itself
+            Some(job) => (
+                duration(job.TotalUserTime as u64).saturating_sub(user),
+                duration(job.TotalKernelTime as u64).saturating_sub(system),
+            ),
+            None => (Duration::ZERO, Duration::ZERO),
+        };
+        Ok(CpuTimes {
+            user,
+            system,
+            children_user,
+            children_system,
+        })
+    }
+
+    /// Working set and private bytes, fails once the process ended.
+    pub fn memory_info(&self) -> ProcessResult<MemoryInfo> {
+        if !self.is_running() {
+            return Err(ProcessError::NoSuchProcess { pid: self.pid });
+        }
+        let mut counters: PROCESS_MEMORY_COUNTERS_EX = unsafe { mem::zeroed() };
+        counters.cb = mem::size_of::<PROCESS_MEMORY_COUNTERS_EX>() as u32;
+        let res = unsafe {
+            GetProcessMemoryInfo(
+                self.handle.0,
+                &mut counters as *mut PROCESS_MEMORY_COUNTERS_EX as *mut PROCESS_MEMORY_COUNTERS,
+                counters.cb,
+            )
+        };
+        if res == 0 {
+            return Err(self.last_error());
+        }
+        Ok(MemoryInfo(counters))
+    }
+
+    /// Bytes transferred by read and write calls so far, including pipes
+    /// and sockets.
+    pub fn io_bytes(&self) -> Option<u64> {
+        let mut counters: IO_COUNTERS = unsafe { mem::zeroed() };
+        let res = unsafe { GetProcessIoCounters(self.handle.0, &mut counters) };
+        (res != 0).then(|| counters.ReadTransferCount + counters.WriteTransferCount)
+    }
+
+    /// Whether the process has not exited yet, a PID can not be reused while
+    /// it is open.
+    pub fn is_running(&self) -> bool {
+        unsafe { WaitForSingleObject(self.handle.0, 0) == WAIT_TIMEOUT }
+    }
+
+    /// End the process, a launched one together with its job.
+    pub fn kill(&self) -> ProcessResult<()> {
+        let res = match &self.job {
+            Some(job) => unsafe { TerminateJobObject(job.0, TERMINATED) },
+            None => {
+                let handle = unsafe { OpenProcess(PROCESS_TERMINATE, 0, self.pid) };
+                let handle = Handle::new(handle).map_err(|e| ProcessError::new(self.pid, e))?;
+                unsafe { TerminateProcess(handle.0, TERMINATED) }
+            }
+        };
+        if res == 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+
+    /// Error of the last failed call on the process.
+    fn last_error(&self) -> ProcessError {
+        ProcessError::new(self.pid, io::Error::last_os_error())
+    }
+
+    /// Let the job of a launched process keep running once procrec closed
+    /// it.
+    pub fn disown_job(&self) {
+        if let Some(job) = &self.job {
+            if let Err(e) = set_kill_on_close(job, false) {
+                eprintln!("Warning: the command may end with procrec: {}", e);
+            }
+        }
+    }
+}
+
+/// Spawn the command suspended, it is resumed by `Process::launched`.
+pub(crate) fn spawn_suspended(cmd: &mut Command) -> io::Result<Child> {
+    cmd.creation_flags(CREATE_SUSPENDED);
+    cmd.spawn()
+}
+
+/// Create a job ending its processes when it is closed and put the child
+/// into it.
+fn create_job(child: &Child) -> io::Result<Handle> {
+    let job = Handle::new(unsafe { CreateJobObjectW(ptr::null(), ptr::null()) })?;
+    set_kill_on_close(&job, true)?;
+    if unsafe { AssignProcessToJobObject(job.0, child.as_raw_handle() as HANDLE) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(job)
+}
+
+fn set_kill_on_close(job: &Handle, kill: bool) -> io::Result<()> {
+    let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { mem::zeroed() };
+    if kill {
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    }
+    let res = unsafe {
+        SetInformationJobObject(
+            job.0,
+            JobObjectExtendedLimitInformation,
+            &limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const c_void,
+            mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )
+    };
+    if res == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// CPU times of all processes which were in the job.
+fn accounting(job: &Handle) -> Option<JOBOBJECT_BASIC_ACCOUNTING_INFORMATION> {
+    let mut info: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION = unsafe { mem::zeroed() };
+    let res = unsafe {
+        QueryInformationJobObject(
+            job.0,
+            JobObjectBasicAccountingInformation,
+            &mut info as *mut JOBOBJECT_BASIC_ACCOUNTING_INFORMATION as *mut c_void,
+            mem::size_of::<JOBOBJECT_BASIC_ACCOUNTING_INFORMATION>() as u32,
+            ptr::null_mut(),
+        )
+    };
+    (res != 0).then_some(info)
+}
+
+/// Resume the threads of a process spawned suspended.
+fn resume(pid: u32) -> io::Result<()> {
+    let snapshot = Handle::new(unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) })?;
+    let mut entry: THREADENTRY32 = unsafe { mem::zeroed() };
+    entry.dwSize = mem::size_of::<THREADENTRY32>() as u32;
+    let mut found = unsafe { Thread32First(snapshot.0, &mut entry) } != 0;
+    while found {
+        if entry.th32OwnerProcessID == pid {
+            let thread =
+                Handle::new(unsafe { OpenThread(THREAD_SUSPEND_RESUME, 0, entry.th32ThreadID) })?;
+            if unsafe { ResumeThread(thread.0) } == u32::MAX {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        found = unsafe { Thread32Next(snapshot.0, &mut entry) } != 0;
+    }
+    Ok(())
+}
+
+/// PID and parent PID of every process.
+pub(crate) fn parents() -> Vec<(u32, u32)> {
+    let snapshot = match Handle::new(unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }) {
+        Ok(snapshot) => snapshot,
+        Err(_) => return vec![],
+    };
+    let mut entry: PROCESSENTRY32W = unsafe { mem::zeroed() };
+    entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as u32;
+    let mut parents = vec![];
+    let mut found = unsafe { Process32FirstW(snapshot.0, &mut entry) } != 0;
+    while found {
+        parents.push((entry.th32ProcessID, entry.th32ParentProcessID));
+        found = unsafe { Process32NextW(snapshot.0, &mut entry) } != 0;
+    }
+    parents
+}
+
+/// Time in 100 ns units.
+fn filetime(time: FILETIME) -> u64 {
+    (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime)
+}
+
+fn duration(units: u64) -> Duration {
+    Duration::from_nanos(units * 100)
+}